@@ -0,0 +1,346 @@
+use std::fmt::{self, Display};
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+use serde::Serialize;
+
+const MAX_DICE: u32 = 1000;
+const MAX_SIDES: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Keep {
+    All,
+    Highest(u32),
+    Lowest(u32),
+}
+
+/// One `NdS` term of an expression, with every individual die result
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiceRoll {
+    pub count: u32,
+    pub sides: u32,
+    pub keep: Keep,
+    /// Every die that rolls its highest face adds another die, which can explode in turn
+    pub exploding: bool,
+    pub negative: bool,
+    pub rolls: Vec<u32>,
+    pub kept: Vec<bool>,
+    pub subtotal: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RollResult {
+    pub expression: String,
+    pub dice: Vec<DiceRoll>,
+    pub modifier: i64,
+    pub total: i64,
+}
+
+impl Display for RollResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.expression)?;
+        for (i, roll) in self.dice.iter().enumerate() {
+            if i > 0 || roll.negative {
+                write!(f, " {}", if roll.negative { '-' } else { '+' })?;
+            }
+            let rolls: Vec<String> = roll
+                .rolls
+                .iter()
+                .zip(roll.kept.iter())
+                .map(|(value, kept)| {
+                    if *kept {
+                        value.to_string()
+                    } else {
+                        format!("({value})")
+                    }
+                })
+                .collect();
+            write!(f, " [{}]", rolls.join(", "))?;
+        }
+        if self.modifier != 0 {
+            write!(
+                f,
+                " {} {}",
+                if self.modifier < 0 { '-' } else { '+' },
+                self.modifier.abs()
+            )?;
+        }
+        write!(f, " = {}", self.total)
+    }
+}
+
+enum Term {
+    Dice {
+        count: u32,
+        sides: u32,
+        keep: Keep,
+        exploding: bool,
+    },
+    Constant(i64),
+}
+
+fn parse_number(text: &str, what: &str) -> Result<u32> {
+    text.parse()
+        .with_context(|| format!("Expected a number for {what}, found '{text}'"))
+}
+
+fn parse_term(term: &str) -> Result<Term> {
+    let Some((count, rest)) = term.split_once(['d', 'D']) else {
+        return Ok(Term::Constant(
+            term.parse()
+                .with_context(|| format!("'{term}' is not a number or dice term"))?,
+        ));
+    };
+
+    let count = if count.is_empty() {
+        1
+    } else {
+        parse_number(count, "the number of dice")?
+    };
+
+    let lowercase_rest = rest.to_lowercase();
+    let (sides, keep) = if let Some((sides, keep)) = lowercase_rest.split_once("kh") {
+        (sides, Keep::Highest(parse_number(keep, "dice to keep")?))
+    } else if let Some((sides, keep)) = lowercase_rest.split_once("kl") {
+        (sides, Keep::Lowest(parse_number(keep, "dice to keep")?))
+    } else {
+        (lowercase_rest.as_str(), Keep::All)
+    };
+
+    let (sides, exploding) = match sides.strip_suffix('!') {
+        Some(sides) => (sides, true),
+        None => (sides, false),
+    };
+    let sides = if sides == "%" {
+        100
+    } else {
+        parse_number(sides, "the number of sides")?
+    };
+
+    if count == 0 || count > MAX_DICE {
+        bail!("The number of dice must be between 1 and {MAX_DICE}");
+    }
+    if sides == 0 || sides > MAX_SIDES {
+        bail!("Dice must have between 1 and {MAX_SIDES} sides");
+    }
+    if exploding && sides == 1 {
+        bail!("One-sided dice can't explode");
+    }
+    if let Keep::Highest(n) | Keep::Lowest(n) = keep {
+        if n == 0 || n > count {
+            bail!("Can't keep {n} of {count} dice");
+        }
+    }
+
+    Ok(Term::Dice {
+        count,
+        sides,
+        keep,
+        exploding,
+    })
+}
+
+/// Rolls a dice expression such as `3d6+2`, `d20`, `2d10kh1` or `3d6!`, using `roll_die` to produce each
+/// die result (which must be between 1 and the number of sides passed to it)
+pub fn roll_with(expr: &str, mut roll_die: impl FnMut(u32) -> u32) -> Result<RollResult> {
+    let expression: String = expr.chars().filter(|ch| !ch.is_whitespace()).collect();
+    if expression.is_empty() {
+        bail!("Empty dice expression");
+    }
+
+    let mut dice = Vec::new();
+    let mut modifier: i64 = 0;
+
+    let mut rest = expression.as_str();
+    let mut negative = false;
+    if let Some(stripped) = rest.strip_prefix('-') {
+        negative = true;
+        rest = stripped;
+    } else if let Some(stripped) = rest.strip_prefix('+') {
+        rest = stripped;
+    }
+
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = &rest[..end];
+        if term.is_empty() {
+            bail!("Malformed dice expression '{expr}'");
+        }
+
+        match parse_term(term)? {
+            Term::Constant(value) => {
+                modifier += if negative { -value } else { value };
+            }
+            Term::Dice {
+                count,
+                sides,
+                keep,
+                exploding,
+            } => {
+                let mut rolls: Vec<u32> = (0..count).map(|_| roll_die(sides)).collect();
+                if exploding {
+                    // Explosions stop once the term has as many dice as any term may start with
+                    let mut i = 0;
+                    while i < rolls.len() && rolls.len() < MAX_DICE as usize {
+                        if rolls[i] == sides {
+                            rolls.push(roll_die(sides));
+                        }
+                        i += 1;
+                    }
+                }
+
+                let mut order: Vec<usize> = (0..rolls.len()).collect();
+                order.sort_by_key(|&i| rolls[i]);
+                let mut kept = vec![true; rolls.len()];
+                match keep {
+                    Keep::All => {}
+                    Keep::Highest(n) => {
+                        for &i in &order[..rolls.len() - n as usize] {
+                            kept[i] = false;
+                        }
+                    }
+                    Keep::Lowest(n) => {
+                        for &i in &order[n as usize..] {
+                            kept[i] = false;
+                        }
+                    }
+                }
+
+                let sum: i64 = rolls
+                    .iter()
+                    .zip(kept.iter())
+                    .filter(|(_, kept)| **kept)
+                    .map(|(value, _)| *value as i64)
+                    .sum();
+
+                dice.push(DiceRoll {
+                    count,
+                    sides,
+                    keep,
+                    exploding,
+                    negative,
+                    rolls,
+                    kept,
+                    subtotal: if negative { -sum } else { sum },
+                });
+            }
+        }
+
+        if end == rest.len() {
+            break;
+        }
+        negative = &rest[end..=end] == "-";
+        rest = &rest[end + 1..];
+    }
+
+    let total = dice.iter().map(|roll| roll.subtotal).sum::<i64>() + modifier;
+
+    Ok(RollResult {
+        expression,
+        dice,
+        modifier,
+        total,
+    })
+}
+
+/// Rolls a dice expression such as `3d6+2`, `d20`, `2d10kh1` or `3d6!` with the given random number generator
+pub fn parse_and_roll<R: Rng + ?Sized>(expr: &str, rng: &mut R) -> Result<RollResult> {
+    roll_with(expr, |sides| rng.gen_range(1..=sides))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Produces the given die results in order, regardless of the number of sides
+    fn scripted(results: &[u32]) -> impl FnMut(u32) -> u32 + '_ {
+        let mut iter = results.iter();
+        move |_| *iter.next().unwrap()
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let result = roll_with("3d6+2", scripted(&[4, 2, 6])).unwrap();
+        assert_eq!(result.total, 14);
+        assert_eq!(result.modifier, 2);
+        assert_eq!(result.dice[0].rolls, vec![4, 2, 6]);
+        assert_eq!(result.to_string(), "3d6+2: [4, 2, 6] + 2 = 14");
+
+        let result = roll_with("d20 - 1 + 1d4", scripted(&[15, 3])).unwrap();
+        assert_eq!(result.total, 17);
+        assert_eq!(result.modifier, -1);
+    }
+
+    #[test]
+    fn test_keep_highest_and_lowest() {
+        let result = roll_with("2d10kh1", scripted(&[3, 8])).unwrap();
+        assert_eq!(result.total, 8);
+        assert_eq!(result.dice[0].kept, vec![false, true]);
+
+        let result = roll_with("4d6kh3", scripted(&[1, 5, 3, 6])).unwrap();
+        assert_eq!(result.total, 14);
+        assert_eq!(result.to_string(), "4d6kh3: [(1), 5, 3, 6] = 14");
+
+        let result = roll_with("2d20kl1+5", scripted(&[17, 4])).unwrap();
+        assert_eq!(result.total, 9);
+        assert_eq!(result.dice[0].kept, vec![false, true]);
+    }
+
+    #[test]
+    fn test_exploding_dice() {
+        let result = roll_with("2d6!", scripted(&[6, 3, 6, 2])).unwrap();
+        assert_eq!(result.dice[0].rolls, vec![6, 3, 6, 2]);
+        assert_eq!(result.total, 17);
+
+        // Exploded dice count towards what's kept
+        let result = roll_with("2d6!kh2+1", scripted(&[6, 1, 4])).unwrap();
+        assert_eq!(result.dice[0].kept, vec![true, false, true]);
+        assert_eq!(result.total, 11);
+        assert_eq!(result.to_string(), "2d6!kh2+1: [6, (1), 4] + 1 = 11");
+
+        // Dice that always explode stop at the limit rather than rolling forever
+        let result = roll_with("d2!", |sides| sides).unwrap();
+        assert_eq!(result.dice[0].rolls.len(), MAX_DICE as usize);
+    }
+
+    #[test]
+    fn test_absurd_expressions_are_rejected() {
+        for expr in ["1000000d1000000", "1001d6", "1d1000001", "d1!"] {
+            assert!(
+                roll_with(expr, |_| 1).is_err(),
+                "'{expr}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_negative_dice() {
+        let result = roll_with("10-1d4", scripted(&[3])).unwrap();
+        assert_eq!(result.total, 7);
+        assert_eq!(result.dice[0].subtotal, -3);
+    }
+
+    #[test]
+    fn test_seeded_rng_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..100 {
+            let result = parse_and_roll("2d6kh1", &mut rng).unwrap();
+            assert!((1..=6).contains(&result.total));
+            assert!(result.dice[0].rolls.iter().all(|roll| (1..=6).contains(roll)));
+        }
+    }
+
+    #[test]
+    fn test_malformed_notation() {
+        for expr in ["", "3d", "d", "2d6+", "abc", "3d6kh4", "0d6", "2d0", "1++2", "2x6"] {
+            assert!(
+                roll_with(expr, scripted(&[1; 8])).is_err(),
+                "'{expr}' should be rejected"
+            );
+        }
+    }
+}
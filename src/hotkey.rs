@@ -1,9 +1,19 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use anyhow::{anyhow, Context, Result};
+use deno_core::serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::UnboundedSender;
 
 use crate::script_runtime::{RuntimeAction, ScriptRuntime};
 
+const HOTKEYS_JSON_FILENAME: &str = "hotkeys.json";
+
 pub enum HotkeyResult {
     Processed,
     Unrecognized,
@@ -17,81 +27,170 @@ impl From<Option<&dyn ExactSizeIterator<Item = &Hotkey>>> for HotkeyResult {
         }
     }
 }
+
+/// A key and the modifiers held with it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KeyCombo {
+    pub scancode: i32,
+    #[serde(default)]
+    pub control: bool,
+    #[serde(default)]
+    pub alt: bool,
+    #[serde(default)]
+    pub shift: bool,
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.control, "Ctrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        write!(f, "{}", self.scancode)
+    }
+}
+
+/// A command sent when a key combination is pressed, as kept in hotkeys.json
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HotkeyBinding {
+    #[serde(flatten)]
+    pub key: KeyCombo,
+    pub command: String,
+}
+
+impl HotkeyBinding {
+    fn new(scancode: i32, command: &str) -> Self {
+        Self {
+            key: KeyCombo {
+                scancode,
+                control: false,
+                alt: false,
+                shift: false,
+            },
+            command: command.to_string(),
+        }
+    }
+}
+
+/// The numpad movement keys, used when a profile hasn't defined its own hotkeys
+fn default_bindings() -> Vec<HotkeyBinding> {
+    vec![
+        HotkeyBinding::new(72, "n"),
+        HotkeyBinding::new(77, "e"),
+        HotkeyBinding::new(80, "s"),
+        HotkeyBinding::new(75, "w"),
+        HotkeyBinding::new(73, "u"),
+        HotkeyBinding::new(81, "d"),
+        HotkeyBinding::new(71, "st"),
+        HotkeyBinding::new(79, "rest"),
+        HotkeyBinding::new(78, "scan"),
+        HotkeyBinding::new(76, "look"),
+    ]
+}
+
+/// Reads a set of bindings, returning None when the file doesn't exist
+pub fn load_bindings(path: &Path) -> Result<Option<Vec<HotkeyBinding>>> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Could not read {}", path.display())),
+        Ok(json) => serde_json::from_str(&json)
+            .map(Some)
+            .with_context(|| format!("Could not parse {}", path.display())),
+    }
+}
+
+pub fn save_bindings(path: &Path, bindings: &[HotkeyBinding]) -> Result<()> {
+    let json = serde_json::to_string_pretty(bindings).context("Could not generate hotkeys json")?;
+    fs::write(path, json).with_context(|| format!("Could not save {}", path.display()))
+}
+
+/// Lays a character's bindings over its profile's, the character's winning wherever both bind
+/// the same key combination. Returns the merged bindings, and the combinations bound at both.
+pub fn layer(
+    profile: Vec<HotkeyBinding>,
+    character: Vec<HotkeyBinding>,
+) -> (Vec<HotkeyBinding>, Vec<KeyCombo>) {
+    let mut conflicts = Vec::new();
+    let mut merged: Vec<HotkeyBinding> = profile
+        .into_iter()
+        .filter(|binding| {
+            let overridden = character.iter().any(|other| other.key == binding.key);
+            if overridden && !conflicts.contains(&binding.key) {
+                conflicts.push(binding.key);
+            }
+            !overridden
+        })
+        .collect();
+    merged.extend(character);
+    (merged, conflicts)
+}
+
+fn load_or_warn(path: &Path) -> Option<Vec<HotkeyBinding>> {
+    load_bindings(path).unwrap_or_else(|e| {
+        warn!("{e:#}");
+        None
+    })
+}
+
 pub struct HotkeyManager {
     hotkeys: HashMap<i32, Vec<Hotkey>>,
     script_eval_tx: UnboundedSender<RuntimeAction>,
+    profile_path: PathBuf,
+    character_path: PathBuf,
+    bindings: Vec<HotkeyBinding>,
+    /// Combinations the character binds over the profile's
+    overridden: Vec<KeyCombo>,
 }
 
 impl HotkeyManager {
-    pub fn new(script_runtime: Arc<ScriptRuntime>) -> Self {
-        let hotkeys = HashMap::new();
+    /// Loads the hotkeys in the profile's and the character's directories, the character's
+    /// overriding the profile's
+    pub fn new(
+        script_runtime: Arc<ScriptRuntime>,
+        mut profile_path: PathBuf,
+        mut character_path: PathBuf,
+    ) -> Self {
+        profile_path.push(HOTKEYS_JSON_FILENAME);
+        character_path.push(HOTKEYS_JSON_FILENAME);
 
         let mut me = Self {
-            hotkeys,
+            hotkeys: HashMap::new(),
             script_eval_tx: script_runtime.tx(),
+            profile_path,
+            character_path,
+            bindings: Vec::new(),
+            overridden: Vec::new(),
         };
+        me.reload();
+        me
+    }
 
-        me.push(Hotkey {
-            name: "n".into(),
-            scancode: 72,
-            script: RuntimeAction::SendRaw(Arc::new("n".into())),
-        });
-        me.push(Hotkey {
-            name: "e".into(),
-            scancode: 77,
-            script: RuntimeAction::SendRaw(Arc::new("e".into())),
-        });
-        me.push(Hotkey {
-            name: "s".into(),
-            scancode: 80,
-            script: RuntimeAction::SendRaw(Arc::new("s".into())),
-        });
-        me.push(Hotkey {
-            name: "w".into(),
-            scancode: 75,
-            script: RuntimeAction::SendRaw(Arc::new("w".into())),
-        });
-        me.push(Hotkey {
-            name: "u".into(),
-            scancode: 73,
-            script: RuntimeAction::SendRaw(Arc::new("u".into())),
-        });
-        me.push(Hotkey {
-            name: "d".into(),
-            scancode: 81,
-            script: RuntimeAction::SendRaw(Arc::new("d".into())),
-        });
-        me.push(Hotkey {
-            name: "st".into(),
-            scancode: 71,
-            script: RuntimeAction::SendRaw(Arc::new("st".into())),
-        });
-        me.push(Hotkey {
-            name: "rest".into(),
-            scancode: 79,
-            script: RuntimeAction::SendRaw(Arc::new("rest".into())),
-        });
-        me.push(Hotkey {
-            name: "scan".into(),
-            scancode: 78,
-            script: RuntimeAction::SendRaw(Arc::new("scan".into())),
-        });
-        me.push(Hotkey {
-            name: "look".into(),
-            scancode: 76,
-            script: RuntimeAction::SendRaw(Arc::new("look".into())),
-        });
+    fn reload(&mut self) {
+        let profile = load_or_warn(&self.profile_path).unwrap_or_else(default_bindings);
+        let character = load_or_warn(&self.character_path).unwrap_or_default();
+        (self.bindings, self.overridden) = layer(profile, character);
 
-        me
+        self.hotkeys.clear();
+        for binding in self.bindings.clone() {
+            self.push(Hotkey {
+                key: binding.key,
+                script: RuntimeAction::SendRaw(Arc::new(binding.command)),
+            });
+        }
     }
 
     fn push(&mut self, hotkey: Hotkey) {
-        match self.hotkeys.get_mut(&hotkey.scancode) {
+        match self.hotkeys.get_mut(&hotkey.key.scancode) {
             Some(vec) => {
                 vec.push(hotkey);
             }
             None => {
-                self.hotkeys.insert(hotkey.scancode, vec![hotkey]);
+                self.hotkeys.insert(hotkey.key.scancode, vec![hotkey]);
             }
         }
     }
@@ -112,24 +211,107 @@ impl HotkeyManager {
             HotkeyResult::Unrecognized
         }
     }
+
+    /// Handles `#hotkeys list`, `#hotkeys export <file>` and `#hotkeys import <file>`, returning
+    /// the lines to echo. Exports are of every binding in effect; imports replace the
+    /// character's own bindings.
+    pub fn process_command(&mut self, args: &str) -> Result<Vec<String>> {
+        let (command, path) = args
+            .split_once(' ')
+            .map_or((args, ""), |(command, path)| (command, path.trim()));
+        match (command, path) {
+            ("list", "") => Ok(self
+                .bindings
+                .iter()
+                .map(|binding| {
+                    let badge = if self.overridden.contains(&binding.key) {
+                        " [overrides profile]"
+                    } else {
+                        ""
+                    };
+                    format!("{}: {}{badge}", binding.key, binding.command)
+                })
+                .collect()),
+            ("export", path) if !path.is_empty() => {
+                save_bindings(Path::new(path), &self.bindings)?;
+                Ok(vec![format!(
+                    "Exported {} hotkeys to {path}",
+                    self.bindings.len()
+                )])
+            }
+            ("import", path) if !path.is_empty() => {
+                let bindings = load_bindings(Path::new(path))?
+                    .ok_or_else(|| anyhow!("No such file: {path}"))?;
+                save_bindings(&self.character_path, &bindings)?;
+                self.reload();
+                Ok(vec![format!(
+                    "Imported {} hotkeys from {path}",
+                    bindings.len()
+                )])
+            }
+            _ => Err(anyhow!(
+                "Usage: #hotkeys list | export <file> | import <file>"
+            )),
+        }
+    }
 }
 
 struct Hotkey {
-    pub name: String,
-    pub scancode: i32,
+    pub key: KeyCombo,
     pub script: RuntimeAction,
 }
 
 impl Hotkey {
-    fn new(name: String, scancode: i32, script: RuntimeAction) -> Self {
-        Self {
-            name,
-            scancode,
-            script,
-        }
+    pub fn matches(&self, ev: &i_slint_core::items::KeyEvent) -> bool {
+        self.key.control == ev.modifiers.control
+            && self.key.alt == ev.modifiers.alt
+            && self.key.shift == ev.modifiers.shift
     }
+}
 
-    pub fn matches(&self, ev: &i_slint_core::items::KeyEvent) -> bool {
-        true
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_character_bindings_override_the_profile() {
+        let mut ctrl_look = HotkeyBinding::new(76, "glance");
+        ctrl_look.key.control = true;
+        let profile = vec![HotkeyBinding::new(72, "n"), HotkeyBinding::new(76, "look")];
+        let character = vec![
+            HotkeyBinding::new(76, "cast 'detect magic'"),
+            ctrl_look.clone(),
+        ];
+
+        let (merged, conflicts) = layer(profile, character);
+        assert_eq!(
+            merged,
+            vec![
+                HotkeyBinding::new(72, "n"),
+                HotkeyBinding::new(76, "cast 'detect magic'"),
+                ctrl_look,
+            ]
+        );
+        assert_eq!(conflicts, vec![HotkeyBinding::new(76, "").key]);
+    }
+
+    #[test]
+    fn test_bindings_round_trip_through_a_file() {
+        let path = std::env::temp_dir().join(format!("smudgy-hotkeys-{}.json", std::process::id()));
+        assert_eq!(load_bindings(&path).unwrap(), None);
+
+        let mut bindings = default_bindings();
+        bindings[0].key.shift = true;
+        save_bindings(&path, &bindings).unwrap();
+        assert_eq!(load_bindings(&path).unwrap(), Some(bindings));
+
+        // Modifiers can be left out of hand-written files
+        fs::write(&path, r#"[{"scancode": 59, "command": "score"}]"#).unwrap();
+        assert_eq!(
+            load_bindings(&path).unwrap(),
+            Some(vec![HotkeyBinding::new(59, "score")])
+        );
+
+        fs::remove_file(path).ok();
     }
 }
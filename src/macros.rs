@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+const MACROS_JSON_FILENAME: &str = "macros.json";
+
+#[derive(Debug, Default)]
+struct MacroStore {
+    path: Option<PathBuf>,
+    macros: BTreeMap<String, Vec<String>>,
+    /// The name of the macro being recorded, and what's been typed since it started
+    recording: Option<(String, Vec<String>)>,
+}
+
+/// Named sequences of typed commands, kept per profile in macros.json. Shared between a session,
+/// which records what's typed, and its scripts, which can play them back.
+#[derive(Clone, Debug, Default)]
+pub struct Macros(Arc<Mutex<MacroStore>>);
+
+impl Macros {
+    /// Loads the macros kept in `dir`, starting with none when there are none or they're unreadable
+    pub fn in_dir(mut dir: PathBuf) -> Self {
+        dir.push(MACROS_JSON_FILENAME);
+
+        let macros = match fs::read_to_string(&dir) {
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                warn!("Could not read macros.json: {e}");
+                BTreeMap::new()
+            }
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Could not parse macros.json: {e}");
+                BTreeMap::new()
+            }),
+        };
+
+        Macros(Arc::new(Mutex::new(MacroStore {
+            path: Some(dir),
+            macros,
+            recording: None,
+        })))
+    }
+
+    fn save(store: &MacroStore) -> Result<()> {
+        let Some(path) = store.path.as_ref() else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&store.macros)
+            .context("Could not generate macros json")?;
+        fs::write(path, json).context("Could not save macros")
+    }
+
+    /// The name of the macro being recorded, if one is
+    pub fn recording(&self) -> Option<String> {
+        let store = self.0.lock().unwrap();
+        store.recording.as_ref().map(|(name, _)| name.clone())
+    }
+
+    /// Starts recording typed commands into the named macro, dropping anything recorded so far
+    /// that wasn't stopped
+    pub fn start_recording(&self, name: &str) {
+        self.0.lock().unwrap().recording = Some((name.to_string(), Vec::new()));
+    }
+
+    /// Stops recording and saves the macro, returning its name and how many commands it has
+    pub fn stop_recording(&self) -> Result<Option<(String, usize)>> {
+        let mut store = self.0.lock().unwrap();
+        let Some((name, commands)) = store.recording.take() else {
+            return Ok(None);
+        };
+        let count = commands.len();
+        store.macros.insert(name.clone(), commands);
+        Macros::save(&store)?;
+        Ok(Some((name, count)))
+    }
+
+    /// Adds a typed command to the macro being recorded, if there is one
+    pub fn record(&self, command: &str) {
+        if let Some((_, commands)) = self.0.lock().unwrap().recording.as_mut() {
+            commands.push(command.to_string());
+        }
+    }
+
+    pub fn commands(&self, name: &str) -> Option<Vec<String>> {
+        self.0.lock().unwrap().macros.get(name).cloned()
+    }
+
+    /// Every macro's name and how many commands it has, by name
+    pub fn list(&self) -> Vec<(String, usize)> {
+        let store = self.0.lock().unwrap();
+        store
+            .macros
+            .iter()
+            .map(|(name, commands)| (name.clone(), commands.len()))
+            .collect()
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut store = self.0.lock().unwrap();
+        if store.macros.remove(name).is_none() {
+            return Err(anyhow!("No macro named '{name}'"));
+        }
+        Macros::save(&store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_captures_commands_in_order() {
+        let macros = Macros::default();
+        macros.record("ignored");
+
+        macros.start_recording("heal");
+        assert_eq!(macros.recording().as_deref(), Some("heal"));
+        for command in ["rem sword", "cast 'heal' self", "wield sword"] {
+            macros.record(command);
+        }
+        assert_eq!(macros.stop_recording().unwrap(), Some(("heal".into(), 3)));
+        macros.record("after");
+
+        assert_eq!(macros.recording(), None);
+        assert_eq!(
+            macros.commands("heal").unwrap(),
+            vec!["rem sword", "cast 'heal' self", "wield sword"]
+        );
+        assert_eq!(macros.list(), vec![("heal".to_string(), 3)]);
+        assert_eq!(macros.stop_recording().unwrap(), None);
+    }
+
+    #[test]
+    fn test_macros_are_saved() {
+        let dir = std::env::temp_dir().join(format!("smudgy-macros-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let macros = Macros::in_dir(dir.clone());
+        macros.start_recording("buff");
+        macros.record("cast armor");
+        macros.stop_recording().unwrap();
+        assert_eq!(
+            Macros::in_dir(dir.clone()).commands("buff").unwrap(),
+            vec!["cast armor"]
+        );
+
+        macros.delete("buff").unwrap();
+        assert!(macros.delete("buff").is_err());
+        assert!(Macros::in_dir(dir.clone()).list().is_empty());
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -3,14 +3,14 @@
 //#![windows_subsystem = "windows"]
 
 use log::{debug, error, info, log_enabled, Level};
-use models::Profile;
+use models::{MultiLinePaste, PaneLayout, Profile, RestoreSessions, SavedSession, Settings, WindowGeometry, WindowState};
 use raw_window_handle::{
     HasRawDisplayHandle, HasRawWindowHandle, HasWindowHandle, RawWindowHandle,
 };
-use ui::ConnectWindowBuilder;
+use ui::{open_session, pane_splits, ConnectWindowBuilder};
 
 use std::{
- cell::RefCell, panic, process, rc::Rc, sync::{Arc, LazyLock, Mutex, Weak}
+ cell::RefCell, panic, rc::Rc, sync::{Arc, LazyLock, Mutex, Weak}, time::Duration
 };
 
 use i_slint_backend_winit::{
@@ -23,21 +23,32 @@ use i_slint_backend_winit::{
 
 use i_slint_core::lengths::LogicalRect;
 use session::Session;
-use slint::{platform::WindowEvent, ComponentHandle, LogicalPosition, VecModel};
+use slint::{
+    platform::WindowEvent, ComponentHandle, LogicalPosition, Model, SharedString, VecModel,
+};
 use tokio::runtime::Builder;
+use validator::Validate;
 
 #[macro_use]
 extern crate log;
 
 slint::include_modules!();
 
+// How often the open sessions and window geometry are saved, in case smudgy doesn't exit cleanly
+const WINDOW_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 pub static TOKIO: std::sync::LazyLock<tokio::runtime::Runtime> =
     std::sync::LazyLock::new(|| Builder::new_multi_thread().enable_all().build().unwrap());
 
+mod dice;
 mod hotkey;
+mod macros;
 pub mod models;
+mod notification;
+mod registry;
 mod script_runtime;
 pub mod session;
+mod sound;
 mod trigger;
 mod ui;
 
@@ -74,6 +85,9 @@ fn main() {
     slint::platform::set_platform(platform).unwrap();
 
     let ui: MainWindow = MainWindow::new().unwrap();
+    let settings = Settings::load();
+    ui.set_ui_font_scale(settings.ui_font_scale);
+    ui.set_session_layout(settings.pane_layout.into());
 
     let sessions: Rc<RefCell<Vec<Arc<Mutex<Session>>>>> = Rc::new(RefCell::new(Vec::new()));
     let sessions_model = Rc::new(VecModel::default());
@@ -83,6 +97,25 @@ fn main() {
 
     ui.set_sessions(sessions_model.clone().into());
 
+    let column_stops = Rc::new(VecModel::<f32>::default());
+    let row_stops = Rc::new(VecModel::<f32>::default());
+    ui.set_column_stops(column_stops.clone().into());
+    ui.set_row_stops(row_stops.clone().into());
+
+    let ui_column_stops = Rc::clone(&column_stops);
+    let ui_row_stops = Rc::clone(&row_stops);
+    ui.on_pane_divider_moved(move |vertical, index, fraction| {
+        let model = if vertical {
+            &ui_column_stops
+        } else {
+            &ui_row_stops
+        };
+        let mut stops: Vec<f32> = model.iter().collect();
+        if let Some(stop) = pane_splits::move_stop(&mut stops, index as usize, fraction) {
+            model.set_row_data(index as usize, stop);
+        }
+    });
+
     let weak_window = ui.as_weak();
     ui.on_toolbar_fullscreen_clicked(move || {
         let ui = weak_window.upgrade().unwrap();
@@ -109,8 +142,9 @@ fn main() {
             });
     });
 
+    // Ending the event loop, rather than the process, lets the sessions close in an orderly way
     ui.on_toolbar_close_clicked(|| {
-        process::exit(0);
+        slint::quit_event_loop().ok();
     });
 
     let weak_window = ui.as_weak();
@@ -138,6 +172,58 @@ fn main() {
         connect.show().unwrap();
     });
 
+    let weak_window = ui.as_weak();
+    ui.on_cycle_session_layout(move || {
+        let window = weak_window.upgrade().unwrap();
+        let mut settings = Settings::load();
+        settings.pane_layout = settings.pane_layout.next();
+        window.set_session_layout(settings.pane_layout.into());
+        if let Err(e) = settings.save() {
+            error!("Failed to save the session layout: {e:#}");
+        }
+    });
+
+    ui.on_load_settings(|| SettingsData::from(&Settings::load()));
+    ui.on_default_settings(|| SettingsData::from(&Settings::default()));
+
+    let ui_sessions = Rc::clone(&sessions);
+    let weak_window = ui.as_weak();
+    ui.on_settings_edited(move |data| match edited_settings(&data) {
+        Ok(settings) => {
+            apply_settings(
+                &weak_window,
+                &ui_sessions,
+                &Settings::load(),
+                &settings,
+                false,
+            );
+            SharedString::new()
+        }
+        Err(e) => format!("{e:#}").into(),
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    let weak_window = ui.as_weak();
+    ui.on_settings_saved(move |data| {
+        let saved = Settings::load();
+        let result = edited_settings(&data).and_then(|settings| {
+            settings.save()?;
+            apply_settings(&weak_window, &ui_sessions, &saved, &settings, true);
+            Ok(())
+        });
+        match result {
+            Ok(()) => SharedString::new(),
+            Err(e) => format!("{e:#}").into(),
+        }
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    let weak_window = ui.as_weak();
+    ui.on_settings_cancelled(move || {
+        let saved = Settings::load();
+        apply_settings(&weak_window, &ui_sessions, &saved, &saved, false);
+    });
+
     let ui_sessions = sessions.clone();
     ui.on_session_accepted(move |session_index: i32, line| {
         let sessions = ui_sessions.borrow_mut();
@@ -167,6 +253,14 @@ fn main() {
         },
     );
 
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_input_edited(move |session_index, input_line, multi_line| -> i32 {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_input_edited(input_line.as_str(), multi_line)
+    });
+
     let ui_sessions = Rc::clone(&sessions);
     ui.on_session_scrollbar_value_changed(move |session_index, value| {
         let sessions = ui_sessions.borrow_mut();
@@ -175,8 +269,66 @@ fn main() {
         guard.view().set_scroll_position(value);
     });
 
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_selection_started(move |session_index, x, y| {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_selection_started(x, y);
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_selection_extended(move |session_index, x, y| {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_selection_extended(x, y);
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_selection_finished(move |session_index| {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_selection_finished();
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_find_edited(move |session_index, query| -> SharedString {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_find_edited(query.as_str()).into()
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_find_next(move |session_index, older| -> SharedString {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_find_next(older).into()
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_find_closed(move |session_index| {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let mut guard = to_invoke.lock().unwrap();
+        guard.on_find_closed();
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    ui.on_session_focus_changed(move |session_index, focused| {
+        let sessions = ui_sessions.borrow_mut();
+        let to_invoke = sessions[session_index as usize].clone();
+        let guard = to_invoke.lock().unwrap();
+        guard.on_focus_changed(focused);
+    });
+
     let ui_sessions = sessions.clone();
     let weak_window = ui.as_weak();
+    let render_column_stops = Rc::clone(&column_stops);
+    let render_row_stops = Rc::clone(&row_stops);
 
     ui.window()
         .set_rendering_notifier(move |state, _graphics_api| match state {
@@ -191,20 +343,65 @@ fn main() {
 
                 if !sessions.is_empty() {
                     let size_hints = window.invoke_get_physical_terminal_area_dimensions();
+                    let columns = window.get_layout_columns().max(1) as usize;
+                    let rows = window.get_layout_rows().max(1) as usize;
+                    // Dragged dividers stay where they are until the layout changes shape
+                    for (stops, count) in
+                        [(&render_column_stops, columns), (&render_row_stops, rows)]
+                    {
+                        let current: Vec<f32> = stops.iter().collect();
+                        if !pane_splits::fits(&current, count) {
+                            stops.set_vec(pane_splits::even_stops(count));
+                        }
+                    }
+                    let column_stops: Vec<f32> = render_column_stops.iter().collect();
+                    let row_stops: Vec<f32> = render_row_stops.iter().collect();
+                    let span = |stops: &[f32], i: usize| {
+                        stops
+                            .get(i + 1)
+                            .zip(stops.get(i))
+                            .map_or(0.0, |(end, start)| end - start)
+                    };
+
                     window.window().with_winit_window(|window| {
                         let window_size = window.inner_size();
-
-                        let terminal_height = std::cmp::max(0, window_size.height
-                            - (size_hints.terminal_padding * 3.5
-                                + size_hints.terminal_spacing
-                                + size_hints.editor_area_height)
-                                as u32);
-                        let terminal_width = (window_size.width
-                            - (size_hints.terminal_padding * 2.0) as u32) / sessions.len() as u32 - size_hints.terminal_spacing as u32 - size_hints.terminal_scrollbar_width as u32;
-
-                        for session in sessions.iter() {
-                            let session_guard = session.lock().unwrap();
-                            session_guard.prepare_render(terminal_width, terminal_height);
+                        let area_width = window_size
+                            .width
+                            .saturating_sub((size_hints.terminal_padding * 2.0) as u32)
+                            as f32;
+                        // Every row of panes but the last is followed by spacing
+                        let area_rows_height = window_size
+                            .height
+                            .saturating_sub((size_hints.terminal_padding * 2.0) as u32)
+                            as f32
+                            + size_hints.terminal_spacing;
+
+                        for (index, session) in sessions.iter().enumerate() {
+                            // Each pane holds a terminal and its editor
+                            let pane_width = span(&column_stops, index % columns) * area_width;
+                            let pane_height = span(&row_stops, index / columns) * area_rows_height
+                                - size_hints.terminal_spacing;
+                            let terminal_width = (pane_width as u32).saturating_sub(
+                                size_hints.terminal_spacing as u32
+                                    + size_hints.terminal_scrollbar_width as u32,
+                            );
+                            let terminal_height = (pane_height.max(0.0) as u32).saturating_sub(
+                                (size_hints.terminal_padding * 1.5
+                                    + size_hints.terminal_spacing
+                                    + size_hints.editor_area_height)
+                                    as u32,
+                            );
+
+                            let mut session_guard = session.lock().unwrap();
+                            // Built-in commands sent by triggers and scripts since the last frame
+                            session_guard.run_session_commands();
+                            // A multi-line input area takes its extra lines from the terminal
+                            let input_height = (session_guard.input_lines() - 1) as u32
+                                * size_hints.editor_line_height as u32;
+                            session_guard.prepare_render(
+                                terminal_width,
+                                terminal_height.saturating_sub(input_height),
+                            );
                         }
                     });
                 }
@@ -232,6 +429,24 @@ fn main() {
         ui_sessions_model.remove(session_index as usize);
     });
 
+    let ui_sessions = Rc::clone(&sessions);
+    let ui_sessions_model = Rc::clone(&sessions_model);
+    ui.on_session_move_requested(move |from: i32, to: i32| {
+        let mut sessions = RefCell::borrow_mut(&ui_sessions);
+        let count = sessions.len() as i32;
+        if from == to || !(0..count).contains(&from) || !(0..count).contains(&to) {
+            return;
+        }
+
+        let session = sessions.remove(from as usize);
+        sessions.insert(to as usize, session);
+        let state = ui_sessions_model.remove(from as usize);
+        ui_sessions_model.insert(to as usize, state);
+        for (id, session) in sessions.iter().enumerate() {
+            session.lock().unwrap().set_id(id as i32);
+        }
+    });
+
     let ui_sessions = Rc::clone(&sessions);
     ui.on_session_reconnect_clicked(move |session_index: i32| {
         let sessions = ui_sessions.borrow();
@@ -240,8 +455,194 @@ fn main() {
         guard.connect();
 });
     
+    let window_state = WindowState::load();
+    column_stops.set_vec(window_state.column_stops.clone());
+    row_stops.set_vec(window_state.row_stops.clone());
+    if let Some(geometry) = window_state.window {
+        ui.window().set_position(slint::PhysicalPosition::new(geometry.x, geometry.y));
+        ui.window().set_size(slint::PhysicalSize::new(geometry.width, geometry.height));
+    }
+
+    let ui_sessions = Rc::clone(&sessions);
+    let ui_sessions_model = Rc::clone(&sessions_model);
+    let weak_window = ui.as_weak();
+    let saved_sessions = window_state.sessions.clone();
+    ui.on_restore_sessions_confirmed(move || {
+        restore_sessions(&weak_window, &ui_sessions, &ui_sessions_model, &saved_sessions);
+    });
+
+    let ui_sessions = Rc::clone(&sessions);
+    let weak_window = ui.as_weak();
+    let save_timer = slint::Timer::default();
+    save_timer.start(slint::TimerMode::Repeated, WINDOW_STATE_SAVE_INTERVAL, move || {
+        if let Some(window) = weak_window.upgrade() {
+            save_window_state(&window, &ui_sessions.borrow());
+        }
+    });
+
     ui.show().unwrap();
+
+    // Sessions are opened once the window is shown, so their views pick up its scale factor
+    if !window_state.sessions.is_empty() {
+        match settings.restore_sessions {
+            RestoreSessions::Always => {
+                restore_sessions(&ui.as_weak(), &sessions, &sessions_model, &window_state.sessions);
+            }
+            RestoreSessions::Ask => ui.invoke_ask_restore_sessions(
+                format!(
+                    "Reopen the {} session(s) that were open when smudgy last exited?",
+                    window_state.sessions.len()
+                )
+                .into(),
+            ),
+            RestoreSessions::Never => {}
+        }
+    }
+
     trace!("Starting ui event loop...");
     slint::run_event_loop().unwrap();
+    save_timer.stop();
+    save_window_state(&ui, &sessions.borrow());
     ui.hide().unwrap();
+
+    // Each session gets to run its scripts' close callbacks and finish its log before the app exits
+    for session in sessions.borrow_mut().drain(..) {
+        session.lock().unwrap().close();
+    }
+}
+
+/// Remembers which sessions are open and where the window is, for the next launch
+fn save_window_state(window: &MainWindow, sessions: &[Arc<Mutex<Session>>]) {
+    let position = window.window().position();
+    let size = window.window().size();
+    let state = WindowState {
+        sessions: sessions
+            .iter()
+            .map(|session| session.lock().unwrap().saved_session())
+            .collect(),
+        window: Some(WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+        }),
+        column_stops: window.get_column_stops().iter().collect(),
+        row_stops: window.get_row_stops().iter().collect(),
+    };
+
+    if let Err(e) = state.save() {
+        error!("Failed to save the open sessions: {e:#}");
+    }
+}
+
+/// Reopens and connects the sessions from the last run, skipping any whose profile or character
+/// has since been deleted
+fn restore_sessions(
+    weak_window: &slint::Weak<MainWindow>,
+    sessions: &Rc<RefCell<Vec<Arc<Mutex<Session>>>>>,
+    sessions_model: &Rc<VecModel<SessionState>>,
+    saved_sessions: &[SavedSession],
+) {
+    let mut skipped = Vec::new();
+    for saved in saved_sessions {
+        if let Err(e) = open_session(weak_window, sessions, sessions_model, &saved.profile, &saved.character) {
+            warn!("Not reopening {} ({}): {e:#}", saved.character, saved.profile);
+            skipped.push(format!("{} ({})", saved.character, saved.profile));
+        }
+    }
+
+    let Some(window) = weak_window.upgrade() else {
+        return;
+    };
+    if !sessions.borrow().is_empty() {
+        window.invoke_set_toolbar_show(false);
+    }
+    if !skipped.is_empty() {
+        window.invoke_show_message(
+            format!(
+                "Couldn't reopen {}; the profile or character no longer exists.",
+                skipped.join(", ")
+            )
+            .into(),
+        );
+    }
+}
+
+/// The saved settings with the settings overlay's edits, if they're valid
+fn edited_settings(data: &SettingsData) -> anyhow::Result<Settings> {
+    let mut separator = data.command_separator.chars();
+    let (Some(command_separator), None) = (separator.next(), separator.next()) else {
+        anyhow::bail!("Command separator must be a single character");
+    };
+    let scrollback_lines = data
+        .scrollback_lines
+        .trim()
+        .replace(',', "")
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Scrollback must be a number of lines"))?;
+
+    let settings = Settings {
+        ui_font_scale: data.ui_font_scale,
+        terminal_font_size: data.terminal_font_size as f32,
+        scrollback_lines,
+        default_command_separator: command_separator,
+        show_timestamps: data.show_timestamps,
+        soft_wrap: data.soft_wrap,
+        multi_line_paste: match data.multi_line_paste.as_str() {
+            "send" => MultiLinePaste::Send,
+            "edit" => MultiLinePaste::Edit,
+            _ => MultiLinePaste::Ask,
+        },
+        ..Settings::load()
+    };
+    settings.validate()?;
+    Ok(settings)
+}
+
+/// Shows `settings` in the window and every open session, which `saved` were last shown in
+fn apply_settings(
+    weak_window: &slint::Weak<MainWindow>,
+    sessions: &Rc<RefCell<Vec<Arc<Mutex<Session>>>>>,
+    saved: &Settings,
+    settings: &Settings,
+    persist: bool,
+) {
+    if let Some(window) = weak_window.upgrade() {
+        window.set_ui_font_scale(settings.ui_font_scale);
+    }
+    for session in sessions.borrow().iter() {
+        session
+            .lock()
+            .unwrap()
+            .apply_settings(saved, settings, persist);
+    }
+}
+
+impl From<&Settings> for SettingsData {
+    fn from(value: &Settings) -> Self {
+        SettingsData {
+            ui_font_scale: value.ui_font_scale,
+            terminal_font_size: value.terminal_font_size.round() as i32,
+            scrollback_lines: value.scrollback_lines.to_string().into(),
+            command_separator: value.default_command_separator.to_string().into(),
+            show_timestamps: value.show_timestamps,
+            soft_wrap: value.soft_wrap,
+            multi_line_paste: match value.multi_line_paste {
+                MultiLinePaste::Send => "send",
+                MultiLinePaste::Edit => "edit",
+                MultiLinePaste::Ask => "ask",
+            }
+            .into(),
+        }
+    }
+}
+
+impl From<PaneLayout> for SessionLayout {
+    fn from(value: PaneLayout) -> Self {
+        match value {
+            PaneLayout::Columns => SessionLayout::Columns,
+            PaneLayout::Rows => SessionLayout::Rows,
+            PaneLayout::Grid => SessionLayout::Grid,
+        }
+    }
 }
@@ -4,9 +4,13 @@ use anyhow::Context;
 
 mod character;
 mod profile;
+mod settings;
+mod window_state;
 
 pub use character::Character;
 pub use profile::{Profile, ProfileData};
+pub use settings::{MultiLinePaste, PaneLayout, RestoreSessions, Settings};
+pub use window_state::{SavedSession, WindowGeometry, WindowState};
 use regex::Regex;
 use validator::ValidationError;
 
@@ -68,7 +68,7 @@ impl Character {
         self.subtext.as_str()
     }
 
-    fn dir(&self) -> PathBuf {
+    pub fn dir(&self) -> PathBuf {
         Character::dir_for(self.name(), self.profile.clone())
     }
 
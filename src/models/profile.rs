@@ -1,13 +1,15 @@
 use std::{
-    borrow::Cow, fs::{self, File}, io::{BufReader, ErrorKind}, path::{Path, PathBuf}, rc::Rc, sync::LazyLock
+    borrow::Cow, collections::BTreeMap, fs::{self, File}, io::{BufReader, ErrorKind}, path::{Path, PathBuf}, rc::Rc, sync::LazyLock
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use deno_core::serde::{Deserialize, Serialize};
+use regex::Regex;
 use slint::VecModel;
-use validator::{Validate, ValidationErrors};
+use validator::{Validate, ValidationError, ValidationErrors};
 
-use super::Character;
+use super::{Character, Settings};
+use crate::session::{AnsiPalette, LoginStep, StatusBinding};
 
 static PROFILES_HOME: LazyLock<PathBuf> = LazyLock::new(|| {
     let mut dir = super::SMUDGY_HOME.clone();
@@ -20,11 +22,126 @@ static PROFILES_HOME: LazyLock<PathBuf> = LazyLock::new(|| {
     dir
 });
 
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+pub const MIN_FONT_SIZE: f32 = 8.0;
+pub const MAX_FONT_SIZE: f32 = 48.0;
+
+/// Keeps a font size within a range the terminal view can sensibly render
+pub fn clamp_font_size(font_size: f32) -> f32 {
+    if font_size.is_finite() {
+        font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE)
+    } else {
+        DEFAULT_FONT_SIZE
+    }
+}
+
+fn default_font_size() -> f32 {
+    DEFAULT_FONT_SIZE
+}
+
+fn default_reconnect_base_delay_secs() -> u64 {
+    2
+}
+
+fn default_reconnect_max_attempts() -> u32 {
+    10
+}
+
+fn default_notifications_enabled() -> bool {
+    true
+}
+
+fn default_mxp_enabled() -> bool {
+    true
+}
+
+fn default_sounds_enabled() -> bool {
+    true
+}
+
+fn default_sound_volume() -> f32 {
+    1.0
+}
+
+fn default_command_burst() -> u32 {
+    5
+}
+
+fn default_command_separator() -> char {
+    crate::trigger::DEFAULT_COMMAND_SEPARATOR
+}
+
+pub(super) fn validate_command_separator(value: &char) -> Result<(), ValidationError> {
+    if value.is_alphanumeric() || value.is_whitespace() {
+        return Err(ValidationError::new("invalid_command_separator").with_message(Cow::Owned(
+            "Command separator must not be a letter, number, or whitespace character.".into(),
+        )));
+    }
+    Ok(())
+}
+
+fn validate_prompt_pattern(value: &str) -> Result<(), ValidationError> {
+    if let Err(e) = Regex::new(value) {
+        return Err(ValidationError::new("invalid_prompt_pattern")
+            .with_message(Cow::Owned(format!("Prompt pattern is not a valid regex: {e}"))));
+    }
+    Ok(())
+}
+
+fn validate_ansi_palette(value: &BTreeMap<String, String>) -> Result<(), ValidationError> {
+    if let Err(e) = AnsiPalette::with_overrides(value) {
+        return Err(ValidationError::new("invalid_ansi_palette")
+            .with_message(Cow::Owned(format!("ANSI palette is invalid: {e}"))));
+    }
+    Ok(())
+}
+
+fn validate_login_steps(value: &Vec<LoginStep>) -> Result<(), ValidationError> {
+    for step in value {
+        if let Err(e) = step.validate() {
+            return Err(ValidationError::new("invalid_login_steps").with_message(Cow::Owned(e.to_string())));
+        }
+    }
+    Ok(())
+}
+
+fn validate_status_bar(value: &Vec<StatusBinding>) -> Result<(), ValidationError> {
+    for binding in value {
+        if let Err(e) = binding.validate() {
+            return Err(ValidationError::new("invalid_status_bar").with_message(Cow::Owned(e.to_string())));
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct Profile {
     name: String,
     host: String,
     port: u16,
+    font_size: f32,
+    command_separator: char,
+    keepalive_interval_secs: u64,
+    keepalive_command: String,
+    auto_reconnect: bool,
+    reconnect_base_delay_secs: u64,
+    reconnect_max_attempts: u32,
+    on_reconnect: String,
+    send_on_close: String,
+    output_sink_path: String,
+    prompt_pattern: String,
+    idle_warning_secs: u64,
+    notifications_enabled: bool,
+    notify_from_hour: u8,
+    notify_until_hour: u8,
+    sounds_enabled: bool,
+    sound_volume: f32,
+    mxp_enabled: bool,
+    ansi_palette: BTreeMap<String, String>,
+    status_bar: Vec<StatusBinding>,
+    login_steps: Vec<LoginStep>,
+    max_commands_per_second: u32,
+    command_burst: u32,
 }
 
 #[derive(Serialize, Deserialize, Validate)]
@@ -38,6 +155,132 @@ pub struct ProfileData {
 
     #[validate(range(min = 1, max = 65535, message = "Port must be between 1 and 65535"))]
     pub port: u16,
+
+    #[validate(range(min = 8.0, max = 48.0, message = "Font size must be between 8 and 48"))]
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+
+    #[validate(custom(function = validate_command_separator))]
+    #[serde(default = "default_command_separator")]
+    pub command_separator: char,
+
+    /// Seconds of outgoing inactivity before a keepalive is sent; 0 disables keepalives
+    #[serde(default)]
+    pub keepalive_interval_secs: u64,
+
+    /// Sent as a keepalive instead of a telnet NOP when not empty
+    #[serde(default)]
+    pub keepalive_command: String,
+
+    #[serde(default)]
+    pub auto_reconnect: bool,
+
+    #[validate(range(min = 1, max = 300, message = "Reconnect delay must be between 1 and 300 seconds"))]
+    #[serde(default = "default_reconnect_base_delay_secs")]
+    pub reconnect_base_delay_secs: u64,
+
+    #[serde(default = "default_reconnect_max_attempts")]
+    pub reconnect_max_attempts: u32,
+
+    /// Commands sent after the character's send_on_connect when a dropped connection is re-established
+    #[serde(default)]
+    pub on_reconnect: String,
+
+    /// Commands sent, without being echoed, when the session is closed while still connected
+    #[serde(default)]
+    pub send_on_close: String,
+
+    /// File or named pipe that completed output lines are mirrored to as plain text; empty disables it
+    #[serde(default)]
+    pub output_sink_path: String,
+
+    /// Partial lines matching this regex are treated as prompts; empty disables prompt detection
+    #[validate(custom(function = validate_prompt_pattern))]
+    #[serde(default)]
+    pub prompt_pattern: String,
+
+    /// Seconds without any server output before a local warning is shown; 0 disables the warning
+    #[serde(default)]
+    pub idle_warning_secs: u64,
+
+    #[serde(default = "default_notifications_enabled")]
+    pub notifications_enabled: bool,
+
+    /// Notifications are only raised from this hour until notify_until_hour; equal hours allow them all day
+    #[validate(range(max = 23, message = "Hours must be between 0 and 23"))]
+    #[serde(default)]
+    pub notify_from_hour: u8,
+
+    #[validate(range(max = 23, message = "Hours must be between 0 and 23"))]
+    #[serde(default)]
+    pub notify_until_hour: u8,
+
+    /// Turning this off silences every sound, whether played by a script or a trigger
+    #[serde(default = "default_sounds_enabled")]
+    pub sounds_enabled: bool,
+
+    /// Master volume that every sound played by this profile's scripts is scaled by
+    #[validate(range(min = 0.0, max = 1.0, message = "Sound volume must be between 0 and 1"))]
+    #[serde(default = "default_sound_volume")]
+    pub sound_volume: f32,
+
+    /// Some servers send broken MXP markup; turning this off refuses MXP when it's offered
+    #[serde(default = "default_mxp_enabled")]
+    pub mxp_enabled: bool,
+
+    /// Replaces some of the 16 ANSI colors, e.g. `"bright_red": "#ff6060"`; the rest keep their defaults
+    #[validate(custom(function = validate_ansi_palette))]
+    #[serde(default)]
+    pub ansi_palette: BTreeMap<String, String>,
+
+    /// Fields shown under the output, bound to MSDP or GMCP variables like HEALTH or Char.Vitals.hp
+    #[validate(custom(function = validate_status_bar))]
+    #[serde(default)]
+    pub status_bar: Vec<StatusBinding>,
+
+    /// Prompts to wait for after connecting and what to send at each, e.g. a name then a password
+    #[validate(custom(function = validate_login_steps))]
+    #[serde(default)]
+    pub login_steps: Vec<LoginStep>,
+
+    /// Commands sent faster than this are held back and sent in order as it allows, for servers
+    /// that disconnect flooders; 0 sends everything straight away
+    #[serde(default)]
+    pub max_commands_per_second: u32,
+
+    /// How many commands can go out at once before max_commands_per_second holds any back
+    #[validate(range(min = 1, message = "Command burst must be at least 1"))]
+    #[serde(default = "default_command_burst")]
+    pub command_burst: u32,
+}
+
+impl ProfileData {
+    /// Puts back the default for each field that doesn't validate, and drops invalid status bar
+    /// fields and login steps, so a profile.json edited by hand still loads and saves. Returns
+    /// what was wrong, if anything. The name, host and port can't be repaired.
+    fn repair(&mut self) -> Option<ValidationErrors> {
+        let errors = self.validate().err()?;
+
+        for field in errors.field_errors().into_keys() {
+            match field {
+                "command_separator" => self.command_separator = default_command_separator(),
+                "reconnect_base_delay_secs" => {
+                    self.reconnect_base_delay_secs = default_reconnect_base_delay_secs()
+                }
+                "prompt_pattern" => self.prompt_pattern.clear(),
+                "notify_from_hour" => self.notify_from_hour = 0,
+                "notify_until_hour" => self.notify_until_hour = 0,
+                "sound_volume" => self.sound_volume = default_sound_volume(),
+                "ansi_palette" => self.ansi_palette.clear(),
+                "status_bar" => self.status_bar.retain(|binding| binding.validate().is_ok()),
+                "login_steps" => self.login_steps.retain(|step| step.validate().is_ok()),
+                "command_burst" => self.command_burst = default_command_burst(),
+                _ => {}
+            }
+        }
+
+        Some(errors)
+    }
 }
 
 const PROFILE_JSON_FILENAME: &str = "profile.json";
@@ -88,6 +331,129 @@ impl Profile {
         self.port = port;
     }
 
+    pub fn font_size(&self) -> f32 {
+        self.font_size
+    }
+
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.font_size = clamp_font_size(font_size);
+    }
+
+    pub fn command_separator(&self) -> char {
+        self.command_separator
+    }
+
+    pub fn set_command_separator(&mut self, command_separator: char) {
+        self.command_separator = command_separator;
+    }
+
+    pub fn keepalive_interval_secs(&self) -> u64 {
+        self.keepalive_interval_secs
+    }
+
+    pub fn set_keepalive_interval_secs(&mut self, keepalive_interval_secs: u64) {
+        self.keepalive_interval_secs = keepalive_interval_secs;
+    }
+
+    pub fn keepalive_command(&self) -> &str {
+        self.keepalive_command.as_str()
+    }
+
+    pub fn set_keepalive_command(&mut self, keepalive_command: &str) {
+        self.keepalive_command = keepalive_command.to_string();
+    }
+
+    pub fn auto_reconnect(&self) -> bool {
+        self.auto_reconnect
+    }
+
+    pub fn set_auto_reconnect(&mut self, auto_reconnect: bool) {
+        self.auto_reconnect = auto_reconnect;
+    }
+
+    pub fn reconnect_base_delay_secs(&self) -> u64 {
+        self.reconnect_base_delay_secs
+    }
+
+    pub fn reconnect_max_attempts(&self) -> u32 {
+        self.reconnect_max_attempts
+    }
+
+    pub fn on_reconnect(&self) -> &str {
+        self.on_reconnect.as_str()
+    }
+
+    pub fn send_on_close(&self) -> &str {
+        self.send_on_close.as_str()
+    }
+
+    pub fn output_sink_path(&self) -> &str {
+        self.output_sink_path.as_str()
+    }
+
+    pub fn idle_warning_secs(&self) -> u64 {
+        self.idle_warning_secs
+    }
+
+    pub fn notifications_enabled(&self) -> bool {
+        self.notifications_enabled
+    }
+
+    pub fn notify_from_hour(&self) -> u8 {
+        self.notify_from_hour
+    }
+
+    pub fn notify_until_hour(&self) -> u8 {
+        self.notify_until_hour
+    }
+
+    pub fn sounds_enabled(&self) -> bool {
+        self.sounds_enabled
+    }
+
+    pub fn sound_volume(&self) -> f32 {
+        self.sound_volume
+    }
+
+    pub fn mxp_enabled(&self) -> bool {
+        self.mxp_enabled
+    }
+
+    /// The colors ANSI color codes are drawn in, with this profile's overrides applied
+    pub fn ansi_palette(&self) -> AnsiPalette {
+        // Invalid overrides are dropped when the profile is loaded, and refused when it's created
+        AnsiPalette::with_overrides(&self.ansi_palette).unwrap_or_default()
+    }
+
+    pub fn status_bar(&self) -> &[StatusBinding] {
+        &self.status_bar
+    }
+
+    pub fn login_steps(&self) -> &[LoginStep] {
+        &self.login_steps
+    }
+
+    pub fn max_commands_per_second(&self) -> u32 {
+        self.max_commands_per_second
+    }
+
+    pub fn command_burst(&self) -> u32 {
+        self.command_burst
+    }
+
+    pub fn prompt_pattern(&self) -> &str {
+        self.prompt_pattern.as_str()
+    }
+
+    /// The compiled prompt pattern, or None when prompt detection is disabled
+    pub fn prompt_regex(&self) -> Option<Regex> {
+        if self.prompt_pattern.is_empty() {
+            None
+        } else {
+            Regex::new(&self.prompt_pattern).ok()
+        }
+    }
+
     pub fn dir(&self) -> PathBuf {
         Profile::dir_for(self.name())
     }
@@ -129,14 +495,16 @@ impl Profile {
         let reader = BufReader::new(file);
 
         // Read the JSON contents of the file as an instance of `User`.
-        let data: ProfileData =
+        let mut data: ProfileData =
             serde_json::from_reader(reader).context("Could not parse profile.json")?;
+        data.name = name.to_string();
+        data.font_size = clamp_font_size(data.font_size);
 
-        Ok(Profile {
-            name: name.to_string(),
-            host: data.host,
-            port: data.port,
-        })
+        if let Some(errors) = data.repair() {
+            log::warn!("Reset invalid fields in profile {name}:\n{errors}");
+        }
+
+        Profile::try_from(data).with_context(|| format!("Profile {name} is invalid"))
     }
 
     pub fn delete(profile: Profile) -> Result<()> {
@@ -159,7 +527,13 @@ impl Profile {
             })
             .map(|dir| dir.unwrap().file_name().to_str().unwrap().to_string())
             .filter(|name| Profile::exists(name))
-            .map(|name| Profile::load(&name).unwrap())
+            .filter_map(|name| match Profile::load(&name) {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    log::warn!("Skipping profile {name}: {e:#}");
+                    None
+                }
+            })
     }
 }
 
@@ -183,10 +557,35 @@ impl From<Profile> for smudgy_connect_window::Profile {
 
 impl From<smudgy_connect_window::Profile> for ProfileData {
     fn from(value: smudgy_connect_window::Profile) -> Self {
+        let settings = Settings::load();
+
         ProfileData {
             name: value.name.to_string(),
             host: value.host.to_string(),
             port: value.port as u16,
+            font_size: settings.terminal_font_size,
+            command_separator: settings.default_command_separator,
+            keepalive_interval_secs: 0,
+            keepalive_command: String::default(),
+            auto_reconnect: false,
+            reconnect_base_delay_secs: default_reconnect_base_delay_secs(),
+            reconnect_max_attempts: default_reconnect_max_attempts(),
+            on_reconnect: String::default(),
+            send_on_close: String::default(),
+            output_sink_path: String::default(),
+            prompt_pattern: String::default(),
+            idle_warning_secs: 0,
+            notifications_enabled: default_notifications_enabled(),
+            notify_from_hour: 0,
+            notify_until_hour: 0,
+            sounds_enabled: default_sounds_enabled(),
+            sound_volume: default_sound_volume(),
+            mxp_enabled: default_mxp_enabled(),
+            ansi_palette: BTreeMap::new(),
+            status_bar: Vec::new(),
+            login_steps: Vec::new(),
+            max_commands_per_second: 0,
+            command_burst: default_command_burst(),
         }
     }
 }
@@ -200,6 +599,29 @@ impl TryFrom<ProfileData> for Profile {
             name: value.name,
             host: value.host,
             port: value.port,
+            font_size: value.font_size,
+            command_separator: value.command_separator,
+            keepalive_interval_secs: value.keepalive_interval_secs,
+            keepalive_command: value.keepalive_command,
+            auto_reconnect: value.auto_reconnect,
+            reconnect_base_delay_secs: value.reconnect_base_delay_secs,
+            reconnect_max_attempts: value.reconnect_max_attempts,
+            on_reconnect: value.on_reconnect,
+            send_on_close: value.send_on_close,
+            output_sink_path: value.output_sink_path,
+            prompt_pattern: value.prompt_pattern,
+            idle_warning_secs: value.idle_warning_secs,
+            notifications_enabled: value.notifications_enabled,
+            notify_from_hour: value.notify_from_hour,
+            notify_until_hour: value.notify_until_hour,
+            sounds_enabled: value.sounds_enabled,
+            sound_volume: value.sound_volume,
+            mxp_enabled: value.mxp_enabled,
+            ansi_palette: value.ansi_palette,
+            status_bar: value.status_bar,
+            login_steps: value.login_steps,
+            max_commands_per_second: value.max_commands_per_second,
+            command_burst: value.command_burst,
         })
     }
 }
@@ -211,8 +633,246 @@ impl TryFrom<Profile> for ProfileData {
             name: value.name,
             host: value.host,
             port: value.port,
+            font_size: value.font_size,
+            command_separator: value.command_separator,
+            keepalive_interval_secs: value.keepalive_interval_secs,
+            keepalive_command: value.keepalive_command,
+            auto_reconnect: value.auto_reconnect,
+            reconnect_base_delay_secs: value.reconnect_base_delay_secs,
+            reconnect_max_attempts: value.reconnect_max_attempts,
+            on_reconnect: value.on_reconnect,
+            send_on_close: value.send_on_close,
+            output_sink_path: value.output_sink_path,
+            prompt_pattern: value.prompt_pattern,
+            idle_warning_secs: value.idle_warning_secs,
+            notifications_enabled: value.notifications_enabled,
+            notify_from_hour: value.notify_from_hour,
+            notify_until_hour: value.notify_until_hour,
+            sounds_enabled: value.sounds_enabled,
+            sound_volume: value.sound_volume,
+            mxp_enabled: value.mxp_enabled,
+            ansi_palette: value.ansi_palette,
+            status_bar: value.status_bar,
+            login_steps: value.login_steps,
+            max_commands_per_second: value.max_commands_per_second,
+            command_burst: value.command_burst,
         };
         ProfileData::validate(&profile_data)?;
         Ok(profile_data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A profile as it's read from a profile.json holding `fields` besides the host and port
+    fn parse(fields: serde_json::Value) -> ProfileData {
+        let mut json = json!({ "host": "localhost", "port": 4000 });
+        json.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+
+        let mut data: ProfileData = serde_json::from_value(json).unwrap();
+        data.name = "test".into();
+        data
+    }
+
+    /// Whether a profile.json with `field` set to `value` fails to validate because of it
+    fn rejects(field: &str, value: serde_json::Value) -> bool {
+        let mut fields = serde_json::Map::new();
+        fields.insert(field.into(), value);
+
+        parse(fields.into())
+            .validate()
+            .is_err_and(|errors| errors.field_errors().contains_key(field))
+    }
+
+    #[test]
+    fn test_font_size_round_trip() {
+        let data = parse(json!({ "font_size": 20.0 }));
+
+        let json = serde_json::to_string(&data).unwrap();
+        let parsed: ProfileData = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parsed.font_size, 20.0);
+        assert_eq!(parsed.host, "localhost");
+        assert_eq!(parsed.port, 4000);
+    }
+
+    #[test]
+    fn test_font_size_defaults_when_missing() {
+        let parsed = parse(json!({}));
+
+        assert_eq!(parsed.font_size, DEFAULT_FONT_SIZE);
+        assert!(!rejects("font_size", json!(20.0)));
+        assert!(rejects("font_size", json!(60.0)));
+    }
+
+    #[test]
+    fn test_font_size_clamping() {
+        assert_eq!(clamp_font_size(16.0), 16.0);
+        assert_eq!(clamp_font_size(1.0), MIN_FONT_SIZE);
+        assert_eq!(clamp_font_size(1000.0), MAX_FONT_SIZE);
+        assert_eq!(clamp_font_size(f32::NAN), DEFAULT_FONT_SIZE);
+        assert_eq!(clamp_font_size(f32::INFINITY), DEFAULT_FONT_SIZE);
+    }
+
+    #[test]
+    fn test_prompt_pattern() {
+        let data = parse(json!({ "prompt_pattern": r"^HP:\d+ MP:\d+>" }));
+
+        let profile = Profile::try_from(data).unwrap();
+        let prompt_regex = profile.prompt_regex().unwrap();
+        assert!(prompt_regex.is_match("HP:100 MP:50>"));
+        assert!(!prompt_regex.is_match("You are hungry."));
+
+        assert!(rejects("prompt_pattern", json!(r"HP:(\d+")));
+    }
+
+    #[test]
+    fn test_invalid_fields_are_reset() {
+        let mut data = parse(json!({
+            "ansi_palette": { "red": "#ff6060", "purple": "#ff00ff" },
+            "notify_from_hour": 30,
+            "command_burst": 0,
+        }));
+
+        assert!(data.repair().is_some());
+        assert!(data.ansi_palette.is_empty());
+        assert_eq!(data.notify_from_hour, 0);
+        assert_eq!(data.command_burst, default_command_burst());
+
+        assert!(data.repair().is_none());
+        assert!(Profile::try_from(data).is_ok());
+
+        // There's nothing to fall back on for where to connect
+        let mut data = parse(json!({ "host": "" }));
+        assert!(data.repair().is_some());
+        assert!(Profile::try_from(data).is_err());
+    }
+
+    #[test]
+    fn test_command_separator() {
+        assert_eq!(parse(json!({})).command_separator, ';');
+        assert!(!rejects("command_separator", json!("|")));
+        assert!(rejects("command_separator", json!("a")));
+        assert!(rejects("command_separator", json!("7")));
+        assert!(rejects("command_separator", json!(" ")));
+    }
+
+    #[test]
+    fn test_keepalive_is_off_by_default() {
+        let parsed = parse(json!({}));
+
+        assert_eq!(parsed.keepalive_interval_secs, 0);
+        assert_eq!(parsed.keepalive_command, "");
+    }
+
+    #[test]
+    fn test_reconnect_defaults() {
+        let parsed = parse(json!({}));
+
+        assert!(!parsed.auto_reconnect);
+        assert_eq!(parsed.reconnect_base_delay_secs, 2);
+        assert_eq!(parsed.reconnect_max_attempts, 10);
+        assert_eq!(parsed.on_reconnect, "");
+    }
+
+    #[test]
+    fn test_reconnect_delay_range() {
+        assert!(!rejects("reconnect_base_delay_secs", json!(300)));
+        assert!(rejects("reconnect_base_delay_secs", json!(0)));
+        assert!(rejects("reconnect_base_delay_secs", json!(301)));
+    }
+
+    #[test]
+    fn test_output_sink_is_off_by_default() {
+        assert_eq!(parse(json!({})).output_sink_path, "");
+    }
+
+    #[test]
+    fn test_idle_warning_is_off_by_default() {
+        assert_eq!(parse(json!({})).idle_warning_secs, 0);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let parsed = parse(json!({}));
+        assert!(parsed.notifications_enabled);
+        assert_eq!(parsed.notify_from_hour, parsed.notify_until_hour);
+
+        for field in ["notify_from_hour", "notify_until_hour"] {
+            assert!(!rejects(field, json!(23)), "{field}");
+            assert!(rejects(field, json!(24)), "{field}");
+        }
+    }
+
+    #[test]
+    fn test_sound_volume() {
+        assert_eq!(parse(json!({})).sound_volume, 1.0);
+        assert!(!rejects("sound_volume", json!(0.0)));
+        assert!(rejects("sound_volume", json!(1.5)));
+        assert!(rejects("sound_volume", json!(-0.1)));
+    }
+
+    #[test]
+    fn test_mxp_is_on_by_default() {
+        assert!(parse(json!({})).mxp_enabled);
+        assert!(!parse(json!({ "mxp_enabled": false })).mxp_enabled);
+    }
+
+    #[test]
+    fn test_sounds_are_on_by_default() {
+        assert!(parse(json!({})).sounds_enabled);
+        assert!(!parse(json!({ "sounds_enabled": false })).sounds_enabled);
+    }
+
+    #[test]
+    fn test_ansi_palette() {
+        assert!(parse(json!({})).ansi_palette.is_empty());
+        assert!(!rejects("ansi_palette", json!({ "bright_red": "#ff6060" })));
+        assert!(rejects("ansi_palette", json!({ "purple": "#ff00ff" })));
+        assert!(rejects("ansi_palette", json!({ "red": "crimson" })));
+    }
+
+    #[test]
+    fn test_nothing_is_sent_on_close_by_default() {
+        assert_eq!(parse(json!({})).send_on_close, "");
+    }
+
+    #[test]
+    fn test_status_bar() {
+        assert!(parse(json!({})).status_bar.is_empty());
+
+        let hp = json!({ "label": "HP", "variable": "Char.Vitals.hp", "color": "#e06c75" });
+        let unbound = json!({ "label": "HP", "variable": "" });
+        let bad_color = json!({ "label": "HP", "variable": "HEALTH", "color": "red" });
+        assert!(!rejects("status_bar", json!([hp])));
+        assert!(rejects("status_bar", json!([unbound])));
+        assert!(rejects("status_bar", json!([bad_color])));
+    }
+
+    #[test]
+    fn test_login_steps() {
+        assert!(parse(json!({})).login_steps.is_empty());
+
+        let name = json!({ "expect": "^Name:", "send": "bob" });
+        let unclosed = json!({ "expect": "Name:(", "send": "bob" });
+        assert!(!parse(json!({ "login_steps": [name] })).login_steps[0].hidden);
+        assert!(!rejects("login_steps", json!([name])));
+        assert!(rejects("login_steps", json!([unclosed])));
+    }
+
+    #[test]
+    fn test_command_rate_limit() {
+        let parsed = parse(json!({}));
+        assert_eq!(parsed.max_commands_per_second, 0);
+        assert_eq!(parsed.command_burst, 5);
+
+        assert!(!rejects("command_burst", json!(1)));
+        assert!(rejects("command_burst", json!(0)));
+    }
+}
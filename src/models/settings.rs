@@ -0,0 +1,231 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result};
+use deno_core::serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::profile::{validate_command_separator, DEFAULT_FONT_SIZE};
+
+const SETTINGS_JSON_FILENAME: &str = "settings.json";
+
+pub const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+pub const MIN_SCROLLBACK_LINES: usize = 1_000;
+pub const MAX_SCROLLBACK_LINES: usize = 1_000_000;
+
+fn default_ui_font_scale() -> f32 {
+    1.0
+}
+
+fn default_terminal_font_size() -> f32 {
+    DEFAULT_FONT_SIZE
+}
+
+fn default_scrollback_lines() -> usize {
+    DEFAULT_SCROLLBACK_LINES
+}
+
+fn default_command_separator() -> char {
+    crate::trigger::DEFAULT_COMMAND_SEPARATOR
+}
+
+/// How the main window arranges session panes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaneLayout {
+    #[default]
+    Columns,
+    Rows,
+    Grid,
+}
+
+impl PaneLayout {
+    /// The layout after this one, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            PaneLayout::Columns => PaneLayout::Rows,
+            PaneLayout::Rows => PaneLayout::Grid,
+            PaneLayout::Grid => PaneLayout::Columns,
+        }
+    }
+}
+
+/// Whether the sessions that were open when smudgy last exited are reopened on launch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreSessions {
+    Always,
+    #[default]
+    Ask,
+    Never,
+}
+
+/// What pasting text with more than one line into the input does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiLinePaste {
+    /// Sends every line as a command straight away
+    Send,
+    /// Opens the text in the multi-line editor to be sent with Enter
+    Edit,
+    #[default]
+    Ask,
+}
+
+/// Application-wide settings. Profiles carry their own font size and command separator, which
+/// override the defaults here; new profiles start out with these.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct Settings {
+    #[validate(range(min = 0.5, max = 3.0, message = "UI font scale must be between 0.5 and 3"))]
+    #[serde(default = "default_ui_font_scale")]
+    pub ui_font_scale: f32,
+
+    #[validate(range(min = 8.0, max = 48.0, message = "Font size must be between 8 and 48"))]
+    #[serde(default = "default_terminal_font_size")]
+    pub terminal_font_size: f32,
+
+    #[validate(range(
+        min = 1000,
+        max = 1000000,
+        message = "Scrollback must be between 1,000 and 1,000,000 lines"
+    ))]
+    #[serde(default = "default_scrollback_lines")]
+    pub scrollback_lines: usize,
+
+    #[validate(custom(function = validate_command_separator))]
+    #[serde(default = "default_command_separator")]
+    pub default_command_separator: char,
+
+    #[serde(default)]
+    pub pane_layout: PaneLayout,
+
+    /// Whether new sessions show when each line arrived; `#timestamps on|off` changes it per session
+    #[serde(default)]
+    pub show_timestamps: bool,
+
+    /// Whether new sessions wrap long lines by column, between words; `#wrap on|off` changes it
+    /// per session
+    #[serde(default)]
+    pub soft_wrap: bool,
+
+    #[serde(default)]
+    pub restore_sessions: RestoreSessions,
+
+    #[serde(default)]
+    pub multi_line_paste: MultiLinePaste,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ui_font_scale: default_ui_font_scale(),
+            terminal_font_size: default_terminal_font_size(),
+            scrollback_lines: default_scrollback_lines(),
+            default_command_separator: default_command_separator(),
+            pane_layout: PaneLayout::default(),
+            show_timestamps: false,
+            soft_wrap: false,
+            restore_sessions: RestoreSessions::default(),
+            multi_line_paste: MultiLinePaste::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> PathBuf {
+        let mut filename = super::SMUDGY_HOME.clone();
+        filename.push(SETTINGS_JSON_FILENAME);
+        filename
+    }
+
+    /// Loads the saved settings, falling back to the defaults when there are none or they're invalid
+    pub fn load() -> Self {
+        match Settings::try_load() {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::warn!("Using default settings: {e:#}");
+                Settings::default()
+            }
+        }
+    }
+
+    fn try_load() -> Result<Self> {
+        let json = match fs::read_to_string(Settings::path()) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Settings::default()),
+            response => response.context("Could not read settings.json")?,
+        };
+
+        let settings: Settings =
+            serde_json::from_str(&json).context("Could not parse settings.json")?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        self.validate()?;
+
+        let json =
+            serde_json::to_string_pretty(self).context("Could not generate settings json")?;
+        fs::write(Settings::path(), json).context("Could not save settings")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_when_missing() {
+        let parsed: Settings = serde_json::from_str("{}").unwrap();
+
+        assert_eq!(parsed.scrollback_lines, DEFAULT_SCROLLBACK_LINES);
+        assert_eq!(parsed.terminal_font_size, DEFAULT_FONT_SIZE);
+        assert_eq!(parsed.default_command_separator, ';');
+        assert_eq!(parsed.pane_layout, PaneLayout::Columns);
+        assert!(!parsed.show_timestamps);
+        assert!(!parsed.soft_wrap);
+        assert_eq!(parsed.restore_sessions, RestoreSessions::Ask);
+        assert_eq!(parsed.multi_line_paste, MultiLinePaste::Ask);
+        assert!(parsed.validate().is_ok());
+    }
+
+    #[test]
+    fn test_scrollback_validation() {
+        let mut settings = Settings::default();
+
+        settings.scrollback_lines = MIN_SCROLLBACK_LINES;
+        assert!(settings.validate().is_ok());
+        settings.scrollback_lines = MAX_SCROLLBACK_LINES;
+        assert!(settings.validate().is_ok());
+
+        settings.scrollback_lines = MIN_SCROLLBACK_LINES - 1;
+        assert!(settings.validate().is_err());
+        settings.scrollback_lines = MAX_SCROLLBACK_LINES + 1;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_command_separator_validation() {
+        let settings = Settings {
+            default_command_separator: 'a',
+            ..Settings::default()
+        };
+
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_pane_layout_round_trip() {
+        let settings = Settings {
+            pane_layout: PaneLayout::Grid,
+            ..Settings::default()
+        };
+
+        let json = serde_json::to_string(&settings).unwrap();
+        assert!(json.contains(r#""pane_layout":"grid""#));
+
+        let parsed: Settings = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.pane_layout, PaneLayout::Grid);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result};
+use deno_core::serde::{Deserialize, Serialize};
+
+const WINDOW_STATE_JSON_FILENAME: &str = "session_state.json";
+
+/// A session that was open when smudgy last exited
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub profile: String,
+    pub character: String,
+}
+
+/// Where the main window was, in physical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What was open when smudgy last exited, so it can be reopened on the next launch. The pane
+/// layout isn't kept here, since it's saved to the settings whenever it changes, but where its
+/// dividers were dragged to is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    #[serde(default)]
+    pub sessions: Vec<SavedSession>,
+    #[serde(default)]
+    pub window: Option<WindowGeometry>,
+    /// Where each column of panes starts, as a fraction of the terminal area's width, then 1.0
+    #[serde(default)]
+    pub column_stops: Vec<f32>,
+    /// Where each row of panes starts, as a fraction of the terminal area's height, then 1.0
+    #[serde(default)]
+    pub row_stops: Vec<f32>,
+}
+
+impl WindowState {
+    fn path() -> PathBuf {
+        let mut filename = super::SMUDGY_HOME.clone();
+        filename.push(WINDOW_STATE_JSON_FILENAME);
+        filename
+    }
+
+    /// Loads the saved state, falling back to an empty one when there is none or it's unreadable
+    pub fn load() -> Self {
+        match WindowState::try_load() {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("Not restoring the previous sessions: {e:#}");
+                WindowState::default()
+            }
+        }
+    }
+
+    fn try_load() -> Result<Self> {
+        let json = match fs::read_to_string(WindowState::path()) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(WindowState::default()),
+            response => response.context("Could not read session_state.json")?,
+        };
+
+        serde_json::from_str(&json).context("Could not parse session_state.json")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(self).context("Could not generate session state json")?;
+        fs::write(WindowState::path(), json).context("Could not save session state")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let state = WindowState {
+            sessions: vec![SavedSession {
+                profile: "Aardwolf".into(),
+                character: "Walt".into(),
+            }],
+            window: Some(WindowGeometry {
+                x: -20,
+                y: 40,
+                width: 1280,
+                height: 800,
+            }),
+            column_stops: vec![0.0, 0.6, 1.0],
+            row_stops: vec![0.0, 1.0],
+        };
+
+        let json = serde_json::to_string(&state).unwrap();
+        let parsed: WindowState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, state);
+    }
+
+    #[test]
+    fn test_defaults_when_missing() {
+        let parsed: WindowState = serde_json::from_str("{}").unwrap();
+        assert!(parsed.sessions.is_empty());
+        assert_eq!(parsed.window, None);
+        assert!(parsed.column_stops.is_empty());
+    }
+}
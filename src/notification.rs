@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Timelike;
+use i_slint_backend_winit::{winit::window::UserAttentionType, WinitWindowAccessor};
+use slint::ComponentHandle;
+
+use crate::{models::Profile, MainWindow};
+
+/// When a profile allows notifications to be raised
+#[derive(Debug, Clone, Copy)]
+pub struct NotificationPolicy {
+    pub enabled: bool,
+    /// Notifications are allowed from this hour (0-23, local time)...
+    pub from_hour: u8,
+    /// ...up to, but not including, this one. When both are equal they're allowed all day.
+    pub until_hour: u8,
+}
+
+impl Default for NotificationPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            from_hour: 0,
+            until_hour: 0,
+        }
+    }
+}
+
+impl From<&Profile> for NotificationPolicy {
+    fn from(profile: &Profile) -> Self {
+        Self {
+            enabled: profile.notifications_enabled(),
+            from_hour: profile.notify_from_hour(),
+            until_hour: profile.notify_until_hour(),
+        }
+    }
+}
+
+impl NotificationPolicy {
+    pub fn allows(&self, hour: u32) -> bool {
+        let (from, until) = (u32::from(self.from_hour), u32::from(self.until_hour));
+
+        if !self.enabled {
+            false
+        } else if from == until {
+            true
+        } else if from < until {
+            (from..until).contains(&hour)
+        } else {
+            // The allowed hours wrap around midnight
+            hour >= from || hour < until
+        }
+    }
+}
+
+/// Most notifications a session may raise in `RATE_LIMIT_WINDOW`; any more are dropped
+const RATE_LIMIT_BURST: usize = 3;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Keeps a busy trigger from flooding the desktop with notifications
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: usize,
+    window: Duration,
+    recent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(burst: usize, window: Duration) -> Self {
+        Self {
+            burst,
+            window,
+            recent: VecDeque::with_capacity(burst),
+        }
+    }
+
+    /// Whether a notification may be raised at `now`, counting it if so
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .recent
+            .front()
+            .is_some_and(|sent| now.saturating_duration_since(*sent) >= self.window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.len() >= self.burst {
+            return false;
+        }
+
+        self.recent.push_back(now);
+        true
+    }
+}
+
+/// Gets the user's attention when the main window isn't focused
+#[derive(Clone)]
+pub struct Notifier {
+    weak_window: slint::Weak<MainWindow>,
+    policy: NotificationPolicy,
+    /// Used when a notification is raised without a title of its own
+    default_title: String,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+}
+
+impl Notifier {
+    pub fn new(
+        weak_window: slint::Weak<MainWindow>,
+        policy: NotificationPolicy,
+        default_title: String,
+    ) -> Self {
+        Self {
+            weak_window,
+            policy,
+            default_title,
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::new(
+                RATE_LIMIT_BURST,
+                RATE_LIMIT_WINDOW,
+            ))),
+        }
+    }
+
+    /// Flashes the taskbar entry and raises an OS notification, unless the window has focus, the
+    /// profile doesn't allow notifications right now, or too many were raised recently
+    pub fn notify(&self, title: String, body: String) {
+        if !self.policy.allows(chrono::Local::now().hour()) {
+            return;
+        }
+
+        let title = if title.is_empty() {
+            self.default_title.clone()
+        } else {
+            title
+        };
+        let rate_limiter = self.rate_limiter.clone();
+
+        self.weak_window
+            .upgrade_in_event_loop(move |window| {
+                window.window().with_winit_window(|winit_window| {
+                    if winit_window.has_focus() {
+                        return;
+                    }
+
+                    // Only notifications that are shown count towards the limit
+                    if !rate_limiter.lock().unwrap().allow(Instant::now()) {
+                        debug!("Dropping notification \"{title}\": too many raised recently");
+                        return;
+                    }
+
+                    winit_window.request_user_attention(Some(UserAttentionType::Informational));
+                    show_desktop_notification(&title, &body);
+                });
+            })
+            .ok();
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos", windows))]
+fn show_desktop_notification(title: &str, body: &str) {
+    if let Err(e) = notify_rust::Notification::new()
+        .appname("smudgy")
+        .summary(title)
+        .body(body)
+        .show()
+    {
+        warn!("Failed to show notification: {e}");
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "macos", windows)))]
+fn show_desktop_notification(_title: &str, _body: &str) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| warn!("Desktop notifications aren't supported on this platform"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(from_hour: u8, until_hour: u8) -> NotificationPolicy {
+        NotificationPolicy {
+            enabled: true,
+            from_hour,
+            until_hour,
+        }
+    }
+
+    #[test]
+    fn test_all_day() {
+        assert!((0..24).all(|hour| policy(0, 0).allows(hour)));
+        assert!((0..24).all(|hour| policy(9, 9).allows(hour)));
+    }
+
+    #[test]
+    fn test_daytime_hours() {
+        let policy = policy(9, 17);
+
+        assert!(!policy.allows(8));
+        assert!(policy.allows(9));
+        assert!(policy.allows(16));
+        assert!(!policy.allows(17));
+    }
+
+    #[test]
+    fn test_hours_wrapping_midnight() {
+        let policy = policy(22, 6);
+
+        assert!(policy.allows(23));
+        assert!(policy.allows(0));
+        assert!(policy.allows(5));
+        assert!(!policy.allows(6));
+        assert!(!policy.allows(12));
+        assert!(policy.allows(22));
+    }
+
+    #[test]
+    fn test_rate_limit_suppresses_bursts() {
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(limiter.allow(start));
+        assert!(limiter.allow(start + Duration::from_secs(1)));
+        assert!(limiter.allow(start + Duration::from_secs(2)));
+        assert!(!limiter.allow(start + Duration::from_secs(3)));
+        assert!(!limiter.allow(start + Duration::from_secs(9)));
+
+        // The first one has aged out of the window
+        assert!(limiter.allow(start + Duration::from_secs(10)));
+        assert!(!limiter.allow(start + Duration::from_millis(10_500)));
+        assert!(limiter.allow(start + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let policy = NotificationPolicy {
+            enabled: false,
+            ..NotificationPolicy::default()
+        };
+
+        assert!(!policy.allows(12));
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{script_runtime::RuntimeAction, trigger::split_commands};
+
+struct RegisteredSession {
+    profile: String,
+    command_separator: char,
+    tx: UnboundedSender<RuntimeAction>,
+}
+
+/// Every open session's runtime, so a command can be sent to all of them at once
+static SESSIONS: LazyLock<Mutex<BTreeMap<u64, RegisteredSession>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Keeps a session's runtime in the registry until it's dropped
+#[derive(Debug)]
+pub struct Registration(u64);
+
+impl Registration {
+    #[cfg(test)]
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        SESSIONS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Adds a session's runtime to the registry, under the name of the profile it's connected with
+pub fn register(
+    profile: &str,
+    command_separator: char,
+    tx: UnboundedSender<RuntimeAction>,
+) -> Registration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SESSIONS.lock().unwrap().insert(
+        id,
+        RegisteredSession {
+            profile: profile.to_string(),
+            command_separator,
+            tx,
+        },
+    );
+    Registration(id)
+}
+
+/// The registry ids of every open session, in the order they were opened
+pub fn get_all_session_ids() -> Vec<u64> {
+    SESSIONS.lock().unwrap().keys().copied().collect()
+}
+
+/// Queues `line` to be sent by every open session, or only those connected with `profile`,
+/// returning how many it was queued for. Each session splits the line on its own command
+/// separator, but there's no alias processing, since another session's aliases may need its
+/// runtime, which could be the one broadcasting.
+pub fn broadcast(line: &str, profile: Option<&str>) -> usize {
+    SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|session| profile.map_or(true, |profile| session.profile == profile))
+        .filter(|session| {
+            split_commands(line, session.command_separator)
+                .into_iter()
+                .all(|command| {
+                    session
+                        .tx
+                        .send(RuntimeAction::SendRaw(Arc::new(command)))
+                        .is_ok()
+                })
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(rx: &mut tokio::sync::mpsc::UnboundedReceiver<RuntimeAction>) -> Vec<String> {
+        let mut sent = Vec::new();
+        while let Ok(action) = rx.try_recv() {
+            if let RuntimeAction::SendRaw(line) = action {
+                sent.push(line.to_string());
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_broadcast_reaches_every_session() {
+        let (first_tx, mut first_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (second_tx, mut second_rx) = tokio::sync::mpsc::unbounded_channel();
+        let first = register("registry-test-a", ';', first_tx);
+        let second = register("registry-test-b", '|', second_tx);
+
+        let ids = get_all_session_ids();
+        assert!(ids.contains(&first.id()) && ids.contains(&second.id()));
+
+        broadcast("save", None);
+        broadcast("quit", Some("registry-test-b"));
+        assert_eq!(sent(&mut first_rx), vec!["save"]);
+        assert_eq!(sent(&mut second_rx), vec!["save", "quit"]);
+
+        // Each session splits the line on its own separator
+        broadcast("wake;stand|rest", None);
+        assert_eq!(sent(&mut first_rx), vec!["wake", "stand|rest"]);
+        assert_eq!(sent(&mut second_rx), vec!["wake;stand", "rest"]);
+
+        // Closed sessions drop out of the registry
+        let second_id = second.id();
+        drop(second);
+        assert!(!get_all_session_ids().contains(&second_id));
+        assert_eq!(broadcast("look", Some("registry-test-b")), 0);
+    }
+}
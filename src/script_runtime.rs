@@ -1,5 +1,5 @@
 use std::{
-    borrow::Borrow, sync::{Arc, Mutex}, thread
+    borrow::Borrow, sync::{mpsc, Arc, Mutex}, thread, time::{Duration, Instant}
 };
 
 use anyhow::{bail, Context};
@@ -9,6 +9,7 @@ use deno_core::{
     JsRuntime, PollEventLoopOptions,
 };
 use slint::ComponentHandle;
+use lifecycle::LifecycleEvent;
 use tokio::{
     select,
     sync::{
@@ -17,8 +18,25 @@ use tokio::{
     },
 };
 
+mod delayed;
+mod lifecycle;
+mod ops;
+mod repaint_batch;
+mod send_queue;
+
+use delayed::DelayedActions;
+use repaint_batch::RepaintBatch;
+pub use send_queue::SendQueue;
+
 use crate::{
-    session::{incoming_line_history::IncomingLineHistory, StyledLine, ViewAction},
+    macros::Macros,
+    notification::Notifier,
+    sound::SoundContext,
+    session::{
+        incoming_line_history::IncomingLineHistory, output_sink::OutputSink, Gauge, LineOperation,
+        LoginSequence, LoginStep, SessionEvent, StyledLine, ViewAction,
+    },
+    trigger::TriggerStats,
     MainWindow,
 };
 
@@ -26,36 +44,95 @@ use crate::{
 pub enum RuntimeAction {
     PassthroughCompleteLine(Arc<StyledLine>),
     PassthroughPartialLine(Arc<StyledLine>),
+    /// A line fed back by `#debug replay`; it's marked as such, and kept out of the history and log
+    PassthroughReplayedLine(Arc<StyledLine>),
+    /// Diverts a line into the named capture buffer, instead of the main one
+    CaptureLine(Arc<String>, Arc<StyledLine>),
+    UpdatePrompt(Arc<StyledLine>),
+    /// Highlights, replaces or removes lines already in the main buffer
+    PerformLineOperation(LineOperation),
     EvalJavascriptTrigger(Arc<StyledLine>, usize, Arc<Vec<(String, String)>>, Arc<oneshot::Sender<Option<Arc<String>>>>),
     EvalJavascriptAlias(Arc<String>, usize, Arc<Vec<(String, String)>>, Arc<oneshot::Sender<Option<Arc<String>>>>),
     SendRaw(Arc<String>),
+    SendHidden(Arc<String>),
     Echo(Arc<String>),
+    Notify(Arc<String>, Arc<String>),
+    /// Plays a builtin sound, or a file relative to the profile's directory, at full volume
+    PlaySound(Arc<String>),
     RequestRepaint,
     UpdateWriteToSocketTx(Option<UnboundedSender<Arc<String>>>),
+    /// The connection was made again after being lost, rather than opened by the user
+    Reconnected,
+    /// The server has taken over echoing input (telnet ECHO), usually while it asks for a password
+    SetEchoSuppressed(bool),
+    /// Variables decoded from an MSDP or GMCP message, which the status bar may be bound to
+    UpdateStatusVariables(Arc<Vec<(String, String)>>),
+    /// Sets a script's status bar gauge, or removes it when there's none
+    SetGauge(Arc<String>, Option<Gauge>),
+    /// Replaces the text in the session's input area
+    SetInputLine(Arc<String>),
+    /// Starts the profile's login sequence over, as a connection has just been made
+    StartLogin(Arc<Vec<LoginStep>>),
+    /// Drops the commands held back by the send queue
+    FlushSendQueue,
     CompileJavascriptAlias(Arc<String>, Arc<oneshot::Sender<usize>>),
-    CloseSession,
+    /// Runs the scripts' close callbacks, sends these commands if still connected, flushes the
+    /// output sink and ends the runtime, then replies
+    CloseSession(Arc<String>, mpsc::Sender<()>),
 }
 
+/// How long scripts' close callbacks get to run, altogether
+const CLOSE_CALLBACK_BUDGET: Duration = Duration::from_millis(500);
+/// How long the output sink gets to write out what's queued when the session closes
+const OUTPUT_SINK_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct ScriptRuntime {
     script_action_tx: UnboundedSender<RuntimeAction>,
 }
 
+/// The text in a session's input area. The session keeps it up to date as it's edited, so
+/// scripts can read it without waiting on the UI thread.
+#[derive(Clone, Debug, Default)]
+pub struct InputLine(Arc<Mutex<String>>);
+
+impl InputLine {
+    pub fn get(&self) -> String {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, text: &str) {
+        let mut input_line = self.0.lock().unwrap();
+        input_line.clear();
+        input_line.push_str(text);
+    }
+}
+
 enum ActionResult {
     RequestRepaint,
     SkipRepaint,
-    CloseSession,
+    CloseSession(mpsc::Sender<()>),
 }
 
 impl ScriptRuntime {
     pub fn new(
         view_line_action_tx: UnboundedSender<ViewAction>,
+        capture_view_action_tx: UnboundedSender<ViewAction>,
         weak_window: slint::Weak<MainWindow>,
         incoming_line_history: Arc<Mutex<IncomingLineHistory>>,
+        output_sink: Option<OutputSink>,
+        notifier: Notifier,
+        sound: SoundContext,
+        input_line: InputLine,
+        macros: Macros,
+        send_queue: SendQueue,
+        trigger_stats: Arc<TriggerStats>,
     ) -> Self {
         let (script_action_tx, script_action_rx) =
             tokio::sync::mpsc::unbounded_channel::<RuntimeAction>();
 
-        let script_runtime = Self { script_action_tx };
+        let script_runtime = Self {
+            script_action_tx: script_action_tx.clone(),
+        };
 
         thread::spawn(move || {
             let runtime = tokio::runtime::Builder::new_current_thread()
@@ -64,10 +141,19 @@ impl ScriptRuntime {
                 .unwrap();
 
             runtime.block_on(ScriptRuntime::run_event_loop(
+                script_action_tx,
                 script_action_rx,
                 view_line_action_tx,
+                capture_view_action_tx,
                 weak_window,
                 incoming_line_history,
+                output_sink,
+                notifier,
+                sound,
+                input_line,
+                macros,
+                send_queue,
+                trigger_stats,
             ))
         });
 
@@ -102,6 +188,70 @@ impl ScriptRuntime {
             .unwrap();
     }
 
+    /// Queues each line of `text` to be sent once the send queue allows
+    fn queue_sends(deno: &mut JsRuntime, text: &str, echo: bool) {
+        let op_state = deno.op_state();
+        let mut op_state = op_state.borrow_mut();
+        let send_queue = op_state.borrow_mut::<SendQueue>();
+        for line in text.split('\n') {
+            send_queue.push(line, echo);
+        }
+    }
+
+    /// Sends whatever the send queue allows by `now`, echoing it as it goes out so the buffer
+    /// shows when commands were really sent. Returns whether anything was echoed or the queue
+    /// changed.
+    fn transmit_queued(
+        deno: &mut JsRuntime,
+        view_line_action_tx: &UnboundedSender<ViewAction>,
+        write_to_socket_tx: &Option<UnboundedSender<Arc<String>>>,
+        now: Instant,
+    ) -> bool {
+        let (sends, len_change) = {
+            let op_state = deno.op_state();
+            let mut op_state = op_state.borrow_mut();
+            let send_queue = op_state.borrow_mut::<SendQueue>();
+            (send_queue.poll(now), send_queue.take_len_change())
+        };
+
+        for send in &sends {
+            if send.echo {
+                ScriptRuntime::send_line_as_command_input(
+                    &send.line,
+                    view_line_action_tx,
+                    write_to_socket_tx,
+                );
+            } else if let Some(ref tx) = write_to_socket_tx {
+                tx.send(Arc::new(format!("{}\r\n", send.line))).unwrap();
+            }
+        }
+        if let Some(len) = len_change {
+            view_line_action_tx
+                .send(ViewAction::SendQueueDepth(len))
+                .ok();
+        }
+
+        !sends.is_empty() || len_change.is_some()
+    }
+
+    /// Sends the next login step's answer if the line received so far is its prompt. It's queued
+    /// like any other send, so goes out after anything already waiting.
+    fn advance_login(deno: &mut JsRuntime, login: &mut LoginSequence, line: &StyledLine) {
+        let Some(step) = login.on_line(&line.plain_text()) else {
+            return;
+        };
+        let send = Arc::new(step.send.clone());
+        deno.op_state()
+            .borrow()
+            .borrow::<UnboundedSender<RuntimeAction>>()
+            .send(if step.hidden {
+                RuntimeAction::SendHidden(send)
+            } else {
+                RuntimeAction::SendRaw(send)
+            })
+            .ok();
+    }
+
     #[inline(always)]
     fn echo_line(
         line: &str,
@@ -134,8 +284,13 @@ impl ScriptRuntime {
     fn handle_incoming_action(
         deno: &mut JsRuntime,
         view_line_action_tx: &UnboundedSender<ViewAction>,
+        capture_view_action_tx: &UnboundedSender<ViewAction>,
         incoming_line_history_arc: &Arc<Mutex<IncomingLineHistory>>,
+        output_sink: Option<&OutputSink>,
+        notifier: &Notifier,
         write_to_socket_tx: &mut Option<UnboundedSender<Arc<String>>>,
+        echo_suppressed: &mut bool,
+        login: &mut LoginSequence,
         compiled_scripts: &mut Vec<v8::Global<v8::Script>>,
         action: RuntimeAction,
     ) -> Result<ActionResult, anyhow::Error> {
@@ -149,17 +304,67 @@ impl ScriptRuntime {
                 view_line_action_tx
                     .send(ViewAction::AppendCompleteLine(line.clone()))
                     .unwrap();
-                let mut incoming_line_history = incoming_line_history_arc.lock().unwrap();
-                incoming_line_history.extend_line(line);
-                incoming_line_history.commit_current_line();
+                let committed_line = {
+                    let mut incoming_line_history = incoming_line_history_arc.lock().unwrap();
+                    incoming_line_history.extend_line(line);
+                    incoming_line_history.commit_current_line()
+                };
+                if let Some(ref committed_line) = committed_line {
+                    if login.is_running() {
+                        ScriptRuntime::advance_login(deno, login, committed_line);
+                    }
+                    if let Some(output_sink) = output_sink {
+                        output_sink.write_line(committed_line);
+                    }
+                }
                 Ok(ActionResult::SkipRepaint)
             }
+            RuntimeAction::PassthroughReplayedLine(line) => {
+                let replayed = StyledLine {
+                    received_at: line.received_at,
+                    ..StyledLine::from_echo_str("[replay] ").append(&line)
+                };
+                view_line_action_tx
+                    .send(ViewAction::AppendCompleteLine(Arc::new(replayed)))
+                    .unwrap();
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::CaptureLine(buffer_name, line) => {
+                let captured = StyledLine {
+                    received_at: line.received_at,
+                    ..StyledLine::from_echo_str(&format!("[{buffer_name}] ")).append(&line)
+                };
+                capture_view_action_tx
+                    .send(ViewAction::AppendCompleteLine(Arc::new(captured)))
+                    .context("Failed to send captured line to view")?;
+                Ok(ActionResult::RequestRepaint)
+            }
             RuntimeAction::PassthroughPartialLine(line) => {
                 view_line_action_tx
                     .send(ViewAction::AppendPartialLine(line.clone()))
                     .unwrap();
-                let mut incoming_line_history = incoming_line_history_arc.lock().unwrap();
-                incoming_line_history.extend_line(line);
+                let current_line = {
+                    let mut incoming_line_history = incoming_line_history_arc.lock().unwrap();
+                    incoming_line_history.extend_line(line);
+                    incoming_line_history.last_line()
+                };
+                // Login prompts rarely end in a newline, so are matched as they arrive
+                if let Some(current_line) = current_line.filter(|_| login.is_running()) {
+                    ScriptRuntime::advance_login(deno, login, &current_line);
+                }
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::UpdatePrompt(line) => {
+                if login.is_running() {
+                    ScriptRuntime::advance_login(deno, login, &line);
+                }
+                view_line_action_tx
+                    .send(ViewAction::UpdatePrompt(line))
+                    .unwrap();
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::StartLogin(steps) => {
+                login.start(&steps);
                 Ok(ActionResult::SkipRepaint)
             }
             RuntimeAction::EvalJavascriptTrigger(_, _, _, _) => {
@@ -222,18 +427,104 @@ impl ScriptRuntime {
                 }
 
             RuntimeAction::SendRaw(str) => {
-                for line in str.split(|ch| ch == ';' || ch == '\n') {
-                    ScriptRuntime::send_line_as_command_input(
-                        line,
-                        &view_line_action_tx,
-                        &write_to_socket_tx,
-                    );
-                }
+                // Command separators were already handled by the alias processor; only newlines
+                // split here. While the server isn't echoing, neither do we; this is most likely
+                // a password.
+                ScriptRuntime::queue_sends(deno, &str, !*echo_suppressed);
+                ScriptRuntime::transmit_queued(
+                    deno,
+                    view_line_action_tx,
+                    write_to_socket_tx,
+                    Instant::now(),
+                );
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::SendHidden(str) => {
+                // Used for things like passwords; nothing is echoed to the view
+                ScriptRuntime::queue_sends(deno, &str, false);
+                ScriptRuntime::transmit_queued(
+                    deno,
+                    view_line_action_tx,
+                    write_to_socket_tx,
+                    Instant::now(),
+                );
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::FlushSendQueue => {
+                let dropped = deno
+                    .op_state()
+                    .borrow_mut()
+                    .borrow_mut::<SendQueue>()
+                    .clear();
+                ScriptRuntime::echo_line(
+                    &format!("Dropped {dropped} queued commands"),
+                    view_line_action_tx,
+                )?;
+                ScriptRuntime::transmit_queued(
+                    deno,
+                    view_line_action_tx,
+                    write_to_socket_tx,
+                    Instant::now(),
+                );
                 Ok(ActionResult::RequestRepaint)
             }
             RuntimeAction::UpdateWriteToSocketTx(option_tx) => {
+                let events = match (&write_to_socket_tx, &option_tx) {
+                    (None, Some(_)) => Some((LifecycleEvent::Connect, SessionEvent::Connected)),
+                    (Some(_), None) => {
+                        Some((LifecycleEvent::Disconnect, SessionEvent::Disconnected))
+                    }
+                    _ => None,
+                };
                 *write_to_socket_tx = option_tx;
-                Ok(ActionResult::SkipRepaint)
+
+                let Some((event, session_event)) = events else {
+                    return Ok(ActionResult::SkipRepaint);
+                };
+                if event == LifecycleEvent::Disconnect {
+                    login.cancel();
+                    // Delayed sends were meant for the connection that's gone
+                    deno.op_state()
+                        .borrow_mut()
+                        .borrow_mut::<DelayedActions>()
+                        .cancel_all();
+                }
+                view_line_action_tx.send(ViewAction::SessionEvent(session_event))?;
+                for exception in lifecycle::run_callbacks(deno, event) {
+                    ScriptRuntime::echo_line(exception.as_str(), &view_line_action_tx)?;
+                }
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::Reconnected => {
+                for exception in lifecycle::run_callbacks(deno, LifecycleEvent::Reconnect) {
+                    ScriptRuntime::echo_line(exception.as_str(), &view_line_action_tx)?;
+                }
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::SetEchoSuppressed(suppressed) => {
+                *echo_suppressed = suppressed;
+                view_line_action_tx
+                    .send(ViewAction::SetInputMasked(suppressed))
+                    .context("Failed to send input mask to view")?;
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::UpdateStatusVariables(variables) => {
+                view_line_action_tx
+                    .send(ViewAction::UpdateStatusVariables(variables))
+                    .context("Failed to send status variables to view")?;
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::SetGauge(id, gauge) => {
+                view_line_action_tx
+                    .send(ViewAction::SetGauge(id, gauge))
+                    .context("Failed to send gauge to view")?;
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::SetInputLine(text) => {
+                view_line_action_tx
+                    .send(ViewAction::SetInputLine(text))
+                    .context("Failed to send input line to view")?;
+                Ok(ActionResult::RequestRepaint)
             }
             RuntimeAction::CompileJavascriptAlias(source, reply_arc) => {
                 let f =
@@ -248,21 +539,74 @@ impl ScriptRuntime {
 
                 Ok(ActionResult::SkipRepaint)
             }
-            RuntimeAction::CloseSession => Ok(ActionResult::CloseSession),
+            RuntimeAction::PlaySound(name) => {
+                deno.op_state()
+                    .borrow()
+                    .borrow::<SoundContext>()
+                    .play(name.as_str(), 1.0, None);
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::PerformLineOperation(operation) => {
+                view_line_action_tx
+                    .send(ViewAction::PerformLineOperation(operation))
+                    .context("Failed to send line operation to view")?;
+                Ok(ActionResult::RequestRepaint)
+            }
+            RuntimeAction::Notify(title, body) => {
+                notifier.notify(title.to_string(), body.to_string());
+                Ok(ActionResult::SkipRepaint)
+            }
+            RuntimeAction::CloseSession(send_on_close, closed_tx) => {
+                // The view may already be gone, so exceptions are only logged
+                for exception in
+                    lifecycle::run_callbacks_within(deno, LifecycleEvent::Close, CLOSE_CALLBACK_BUDGET)
+                {
+                    warn!("Close callback failed: {exception}");
+                }
+
+                if let Some(ref tx) = write_to_socket_tx {
+                    for line in send_on_close.lines().filter(|line| !line.is_empty()) {
+                        tx.send(Arc::new(format!("{line}\r\n"))).ok();
+                    }
+                }
+                Ok(ActionResult::CloseSession(closed_tx))
+            }
         }
     }
 
     async fn run_event_loop(
+        scripted_action_tx: UnboundedSender<RuntimeAction>,
         mut scripted_action_rx: UnboundedReceiver<RuntimeAction>,
         view_line_action_tx: UnboundedSender<ViewAction>,
+        capture_view_action_tx: UnboundedSender<ViewAction>,
         weak_window: slint::Weak<MainWindow>,
         incoming_line_history_arc: Arc<Mutex<IncomingLineHistory>>,
+        mut output_sink: Option<OutputSink>,
+        notifier: Notifier,
+        sound: SoundContext,
+        input_line: InputLine,
+        macros: Macros,
+        send_queue: SendQueue,
+        trigger_stats: Arc<TriggerStats>,
     ) {
         let mut write_to_socket_tx: Option<UnboundedSender<Arc<String>>> = None;
+        let mut echo_suppressed = false;
+        let mut login = LoginSequence::default();
 
         let mut deno = deno_core::JsRuntime::new(deno_core::RuntimeOptions {
+            extensions: vec![ops::smudgy::init_ops()],
             ..Default::default()
         });
+        deno.execute_script("[smudgy:bootstrap.js]", include_str!("script_runtime/bootstrap.js"))
+            .expect("Failed to bootstrap the smudgy script API");
+        deno.op_state().borrow_mut().put(sound);
+        deno.op_state().borrow_mut().put(input_line);
+        deno.op_state().borrow_mut().put(macros);
+        deno.op_state().borrow_mut().put(send_queue);
+        deno.op_state().borrow_mut().put(trigger_stats);
+        deno.op_state().borrow_mut().put(incoming_line_history_arc.clone());
+        // Ops queue actions back to this loop, like anything else
+        deno.op_state().borrow_mut().put(scripted_action_tx);
 
         let mut compiled_scripts: Vec<v8::Global<v8::Script>> = Vec::new();
 
@@ -270,6 +614,8 @@ impl ScriptRuntime {
             tokio::time::interval(tokio::time::Duration::from_micros(100));
         deno_event_loop_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
+        let mut repaint_batch = RepaintBatch::default();
+
         loop {
             deno.run_event_loop(PollEventLoopOptions::default())
                 .await
@@ -278,23 +624,49 @@ impl ScriptRuntime {
             select! {
                 _ = deno_event_loop_interval.tick() => {
                     // this serves to trigger a cancel on the pending receive below when it's time
-                    // for the event loop above to tick
+                    // for the event loop above to tick, to run whatever scripts delayed, and to
+                    // send what the send queue held back
+                    let exceptions = delayed::run_due(&mut deno, Instant::now());
+                    for exception in &exceptions {
+                        ScriptRuntime::echo_line(exception.as_str(), &view_line_action_tx).ok();
+                    }
+                    let transmitted = ScriptRuntime::transmit_queued(
+                        &mut deno,
+                        &view_line_action_tx,
+                        &write_to_socket_tx,
+                        Instant::now(),
+                    );
+                    if transmitted || !exceptions.is_empty() {
+                        weak_window.upgrade_in_event_loop(move |handle| handle.window().request_redraw()).ok();
+                    }
                 }
                 Some(action) = scripted_action_rx.recv() => {
+                    let forces_flush = repaint_batch::forces_flush(&action);
                     match ScriptRuntime::handle_incoming_action(
                     &mut deno,
                     &view_line_action_tx,
+                    &capture_view_action_tx,
                     &incoming_line_history_arc,
+                    output_sink.as_ref(),
+                    &notifier,
                     &mut write_to_socket_tx,
+                    &mut echo_suppressed,
+                    &mut login,
                     &mut compiled_scripts,
                     action,
                 ) {
                     Ok(ActionResult::RequestRepaint) => {
-                        weak_window.upgrade_in_event_loop(move |handle| handle.window().request_redraw()).expect("Failed to request redraw");
+                        repaint_batch.request(Instant::now());
                     }
                     Ok(ActionResult::SkipRepaint) => {}
-                    Ok(ActionResult::CloseSession) => {
+                    Ok(ActionResult::CloseSession(closed_tx)) => {
                         trace!("Session runtime event loop ending");
+                        if let Some(output_sink) = output_sink.take() {
+                            if !output_sink.close(OUTPUT_SINK_CLOSE_TIMEOUT) {
+                                warn!("Output sink didn't finish writing before the session closed");
+                            }
+                        }
+                        closed_tx.send(()).ok();
                         break;
                     }
                     Err(err) => {
@@ -302,6 +674,14 @@ impl ScriptRuntime {
                         break;
                     }
                      }
+
+                    let more_queued = !scripted_action_rx.is_empty();
+                    if (forces_flush || repaint_batch.should_flush(Instant::now(), more_queued))
+                        && repaint_batch.take() > 0
+                    {
+                        // The event loop has already ended when sessions are closed as the app quits
+                        weak_window.upgrade_in_event_loop(move |handle| handle.window().request_redraw()).ok();
+                    }
                 }
             }
         }
@@ -0,0 +1,83 @@
+"use strict";
+
+((globalThis) => {
+  const ops = Deno.core.ops;
+
+  globalThis.smudgy = {
+    // { version, buildName, buildTime, regexBackend }
+    version: Object.freeze(ops.op_smudgy_version()),
+    dice: {
+      roll: (expr) => ops.op_smudgy_dice_roll(String(expr)),
+    },
+    notify: (title, body = "") => ops.op_smudgy_notify(String(title), String(body)),
+    capture: (bufferName, text) => ops.op_smudgy_capture(String(bufferName), String(text)),
+    // Gauges are shown in the status bar, after the profile's own fields and lowest order first.
+    // The session keeps them, so they stay put when scripts are reloaded.
+    ui: {
+      setGauge: (id, { label, value, max, color, order } = {}) =>
+        ops.op_smudgy_ui_set_gauge(String(id), {
+          label: label === undefined ? null : String(label),
+          value: Number(value),
+          max: max === undefined ? null : Number(max),
+          color: color === undefined ? null : String(color),
+          order: order === undefined ? null : Number(order),
+        }),
+      setStatus: (id, text, { order = 0 } = {}) =>
+        ops.op_smudgy_ui_set_status(String(id), String(text), Number(order)),
+      clearGauge: (id) => ops.op_smudgy_ui_clear_gauge(String(id)),
+    },
+    // Lines are counted back from the most recent output line, which is 0
+    lines: {
+      highlight: (color, start = 0, end = start) =>
+        ops.op_smudgy_lines_highlight(String(color), Number(start), Number(end)),
+      replace: (line, text) => ops.op_smudgy_lines_replace(Number(line), String(text)),
+      remove: (start = 0, end = start) => ops.op_smudgy_lines_remove(Number(start), Number(end)),
+      // Highlights every match of a regular expression in the most recent line
+      highlightPattern: (pattern, color) =>
+        ops.op_smudgy_highlight_pattern(
+          pattern instanceof RegExp ? pattern.source : String(pattern),
+          String(color),
+        ),
+    },
+    sound: {
+      play: (nameOrPath, { volume, id } = {}) =>
+        ops.op_smudgy_sound_play(String(nameOrPath), {
+          volume: volume === undefined ? null : Number(volume),
+          id: id === undefined ? null : String(id),
+        }),
+      stop: (id) => ops.op_smudgy_sound_stop(String(id)),
+    },
+    // The text in the session's input area
+    input: {
+      get: () => ops.op_smudgy_get_input_line(),
+      set: (text) => ops.op_smudgy_set_input_line(String(text)),
+    },
+    // Recorded with #macro record or Ctrl+Shift+R; played back as they were typed, without aliases
+    macro: {
+      play: (name) => ops.op_smudgy_macro_play(String(name)),
+      list: () => ops.op_smudgy_macro_list(),
+    },
+    // One-shot delays, in milliseconds; anything pending is dropped when the connection closes
+    sendAfter: (millis, line) => ops.op_smudgy_send_after(Number(millis), String(line)),
+    callAfter: (millis, callback) => ops.op_smudgy_call_after(Number(millis), callback),
+    // Commands held back by the profile's rate limit; #flushqueue drops them
+    sendQueue: {
+      get length() {
+        return ops.op_smudgy_send_queue_length();
+      },
+    },
+    // Trigger and alias profiling counters, most expensive first; #stats on starts counting
+    stats: () => ops.op_smudgy_stats(),
+    // Sends a line from every open session, or only those connected with the named profile
+    broadcast: (line, { profile } = {}) =>
+      ops.op_smudgy_broadcast(String(line), profile === undefined ? "" : String(profile)),
+    session: {
+      onConnect: (callback) => ops.op_smudgy_session_on("connect", callback),
+      // Runs after onConnect when auto-reconnect brings a dropped connection back
+      onReconnect: (callback) => ops.op_smudgy_session_on("reconnect", callback),
+      onDisconnect: (callback) => ops.op_smudgy_session_on("disconnect", callback),
+      // Runs when the session is closed, for up to half a second altogether
+      onClose: (callback) => ops.op_smudgy_session_on("close", callback),
+    },
+  };
+})(globalThis);
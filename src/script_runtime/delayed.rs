@@ -0,0 +1,160 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use deno_core::{v8, JsRuntime};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::RuntimeAction;
+
+pub enum DelayedAction {
+    Send(String),
+    Call(v8::Global<v8::Function>),
+}
+
+/// One-shot actions scripts have scheduled, kept in the runtime's `OpState` and run by its
+/// event loop once they're due
+#[derive(Default)]
+pub struct DelayedActions {
+    /// Keyed by when they're due, then by the order they were scheduled in
+    pending: BTreeMap<(Instant, u64), DelayedAction>,
+    next_seq: u64,
+}
+
+impl DelayedActions {
+    pub fn schedule(&mut self, delay: Duration, action: DelayedAction) {
+        self.schedule_at(Instant::now() + delay, action);
+    }
+
+    fn schedule_at(&mut self, due: Instant, action: DelayedAction) {
+        self.pending.insert((due, self.next_seq), action);
+        self.next_seq += 1;
+    }
+
+    /// Removes and returns the actions due by `now`, earliest first
+    fn take_due(&mut self, now: Instant) -> Vec<DelayedAction> {
+        let not_due = self.pending.split_off(&(now, u64::MAX));
+        std::mem::replace(&mut self.pending, not_due)
+            .into_values()
+            .collect()
+    }
+
+    pub fn cancel_all(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Runs the delayed actions due by `now`, returning the exceptions any callbacks threw. Lines
+/// are queued back to the runtime as they are, without alias processing, like macros.
+pub fn run_due(deno: &mut JsRuntime, now: Instant) -> Vec<String> {
+    let (due, tx) = {
+        let op_state = deno.op_state();
+        let mut op_state = op_state.borrow_mut();
+        let due = op_state.borrow_mut::<DelayedActions>().take_due(now);
+        if due.is_empty() {
+            return Vec::new();
+        }
+        (
+            due,
+            op_state.borrow::<UnboundedSender<RuntimeAction>>().clone(),
+        )
+    };
+
+    let scope = &mut deno.handle_scope();
+    let try_catch = &mut v8::TryCatch::new(scope);
+    let undefined = v8::undefined(try_catch).into();
+
+    let mut exceptions = Vec::new();
+    for action in due {
+        match action {
+            DelayedAction::Send(line) => {
+                tx.send(RuntimeAction::SendRaw(Arc::new(line))).ok();
+            }
+            DelayedAction::Call(callback) => {
+                v8::Local::new(try_catch, callback).call(try_catch, undefined, &[]);
+                if let Some(exception) = try_catch.exception() {
+                    exceptions.push(exception.to_rust_string_lossy(try_catch));
+                    try_catch.reset();
+                }
+            }
+        }
+    }
+
+    exceptions
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_core::RuntimeOptions;
+
+    use super::*;
+    use crate::script_runtime::ops;
+
+    fn sent(rx: &mut tokio::sync::mpsc::UnboundedReceiver<RuntimeAction>) -> Vec<String> {
+        let mut sent = Vec::new();
+        while let Ok(action) = rx.try_recv() {
+            if let RuntimeAction::SendRaw(line) = action {
+                sent.push(line.to_string());
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_due_actions_are_taken_in_order() {
+        let now = Instant::now();
+        let mut delayed = DelayedActions::default();
+        for (millis, line) in [(20, "second"), (10, "first"), (20, "third"), (30, "later")] {
+            delayed.schedule_at(
+                now + Duration::from_millis(millis),
+                DelayedAction::Send(line.into()),
+            );
+        }
+
+        assert!(delayed.take_due(now).is_empty());
+        let due: Vec<String> = delayed
+            .take_due(now + Duration::from_millis(20))
+            .into_iter()
+            .map(|action| match action {
+                DelayedAction::Send(line) => line,
+                DelayedAction::Call(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(due, vec!["first", "second", "third"]);
+
+        delayed.cancel_all();
+        assert!(delayed.take_due(now + Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_send_after_waits_for_the_delay() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![ops::smudgy::init_ops()],
+            ..Default::default()
+        });
+        deno.op_state().borrow_mut().put(tx);
+
+        let scheduled = Instant::now();
+        deno.execute_script(
+            "[test]",
+            r#"
+            globalThis.calls = 0;
+            Deno.core.ops.op_smudgy_send_after(2000, "stand");
+            Deno.core.ops.op_smudgy_call_after(2000, () => calls++);
+            "#,
+        )
+        .unwrap();
+
+        assert!(run_due(&mut deno, scheduled + Duration::from_millis(1900)).is_empty());
+        assert!(sent(&mut rx).is_empty());
+
+        assert!(run_due(&mut deno, scheduled + Duration::from_millis(2100)).is_empty());
+        assert_eq!(sent(&mut rx), vec!["stand"]);
+        let calls = deno.execute_script("[test]", "calls").unwrap();
+        let scope = &mut deno.handle_scope();
+        assert_eq!(v8::Local::new(scope, calls).int32_value(scope), Some(1));
+    }
+}
@@ -0,0 +1,185 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use anyhow::{bail, Result};
+use deno_core::{v8, JsRuntime};
+
+/// Session events scripts can register callbacks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    Connect,
+    /// The connection came back by itself after being lost, once the connect callbacks have run
+    Reconnect,
+    Disconnect,
+    /// The session is being closed, so scripts can save state
+    Close,
+}
+
+impl LifecycleEvent {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "connect" => Ok(LifecycleEvent::Connect),
+            "reconnect" => Ok(LifecycleEvent::Reconnect),
+            "disconnect" => Ok(LifecycleEvent::Disconnect),
+            "close" => Ok(LifecycleEvent::Close),
+            _ => bail!("Unknown session event: {name}"),
+        }
+    }
+}
+
+/// The callbacks scripts have registered, kept in the runtime's `OpState`
+#[derive(Default)]
+pub struct LifecycleCallbacks {
+    connect: Vec<v8::Global<v8::Function>>,
+    reconnect: Vec<v8::Global<v8::Function>>,
+    disconnect: Vec<v8::Global<v8::Function>>,
+    close: Vec<v8::Global<v8::Function>>,
+}
+
+impl LifecycleCallbacks {
+    pub fn register(&mut self, event: LifecycleEvent, callback: v8::Global<v8::Function>) {
+        self.callbacks_mut(event).push(callback);
+    }
+
+    fn callbacks_mut(&mut self, event: LifecycleEvent) -> &mut Vec<v8::Global<v8::Function>> {
+        match event {
+            LifecycleEvent::Connect => &mut self.connect,
+            LifecycleEvent::Reconnect => &mut self.reconnect,
+            LifecycleEvent::Disconnect => &mut self.disconnect,
+            LifecycleEvent::Close => &mut self.close,
+        }
+    }
+}
+
+/// Calls every callback registered for `event`, in the order they were registered, returning
+/// the exceptions any of them threw
+pub fn run_callbacks(deno: &mut JsRuntime, event: LifecycleEvent) -> Vec<String> {
+    let callbacks = deno
+        .op_state()
+        .borrow_mut()
+        .borrow_mut::<LifecycleCallbacks>()
+        .callbacks_mut(event)
+        .clone();
+
+    let scope = &mut deno.handle_scope();
+    let try_catch = &mut v8::TryCatch::new(scope);
+    let undefined = v8::undefined(try_catch).into();
+
+    let mut exceptions = Vec::new();
+    for callback in callbacks {
+        v8::Local::new(try_catch, callback).call(try_catch, undefined, &[]);
+
+        if try_catch.has_terminated() {
+            // Stopped by `run_callbacks_within`, so the rest don't get to run either
+            exceptions.push(format!(
+                "{event:?} callbacks took too long and were stopped"
+            ));
+            break;
+        }
+        if let Some(exception) = try_catch.exception() {
+            exceptions.push(exception.to_rust_string_lossy(try_catch));
+            try_catch.reset();
+        }
+    }
+
+    exceptions
+}
+
+/// Like `run_callbacks`, but the callbacks are stopped if they haven't all returned within
+/// `budget` altogether, so that one stuck in a loop can't hold up closing the session
+pub fn run_callbacks_within(
+    deno: &mut JsRuntime,
+    event: LifecycleEvent,
+    budget: Duration,
+) -> Vec<String> {
+    let isolate = deno.v8_isolate().thread_safe_handle();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let watchdog = thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = done_rx.recv_timeout(budget) {
+            isolate.terminate_execution();
+        }
+    });
+
+    let exceptions = run_callbacks(deno, event);
+    drop(done_tx);
+    watchdog.join().ok();
+
+    // The watchdog may have fired just as the callbacks finished, which would stop whatever
+    // script runs next
+    deno.v8_isolate().cancel_terminate_execution();
+
+    exceptions
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_core::RuntimeOptions;
+
+    use super::*;
+    use crate::script_runtime::ops;
+
+    fn eval_i32(deno: &mut JsRuntime, source: &'static str) -> i32 {
+        let result = deno.execute_script("[test]", source).unwrap();
+        let scope = &mut deno.handle_scope();
+        v8::Local::new(scope, result).int32_value(scope).unwrap()
+    }
+
+    #[test]
+    fn test_callbacks_run_on_their_event() {
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![ops::smudgy::init_ops()],
+            ..Default::default()
+        });
+
+        deno.execute_script(
+            "[test]",
+            r#"
+            globalThis.connects = 0;
+            globalThis.disconnects = 0;
+            Deno.core.ops.op_smudgy_session_on("connect", () => connects++);
+            Deno.core.ops.op_smudgy_session_on("disconnect", () => disconnects++);
+            Deno.core.ops.op_smudgy_session_on("connect", () => { throw new Error("oops"); });
+            "#,
+        )
+        .unwrap();
+
+        let exceptions = run_callbacks(&mut deno, LifecycleEvent::Connect);
+        assert_eq!(exceptions, vec!["Error: oops".to_string()]);
+        assert_eq!(eval_i32(&mut deno, "connects"), 1);
+        assert_eq!(eval_i32(&mut deno, "disconnects"), 0);
+
+        assert!(run_callbacks(&mut deno, LifecycleEvent::Disconnect).is_empty());
+        assert_eq!(eval_i32(&mut deno, "disconnects"), 1);
+    }
+
+    #[test]
+    fn test_close_callbacks_are_stopped_after_the_budget() {
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![ops::smudgy::init_ops()],
+            ..Default::default()
+        });
+
+        deno.execute_script(
+            "[test]",
+            r#"
+            globalThis.closes = 0;
+            Deno.core.ops.op_smudgy_session_on("close", () => closes++);
+            Deno.core.ops.op_smudgy_session_on("close", () => { for (;;) {} });
+            "#,
+        )
+        .unwrap();
+
+        let exceptions =
+            run_callbacks_within(&mut deno, LifecycleEvent::Close, Duration::from_millis(50));
+        assert_eq!(
+            exceptions,
+            vec!["Close callbacks took too long and were stopped".to_string()]
+        );
+        // The runtime is still usable afterwards
+        assert_eq!(eval_i32(&mut deno, "closes"), 1);
+    }
+
+    #[test]
+    fn test_unknown_event() {
+        assert!(LifecycleEvent::from_name("login").is_err());
+    }
+}
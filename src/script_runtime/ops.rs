@@ -0,0 +1,640 @@
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use deno_core::{
+    error::AnyError,
+    op2,
+    serde::{Deserialize, Serialize},
+    v8, OpState,
+};
+use regex::Regex;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    delayed::{DelayedAction, DelayedActions},
+    lifecycle::{LifecycleCallbacks, LifecycleEvent},
+    InputLine, RuntimeAction, SendQueue,
+};
+use crate::{
+    dice::{self, RollResult},
+    macros::Macros,
+    registry,
+    session::{
+        incoming_line_history::IncomingLineHistory, parse_hex_color, Gauge, LineOperation,
+        StyledLine,
+    },
+    sound::SoundContext,
+    trigger::{StatsReport, TriggerStats},
+};
+
+#[derive(Debug, Default, Deserialize)]
+struct PlayOptions {
+    volume: Option<f32>,
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GaugeOptions {
+    label: Option<String>,
+    value: f64,
+    max: Option<f64>,
+    color: Option<String>,
+    order: Option<i32>,
+}
+
+/// Which build of smudgy scripts are running in, so they can check for features
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionInfo {
+    version: &'static str,
+    build_name: &'static str,
+    build_time: &'static str,
+    /// What trigger and alias patterns are matched with
+    regex_backend: &'static str,
+}
+
+#[op2]
+#[serde]
+fn op_smudgy_version() -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        build_name: env!("SMUDGY_BUILD_NAME"),
+        build_time: build_time::build_time_utc!(),
+        regex_backend: "regex",
+    }
+}
+
+#[op2]
+#[serde]
+fn op_smudgy_dice_roll(#[string] expr: String) -> Result<RollResult, AnyError> {
+    dice::parse_and_roll(&expr, &mut rand::thread_rng())
+}
+
+#[op2]
+fn op_smudgy_notify(state: &mut OpState, #[string] title: String, #[string] body: String) {
+    // Raised by the runtime's notifier, which rate limits them
+    state
+        .borrow::<UnboundedSender<RuntimeAction>>()
+        .send(RuntimeAction::Notify(Arc::new(title), Arc::new(body)))
+        .ok();
+}
+
+#[op2]
+fn op_smudgy_capture(state: &mut OpState, #[string] buffer_name: String, #[string] text: String) {
+    state
+        .borrow::<UnboundedSender<RuntimeAction>>()
+        .send(RuntimeAction::CaptureLine(
+            Arc::new(buffer_name),
+            Arc::new(StyledLine::from_output_str(&text)),
+        ))
+        .ok();
+}
+
+fn set_gauge(state: &mut OpState, id: String, gauge: Option<Gauge>) {
+    state
+        .borrow::<UnboundedSender<RuntimeAction>>()
+        .send(RuntimeAction::SetGauge(Arc::new(id), gauge))
+        .ok();
+}
+
+/// Shows a bar in the status bar, replacing any gauge with the same id
+#[op2]
+fn op_smudgy_ui_set_gauge(
+    state: &mut OpState,
+    #[string] id: String,
+    #[serde] options: GaugeOptions,
+) -> Result<(), AnyError> {
+    let color = options
+        .color
+        .filter(|color| !color.is_empty())
+        .map(|color| parse_hex_color(&color))
+        .transpose()?;
+    let gauge = Gauge::bar(
+        options.label.unwrap_or_default(),
+        options.value,
+        options.max,
+        color,
+        options.order.unwrap_or_default(),
+    );
+    set_gauge(state, id, Some(gauge));
+    Ok(())
+}
+
+/// Shows text in the status bar, replacing any gauge with the same id
+#[op2]
+fn op_smudgy_ui_set_status(
+    state: &mut OpState,
+    #[string] id: String,
+    #[string] text: String,
+    order: i32,
+) {
+    set_gauge(state, id, Some(Gauge::text(text, order)));
+}
+
+#[op2]
+fn op_smudgy_ui_clear_gauge(state: &mut OpState, #[string] id: String) {
+    set_gauge(state, id, None);
+}
+
+fn perform_line_operation(state: &mut OpState, operation: LineOperation) {
+    state
+        .borrow::<UnboundedSender<RuntimeAction>>()
+        .send(RuntimeAction::PerformLineOperation(operation))
+        .ok();
+}
+
+#[op2]
+fn op_smudgy_lines_highlight(
+    state: &mut OpState,
+    #[string] color: String,
+    start_line: u32,
+    end_line: u32,
+) -> Result<(), AnyError> {
+    let color = parse_hex_color(&color)?;
+    let (start_line, end_line) = (start_line as usize, end_line as usize);
+    perform_line_operation(
+        state,
+        if start_line == end_line {
+            LineOperation::Highlight {
+                line: start_line,
+                color,
+            }
+        } else {
+            LineOperation::HighlightRange {
+                start_line,
+                end_line,
+                color,
+            }
+        },
+    );
+    Ok(())
+}
+
+/// The byte ranges `regex` matches in `text`, with touching or overlapping ones merged
+fn match_ranges(regex: &Regex, text: &str) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for found in regex.find_iter(text).filter(|found| !found.is_empty()) {
+        match ranges.last_mut() {
+            Some(last) if found.start() <= last.end => last.end = last.end.max(found.end()),
+            _ => ranges.push(found.range()),
+        }
+    }
+    ranges
+}
+
+/// Highlights every match of `pattern` in the most recent line, so scripts needn't work out
+/// ranges themselves. Matches always start and end on character boundaries.
+#[op2]
+fn op_smudgy_highlight_pattern(
+    state: &mut OpState,
+    #[string] pattern: String,
+    #[string] color: String,
+) -> Result<(), AnyError> {
+    let regex = Regex::new(&pattern)?;
+    let color = parse_hex_color(&color)?;
+    let Some(line) = state
+        .borrow::<Arc<Mutex<IncomingLineHistory>>>()
+        .lock()
+        .unwrap()
+        .last_line()
+    else {
+        return Ok(());
+    };
+    for range in match_ranges(&regex, &line.text) {
+        perform_line_operation(
+            state,
+            LineOperation::HighlightText {
+                line: 0,
+                range,
+                color,
+            },
+        );
+    }
+    Ok(())
+}
+
+#[op2]
+fn op_smudgy_lines_replace(state: &mut OpState, line: u32, #[string] text: String) {
+    perform_line_operation(
+        state,
+        LineOperation::Replace {
+            line: line as usize,
+            with: Arc::new(StyledLine::from_output_str(&text)),
+        },
+    );
+}
+
+#[op2]
+fn op_smudgy_lines_remove(state: &mut OpState, start_line: u32, end_line: u32) {
+    let (start_line, end_line) = (start_line as usize, end_line as usize);
+    perform_line_operation(
+        state,
+        if start_line == end_line {
+            LineOperation::Remove { line: start_line }
+        } else {
+            LineOperation::RemoveRange {
+                start_line,
+                end_line,
+            }
+        },
+    );
+}
+
+#[op2]
+fn op_smudgy_sound_play(
+    state: &mut OpState,
+    #[string] name: String,
+    #[serde] options: Option<PlayOptions>,
+) {
+    let options = options.unwrap_or_default();
+    state
+        .borrow::<SoundContext>()
+        .play(&name, options.volume.unwrap_or(1.0), options.id);
+}
+
+#[op2]
+fn op_smudgy_sound_stop(state: &mut OpState, #[string] id: String) {
+    state.borrow::<SoundContext>().stop(id);
+}
+
+#[op2]
+fn op_smudgy_session_on(
+    state: &mut OpState,
+    #[string] event: String,
+    #[global] callback: v8::Global<v8::Function>,
+) -> Result<(), AnyError> {
+    let event = LifecycleEvent::from_name(&event)?;
+    state
+        .borrow_mut::<LifecycleCallbacks>()
+        .register(event, callback);
+    Ok(())
+}
+
+#[op2]
+#[string]
+fn op_smudgy_get_input_line(state: &mut OpState) -> String {
+    state.borrow::<InputLine>().get()
+}
+
+#[op2]
+fn op_smudgy_set_input_line(state: &mut OpState, #[string] text: String) {
+    // Reads straight after this see the new text, even before the UI has caught up
+    state.borrow::<InputLine>().set(&text);
+    state
+        .borrow::<UnboundedSender<RuntimeAction>>()
+        .send(RuntimeAction::SetInputLine(Arc::new(text)))
+        .ok();
+}
+
+/// Sends a recorded macro's commands in order. They're sent as they were typed, without alias
+/// processing, since aliases can't run while a script is.
+#[op2]
+fn op_smudgy_macro_play(state: &mut OpState, #[string] name: String) -> Result<(), AnyError> {
+    let commands = state
+        .borrow::<Macros>()
+        .commands(&name)
+        .ok_or_else(|| anyhow!("No macro named '{name}'"))?;
+    let tx = state.borrow::<UnboundedSender<RuntimeAction>>();
+    for command in commands {
+        tx.send(RuntimeAction::SendRaw(Arc::new(command))).ok();
+    }
+    Ok(())
+}
+
+#[op2]
+#[serde]
+fn op_smudgy_macro_list(state: &mut OpState) -> Vec<String> {
+    state
+        .borrow::<Macros>()
+        .list()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// How many commands are held back, waiting for the profile's rate limit to allow them
+#[op2]
+fn op_smudgy_send_queue_length(state: &mut OpState) -> u32 {
+    state.borrow::<SendQueue>().len() as u32
+}
+
+/// Sends a line once `millis` have passed, unless the connection closes first
+#[op2]
+fn op_smudgy_send_after(state: &mut OpState, millis: u32, #[string] line: String) {
+    state.borrow_mut::<DelayedActions>().schedule(
+        Duration::from_millis(millis.into()),
+        DelayedAction::Send(line),
+    );
+}
+
+/// Calls `callback` once `millis` have passed, unless the connection closes first
+#[op2]
+fn op_smudgy_call_after(
+    state: &mut OpState,
+    millis: u32,
+    #[global] callback: v8::Global<v8::Function>,
+) {
+    state.borrow_mut::<DelayedActions>().schedule(
+        Duration::from_millis(millis.into()),
+        DelayedAction::Call(callback),
+    );
+}
+
+/// The trigger and alias profiling counters, as `#stats` shows them. They're only counted while
+/// profiling is on, and start over whenever the triggers or aliases change.
+#[op2]
+#[serde]
+fn op_smudgy_stats(state: &mut OpState) -> StatsReport {
+    state.borrow::<Arc<TriggerStats>>().snapshot()
+}
+
+/// Sends a line from every open session, or only those connected with `profile`, returning how
+/// many it was sent from
+#[op2]
+fn op_smudgy_broadcast(#[string] line: String, #[string] profile: String) -> u32 {
+    let profile = (!profile.is_empty()).then_some(profile.as_str());
+    registry::broadcast(&line, profile) as u32
+}
+
+deno_core::extension!(
+    smudgy,
+    ops = [
+        op_smudgy_version,
+        op_smudgy_dice_roll,
+        op_smudgy_notify,
+        op_smudgy_capture,
+        op_smudgy_ui_set_gauge,
+        op_smudgy_ui_set_status,
+        op_smudgy_ui_clear_gauge,
+        op_smudgy_lines_highlight,
+        op_smudgy_highlight_pattern,
+        op_smudgy_lines_replace,
+        op_smudgy_lines_remove,
+        op_smudgy_sound_play,
+        op_smudgy_sound_stop,
+        op_smudgy_session_on,
+        op_smudgy_get_input_line,
+        op_smudgy_set_input_line,
+        op_smudgy_macro_play,
+        op_smudgy_macro_list,
+        op_smudgy_broadcast,
+        op_smudgy_send_after,
+        op_smudgy_call_after,
+        op_smudgy_send_queue_length,
+        op_smudgy_stats
+    ],
+    state = |state| {
+        state.put(LifecycleCallbacks::default());
+        state.put(DelayedActions::default());
+        // Replaced by the session's own when the runtime starts
+        state.put(InputLine::default());
+        state.put(Arc::new(Mutex::new(IncomingLineHistory::new(1))));
+        state.put(Macros::default());
+        state.put(SendQueue::default());
+        state.put(Arc::new(TriggerStats::default()));
+    }
+);
+
+#[cfg(test)]
+mod tests {
+    use deno_core::{JsRuntime, RuntimeOptions};
+
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+
+        let result = deno
+            .execute_script(
+                "[test]",
+                "JSON.stringify(Deno.core.ops.op_smudgy_version())",
+            )
+            .unwrap();
+        let json = {
+            let scope = &mut deno.handle_scope();
+            v8::Local::new(scope, result).to_rust_string_lossy(scope)
+        };
+        let version: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        for key in ["version", "buildName", "buildTime", "regexBackend"] {
+            assert!(version[key].is_string(), "missing {key} in {json}");
+        }
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!env!("CARGO_PKG_VERSION").is_empty());
+    }
+
+    #[test]
+    fn test_notify_queues_an_action() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        deno.op_state().borrow_mut().put(tx);
+
+        deno.execute_script(
+            "[test]",
+            r#"Deno.core.ops.op_smudgy_notify("Gandalf", "Gandalf tells you 'fly'");"#,
+        )
+        .unwrap();
+
+        match rx.try_recv().unwrap() {
+            RuntimeAction::Notify(title, body) => {
+                assert_eq!(title.as_str(), "Gandalf");
+                assert_eq!(body.as_str(), "Gandalf tells you 'fly'");
+            }
+            _ => panic!("expected a notification"),
+        }
+    }
+
+    #[test]
+    fn test_set_gauge_queues_an_action() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        deno.op_state().borrow_mut().put(tx);
+
+        deno.execute_script(
+            "[test]",
+            r##"Deno.core.ops.op_smudgy_ui_set_gauge("hp", { label: "HP", value: 50, max: 200, color: "#800000" });"##,
+        )
+        .unwrap();
+        assert!(deno
+            .execute_script(
+                "[test]",
+                r#"Deno.core.ops.op_smudgy_ui_set_gauge("hp", { value: 50, color: "red" });"#,
+            )
+            .is_err());
+        deno.execute_script("[test]", r#"Deno.core.ops.op_smudgy_ui_clear_gauge("hp");"#)
+            .unwrap();
+
+        match rx.try_recv().unwrap() {
+            RuntimeAction::SetGauge(id, Some(gauge)) => {
+                assert_eq!(id.as_str(), "hp");
+                assert_eq!(gauge.value.text, "50/200");
+                assert_eq!(gauge.value.fraction, Some(0.25));
+                assert_eq!(
+                    gauge.value.color,
+                    Some(slint::Color::from_rgb_u8(128, 0, 0))
+                );
+            }
+            _ => panic!("expected a gauge to be set"),
+        }
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            RuntimeAction::SetGauge(id, None) if id.as_str() == "hp"
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_highlight_queues_a_range_operation() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        deno.op_state().borrow_mut().put(tx);
+
+        deno.execute_script(
+            "[test]",
+            r##"Deno.core.ops.op_smudgy_lines_highlight("#800000", 0, 2);"##,
+        )
+        .unwrap();
+
+        match rx.try_recv().unwrap() {
+            RuntimeAction::PerformLineOperation(LineOperation::HighlightRange {
+                start_line,
+                end_line,
+                color,
+            }) => {
+                assert_eq!((start_line, end_line), (0, 2));
+                assert_eq!(color, slint::Color::from_rgb_u8(128, 0, 0));
+            }
+            _ => panic!("expected a highlight over a range of lines"),
+        }
+    }
+
+    #[test]
+    fn test_highlight_pattern_queues_a_range_per_match() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        let history = Arc::new(Mutex::new(IncomingLineHistory::new(10)));
+        history
+            .lock()
+            .unwrap()
+            .extend_line(Arc::new(StyledLine::from_output_str(
+                "The orc hits you. The orc misses you.",
+            )));
+        deno.op_state().borrow_mut().put(tx);
+        deno.op_state().borrow_mut().put(history);
+
+        deno.execute_script(
+            "[test]",
+            r##"Deno.core.ops.op_smudgy_highlight_pattern("\\borc\\b", "#800000");"##,
+        )
+        .unwrap();
+
+        let mut ranges = Vec::new();
+        while let Ok(action) = rx.try_recv() {
+            match action {
+                RuntimeAction::PerformLineOperation(LineOperation::HighlightText {
+                    line: 0,
+                    range,
+                    ..
+                }) => ranges.push(range),
+                _ => panic!("expected highlights of the current line"),
+            }
+        }
+        assert_eq!(ranges, vec![4..7, 22..25]);
+    }
+
+    #[test]
+    fn test_match_ranges_merge() {
+        let regex = Regex::new("ab|ba|x").unwrap();
+        assert_eq!(match_ranges(&regex, "abba x x"), vec![0..4, 5..6, 7..8]);
+        assert_eq!(match_ranges(&Regex::new("b*").unwrap(), "abb"), vec![1..3]);
+    }
+
+    #[test]
+    fn test_set_input_line_queues_an_action() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        let input_line = InputLine::default();
+        input_line.set("kill ");
+        deno.op_state().borrow_mut().put(tx);
+        deno.op_state().borrow_mut().put(input_line.clone());
+
+        let result = deno
+            .execute_script(
+                "[test]",
+                r#"
+                const before = Deno.core.ops.op_smudgy_get_input_line();
+                Deno.core.ops.op_smudgy_set_input_line(before + "orc");
+                Deno.core.ops.op_smudgy_get_input_line();
+                "#,
+            )
+            .unwrap();
+        let read_back = {
+            let scope = &mut deno.handle_scope();
+            v8::Local::new(scope, result).to_rust_string_lossy(scope)
+        };
+
+        // The new text can be read back straight away, without waiting on the UI
+        assert_eq!(read_back, "kill orc");
+        assert_eq!(input_line.get(), "kill orc");
+        match rx.try_recv().unwrap() {
+            RuntimeAction::SetInputLine(text) => assert_eq!(text.as_str(), "kill orc"),
+            _ => panic!("expected the input line to be set"),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_macro_play_sends_commands_in_order() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut deno = JsRuntime::new(RuntimeOptions {
+            extensions: vec![smudgy::init_ops()],
+            ..Default::default()
+        });
+        let macros = Macros::default();
+        macros.start_recording("heal");
+        for command in ["rem sword", "cast 'heal' self", "wield sword"] {
+            macros.record(command);
+        }
+        macros.stop_recording().unwrap();
+        deno.op_state().borrow_mut().put(tx);
+        deno.op_state().borrow_mut().put(macros);
+
+        deno.execute_script("[test]", r#"Deno.core.ops.op_smudgy_macro_play("heal");"#)
+            .unwrap();
+        assert!(deno
+            .execute_script("[test]", r#"Deno.core.ops.op_smudgy_macro_play("nope");"#)
+            .is_err());
+
+        let mut sent = Vec::new();
+        while let Ok(action) = rx.try_recv() {
+            match action {
+                RuntimeAction::SendRaw(command) => sent.push(command.to_string()),
+                _ => panic!("expected only commands to be sent"),
+            }
+        }
+        assert_eq!(sent, vec!["rem sword", "cast 'heal' self", "wield sword"]);
+    }
+}
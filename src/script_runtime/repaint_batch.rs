@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+use super::RuntimeAction;
+
+/// The most redraws that are folded into one before the UI is woken regardless
+pub const MAX_BATCH_SIZE: usize = 512;
+/// The longest a redraw is held back while more actions keep arriving
+pub const MAX_BATCH_LATENCY: Duration = Duration::from_millis(16);
+
+/// Coalesces the redraws that appended lines ask for, so that a burst of output wakes the UI
+/// once instead of once per line. Redraws are held back only while more actions are already
+/// queued, and never for longer than `MAX_BATCH_LATENCY`.
+#[derive(Debug, Default)]
+pub struct RepaintBatch {
+    pending: usize,
+    oldest: Option<Instant>,
+}
+
+impl RepaintBatch {
+    pub fn request(&mut self, now: Instant) {
+        if self.pending == 0 {
+            self.oldest = Some(now);
+        }
+        self.pending += 1;
+    }
+
+    /// Whether the pending redraws should go out now. `more_queued` is whether another action
+    /// is waiting to be handled, since the loop won't block until it has been.
+    pub fn should_flush(&self, now: Instant, more_queued: bool) -> bool {
+        match self.oldest {
+            None => false,
+            Some(oldest) => {
+                !more_queued
+                    || self.pending >= MAX_BATCH_SIZE
+                    || now.duration_since(oldest) >= MAX_BATCH_LATENCY
+            }
+        }
+    }
+
+    /// Empties the batch, returning how many redraws it covered
+    pub fn take(&mut self) -> usize {
+        self.oldest = None;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Whether an action's redraw goes out straight away, along with any held back. Prompts and
+/// partial lines shouldn't visually lag, and an explicit request is made at the end of each
+/// read from the server.
+pub fn forces_flush(action: &RuntimeAction) -> bool {
+    matches!(
+        action,
+        RuntimeAction::RequestRepaint
+            | RuntimeAction::UpdatePrompt(_)
+            | RuntimeAction::PassthroughPartialLine(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::session::StyledLine;
+
+    /// Requests a redraw per line as the runtime loop would, with more actions queued behind
+    /// every line but the last, returning the size of each batch that was flushed
+    fn run(lines: usize, now: impl Fn(usize) -> Instant) -> Vec<usize> {
+        let mut batch = RepaintBatch::default();
+        let mut flushed = Vec::new();
+        for line in 0..lines {
+            batch.request(now(line));
+            if batch.should_flush(now(line), line + 1 < lines) {
+                flushed.push(batch.take());
+            }
+        }
+        flushed
+    }
+
+    #[test]
+    fn test_burst_is_one_redraw() {
+        let start = Instant::now();
+
+        assert_eq!(run(200, |_| start), vec![200]);
+    }
+
+    #[test]
+    fn test_batches_are_capped() {
+        let start = Instant::now();
+
+        assert_eq!(
+            run(1200, |_| start),
+            vec![MAX_BATCH_SIZE, MAX_BATCH_SIZE, 1200 - 2 * MAX_BATCH_SIZE]
+        );
+    }
+
+    #[test]
+    fn test_batches_are_flushed_after_the_latency() {
+        let start = Instant::now();
+        // A line every 5ms, so each batch goes out with the line that arrives 20ms after its first
+        let flushed = run(12, |line| start + Duration::from_millis(5 * line as u64));
+
+        assert_eq!(flushed, vec![5, 5, 2]);
+    }
+
+    #[test]
+    fn test_repaint_requests_and_prompts_force_a_flush() {
+        let line = Arc::new(StyledLine::from_output_str("HP: 100>"));
+
+        assert!(forces_flush(&RuntimeAction::RequestRepaint));
+        assert!(forces_flush(&RuntimeAction::UpdatePrompt(line.clone())));
+        assert!(forces_flush(&RuntimeAction::PassthroughPartialLine(
+            line.clone()
+        )));
+        assert!(!forces_flush(&RuntimeAction::PassthroughCompleteLine(line)));
+    }
+
+    #[test]
+    fn test_nothing_to_flush() {
+        let mut batch = RepaintBatch::default();
+
+        assert!(!batch.should_flush(Instant::now(), false));
+        assert_eq!(batch.take(), 0);
+    }
+}
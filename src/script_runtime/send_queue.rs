@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, time::Instant};
+
+/// A line waiting to go out, and whether it's echoed when it does
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedSend {
+    pub line: String,
+    pub echo: bool,
+}
+
+/// Holds back commands so no more than `rate` a second go out, after an initial `burst`, for
+/// servers that disconnect anyone sending faster. Commands keep the order they were queued in.
+/// A rate of 0 lets everything straight through.
+#[derive(Debug)]
+pub struct SendQueue {
+    rate: u32,
+    burst: u32,
+    tokens: f64,
+    refilled_at: Option<Instant>,
+    pending: VecDeque<QueuedSend>,
+    reported_len: usize,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        SendQueue::new(0, 1)
+    }
+}
+
+impl SendQueue {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        let burst = burst.max(1);
+        SendQueue {
+            rate,
+            burst,
+            tokens: burst as f64,
+            refilled_at: None,
+            pending: VecDeque::new(),
+            reported_len: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: &str, echo: bool) {
+        self.pending.push_back(QueuedSend {
+            line: line.to_string(),
+            echo,
+        });
+    }
+
+    /// Takes the commands that may go out by `now`, oldest first
+    pub fn poll(&mut self, now: Instant) -> Vec<QueuedSend> {
+        if self.rate == 0 {
+            return self.pending.drain(..).collect();
+        }
+
+        if let Some(refilled_at) = self.refilled_at {
+            let elapsed = now.saturating_duration_since(refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+        }
+        self.refilled_at = Some(now);
+
+        let allowed = (self.tokens.floor() as usize).min(self.pending.len());
+        self.tokens -= allowed as f64;
+        self.pending.drain(..allowed).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drops every command still waiting, returning how many there were
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.pending.len();
+        self.pending.clear();
+        dropped
+    }
+
+    /// The number waiting, if it's changed since this was last asked
+    pub fn take_len_change(&mut self) -> Option<usize> {
+        let len = self.pending.len();
+        (len != self.reported_len).then(|| {
+            self.reported_len = len;
+            len
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn lines(sends: Vec<QueuedSend>) -> Vec<String> {
+        sends.into_iter().map(|send| send.line).collect()
+    }
+
+    #[test]
+    fn test_unlimited_sends_everything() {
+        let mut queue = SendQueue::default();
+        for line in ["n", "e", "s"] {
+            queue.push(line, true);
+        }
+        assert_eq!(lines(queue.poll(Instant::now())), vec!["n", "e", "s"]);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_burst_then_paced_in_order() {
+        let start = Instant::now();
+        let mut queue = SendQueue::new(2, 3);
+        for line in ["1", "2", "3", "4", "5", "6"] {
+            queue.push(line, true);
+        }
+
+        assert_eq!(lines(queue.poll(start)), vec!["1", "2", "3"]);
+        assert_eq!(queue.take_len_change(), Some(3));
+        assert!(queue.poll(start + Duration::from_millis(400)).is_empty());
+        assert_eq!(
+            lines(queue.poll(start + Duration::from_millis(600))),
+            vec!["4"]
+        );
+        assert_eq!(
+            lines(queue.poll(start + Duration::from_millis(1100))),
+            vec!["5"]
+        );
+        assert_eq!(queue.take_len_change(), Some(1));
+        assert_eq!(queue.take_len_change(), None);
+
+        // Allowance doesn't build up past the burst while idle
+        assert_eq!(
+            lines(queue.poll(start + Duration::from_secs(60))),
+            vec!["6"]
+        );
+        for line in ["7", "8", "9", "10"] {
+            queue.push(line, true);
+        }
+        assert_eq!(
+            lines(queue.poll(start + Duration::from_secs(60))),
+            vec!["7", "8"]
+        );
+        assert_eq!(queue.clear(), 2);
+    }
+}
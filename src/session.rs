@@ -1,91 +1,236 @@
 use std::{
-    collections::HashSet,
     num::{NonZeroU32},
     rc::Rc,
-    sync::{Arc, Mutex},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
 };
 
 use crate::{
-    hotkey::{HotkeyManager, HotkeyResult}, models::Profile, script_runtime::ScriptRuntime, trigger::TriggerManager, SessionKeyPressResponse, SessionKeyPressResponseType
+    hotkey::{HotkeyManager, HotkeyResult}, macros::Macros, models::{Character, MultiLinePaste, Profile, SavedSession, Settings}, notification::{NotificationPolicy, Notifier}, registry, script_runtime::{InputLine, RuntimeAction, ScriptRuntime, SendQueue}, sound::SoundContext, trigger::{Highlights, SessionCommand, TriggerManager, TriggerStats}, SessionKeyPressResponse, SessionKeyPressResponseType
 };
 
 use command_history::CommandHistory;
-use connection::Connection;
+use connection::{ConnectScripts, Connection};
+use find::{Direction, FindMatch};
 use regex::Regex;
+use selection::Selection;
+use styled_line::LinkAction;
 use slint::VecModel;
 use terminal_view::TerminalView;
 
 use crate::{AutocompleteResult, MainWindow};
 
+mod activity;
+mod ansi_palette;
 mod command_history;
+mod completion;
 mod connection;
+mod debug_capture;
+mod find;
+mod login;
 pub mod incoming_line_history;
+pub mod output_sink;
+mod selection;
+mod status_bar;
 mod styled_line;
 mod terminal_view;
 
 use incoming_line_history::IncomingLineHistory;
-pub use styled_line::StyledLine;
-pub use terminal_view::ViewAction;
+use output_sink::OutputSink;
+pub use activity::SessionEvent;
+pub use ansi_palette::{parse_hex_color, AnsiPalette};
+pub use login::{LoginSequence, LoginStep};
+pub use status_bar::{Gauge, StatusBinding};
+pub use styled_line::{AnsiColor, Color, Style, StyledLine};
+pub use terminal_view::{LineOperation, ViewAction};
+
+// How much Ctrl+= / Ctrl+- change the font size by
+const FONT_SIZE_STEP: f32 = 1.0;
+
+// How tall the input area may grow in multi-line mode
+const MAX_INPUT_LINES: usize = 8;
+
+// The macro Ctrl+Shift+R records into
+const QUICK_MACRO_NAME: &str = "quick";
+
+// How many captured lines are kept, and how much of the terminal's height they're shown in
+const CAPTURE_LINES: usize = 1_000;
+const CAPTURE_HEIGHT_DIVISOR: u32 = 4;
+
+// How long closing a session waits for its scripts and log to finish up
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
 
 // Regex which matches on word boundaries
 static BOUNDARY_REGEX: std::sync::LazyLock<Regex> =
     std::sync::LazyLock::new(|| Regex::new(r"\b").unwrap());
 
+#[derive(Debug, Default)]
+struct FindState {
+    query: String,
+    matches: Vec<FindMatch>,
+    current: Option<usize>,
+}
+
 #[derive(Debug, Default)]
 struct AutocompleteState {
     autocomplete_prefix: String,
     text_prior_to_autocomplete: String,
-    history: HashSet<String>,
 }
 
 pub struct Session {
     pub id: Arc<Mutex<i32>>,
     incoming_line_history: Arc<Mutex<IncomingLineHistory>>,
     view: Rc<TerminalView>,
+    /// Lines triggers have diverted out of the main buffer, shown above it once there are any
+    capture_view: Rc<TerminalView>,
     trigger_manager: Arc<TriggerManager>,
+    /// Built-in commands, like `#reconnect`, that the trigger manager hands back to the session
+    session_command_rx: tokio::sync::mpsc::UnboundedReceiver<SessionCommand>,
     profile: Profile,
+    character_name: String,
     synced_width: NonZeroU32,
     synced_height: NonZeroU32,
     autocomplete_state: AutocompleteState,
     command_history: CommandHistory,
     hotkey_manager: HotkeyManager,
     script_runtime: Arc<ScriptRuntime>,
+    /// The input area's text, shared with scripts
+    input_line: InputLine,
+    /// Typed commands recorded for playback, shared with scripts
+    macros: Macros,
+    input_lines: usize,
+    connect_scripts: ConnectScripts,
+    auto_reconnect: bool,
+    /// Lets other sessions and scripts broadcast commands through this one
+    _registration: registry::Registration,
+    /// The text being dragged over, copied to the clipboard once the mouse is released
+    selection: Option<Selection>,
+    /// Opened on first copy, since some platforms keep it open for as long as it's held
+    clipboard: Option<arboard::Clipboard>,
+    multi_line_paste: MultiLinePaste,
+    find_state: FindState,
 
     // ----
     connection: Connection,
 }
 
 impl Session {
-    pub fn new(id: i32, weak_window: slint::Weak<MainWindow>, profile: Profile) -> Session {
+    pub fn new(
+        id: i32,
+        weak_window: slint::Weak<MainWindow>,
+        profile: Profile,
+        character: &Character,
+    ) -> Session {
         let id = Arc::new(Mutex::new(id));
-        let view = Rc::new(TerminalView::new(weak_window.clone()));
-
-        let incoming_line_history = Arc::new(Mutex::new(IncomingLineHistory::new()));
+        let settings = Settings::load();
+        let view = Rc::new(TerminalView::new(
+            weak_window.clone(),
+            profile.font_size(),
+            settings.scrollback_lines,
+            settings.show_timestamps,
+            settings.soft_wrap,
+        ));
+        let capture_view = Rc::new(TerminalView::new(
+            weak_window.clone(),
+            profile.font_size(),
+            CAPTURE_LINES,
+            settings.show_timestamps,
+            settings.soft_wrap,
+        ));
+        view.set_palette(profile.ansi_palette());
+        capture_view.set_palette(profile.ansi_palette());
+        view.set_status_bindings(profile.status_bar().to_vec());
+
+        let incoming_line_history = Arc::new(Mutex::new(IncomingLineHistory::new(
+            settings.scrollback_lines,
+        )));
+        let input_line = InputLine::default();
+        let macros = Macros::in_dir(profile.dir());
+        let trigger_stats = Arc::new(TriggerStats::default());
+        let (session_command_tx, session_command_rx) = tokio::sync::mpsc::unbounded_channel();
         let script_runtime = Arc::new(ScriptRuntime::new(
             view.tx.clone(),
+            capture_view.tx.clone(),
             weak_window.clone(),
             incoming_line_history.clone(),
+            (!profile.output_sink_path().is_empty())
+                .then(|| OutputSink::new(profile.output_sink_path().into())),
+            Notifier::new(
+                weak_window.clone(),
+                NotificationPolicy::from(&profile),
+                character.name().to_string(),
+            ),
+            SoundContext::new(
+                profile.sounds_enabled(),
+                profile.sound_volume(),
+                profile.dir(),
+            ),
+            input_line.clone(),
+            macros.clone(),
+            SendQueue::new(profile.max_commands_per_second(), profile.command_burst()),
+            trigger_stats.clone(),
         ));
 
-        let trigger_manager = Arc::new(TriggerManager::new(script_runtime.tx()));
+        let trigger_manager = Arc::new(
+            TriggerManager::new(
+                script_runtime.tx(),
+                profile.command_separator(),
+                profile.prompt_regex(),
+            )
+            .with_highlights(Highlights::in_dir(profile.dir()))
+            .with_stats(trigger_stats)
+            .with_session_commands(session_command_tx),
+        );
 
         let connection = Connection::new(trigger_manager.clone(), script_runtime.clone());
 
-        let hotkey_manager = HotkeyManager::new(script_runtime.clone());
+        let hotkey_manager =
+            HotkeyManager::new(script_runtime.clone(), profile.dir(), character.dir());
 
         Self {
             id,
             view,
+            capture_view,
             incoming_line_history,
             profile: profile.clone(),
+            character_name: character.name().to_string(),
             synced_width: NonZeroU32::MIN,
             synced_height: NonZeroU32::MIN,
             autocomplete_state: AutocompleteState::default(),
             command_history: CommandHistory::default(),
             hotkey_manager,
             trigger_manager,
+            session_command_rx,
             connection,
-            script_runtime
+            script_runtime,
+            input_line,
+            macros,
+            input_lines: 1,
+            _registration: registry::register(
+                profile.name(),
+                profile.command_separator(),
+                script_runtime.tx(),
+            ),
+            connect_scripts: ConnectScripts {
+                send_on_connect: character.send_on_connect().to_string(),
+                send_on_connect_hidden: character.send_on_connect_hidden(),
+                on_reconnect: profile.on_reconnect().to_string(),
+                command_separator: profile.command_separator(),
+                login_steps: profile.login_steps().to_vec(),
+            },
+            auto_reconnect: profile.auto_reconnect(),
+            selection: None,
+            clipboard: None,
+            multi_line_paste: settings.multi_line_paste,
+            find_state: FindState::default(),
+        }
+    }
+
+    /// The profile and character this session was opened for, as remembered between launches
+    pub fn saved_session(&self) -> SavedSession {
+        SavedSession {
+            profile: self.profile.name().to_string(),
+            character: self.character_name.clone(),
         }
     }
 
@@ -99,23 +244,377 @@ impl Session {
         let nz_height = NonZeroU32::new(height).unwrap_or(NonZeroU32::MIN);
 
         if self.synced_width != nz_width || self.synced_height != nz_height {
+            // Once anything has been captured, the capture pane takes the top of the terminal
+            self.capture_view.handle_incoming_lines();
+            let nz_height = if self.capture_view.is_empty() {
+                nz_height
+            } else {
+                let capture_height = height / CAPTURE_HEIGHT_DIVISOR;
+                self.capture_view.set_viewable_size(
+                    nz_width,
+                    NonZeroU32::new(capture_height).unwrap_or(NonZeroU32::MIN),
+                );
+                NonZeroU32::new(height - capture_height).unwrap_or(NonZeroU32::MIN)
+            };
+
             self.view.set_viewable_size(nz_width, nz_height);
             self.view.handle_incoming_lines();
+
+            let (columns, rows) = self.view.size_in_characters(width, nz_height.get());
+            self.connection.set_terminal_size(columns, rows);
+        }
+    }
+
+    /// Number of lines the input area currently occupies
+    pub fn input_lines(&self) -> usize {
+        self.input_lines
+    }
+
+    pub fn on_input_edited(&mut self, input_line: &str, multi_line: bool) -> i32 {
+        self.input_line.set(input_line);
+        self.input_lines = if multi_line {
+            input_line.split('\n').count().clamp(1, MAX_INPUT_LINES)
+        } else {
+            1
+        };
+        self.input_lines as i32
+    }
+
+    pub fn on_focus_changed(&self, focused: bool) {
+        self.view.handle_session_event(if focused {
+            SessionEvent::Focused
+        } else {
+            SessionEvent::Unfocused
+        });
+    }
+
+    pub fn on_selection_started(&mut self, x: f32, y_from_bottom: f32) {
+        self.selection = self.view.hit_test(x, y_from_bottom).map(Selection::new);
+    }
+
+    pub fn on_selection_extended(&mut self, x: f32, y_from_bottom: f32) {
+        if let (Some(selection), Some(point)) =
+            (self.selection.as_mut(), self.view.hit_test(x, y_from_bottom))
+        {
+            selection.head = point;
+        }
+    }
+
+    pub fn on_selection_finished(&mut self) {
+        let Some(selection) = self.selection.take() else {
+            return;
+        };
+        if selection.is_empty() {
+            // A click rather than a drag, which activates any link under it
+            if let Some(link) = self.view.link_at(selection.anchor) {
+                self.activate_link(link);
+            }
+            return;
+        }
+
+        let text = self.view.selected_text(&selection);
+        if let Err(e) = self.copy_to_clipboard(text) {
+            warn!("Could not copy the selection to the clipboard: {e:?}");
         }
     }
 
+    fn activate_link(&self, link: LinkAction) {
+        match link {
+            LinkAction::Send(command) => self.trigger_manager.process_outgoing_line(&command),
+            LinkAction::Url(url) => {
+                if let Err(e) = open_url(&url) {
+                    warn!("Could not open {url}: {e:?}");
+                }
+            }
+        }
+    }
+
+    fn copy_to_clipboard(&mut self, text: String) -> Result<(), arboard::Error> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(arboard::Clipboard::new()?);
+        }
+        self.clipboard.as_mut().unwrap().set_text(text)
+    }
+
+    /// Pastes text with more than one line the way the settings say to, rather than letting the
+    /// input take it as it is. Returns None for anything else, which the input pastes itself.
+    fn on_paste(&mut self, input_line: &str) -> Option<SessionKeyPressResponse> {
+        if self.view.is_input_masked() {
+            return None;
+        }
+        if self.clipboard.is_none() {
+            self.clipboard = arboard::Clipboard::new().ok();
+        }
+        let text = self.clipboard.as_mut()?.get_text().ok()?;
+        let text = text.replace("\r\n", "\n");
+        let text = text.trim_end_matches('\n');
+        if !text.contains('\n') {
+            return None;
+        }
+
+        let response = match self.multi_line_paste {
+            MultiLinePaste::Send => {
+                self.on_session_accepted(text);
+                SessionKeyPressResponse {
+                    response: SessionKeyPressResponseType::Accept,
+                    str_args: Rc::new(VecModel::from(vec![])).into(),
+                    int_args: Rc::new(VecModel::from(vec![])).into(),
+                }
+            }
+            MultiLinePaste::Edit => SessionKeyPressResponse {
+                response: SessionKeyPressResponseType::EditInput,
+                str_args: Rc::new(VecModel::from(vec![format!("{input_line}{text}").into()]))
+                    .into(),
+                int_args: Rc::new(VecModel::from(vec![])).into(),
+            },
+            MultiLinePaste::Ask => SessionKeyPressResponse {
+                response: SessionKeyPressResponseType::ConfirmPaste,
+                str_args: Rc::new(VecModel::from(vec![text.into()])).into(),
+                int_args: Rc::new(VecModel::from(vec![text.lines().count() as i32])).into(),
+            },
+        };
+        Some(response)
+    }
+
+    /// Searches the buffer for the find bar's new text, returning the status to show next to it
+    pub fn on_find_edited(&mut self, query: &str) -> String {
+        if query.is_empty() {
+            self.on_find_closed();
+            return String::new();
+        }
+
+        self.find_state = FindState {
+            query: query.to_string(),
+            matches: self.view.search(query),
+            current: None,
+        };
+        self.view.set_highlights(&self.find_state.matches, None);
+
+        find::status(None, self.find_state.matches.len())
+    }
+
+    /// Moves to the next match (`older` ones are further up the buffer) and scrolls to it
+    pub fn on_find_next(&mut self, older: bool) -> String {
+        if self.find_state.query.is_empty() {
+            return String::new();
+        }
+
+        // Lines may have arrived or scrolled out of the buffer since the last search
+        let matches = self.view.search(&self.find_state.query);
+        if matches != self.find_state.matches {
+            let current = self
+                .find_state
+                .current
+                .and_then(|idx| self.find_state.matches.get(idx))
+                .and_then(|current| matches.iter().position(|found| found == current));
+            self.find_state.matches = matches;
+            self.find_state.current = current;
+        }
+
+        let direction = if older { Direction::Older } else { Direction::Newer };
+        let count = self.find_state.matches.len();
+        self.find_state.current = find::step(self.find_state.current, count, direction);
+        self.view.set_highlights(&self.find_state.matches, self.find_state.current);
+
+        if let Some(current) = self.find_state.current {
+            self.view.scroll_to_line(self.find_state.matches[current].line);
+        }
+
+        find::status(self.find_state.current, count)
+    }
+
+    pub fn on_find_closed(&mut self) {
+        self.find_state = FindState::default();
+        self.view.set_highlights(&[], None);
+    }
+
     pub fn on_session_accepted(&mut self, line: &str) {
-        self.command_history.push(&line);
+        // Keep passwords out of the history and any macro being recorded
+        if !self.view.is_input_masked() {
+            self.command_history.push(&line);
+            if !line.trim_start().starts_with("#macro") {
+                self.macros.record(line);
+            }
+        }
+
         self.trigger_manager.process_outgoing_line(line);
+        self.run_session_commands();
+    }
+
+    /// Runs the built-in commands the trigger manager has handed over, whether they were typed
+    /// or sent by an alias, trigger or script
+    pub fn run_session_commands(&mut self) {
+        while let Ok(command) = self.session_command_rx.try_recv() {
+            self.run_session_command(command);
+        }
+    }
+
+    fn run_session_command(&mut self, command: SessionCommand) {
+        match command {
+            SessionCommand::Reconnect => {
+                self.auto_reconnect = self.profile.auto_reconnect();
+                self.connect();
+            }
+            SessionCommand::Disconnect => {
+                self.auto_reconnect = false;
+                self.connection.disconnect();
+            }
+            SessionCommand::Debug(args) => self.process_debug_command(&args),
+            SessionCommand::All(line) => {
+                let count = registry::broadcast(&line, None);
+                let total = registry::get_all_session_ids().len();
+                if count < total {
+                    let message = format!("Sent to {count} of {total} sessions");
+                    self.script_runtime
+                        .tx()
+                        .send(RuntimeAction::Echo(Arc::new(message)))
+                        .ok();
+                }
+            }
+            SessionCommand::Macro(args) => self.process_macro_command(&args),
+            SessionCommand::Hotkeys(args) => {
+                let result = self.hotkey_manager.process_command(&args);
+                for message in result.unwrap_or_else(|e| vec![format!("{e:#}")]) {
+                    self.script_runtime
+                        .tx()
+                        .send(RuntimeAction::Echo(Arc::new(message)))
+                        .ok();
+                }
+            }
+            SessionCommand::FlushQueue => {
+                self.script_runtime
+                    .tx()
+                    .send(RuntimeAction::FlushSendQueue)
+                    .ok();
+            }
+            SessionCommand::Timestamps(on) => {
+                self.view.set_show_timestamps(on);
+                self.capture_view.set_show_timestamps(on);
+            }
+            SessionCommand::Wrap(on) => {
+                self.view.set_soft_wrap(on);
+                self.capture_view.set_soft_wrap(on);
+            }
+        }
+    }
+
+    /// Handles `#debug capture N`, which snapshots the last N lines as they were received, and
+    /// `#debug replay <file>`, which feeds a snapshot back through the triggers
+    fn process_debug_command(&self, args: &str) {
+        let debug_dir = self.profile.dir().join(debug_capture::DEBUG_DIR);
+        let result = match args.split_once(' ') {
+            Some(("capture", count)) => match count.trim().parse::<usize>() {
+                Ok(count) => {
+                    let lines = self.trigger_manager.recent_raw_lines(count);
+                    debug_capture::write_capture(&debug_dir, &lines).map(|path| {
+                        format!("Captured {} lines to {}", lines.len(), path.display())
+                    })
+                }
+                Err(_) => Err(anyhow::anyhow!("Usage: #debug capture <lines>")),
+            },
+            Some(("replay", file)) => debug_capture::read_capture(&debug_dir, file.trim())
+                .map(|lines| {
+                    debug_capture::replay(&self.trigger_manager, &lines);
+                    format!("Replayed {} lines", lines.len())
+                }),
+            _ => Err(anyhow::anyhow!(
+                "Usage: #debug capture <lines> | #debug replay <file>"
+            )),
+        };
+
+        let message = result.unwrap_or_else(|e| format!("{e:#}"));
+        self.script_runtime
+            .tx()
+            .send(RuntimeAction::Echo(Arc::new(message)))
+            .ok();
+    }
+
+    /// Handles `#macro record <name>`, `#macro stop`, `#macro play <name>`, `#macro list` and
+    /// `#macro delete <name>`. Played commands go through the aliases, as if typed again.
+    fn process_macro_command(&self, args: &str) {
+        let (command, name) = args
+            .split_once(' ')
+            .map_or((args, ""), |(command, name)| (command, name.trim()));
+        let result = match (command, name) {
+            ("record", name) if !name.is_empty() => {
+                self.macros.start_recording(name);
+                Ok(vec![format!("Recording macro '{name}'; #macro stop saves it")])
+            }
+            ("stop", "") => self.stop_macro_recording().map(|message| vec![message]),
+            ("play", name) if !name.is_empty() => match self.macros.commands(name) {
+                Some(commands) => {
+                    for command in commands {
+                        self.trigger_manager.process_outgoing_line(&command);
+                    }
+                    Ok(Vec::new())
+                }
+                None => Err(anyhow::anyhow!("No macro named '{name}'")),
+            },
+            ("list", "") => {
+                let macros = self.macros.list();
+                if macros.is_empty() {
+                    Ok(vec!["No macros".to_string()])
+                } else {
+                    Ok(macros
+                        .into_iter()
+                        .map(|(name, count)| format!("{name}: {count} commands"))
+                        .collect())
+                }
+            }
+            ("delete", name) if !name.is_empty() => self
+                .macros
+                .delete(name)
+                .map(|()| vec![format!("Deleted macro '{name}'")]),
+            _ => Err(anyhow::anyhow!(
+                "Usage: #macro record <name> | stop | play <name> | list | delete <name>"
+            )),
+        };
+
+        for message in result.unwrap_or_else(|e| vec![format!("{e:#}")]) {
+            self.script_runtime
+                .tx()
+                .send(RuntimeAction::Echo(Arc::new(message)))
+                .ok();
+        }
+    }
+
+    fn stop_macro_recording(&self) -> anyhow::Result<String> {
+        Ok(match self.macros.stop_recording()? {
+            Some((name, count)) => format!("Saved macro '{name}' with {count} commands"),
+            None => "No macro is being recorded".to_string(),
+        })
+    }
+
+    /// Ctrl+Shift+R starts recording the quick macro, or stops whichever macro is recording
+    fn toggle_macro_recording(&self) -> SessionKeyPressResponse {
+        let message = if self.macros.recording().is_some() {
+            self.stop_macro_recording().unwrap_or_else(|e| format!("{e:#}"))
+        } else {
+            self.macros.start_recording(QUICK_MACRO_NAME);
+            format!("Recording macro '{QUICK_MACRO_NAME}'; Ctrl+Shift+R again saves it")
+        };
+        self.script_runtime
+            .tx()
+            .send(RuntimeAction::Echo(Arc::new(message)))
+            .ok();
+
+        SessionKeyPressResponse {
+            response: SessionKeyPressResponseType::Accept,
+            str_args: Rc::new(VecModel::from(vec![])).into(),
+            int_args: Rc::new(VecModel::from(vec![])).into(),
+        }
     }
 
     pub fn on_history_up(&mut self, input_line: &str) -> SessionKeyPressResponse {
         match self.command_history.next(input_line) {
-            Some(str) => SessionKeyPressResponse {
-                response: SessionKeyPressResponseType::ReplaceInput,
-                str_args: Rc::new(VecModel::from(vec![str.into()])).into(),
-                int_args: Rc::new(VecModel::from(vec![])).into(),
-            },
+            Some(str) => {
+                self.input_line.set(str);
+                SessionKeyPressResponse {
+                    response: SessionKeyPressResponseType::ReplaceInput,
+                    str_args: Rc::new(VecModel::from(vec![str.into()])).into(),
+                    int_args: Rc::new(VecModel::from(vec![])).into(),
+                }
+            }
             _ => SessionKeyPressResponse {
                 response: SessionKeyPressResponseType::Accept,
                 str_args: Rc::new(VecModel::from(vec![])).into(),
@@ -126,11 +625,14 @@ impl Session {
 
     pub fn on_history_down(&mut self, _input_line: &str) -> SessionKeyPressResponse {
         match self.command_history.prev() {
-            Some(str) => SessionKeyPressResponse {
-                response: SessionKeyPressResponseType::ReplaceInput,
-                str_args: Rc::new(VecModel::from(vec![str.into()])).into(),
-                int_args: Rc::new(VecModel::from(vec![])).into(),
-            },
+            Some(str) => {
+                self.input_line.set(str);
+                SessionKeyPressResponse {
+                    response: SessionKeyPressResponseType::ReplaceInput,
+                    str_args: Rc::new(VecModel::from(vec![str.into()])).into(),
+                    int_args: Rc::new(VecModel::from(vec![])).into(),
+                }
+            }
             _ => SessionKeyPressResponse {
                 response: SessionKeyPressResponseType::Accept,
                 str_args: Rc::new(VecModel::from(vec![])).into(),
@@ -139,6 +641,52 @@ impl Session {
         }
     }
 
+    /// Shows edited settings in this session. Profiles still on the saved default font size follow
+    /// the new one, and with `persist` they're saved with it. The scrollback limit only changes
+    /// with `persist`.
+    pub fn apply_settings(&mut self, saved: &Settings, settings: &Settings, persist: bool) {
+        if self.profile.font_size() == saved.terminal_font_size {
+            self.view.set_font_size(settings.terminal_font_size);
+            self.capture_view.set_font_size(settings.terminal_font_size);
+            if persist {
+                self.profile.set_font_size(settings.terminal_font_size);
+                if let Err(e) = self.profile.save() {
+                    warn!("Could not persist font size: {e:?}");
+                }
+            }
+        }
+
+        // Lowering the limit drops lines for good, so it waits until the settings are saved
+        if persist {
+            self.view.set_max_lines(settings.scrollback_lines);
+            self.incoming_line_history
+                .lock()
+                .unwrap()
+                .set_max_len(settings.scrollback_lines);
+        }
+        for view in [&self.view, &self.capture_view] {
+            view.set_show_timestamps(settings.show_timestamps);
+            view.set_soft_wrap(settings.soft_wrap);
+        }
+        self.multi_line_paste = settings.multi_line_paste;
+    }
+
+    pub fn adjust_font_size(&mut self, delta: f32) -> SessionKeyPressResponse {
+        self.profile.set_font_size(self.profile.font_size() + delta);
+        self.view.set_font_size(self.profile.font_size());
+        self.capture_view.set_font_size(self.profile.font_size());
+
+        if let Err(e) = self.profile.save() {
+            warn!("Could not persist font size: {e:?}");
+        }
+
+        SessionKeyPressResponse {
+            response: SessionKeyPressResponseType::Accept,
+            str_args: Rc::new(VecModel::from(vec![])).into(),
+            int_args: Rc::new(VecModel::from(vec![])).into(),
+        }
+    }
+
     pub fn on_key_pressed(
         &mut self,
         ev: i_slint_core::items::KeyEvent,
@@ -146,6 +694,27 @@ impl Session {
     ) -> SessionKeyPressResponse {
         if ev.modifiers.control {
             println!("{ev:?}");
+
+            if !ev.modifiers.alt && !ev.modifiers.meta {
+                match ev.text.as_str() {
+                    "=" | "+" => return self.adjust_font_size(FONT_SIZE_STEP),
+                    "-" => return self.adjust_font_size(-FONT_SIZE_STEP),
+                    "r" | "R" if ev.modifiers.shift => return self.toggle_macro_recording(),
+                    "v" | "V" => {
+                        if let Some(response) = self.on_paste(input_line) {
+                            return response;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Shift+Insert
+        if ev.modifiers.shift && ev.scancode == 0xe052 {
+            if let Some(response) = self.on_paste(input_line) {
+                return response;
+            }
         }
 
         match self.hotkey_manager.process_keypress(&ev) {
@@ -186,8 +755,6 @@ impl Session {
         continue_from_last_request: bool,
     ) -> AutocompleteResult {
         if !continue_from_last_request {
-            self.autocomplete_state.history.clear();
-
             let all_words: Vec<&str> = line.split_inclusive(&*BOUNDARY_REGEX).collect();
             let last_word = all_words.last().or(Some(&"")).unwrap().trim();
 
@@ -197,20 +764,18 @@ impl Session {
             self.autocomplete_state.autocomplete_prefix = last_word.to_string();
         }
 
-        let scrollback_guard = self.incoming_line_history.lock().unwrap();
-        let search_result = scrollback_guard.find_recent_word_by_prefix(
+        let mut scrollback_guard = self.incoming_line_history.lock().unwrap();
+        let search_result = scrollback_guard.completions_mut().complete(
             &self.autocomplete_state.autocomplete_prefix,
-            Some(&self.autocomplete_state.history),
-            500,
+            continue_from_last_request,
         );
         drop(scrollback_guard);
 
         match search_result {
             Some(found) => {
-                self.autocomplete_state.history.insert(found.clone());
-
                 let mut new_line = self.autocomplete_state.text_prior_to_autocomplete.clone();
                 new_line.push_str(&found);
+                self.input_line.set(&new_line);
 
                 AutocompleteResult {
                     success: true,
@@ -234,12 +799,49 @@ impl Session {
         self.view.clone()
     }
 
+    pub fn capture_view(&self) -> Rc<TerminalView> {
+        self.capture_view.clone()
+    }
+
     pub fn connect(&mut self) {
-        self.connection
-            .connect(&self.profile.host(), self.profile.port());
+        self.connection.connect(
+            &self.profile,
+            self.connect_scripts.clone(),
+            self.auto_reconnect,
+        );
     }
 
-    pub fn close(&self)  {
-        self.script_runtime.tx().send(crate::script_runtime::RuntimeAction::CloseSession).unwrap();
+    /// Lets scripts clean up, sends the profile's send-on-close commands and flushes the output
+    /// sink, then disconnects. Gives up waiting on the runtime after `CLOSE_TIMEOUT`.
+    pub fn close(&mut self) {
+        let (closed_tx, closed_rx) = mpsc::channel();
+        let close = RuntimeAction::CloseSession(
+            Arc::new(self.profile.send_on_close().to_string()),
+            closed_tx,
+        );
+        if self.script_runtime.tx().send(close).is_ok()
+            && closed_rx.recv_timeout(CLOSE_TIMEOUT).is_err()
+        {
+            warn!("Session runtime didn't close within {CLOSE_TIMEOUT:?}");
+        }
+
+        self.connection.disconnect();
+    }
+}
+
+/// Opens a web link from the output in the default browser. Other schemes could run anything,
+/// so they're refused.
+fn open_url(url: &str) -> anyhow::Result<()> {
+    #[cfg(target_os = "windows")]
+    const OPENER: &str = "explorer";
+    #[cfg(target_os = "macos")]
+    const OPENER: &str = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    const OPENER: &str = "xdg-open";
+
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        anyhow::bail!("only http and https links are opened");
     }
+    std::process::Command::new(OPENER).arg(url).spawn()?;
+    Ok(())
 }
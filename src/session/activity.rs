@@ -0,0 +1,107 @@
+/// What a session's pane shows about it, in the colors the theme gives each
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected = 0,
+    Connected = 1,
+    /// Connected, and lines have arrived since the pane last had focus
+    HasActivity = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    Connected,
+    Disconnected,
+    LinesArrived,
+    Focused,
+    Unfocused,
+}
+
+/// Works out a session's connection state from what happens to it
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    connected: bool,
+    focused: bool,
+    activity: bool,
+}
+
+impl ConnectionTracker {
+    pub fn state(&self) -> ConnectionState {
+        if !self.connected {
+            ConnectionState::Disconnected
+        } else if self.activity {
+            ConnectionState::HasActivity
+        } else {
+            ConnectionState::Connected
+        }
+    }
+
+    /// Records an event, returning whether the state changed
+    pub fn handle(&mut self, event: SessionEvent) -> bool {
+        let before = self.state();
+        match event {
+            SessionEvent::Connected => self.connected = true,
+            // Whatever arrived before the disconnect has been seen or not, either way
+            SessionEvent::Disconnected => {
+                self.connected = false;
+                self.activity = false;
+            }
+            SessionEvent::LinesArrived => self.activity |= self.connected && !self.focused,
+            SessionEvent::Focused => {
+                self.focused = true;
+                self.activity = false;
+            }
+            SessionEvent::Unfocused => self.focused = false,
+        }
+        self.state() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activity_while_unfocused() {
+        let mut tracker = ConnectionTracker::default();
+        assert_eq!(tracker.state(), ConnectionState::Disconnected);
+
+        assert!(tracker.handle(SessionEvent::Connected));
+        assert_eq!(tracker.state(), ConnectionState::Connected);
+
+        assert!(tracker.handle(SessionEvent::LinesArrived));
+        assert_eq!(tracker.state(), ConnectionState::HasActivity);
+        assert!(!tracker.handle(SessionEvent::LinesArrived));
+
+        assert!(tracker.handle(SessionEvent::Focused));
+        assert_eq!(tracker.state(), ConnectionState::Connected);
+    }
+
+    #[test]
+    fn test_no_activity_while_focused() {
+        let mut tracker = ConnectionTracker::default();
+        tracker.handle(SessionEvent::Connected);
+        tracker.handle(SessionEvent::Focused);
+
+        assert!(!tracker.handle(SessionEvent::LinesArrived));
+        assert_eq!(tracker.state(), ConnectionState::Connected);
+
+        assert!(!tracker.handle(SessionEvent::Unfocused));
+        assert!(tracker.handle(SessionEvent::LinesArrived));
+        assert_eq!(tracker.state(), ConnectionState::HasActivity);
+    }
+
+    #[test]
+    fn test_disconnect_clears_activity() {
+        let mut tracker = ConnectionTracker::default();
+        tracker.handle(SessionEvent::Connected);
+        tracker.handle(SessionEvent::LinesArrived);
+
+        assert!(tracker.handle(SessionEvent::Disconnected));
+        assert_eq!(tracker.state(), ConnectionState::Disconnected);
+
+        // Lines like the disconnect notice don't count as activity on a closed connection
+        assert!(!tracker.handle(SessionEvent::LinesArrived));
+        assert!(tracker.handle(SessionEvent::Connected));
+        assert_eq!(tracker.state(), ConnectionState::Connected);
+    }
+}
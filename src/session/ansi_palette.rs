@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+use super::styled_line;
+
+static ECHO_COLOR: slint::Color = slint::Color::from_rgb_u8(255, 192, 255);
+static OUTPUT_COLOR: slint::Color = slint::Color::from_rgb_u8(255, 255, 192);
+
+/// The names palette entries are overridden by, in ANSI order: the 8 basic colors, then their
+/// bright (bold) variants
+pub const ANSI_COLOR_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright_black",
+    "bright_red",
+    "bright_green",
+    "bright_yellow",
+    "bright_blue",
+    "bright_magenta",
+    "bright_cyan",
+    "bright_white",
+];
+
+static DEFAULT_ANSI_COLORS: [slint::Color; 16] = [
+    slint::Color::from_rgb_u8(0, 0, 0),
+    slint::Color::from_rgb_u8(170, 0, 0),
+    slint::Color::from_rgb_u8(0, 170, 0),
+    slint::Color::from_rgb_u8(170, 170, 0),
+    slint::Color::from_rgb_u8(0, 0, 170),
+    slint::Color::from_rgb_u8(170, 0, 170),
+    slint::Color::from_rgb_u8(0, 170, 170),
+    slint::Color::from_rgb_u8(204, 204, 204),
+    slint::Color::from_rgb_u8(85, 85, 85),
+    slint::Color::from_rgb_u8(255, 85, 85),
+    slint::Color::from_rgb_u8(85, 255, 85),
+    slint::Color::from_rgb_u8(255, 255, 85),
+    slint::Color::from_rgb_u8(85, 85, 255),
+    slint::Color::from_rgb_u8(255, 85, 255),
+    slint::Color::from_rgb_u8(85, 255, 255),
+    slint::Color::from_rgb_u8(255, 255, 255),
+];
+
+/// The colors ANSI color codes are drawn in. Lines keep the codes themselves, so changing the
+/// palette recolors everything already in the buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnsiPalette {
+    colors: [slint::Color; 16],
+}
+
+impl Default for AnsiPalette {
+    fn default() -> Self {
+        Self {
+            colors: DEFAULT_ANSI_COLORS,
+        }
+    }
+}
+
+impl AnsiPalette {
+    /// The default palette with some entries replaced, as stored in a profile: color names from
+    /// `ANSI_COLOR_NAMES` mapped to `#rrggbb` colors
+    pub fn with_overrides(overrides: &BTreeMap<String, String>) -> Result<Self> {
+        let mut palette = Self::default();
+        for (name, color) in overrides {
+            let idx = ANSI_COLOR_NAMES
+                .iter()
+                .position(|known| known == name)
+                .ok_or_else(|| anyhow!("'{name}' is not an ANSI color name"))?;
+            palette.colors[idx] = parse_hex_color(color).map_err(|e| anyhow!("{e} for {name}"))?;
+        }
+        Ok(palette)
+    }
+
+    /// What reverse video text is drawn in, over a block of its own color
+    pub fn background(&self) -> slint::Color {
+        self.colors[0]
+    }
+
+    pub fn resolve(&self, color: styled_line::Color) -> slint::Color {
+        match color {
+            styled_line::Color::AnsiColor { color, bold } => {
+                self.colors[color as usize + if bold { 8 } else { 0 }]
+            }
+            styled_line::Color::Output => OUTPUT_COLOR,
+            styled_line::Color::Echo => ECHO_COLOR,
+            styled_line::Color::RGB { r, g, b } => slint::Color::from_rgb_u8(r, g, b),
+        }
+    }
+}
+
+/// Parses a `#rrggbb` color, the form colors take everywhere they're configured
+pub fn parse_hex_color(color: &str) -> Result<slint::Color> {
+    color
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6 && hex.chars().all(|ch| ch.is_ascii_hexdigit()))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .map(|rgb| slint::Color::from_argb_encoded(0xff000000 | rgb))
+        .ok_or_else(|| anyhow!("Expected a color like #rrggbb, got '{color}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::styled_line::AnsiColor;
+
+    fn overrides(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, color)| (name.to_string(), color.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_override_changes_resolved_color() {
+        let red = styled_line::Color::AnsiColor {
+            color: AnsiColor::Red,
+            bold: false,
+        };
+        let bright_red = styled_line::Color::AnsiColor {
+            color: AnsiColor::Red,
+            bold: true,
+        };
+        assert_eq!(
+            AnsiPalette::default().resolve(red),
+            slint::Color::from_rgb_u8(170, 0, 0)
+        );
+
+        let palette = AnsiPalette::with_overrides(&overrides(&[("red", "#e06c75")])).unwrap();
+        assert_eq!(palette.resolve(red), slint::Color::from_rgb_u8(0xe0, 0x6c, 0x75));
+        // Entries that aren't overridden keep their defaults
+        assert_eq!(
+            palette.resolve(bright_red),
+            slint::Color::from_rgb_u8(255, 85, 85)
+        );
+        assert_eq!(
+            palette.resolve(styled_line::Color::RGB { r: 1, g: 2, b: 3 }),
+            slint::Color::from_rgb_u8(1, 2, 3)
+        );
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(
+            parse_hex_color("#20a0FF").unwrap(),
+            slint::Color::from_rgb_u8(0x20, 0xa0, 0xff)
+        );
+        assert!(parse_hex_color("20a0ff").is_err());
+        assert!(parse_hex_color("#20a0f").is_err());
+        assert!(parse_hex_color("#zzzzzz").is_err());
+    }
+
+    #[test]
+    fn test_invalid_overrides() {
+        assert!(AnsiPalette::with_overrides(&overrides(&[("crimson", "#ff0000")])).is_err());
+        assert!(AnsiPalette::with_overrides(&overrides(&[("red", "ff0000")])).is_err());
+        assert!(AnsiPalette::with_overrides(&overrides(&[("red", "#+f0000")])).is_err());
+    }
+}
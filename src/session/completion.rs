@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+const DEFAULT_MAX_WORDS: usize = 5000;
+const DEFAULT_MIN_PREFIX_LEN: usize = 1;
+const MIN_WORD_LEN: usize = 3;
+
+#[derive(Debug, Clone)]
+struct WordEntry {
+    word: String,
+    count: u32,
+    last_seen: u64,
+}
+
+#[derive(Debug)]
+struct Cycle {
+    candidates: Vec<String>,
+    position: usize,
+}
+
+/// Indexes words seen in the terminal buffer and completes prefixes from them, preferring words
+/// that have been seen more often, then more recently. Repeated requests cycle through the
+/// candidates in a stable order.
+#[derive(Debug)]
+pub struct CompletionProvider {
+    words: HashMap<String, WordEntry>,
+    sequence: u64,
+    max_words: usize,
+    min_prefix_len: usize,
+    cycle: Option<Cycle>,
+}
+
+impl Default for CompletionProvider {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_WORDS, DEFAULT_MIN_PREFIX_LEN)
+    }
+}
+
+impl CompletionProvider {
+    pub fn new(max_words: usize, min_prefix_len: usize) -> Self {
+        Self {
+            words: HashMap::new(),
+            sequence: 0,
+            max_words,
+            min_prefix_len,
+            cycle: None,
+        }
+    }
+
+    pub fn index_line(&mut self, line: &str) {
+        for word in line
+            .split(|ch: char| !ch.is_alphanumeric() && ch != '\'' && ch != '-')
+            .map(|word| word.trim_matches(|ch| ch == '\'' || ch == '-'))
+            .filter(|word| word.chars().count() >= MIN_WORD_LEN)
+        {
+            self.sequence += 1;
+            let entry = self
+                .words
+                .entry(word.to_lowercase())
+                .or_insert_with(|| WordEntry {
+                    word: word.to_string(),
+                    count: 0,
+                    last_seen: 0,
+                });
+            entry.word = word.to_string();
+            entry.count = entry.count.saturating_add(1);
+            entry.last_seen = self.sequence;
+        }
+
+        if self.words.len() > self.max_words {
+            self.evict_oldest();
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let mut last_seen: Vec<u64> = self.words.values().map(|entry| entry.last_seen).collect();
+        let excess = self.words.len() - self.max_words;
+        let (_, cutoff, _) = last_seen.select_nth_unstable(excess);
+        let cutoff = *cutoff;
+        self.words.retain(|_, entry| entry.last_seen >= cutoff);
+    }
+
+    /// Returns the best completion for `prefix`, or the next candidate when continuing a previous request
+    pub fn complete(&mut self, prefix: &str, continue_from_last_request: bool) -> Option<String> {
+        if continue_from_last_request {
+            if let Some(cycle) = self.cycle.as_mut() {
+                cycle.position = (cycle.position + 1) % cycle.candidates.len();
+                return cycle.candidates.get(cycle.position).cloned();
+            }
+        }
+
+        self.cycle = None;
+
+        if prefix.chars().count() < self.min_prefix_len {
+            return None;
+        }
+
+        let lowercase_prefix = prefix.to_lowercase();
+        let mut candidates: Vec<&WordEntry> = self
+            .words
+            .iter()
+            .filter(|(key, _)| key.starts_with(&lowercase_prefix) && key.as_str() != lowercase_prefix)
+            .map(|(_, entry)| entry)
+            .collect();
+
+        candidates.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.last_seen.cmp(&a.last_seen))
+                .then(a.word.cmp(&b.word))
+        });
+
+        let candidates: Vec<String> = candidates.into_iter().map(|entry| entry.word.clone()).collect();
+        let first = candidates.first().cloned();
+
+        if first.is_some() {
+            self.cycle = Some(Cycle {
+                candidates,
+                position: 0,
+            });
+        }
+
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_matching() {
+        let mut provider = CompletionProvider::default();
+        provider.index_line("A goblin warrior stands here.");
+        provider.index_line("The Gobbler waddles in.");
+
+        assert_eq!(provider.complete("war", false), Some("warrior".to_string()));
+        assert_eq!(provider.complete("xyz", false), None);
+        // Exact matches aren't completions
+        assert_eq!(provider.complete("warrior", false), None);
+    }
+
+    #[test]
+    fn test_frequency_then_recency() {
+        let mut provider = CompletionProvider::default();
+        provider.index_line("goblin gobbler");
+        provider.index_line("goblin");
+        provider.index_line("gobstopper");
+
+        assert_eq!(provider.complete("gob", false), Some("goblin".to_string()));
+        assert_eq!(provider.complete("gob", true), Some("gobstopper".to_string()));
+        assert_eq!(provider.complete("gob", true), Some("gobbler".to_string()));
+        // Cycling wraps around
+        assert_eq!(provider.complete("gob", true), Some("goblin".to_string()));
+    }
+
+    #[test]
+    fn test_cycling_order_is_stable() {
+        let mut provider = CompletionProvider::default();
+        provider.index_line("alpha albatross alcove");
+
+        let first_pass: Vec<_> = (0..3)
+            .map(|i| provider.complete("al", i > 0).unwrap())
+            .collect();
+        let second_pass: Vec<_> = (0..3)
+            .map(|i| provider.complete("al", i > 0).unwrap())
+            .collect();
+
+        assert_eq!(first_pass, second_pass);
+        assert_eq!(first_pass, vec!["alcove", "albatross", "alpha"]);
+    }
+
+    #[test]
+    fn test_minimum_prefix_length() {
+        let mut provider = CompletionProvider::new(100, 2);
+        provider.index_line("goblin");
+
+        assert_eq!(provider.complete("g", false), None);
+        assert_eq!(provider.complete("go", false), Some("goblin".to_string()));
+    }
+
+    #[test]
+    fn test_eviction_keeps_recent_words() {
+        let mut provider = CompletionProvider::new(2, 1);
+        provider.index_line("apple");
+        provider.index_line("banana");
+        provider.index_line("cherry");
+
+        assert_eq!(provider.complete("app", false), None);
+        assert_eq!(provider.complete("che", false), Some("cherry".to_string()));
+    }
+}
@@ -1,24 +1,147 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use tokio::{
     io::{self, AsyncWriteExt, Interest},
     net::TcpStream,
     select,
-    sync::{mpsc::UnboundedSender, oneshot},
+    sync::{mpsc::UnboundedSender, oneshot, watch},
+    time::Instant,
 };
+use idle::IdleWarning;
+use keepalive::Keepalive;
+pub use reconnect::ReconnectPolicy;
+use telnet::{TelnetEvent, TelnetParser};
 use vt_processor::VtProcessor;
 use vtparse::VTParser;
 
 use crate::{
+    models::Profile,
     script_runtime::{RuntimeAction, ScriptRuntime},
-    trigger::TriggerManager,
+    session::LoginStep,
+    trigger::{split_commands, TriggerManager},
 };
 
+mod idle;
+mod keepalive;
+mod out_of_band;
+mod reconnect;
+mod telnet;
 pub mod vt_processor;
+
 pub struct Connection {
     trigger_manager: Arc<TriggerManager>,
     disconnect: Option<oneshot::Sender<()>>,
     script_action_tx: UnboundedSender<RuntimeAction>,
+    terminal_size: watch::Sender<(u16, u16)>,
+}
+
+/// Commands sent automatically once a connection has been established
+#[derive(Debug, Clone, Default)]
+pub struct ConnectScripts {
+    pub send_on_connect: String,
+    pub send_on_connect_hidden: bool,
+    pub on_reconnect: String,
+    /// What the commands above are split on, as if they'd been typed
+    pub command_separator: char,
+    /// Prompts to wait for and what to answer them with, which the runtime works through
+    pub login_steps: Vec<LoginStep>,
+}
+
+enum ConnectionOutcome {
+    /// The session asked for the connection to end
+    Closed,
+    /// The connection was established, then dropped
+    Lost,
+    /// The connection could not be established
+    Failed,
+}
+
+/// Tracks which telnet options have been agreed with the server
+#[derive(Debug, Default)]
+struct TelnetOptions {
+    /// The server is echoing our input, which it does while reading things like passwords
+    echo: bool,
+    naws: bool,
+    mxp: bool,
+    msdp: bool,
+    gmcp: bool,
+    /// Whether the profile lets MXP be negotiated at all
+    allow_mxp: bool,
+    /// The MSDP variables the profile's status bar is bound to; MSDP and GMCP are only agreed to
+    /// when there are some
+    status_variables: Vec<String>,
+}
+
+impl TelnetOptions {
+    /// Answers a negotiation from the server, appending any reply to `replies`
+    fn negotiate(&mut self, command: u8, option: u8, terminal_size: (u16, u16), replies: &mut Vec<u8>) {
+        match (command, option) {
+            (telnet::DO, telnet::OPTION_NAWS) => {
+                if !self.naws {
+                    self.naws = true;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::WILL, telnet::OPTION_NAWS));
+                }
+                replies.extend(telnet::naws(terminal_size.0, terminal_size.1));
+            }
+            (telnet::DONT, telnet::OPTION_NAWS) => {
+                if self.naws {
+                    self.naws = false;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::WONT, telnet::OPTION_NAWS));
+                }
+            }
+            (telnet::WILL, telnet::OPTION_ECHO) => {
+                if !self.echo {
+                    self.echo = true;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::DO, telnet::OPTION_ECHO));
+                }
+            }
+            (telnet::WONT, telnet::OPTION_ECHO) => {
+                if self.echo {
+                    self.echo = false;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::DONT, telnet::OPTION_ECHO));
+                }
+            }
+            // Servers differ in which side they expect to offer MXP, so agree to either
+            (telnet::WILL | telnet::DO, telnet::OPTION_MXP) if self.allow_mxp => {
+                if !self.mxp {
+                    self.mxp = true;
+                    let reply = if command == telnet::WILL { telnet::DO } else { telnet::WILL };
+                    replies.extend_from_slice(&telnet::negotiate(reply, telnet::OPTION_MXP));
+                }
+            }
+            (telnet::WONT | telnet::DONT, telnet::OPTION_MXP) => {
+                if self.mxp {
+                    self.mxp = false;
+                    let reply = if command == telnet::WONT { telnet::DONT } else { telnet::WONT };
+                    replies.extend_from_slice(&telnet::negotiate(reply, telnet::OPTION_MXP));
+                }
+            }
+            (telnet::WILL, telnet::OPTION_MSDP) if !self.status_variables.is_empty() => {
+                if !self.msdp {
+                    self.msdp = true;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::DO, telnet::OPTION_MSDP));
+                    replies.extend(out_of_band::msdp_report(&self.status_variables));
+                }
+            }
+            (telnet::WILL, telnet::OPTION_GMCP) if !self.status_variables.is_empty() => {
+                if !self.gmcp {
+                    self.gmcp = true;
+                    replies.extend_from_slice(&telnet::negotiate(telnet::DO, telnet::OPTION_GMCP));
+                    replies.extend(out_of_band::gmcp_hello());
+                }
+            }
+            (telnet::WONT, telnet::OPTION_MSDP) => self.msdp = false,
+            (telnet::WONT, telnet::OPTION_GMCP) => self.gmcp = false,
+            // Refuse anything we don't support
+            (telnet::DO, option) => {
+                replies.extend_from_slice(&telnet::negotiate(telnet::WONT, option));
+            }
+            (telnet::WILL, option) => {
+                replies.extend_from_slice(&telnet::negotiate(telnet::DONT, option));
+            }
+            _ => {}
+        }
+    }
 }
 
 impl Connection {
@@ -27,94 +150,364 @@ impl Connection {
             trigger_manager,
             disconnect: None,
             script_action_tx: script_runtime.tx(),
+            terminal_size: watch::Sender::new((80, 24)),
         }
     }
 
-    pub fn connect(&mut self, host: &str, port: u16) {
-        let addr = format!("{host}:{port}");
-        let arc_trigger_manager = self.trigger_manager.clone();
-        let script_action_tx = self.script_action_tx.clone();
-        let (tx, mut disconnect_rx) = oneshot::channel();
+    /// Updates the terminal size (in characters) reported to the server via NAWS
+    pub fn set_terminal_size(&self, columns: u16, rows: u16) {
+        self.terminal_size.send_if_modified(|size| {
+            if *size != (columns, rows) {
+                *size = (columns, rows);
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn connect(&mut self, profile: &Profile, connect_scripts: ConnectScripts, auto_reconnect: bool) {
+        let (tx, disconnect_rx) = oneshot::channel();
+
+        self.disconnect();
+        self.disconnect = Some(tx);
+
+        let task = ConnectionTask {
+            addr: format!("{}:{}", profile.host(), profile.port()),
+            trigger_manager: self.trigger_manager.clone(),
+            script_action_tx: self.script_action_tx.clone(),
+            terminal_size_rx: self.terminal_size.subscribe(),
+            keepalive_interval_secs: profile.keepalive_interval_secs(),
+            keepalive_command: profile.keepalive_command().to_string(),
+            idle_warning_secs: profile.idle_warning_secs(),
+            mxp_enabled: profile.mxp_enabled(),
+            status_variables: profile
+                .status_bar()
+                .iter()
+                .flat_map(|binding| [binding.variable.clone(), binding.max_variable.clone()])
+                .filter(|variable| !variable.is_empty())
+                .collect(),
+            connect_scripts,
+        };
 
+        let reconnect = ReconnectPolicy {
+            enabled: auto_reconnect,
+            base_delay: Duration::from_secs(profile.reconnect_base_delay_secs()),
+            max_attempts: profile.reconnect_max_attempts(),
+        };
+
+        crate::TOKIO.spawn(task.run(reconnect, disconnect_rx));
+    }
+
+    /// Ends the current connection (and any pending reconnect attempts), if there is one
+    pub fn disconnect(&mut self) {
         if let Some(disconnect) = self.disconnect.take() {
             // This will error if the channel is already closed, which is fine
             disconnect.send(()).ok();
         }
+    }
+}
 
-        self.disconnect = Some(tx);
+struct ConnectionTask {
+    addr: String,
+    trigger_manager: Arc<TriggerManager>,
+    script_action_tx: UnboundedSender<RuntimeAction>,
+    terminal_size_rx: watch::Receiver<(u16, u16)>,
+    keepalive_interval_secs: u64,
+    keepalive_command: String,
+    idle_warning_secs: u64,
+    mxp_enabled: bool,
+    status_variables: Vec<String>,
+    connect_scripts: ConnectScripts,
+}
 
-        crate::TOKIO.spawn(async move {
-            let mut vt_parser = VTParser::new();
-            let mut vt_processor = VtProcessor::new(arc_trigger_manager);
-            let (write_to_socket_tx, mut write_to_socket_rx) = tokio::sync::mpsc::unbounded_channel::<Arc<String>>();
-
-            script_action_tx.send(RuntimeAction::Echo(Arc::new(format!("\r\nConnecting to {addr}...")))).unwrap();
-            trace!("Connecting to {addr}...");
-
-            match TcpStream::connect(addr).await {
-                Ok(mut stream) => {
-                    stream.set_nodelay(true).unwrap();
-                    trace!("Connected");
-                    script_action_tx.send(RuntimeAction::UpdateWriteToSocketTx(Some(write_to_socket_tx))).unwrap();
-
-                    loop {
-                        select! {
-                            Ok(ready) = stream.ready(Interest::READABLE) => {
-                                if ready.is_readable() {
-                                    let mut data: Vec<u8> = Vec::with_capacity(4096);
-
-                                    match stream.try_read_buf(&mut data) {
-                                        Ok(n) => {
-                                            if n == 0 {
-                                                // TODO: notify session that the connection was reset
-        //                                        echo_tx.send("Connection reset!".into()).unwrap();
-                                                break;
-                                            }
+impl ConnectionTask {
+    fn echo(&self, message: String) {
+        // Silently ignore errors here; when a session is closing the runtime may already be gone
+        self.script_action_tx
+            .send(RuntimeAction::Echo(Arc::new(message)))
+            .ok();
+    }
 
-                                            for b in &data {
-                                                vt_parser.parse_byte(*b, &mut vt_processor);
-                                            }
+    async fn run(mut self, reconnect: ReconnectPolicy, mut disconnect_rx: oneshot::Receiver<()>) {
+        let mut attempt = 0;
+        let mut is_reconnect = false;
 
-                                            vt_processor.notify_end_of_buffer();
-                                        }
-                                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                            continue;
+        loop {
+            match self.run_once(&mut disconnect_rx, is_reconnect).await {
+                ConnectionOutcome::Closed => break,
+                ConnectionOutcome::Lost => attempt = 1,
+                ConnectionOutcome::Failed => attempt += 1,
+            }
+
+            let Some(delay) = reconnect.delay_for(attempt) else {
+                break;
+            };
+
+            self.echo(format!(
+                "\r\nReconnecting in {}s (attempt {attempt} of {})...",
+                delay.as_secs(),
+                reconnect.max_attempts
+            ));
+
+            select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut disconnect_rx => {
+                    break;
+                }
+            }
+
+            is_reconnect = true;
+        }
+
+        trace!("Connection cleaning up");
+    }
+
+    fn send_connect_scripts(&self, is_reconnect: bool) {
+        let scripts = &self.connect_scripts;
+
+        if !scripts.login_steps.is_empty() {
+            self.script_action_tx
+                .send(RuntimeAction::StartLogin(Arc::new(scripts.login_steps.clone())))
+                .ok();
+        }
+
+        if !scripts.send_on_connect.is_empty() {
+            for command in split_commands(&scripts.send_on_connect, scripts.command_separator) {
+                let command = Arc::new(command);
+                let action = if scripts.send_on_connect_hidden {
+                    RuntimeAction::SendHidden(command)
+                } else {
+                    RuntimeAction::SendRaw(command)
+                };
+                self.script_action_tx.send(action).ok();
+            }
+        }
+
+        if is_reconnect {
+            self.script_action_tx.send(RuntimeAction::Reconnected).ok();
+        }
+
+        if is_reconnect && !scripts.on_reconnect.is_empty() {
+            for command in split_commands(&scripts.on_reconnect, scripts.command_separator) {
+                self.script_action_tx
+                    .send(RuntimeAction::SendRaw(Arc::new(command)))
+                    .ok();
+            }
+        }
+    }
+
+    async fn run_once(
+        &mut self,
+        disconnect_rx: &mut oneshot::Receiver<()>,
+        is_reconnect: bool,
+    ) -> ConnectionOutcome {
+        let addr = self.addr.clone();
+        let mut vt_parser = VTParser::new();
+        let mut telnet_parser = TelnetParser::new();
+        let mut telnet_options = TelnetOptions {
+            allow_mxp: self.mxp_enabled,
+            status_variables: self.status_variables.clone(),
+            ..TelnetOptions::default()
+        };
+        let mut vt_processor = VtProcessor::new(self.trigger_manager.clone());
+        let (write_to_socket_tx, mut write_to_socket_rx) = tokio::sync::mpsc::unbounded_channel::<Arc<String>>();
+
+        self.echo(format!("\r\nConnecting to {addr}..."));
+        trace!("Connecting to {addr}...");
+
+        let mut stream = select! {
+            result = TcpStream::connect(addr) => match result {
+                Ok(stream) => stream,
+                Err(_) => {
+                    self.echo("\r\nConnection failed".to_string());
+                    return ConnectionOutcome::Failed;
+                }
+            },
+            _ = &mut *disconnect_rx => {
+                return ConnectionOutcome::Closed;
+            }
+        };
+
+        stream.set_nodelay(true).unwrap();
+        trace!("Connected");
+        if self.script_action_tx.send(RuntimeAction::UpdateWriteToSocketTx(Some(write_to_socket_tx))).is_err() {
+            return ConnectionOutcome::Closed;
+        }
+
+        self.send_connect_scripts(is_reconnect);
+
+        let mut keepalive = Keepalive::new(self.keepalive_interval_secs, &self.keepalive_command, Instant::now());
+        let mut idle_warning = IdleWarning::new(self.idle_warning_secs, Instant::now());
+        let terminal_size_rx = &mut self.terminal_size_rx;
+
+        let outcome = loop {
+            let keepalive_deadline = keepalive.deadline();
+            let idle_warning_deadline = idle_warning.deadline();
+
+            select! {
+                Ok(ready) = stream.ready(Interest::READABLE) => {
+                    if ready.is_readable() {
+                        let mut data: Vec<u8> = Vec::with_capacity(4096);
+
+                        match stream.try_read_buf(&mut data) {
+                            Ok(n) => {
+                                if n == 0 {
+                                    break ConnectionOutcome::Lost;
+                                }
+
+                                idle_warning.notify_received(Instant::now());
+
+                                let mut replies: Vec<u8> = Vec::new();
+
+                                for b in &data {
+                                    match telnet_parser.parse_byte(*b) {
+                                        Some(TelnetEvent::Data(b)) => vt_processor.parse_byte(&mut vt_parser, b),
+                                        Some(TelnetEvent::Negotiate { command, option }) => {
+                                            let echo = telnet_options.echo;
+                                            telnet_options.negotiate(command, option, *terminal_size_rx.borrow(), &mut replies);
+                                            vt_processor.set_mxp_enabled(telnet_options.mxp);
+                                            if telnet_options.echo != echo {
+                                                self.script_action_tx.send(RuntimeAction::SetEchoSuppressed(telnet_options.echo)).ok();
+                                            }
                                         }
-                                        Err(_) => {
-                                            // TODO: notify session that the try_read_buf errored
-                                            // return Err::<(), anyhow::Error>(e.into());
-                                            break;
+                                        Some(TelnetEvent::Subnegotiate { option, data }) => {
+                                            let variables = match option {
+                                                telnet::OPTION_MSDP if telnet_options.msdp => out_of_band::decode_msdp(&data),
+                                                telnet::OPTION_GMCP if telnet_options.gmcp => out_of_band::decode_gmcp(&data),
+                                                _ => continue,
+                                            };
+                                            if !variables.is_empty() {
+                                                self.script_action_tx.send(RuntimeAction::UpdateStatusVariables(Arc::new(variables))).ok();
+                                            }
                                         }
+                                        _ => {}
                                     }
                                 }
-                            }
-                            Some(ref data) = write_to_socket_rx.recv() => {
-                                if stream.write_all(data.as_bytes()).await.is_err() {
-                                    break;
+
+                                vt_processor.notify_end_of_buffer();
+
+                                if !replies.is_empty() && stream.write_all(&replies).await.is_err() {
+                                    break ConnectionOutcome::Lost;
                                 }
                             }
-                            _ = &mut disconnect_rx => {
-                                break;
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                                continue;
                             }
-                            else => {
-                                break;
+                            Err(_) => {
+                                break ConnectionOutcome::Lost;
                             }
                         }
                     }
-
-                    // Silently ignore errors here; when a session is closing the runtime may already be gone by the time
-                    // we get here
-                    script_action_tx.send(RuntimeAction::UpdateWriteToSocketTx(None)).map(|_| {
-                        script_action_tx.send(RuntimeAction::Echo(Arc::new(format!("\r\nConnection lost")))).ok();
-                    }).ok();
                 }
-                _ => {
-                    script_action_tx.send(RuntimeAction::Echo(Arc::new(format!("\r\nConnection failed")))).map_err(|_| {
-                        warn!("Error notifying runtime of connection failure; ignoring");
-                    }).ok();
+                Some(ref data) = write_to_socket_rx.recv() => {
+                    if stream.write_all(data.as_bytes()).await.is_err() {
+                        break ConnectionOutcome::Lost;
+                    }
+                    keepalive.notify_activity(Instant::now());
+                }
+                Ok(()) = terminal_size_rx.changed() => {
+                    if telnet_options.naws {
+                        let (columns, rows) = *terminal_size_rx.borrow_and_update();
+                        if stream.write_all(&telnet::naws(columns, rows)).await.is_err() {
+                            break ConnectionOutcome::Lost;
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(keepalive_deadline.unwrap_or_else(Instant::now)), if keepalive_deadline.is_some() => {
+                    if let Some(payload) = keepalive.poll(Instant::now()) {
+                        if stream.write_all(payload).await.is_err() {
+                            break ConnectionOutcome::Lost;
+                        }
+                    }
+                }
+                _ = tokio::time::sleep_until(idle_warning_deadline.unwrap_or_else(Instant::now)), if idle_warning_deadline.is_some() => {
+                    if let Some(warning) = idle_warning.poll(Instant::now()) {
+                        self.echo(warning);
+                    }
+                }
+                _ = &mut *disconnect_rx => {
+                    // Whatever was queued before the session closed, like a quit command, still goes out
+                    while let Ok(data) = write_to_socket_rx.try_recv() {
+                        if stream.write_all(data.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    break ConnectionOutcome::Closed;
+                }
+                else => {
+                    break ConnectionOutcome::Lost;
                 }
             }
-            trace!("Connection cleaning up");
-        });
+        };
+
+        if telnet_options.echo {
+            self.script_action_tx.send(RuntimeAction::SetEchoSuppressed(false)).ok();
+        }
+
+        // Silently ignore errors here; when a session is closing the runtime may already be gone by the time
+        // we get here
+        if self.script_action_tx.send(RuntimeAction::UpdateWriteToSocketTx(None)).is_ok() {
+            self.echo("\r\nConnection lost".to_string());
+        }
+
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(options: &mut TelnetOptions, command: u8, option: u8) -> Vec<u8> {
+        let mut replies = Vec::new();
+        options.negotiate(command, option, (80, 24), &mut replies);
+        replies
+    }
+
+    #[test]
+    fn test_will_echo_suppresses_local_echo() {
+        let mut options = TelnetOptions::default();
+
+        assert_eq!(
+            negotiate(&mut options, telnet::WILL, telnet::OPTION_ECHO),
+            telnet::negotiate(telnet::DO, telnet::OPTION_ECHO)
+        );
+        assert!(options.echo);
+
+        // Agreeing again would start a negotiation loop with some servers
+        assert!(negotiate(&mut options, telnet::WILL, telnet::OPTION_ECHO).is_empty());
+        assert!(options.echo);
+    }
+
+    #[test]
+    fn test_wont_echo_restores_local_echo() {
+        let mut options = TelnetOptions::default();
+
+        // Nothing to undo before the server has taken over echoing
+        assert!(negotiate(&mut options, telnet::WONT, telnet::OPTION_ECHO).is_empty());
+        assert!(!options.echo);
+
+        negotiate(&mut options, telnet::WILL, telnet::OPTION_ECHO);
+        assert_eq!(
+            negotiate(&mut options, telnet::WONT, telnet::OPTION_ECHO),
+            telnet::negotiate(telnet::DONT, telnet::OPTION_ECHO)
+        );
+        assert!(!options.echo);
+
+        // A password prompt later in the session suppresses it again
+        negotiate(&mut options, telnet::WILL, telnet::OPTION_ECHO);
+        assert!(options.echo);
+    }
+
+    #[test]
+    fn test_do_echo_is_refused() {
+        // We never echo the server's output back to it
+        let mut options = TelnetOptions::default();
+        assert_eq!(
+            negotiate(&mut options, telnet::DO, telnet::OPTION_ECHO),
+            telnet::negotiate(telnet::WONT, telnet::OPTION_ECHO)
+        );
+        assert!(!options.echo);
     }
 }
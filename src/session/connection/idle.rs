@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// A deadline that's pushed back whenever there's activity, for things that should happen once a
+/// connection has been quiet for a while. Time is always passed in so it can be driven by a mock
+/// clock.
+#[derive(Debug)]
+pub struct IdleTimer {
+    timeout: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl IdleTimer {
+    /// A timeout of zero never expires
+    pub fn new(timeout_secs: u64, now: Instant) -> Self {
+        Self {
+            timeout: (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs)),
+            last_activity: now,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Starts the timeout over from `now`
+    pub fn notify_activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// When the timer expires, or None when it's disabled
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| self.last_activity + timeout)
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|deadline| now >= deadline)
+    }
+}
+
+/// Tracks incoming activity on a connection and decides when to warn that the server has gone
+/// quiet. The warning is given once per idle period, and re-armed when output arrives again.
+#[derive(Debug)]
+pub struct IdleWarning {
+    timer: IdleTimer,
+    warned: bool,
+}
+
+impl IdleWarning {
+    /// A timeout of zero disables the warning
+    pub fn new(timeout_secs: u64, now: Instant) -> Self {
+        Self {
+            timer: IdleTimer::new(timeout_secs, now),
+            warned: false,
+        }
+    }
+
+    /// Notify the IdleWarning that something was received from the server
+    pub fn notify_received(&mut self, now: Instant) {
+        self.timer.notify_activity(now);
+        self.warned = false;
+    }
+
+    /// When the warning is next due, or None when it's disabled or has already been given
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.warned {
+            return None;
+        }
+        self.timer.deadline()
+    }
+
+    /// Returns the message to show if the warning is due at `now`
+    pub fn poll(&mut self, now: Instant) -> Option<String> {
+        let timeout = self.timer.timeout()?;
+        if self.warned || !self.timer.is_expired(now) {
+            return None;
+        }
+
+        self.warned = true;
+        Some(format!(
+            "No output from the server for {}s; the connection may have stalled",
+            timeout.as_secs()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warns_once_per_idle_period() {
+        let start = Instant::now();
+        let mut idle = IdleWarning::new(60, start);
+
+        assert_eq!(idle.poll(start + Duration::from_secs(59)), None);
+        assert!(idle.poll(start + Duration::from_secs(60)).is_some());
+
+        // Staying idle doesn't repeat the warning
+        assert_eq!(idle.deadline(), None);
+        assert_eq!(idle.poll(start + Duration::from_secs(120)), None);
+        assert_eq!(idle.poll(start + Duration::from_secs(600)), None);
+    }
+
+    #[test]
+    fn test_output_resets_timer() {
+        let start = Instant::now();
+        let mut idle = IdleWarning::new(60, start);
+
+        idle.notify_received(start + Duration::from_secs(50));
+        assert_eq!(idle.poll(start + Duration::from_secs(60)), None);
+        assert!(idle.poll(start + Duration::from_secs(110)).is_some());
+
+        // New output re-arms the warning for the next idle period
+        idle.notify_received(start + Duration::from_secs(200));
+        assert_eq!(
+            idle.deadline(),
+            Some(start + Duration::from_secs(260))
+        );
+        assert!(idle.poll(start + Duration::from_secs(260)).is_some());
+    }
+
+    #[test]
+    fn test_zero_timeout_disables() {
+        let start = Instant::now();
+        let mut idle = IdleWarning::new(0, start);
+
+        assert_eq!(idle.deadline(), None);
+        assert_eq!(idle.poll(start + Duration::from_secs(86400)), None);
+    }
+}
@@ -0,0 +1,105 @@
+use tokio::time::Instant;
+
+use super::{
+    idle::IdleTimer,
+    telnet::{IAC, NOP},
+};
+
+/// Tracks outgoing activity on a connection and decides when a keepalive needs to be sent
+#[derive(Debug)]
+pub struct Keepalive {
+    timer: IdleTimer,
+    payload: Vec<u8>,
+}
+
+impl Keepalive {
+    /// An interval of zero disables keepalives. An empty command sends a telnet `IAC NOP`,
+    /// otherwise the command is sent as a line of its own.
+    pub fn new(interval_secs: u64, command: &str, now: Instant) -> Self {
+        let payload = if command.is_empty() {
+            vec![IAC, NOP]
+        } else {
+            format!("{command}\r\n").into_bytes()
+        };
+
+        Self {
+            timer: IdleTimer::new(interval_secs, now),
+            payload,
+        }
+    }
+
+    /// Notify the Keepalive that something was written to the socket
+    pub fn notify_activity(&mut self, now: Instant) {
+        self.timer.notify_activity(now);
+    }
+
+    /// When the next keepalive is due, or None when keepalives are disabled
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timer.deadline()
+    }
+
+    /// Returns the bytes to send if a keepalive is due at `now`, and restarts the idle timer
+    pub fn poll(&mut self, now: Instant) -> Option<&[u8]> {
+        if !self.timer.is_expired(now) {
+            return None;
+        }
+        self.timer.notify_activity(now);
+        Some(self.payload.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_nop_after_idle_interval() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(30, "", start);
+
+        assert_eq!(keepalive.poll(start + Duration::from_secs(29)), None);
+        assert_eq!(
+            keepalive.poll(start + Duration::from_secs(30)),
+            Some([IAC, NOP].as_slice())
+        );
+
+        // The timer restarts after a keepalive is sent
+        assert_eq!(keepalive.poll(start + Duration::from_secs(31)), None);
+        assert_eq!(
+            keepalive.deadline(),
+            Some(start + Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_activity_resets_idle_timer() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(30, "", start);
+
+        keepalive.notify_activity(start + Duration::from_secs(20));
+        assert_eq!(keepalive.poll(start + Duration::from_secs(30)), None);
+        assert!(keepalive.poll(start + Duration::from_secs(50)).is_some());
+    }
+
+    #[test]
+    fn test_app_level_command() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(10, "idle", start);
+
+        assert_eq!(
+            keepalive.poll(start + Duration::from_secs(10)),
+            Some(b"idle\r\n".as_slice())
+        );
+    }
+
+    #[test]
+    fn test_zero_interval_disables() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::new(0, "", start);
+
+        assert_eq!(keepalive.deadline(), None);
+        assert_eq!(keepalive.poll(start + Duration::from_secs(86400)), None);
+    }
+}
@@ -0,0 +1,329 @@
+use serde_json::Value;
+
+use super::telnet;
+
+const MSDP_VAR: u8 = 1;
+const MSDP_VAL: u8 = 2;
+const MSDP_TABLE_OPEN: u8 = 3;
+const MSDP_TABLE_CLOSE: u8 = 4;
+const MSDP_ARRAY_OPEN: u8 = 5;
+const MSDP_ARRAY_CLOSE: u8 = 6;
+
+/// Asks an MSDP server to send these variables whenever they change
+pub fn msdp_report(variables: &[String]) -> Vec<u8> {
+    let mut data = vec![MSDP_VAR];
+    data.extend_from_slice(b"REPORT");
+    for variable in variables {
+        data.push(MSDP_VAL);
+        data.extend_from_slice(variable.as_bytes());
+    }
+    telnet::subnegotiate(telnet::OPTION_MSDP, &data)
+}
+
+/// Introduces us to a GMCP server and asks it for the packages vitals are usually sent in
+pub fn gmcp_hello() -> Vec<u8> {
+    let mut bytes = telnet::subnegotiate(
+        telnet::OPTION_GMCP,
+        format!(
+            r#"Core.Hello {{ "client": "smudgy", "version": "{}" }}"#,
+            env!("CARGO_PKG_VERSION")
+        )
+        .as_bytes(),
+    );
+    bytes.extend(telnet::subnegotiate(
+        telnet::OPTION_GMCP,
+        br#"Core.Supports.Set [ "Char 1", "Char.Vitals 1" ]"#,
+    ));
+    bytes
+}
+
+/// How deeply tables and arrays may nest. Anything deeper is skipped over rather than decoded,
+/// so a hostile server can't exhaust the stack.
+const MSDP_MAX_DEPTH: usize = 16;
+
+/// Decodes an MSDP subnegotiation into variable names and values. Tables are flattened into
+/// `NAME.KEY` variables, and arrays are joined with commas. Tables and arrays inside an array
+/// are flattened into `NAME.INDEX` variables instead.
+pub fn decode_msdp(data: &[u8]) -> Vec<(String, String)> {
+    let mut variables = Vec::new();
+    let mut pos = 0;
+    decode_msdp_pairs(data, &mut pos, "", 0, &mut variables);
+    variables
+}
+
+fn decode_msdp_pairs(
+    data: &[u8],
+    pos: &mut usize,
+    prefix: &str,
+    depth: usize,
+    variables: &mut Vec<(String, String)>,
+) {
+    while *pos < data.len() {
+        match data[*pos] {
+            MSDP_VAR => {
+                *pos += 1;
+                let name = format!("{prefix}{}", read_msdp_string(data, pos));
+                if data.get(*pos) != Some(&MSDP_VAL) {
+                    continue;
+                }
+                *pos += 1;
+                decode_msdp_value(data, pos, name, depth, variables);
+            }
+            MSDP_TABLE_CLOSE if depth > 0 => {
+                *pos += 1;
+                return;
+            }
+            // Anything else is out of place; skip it rather than give up on the rest
+            _ => *pos += 1,
+        }
+    }
+}
+
+/// Decodes the value starting at `pos`, which follows a VAL
+fn decode_msdp_value(
+    data: &[u8],
+    pos: &mut usize,
+    name: String,
+    depth: usize,
+    variables: &mut Vec<(String, String)>,
+) {
+    match data.get(*pos) {
+        Some(&(MSDP_TABLE_OPEN | MSDP_ARRAY_OPEN)) if depth >= MSDP_MAX_DEPTH => {
+            skip_msdp_nested(data, pos);
+        }
+        Some(&MSDP_TABLE_OPEN) => {
+            *pos += 1;
+            decode_msdp_pairs(data, pos, &format!("{name}."), depth + 1, variables);
+        }
+        Some(&MSDP_ARRAY_OPEN) => {
+            *pos += 1;
+            let nested_start = variables.len();
+            let values = decode_msdp_array(data, pos, &name, depth + 1, variables);
+            // An array of only tables is already covered by their variables
+            if !values.is_empty() || variables.len() == nested_start {
+                variables.push((name, values.join(",")));
+            }
+        }
+        _ => variables.push((name, read_msdp_string(data, pos))),
+    }
+}
+
+/// Decodes an array's elements up to its closing byte, returning the plain ones. Every step
+/// moves past at least one byte, however malformed the array is.
+fn decode_msdp_array(
+    data: &[u8],
+    pos: &mut usize,
+    name: &str,
+    depth: usize,
+    variables: &mut Vec<(String, String)>,
+) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut index = 0;
+    while let Some(&byte) = data.get(*pos) {
+        match byte {
+            MSDP_ARRAY_CLOSE => {
+                *pos += 1;
+                break;
+            }
+            MSDP_VAL => {
+                *pos += 1;
+                match data.get(*pos) {
+                    Some(&(MSDP_TABLE_OPEN | MSDP_ARRAY_OPEN)) => {
+                        decode_msdp_value(data, pos, format!("{name}.{index}"), depth, variables);
+                    }
+                    _ => values.push(read_msdp_string(data, pos)),
+                }
+                index += 1;
+            }
+            byte if byte > MSDP_ARRAY_CLOSE => {
+                values.push(read_msdp_string(data, pos));
+                index += 1;
+            }
+            // A VAR or TABLE_CLOSE doesn't belong in an array
+            _ => *pos += 1,
+        }
+    }
+    values
+}
+
+/// Moves past a table or array that's nested too deeply to decode, without recursing
+fn skip_msdp_nested(data: &[u8], pos: &mut usize) {
+    let mut depth = 0;
+    while let Some(&byte) = data.get(*pos) {
+        *pos += 1;
+        match byte {
+            MSDP_TABLE_OPEN | MSDP_ARRAY_OPEN => depth += 1,
+            MSDP_TABLE_CLOSE | MSDP_ARRAY_CLOSE => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_msdp_string(data: &[u8], pos: &mut usize) -> String {
+    let start = *pos;
+    while *pos < data.len() && data[*pos] > MSDP_ARRAY_CLOSE {
+        *pos += 1;
+    }
+    String::from_utf8_lossy(&data[start..*pos]).into_owned()
+}
+
+/// Decodes a GMCP message into variables named after its package. Objects are flattened into
+/// `Package.key` variables; other JSON values are kept as they were sent, minus string quotes.
+pub fn decode_gmcp(data: &[u8]) -> Vec<(String, String)> {
+    let message = String::from_utf8_lossy(data);
+    let (package, json) = message.split_once(' ').unwrap_or((&message, ""));
+
+    let mut variables = Vec::new();
+    match serde_json::from_str::<Value>(json.trim()) {
+        Ok(value) => flatten_json(package.trim().to_string(), value, &mut variables),
+        Err(_) if json.trim().is_empty() => {}
+        Err(e) => warn!("Ignoring GMCP {package} with invalid JSON: {e}"),
+    }
+    variables
+}
+
+fn flatten_json(name: String, value: Value, variables: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten_json(format!("{name}.{key}"), value, variables);
+            }
+        }
+        Value::String(text) => variables.push((name, text)),
+        value => variables.push((name, value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_msdp_values() {
+        let mut data = vec![MSDP_VAR];
+        data.extend_from_slice(b"HEALTH");
+        data.push(MSDP_VAL);
+        data.extend_from_slice(b"84");
+        data.push(MSDP_VAR);
+        data.extend_from_slice(b"HEALTH_MAX");
+        data.push(MSDP_VAL);
+        data.extend_from_slice(b"120");
+
+        assert_eq!(
+            decode_msdp(&data),
+            vars(&[("HEALTH", "84"), ("HEALTH_MAX", "120")])
+        );
+    }
+
+    #[test]
+    fn test_decode_msdp_tables_and_arrays() {
+        let mut data = vec![MSDP_VAR];
+        data.extend_from_slice(b"ROOM");
+        data.extend_from_slice(&[MSDP_VAL, MSDP_TABLE_OPEN, MSDP_VAR]);
+        data.extend_from_slice(b"NAME");
+        data.push(MSDP_VAL);
+        data.extend_from_slice(b"Town Square");
+        data.extend_from_slice(&[MSDP_TABLE_CLOSE, MSDP_VAR]);
+        data.extend_from_slice(b"EXITS");
+        data.extend_from_slice(&[MSDP_VAL, MSDP_ARRAY_OPEN, MSDP_VAL]);
+        data.extend_from_slice(b"n");
+        data.push(MSDP_VAL);
+        data.extend_from_slice(b"s");
+        data.push(MSDP_ARRAY_CLOSE);
+
+        assert_eq!(
+            decode_msdp(&data),
+            vars(&[("ROOM.NAME", "Town Square"), ("EXITS", "n,s")])
+        );
+    }
+
+    #[test]
+    fn test_decode_msdp_array_of_tables() {
+        let mut data = vec![MSDP_VAR];
+        data.extend_from_slice(b"GROUP");
+        data.extend_from_slice(&[MSDP_VAL, MSDP_ARRAY_OPEN]);
+        for name in [&b"Frodo"[..], b"Sam"] {
+            data.extend_from_slice(&[MSDP_VAL, MSDP_TABLE_OPEN, MSDP_VAR]);
+            data.extend_from_slice(b"NAME");
+            data.push(MSDP_VAL);
+            data.extend_from_slice(name);
+            data.push(MSDP_TABLE_CLOSE);
+        }
+        data.extend_from_slice(&[MSDP_ARRAY_CLOSE, MSDP_VAR]);
+        data.extend_from_slice(b"HEALTH");
+        data.push(MSDP_VAL);
+        data.extend_from_slice(b"84");
+
+        assert_eq!(
+            decode_msdp(&data),
+            vars(&[
+                ("GROUP.0.NAME", "Frodo"),
+                ("GROUP.1.NAME", "Sam"),
+                ("HEALTH", "84")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_decode_malformed_msdp_arrays() {
+        // Stray control bytes, and an array that's never closed
+        let mut data = vec![MSDP_VAR];
+        data.extend_from_slice(b"EXITS");
+        data.extend_from_slice(&[MSDP_VAL, MSDP_ARRAY_OPEN, MSDP_VAL]);
+        data.extend_from_slice(b"n");
+        data.extend_from_slice(&[MSDP_VAR, MSDP_TABLE_CLOSE, MSDP_VAL]);
+        data.extend_from_slice(b"s");
+        assert_eq!(decode_msdp(&data), vars(&[("EXITS", "n,s")]));
+
+        assert_eq!(
+            decode_msdp(&[MSDP_VAR, b'X', MSDP_VAL, MSDP_ARRAY_OPEN]),
+            vars(&[("X", "")])
+        );
+    }
+
+    #[test]
+    fn test_decode_deeply_nested_msdp() {
+        let mut data = vec![MSDP_VAR, b'X'];
+        for _ in 0..100_000 {
+            data.extend_from_slice(&[MSDP_VAL, MSDP_ARRAY_OPEN]);
+        }
+        data.resize(data.len() + 100_000, MSDP_ARRAY_CLOSE);
+        data.extend_from_slice(&[MSDP_VAR, b'Y', MSDP_VAL, b'1']);
+
+        let variables = decode_msdp(&data);
+        assert_eq!(variables.last(), Some(&("Y".to_string(), "1".to_string())));
+        assert_eq!(variables.len(), 2);
+    }
+
+    #[test]
+    fn test_decode_gmcp() {
+        assert_eq!(
+            decode_gmcp(
+                br#"Char.Vitals { "hp": 84, "maxhp": 120, "status": { "stance": "Aggressive" } }"#
+            ),
+            vars(&[
+                ("Char.Vitals.hp", "84"),
+                ("Char.Vitals.maxhp", "120"),
+                ("Char.Vitals.status.stance", "Aggressive")
+            ])
+        );
+        assert_eq!(
+            decode_gmcp(br#"Room.Name "Town Square""#),
+            vars(&[("Room.Name", "Town Square")])
+        );
+        assert!(decode_gmcp(b"Core.Ping").is_empty());
+        assert!(decode_gmcp(b"Char.Vitals { hp").is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+/// Longest we'll ever wait between two reconnect attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+/// Decides whether, and after how long, a dropped connection should be retried
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    pub base_delay: Duration,
+    pub max_attempts: u32,
+}
+
+impl ReconnectPolicy {
+    /// The delay before the given (1-based) attempt, doubling each time, or None once
+    /// reconnecting is disabled or the attempts are used up
+    pub fn delay_for(&self, attempt: u32) -> Option<Duration> {
+        if !self.enabled || attempt == 0 || attempt > self.max_attempts {
+            return None;
+        }
+
+        let factor = 2u32.saturating_pow(attempt - 1);
+        Some(
+            self.base_delay
+                .checked_mul(factor)
+                .unwrap_or(MAX_RECONNECT_DELAY)
+                .min(MAX_RECONNECT_DELAY),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(enabled: bool) -> ReconnectPolicy {
+        ReconnectPolicy {
+            enabled,
+            base_delay: Duration::from_secs(2),
+            max_attempts: 12,
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = policy(true);
+
+        assert_eq!(policy.delay_for(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay_for(2), Some(Duration::from_secs(4)));
+        assert_eq!(policy.delay_for(3), Some(Duration::from_secs(8)));
+        assert_eq!(policy.delay_for(8), Some(Duration::from_secs(256)));
+        assert_eq!(policy.delay_for(9), Some(MAX_RECONNECT_DELAY));
+        assert_eq!(policy.delay_for(12), Some(MAX_RECONNECT_DELAY));
+    }
+
+    #[test]
+    fn test_attempts_exhausted() {
+        assert_eq!(policy(true).delay_for(13), None);
+        assert_eq!(policy(true).delay_for(0), None);
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert_eq!(policy(false).delay_for(1), None);
+    }
+}
@@ -0,0 +1,233 @@
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const NOP: u8 = 241;
+pub const SE: u8 = 240;
+
+pub const OPTION_ECHO: u8 = 1;
+pub const OPTION_NAWS: u8 = 31;
+pub const OPTION_MSDP: u8 = 69;
+pub const OPTION_MXP: u8 = 91;
+pub const OPTION_GMCP: u8 = 201;
+
+/// Subnegotiations longer than this are dropped, so a server can't grow the buffer without end
+const MAX_SUBNEGOTIATION_LEN: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TelnetEvent {
+    Data(u8),
+    Negotiate { command: u8, option: u8 },
+    Subnegotiate { option: u8, data: Vec<u8> },
+    Command(u8),
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Data,
+    Iac,
+    Negotiate(u8),
+    SubnegotiateOption,
+    Subnegotiate,
+    SubnegotiateIac,
+}
+
+/// Separates telnet commands from the data stream, one byte at a time
+#[derive(Debug, Default)]
+pub struct TelnetParser {
+    state: State,
+    sb_option: u8,
+    sb_data: Vec<u8>,
+    /// Set once the subnegotiation being read has grown past `MAX_SUBNEGOTIATION_LEN`
+    sb_overflowed: bool,
+}
+
+impl TelnetParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse_byte(&mut self, b: u8) -> Option<TelnetEvent> {
+        match self.state {
+            State::Data => {
+                if b == IAC {
+                    self.state = State::Iac;
+                    None
+                } else {
+                    Some(TelnetEvent::Data(b))
+                }
+            }
+            State::Iac => match b {
+                IAC => {
+                    self.state = State::Data;
+                    Some(TelnetEvent::Data(IAC))
+                }
+                DO | DONT | WILL | WONT => {
+                    self.state = State::Negotiate(b);
+                    None
+                }
+                SB => {
+                    self.state = State::SubnegotiateOption;
+                    None
+                }
+                _ => {
+                    self.state = State::Data;
+                    Some(TelnetEvent::Command(b))
+                }
+            },
+            State::Negotiate(command) => {
+                self.state = State::Data;
+                Some(TelnetEvent::Negotiate { command, option: b })
+            }
+            State::SubnegotiateOption => {
+                self.sb_option = b;
+                self.sb_data.clear();
+                self.sb_overflowed = false;
+                self.state = State::Subnegotiate;
+                None
+            }
+            State::Subnegotiate => {
+                if b == IAC {
+                    self.state = State::SubnegotiateIac;
+                } else {
+                    self.push_sb_byte(b);
+                }
+                None
+            }
+            State::SubnegotiateIac => match b {
+                SE if self.sb_overflowed => {
+                    warn!(
+                        "Dropping a subnegotiation for option {} longer than {MAX_SUBNEGOTIATION_LEN} bytes",
+                        self.sb_option
+                    );
+                    self.sb_data.clear();
+                    self.state = State::Data;
+                    None
+                }
+                SE => {
+                    self.state = State::Data;
+                    Some(TelnetEvent::Subnegotiate {
+                        option: self.sb_option,
+                        data: std::mem::take(&mut self.sb_data),
+                    })
+                }
+                IAC => {
+                    self.push_sb_byte(IAC);
+                    self.state = State::Subnegotiate;
+                    None
+                }
+                _ => {
+                    // Malformed; drop what we've collected and resume with the data stream
+                    self.sb_data.clear();
+                    self.state = State::Data;
+                    None
+                }
+            },
+        }
+    }
+
+    fn push_sb_byte(&mut self, b: u8) {
+        if self.sb_data.len() < MAX_SUBNEGOTIATION_LEN {
+            self.sb_data.push(b);
+        } else {
+            self.sb_overflowed = true;
+        }
+    }
+}
+
+pub fn negotiate(command: u8, option: u8) -> [u8; 3] {
+    [IAC, command, option]
+}
+
+/// Encodes a subnegotiation, escaping any IAC bytes in the payload
+pub fn subnegotiate(option: u8, data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len() + 5);
+    bytes.extend_from_slice(&[IAC, SB, option]);
+    for b in data {
+        if *b == IAC {
+            bytes.push(IAC);
+        }
+        bytes.push(*b);
+    }
+    bytes.extend_from_slice(&[IAC, SE]);
+    bytes
+}
+
+/// Encodes the NAWS subnegotiation reporting the terminal size in characters
+pub fn naws(width: u16, height: u16) -> Vec<u8> {
+    let [w1, w2] = width.to_be_bytes();
+    let [h1, h2] = height.to_be_bytes();
+    subnegotiate(OPTION_NAWS, &[w1, w2, h1, h2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(bytes: &[u8]) -> Vec<TelnetEvent> {
+        let mut parser = TelnetParser::new();
+        bytes.iter().filter_map(|b| parser.parse_byte(*b)).collect()
+    }
+
+    #[test]
+    fn test_naws_encoding() {
+        assert_eq!(
+            naws(80, 24),
+            vec![IAC, SB, OPTION_NAWS, 0, 80, 0, 24, IAC, SE]
+        );
+        assert_eq!(
+            naws(300, 40),
+            vec![IAC, SB, OPTION_NAWS, 1, 44, 0, 40, IAC, SE]
+        );
+    }
+
+    #[test]
+    fn test_naws_encoding_escapes_iac() {
+        assert_eq!(
+            naws(255, 511),
+            vec![IAC, SB, OPTION_NAWS, 0, IAC, IAC, 1, IAC, IAC, IAC, SE]
+        );
+    }
+
+    #[test]
+    fn test_parse_negotiation_and_data() {
+        assert_eq!(
+            parse_all(&[b'h', IAC, DO, OPTION_NAWS, b'i', IAC, IAC]),
+            vec![
+                TelnetEvent::Data(b'h'),
+                TelnetEvent::Negotiate {
+                    command: DO,
+                    option: OPTION_NAWS
+                },
+                TelnetEvent::Data(b'i'),
+                TelnetEvent::Data(IAC),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_oversized_subnegotiation_is_dropped() {
+        let mut bytes = vec![IAC, SB, OPTION_MSDP];
+        bytes.resize(MAX_SUBNEGOTIATION_LEN + 10, b'a');
+        bytes.extend_from_slice(&[IAC, SE, b'c']);
+
+        assert_eq!(parse_all(&bytes), vec![TelnetEvent::Data(b'c')]);
+    }
+
+    #[test]
+    fn test_parse_subnegotiation() {
+        assert_eq!(
+            parse_all(&[IAC, SB, 201, b'a', IAC, IAC, b'b', IAC, SE, b'c']),
+            vec![
+                TelnetEvent::Subnegotiate {
+                    option: 201,
+                    data: vec![b'a', IAC, b'b']
+                },
+                TelnetEvent::Data(b'c'),
+            ]
+        );
+    }
+}
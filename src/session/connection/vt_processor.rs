@@ -1,24 +1,45 @@
 use std::sync::Arc;
 
-use vtparse::{CsiParam, VTActor};
+use vtparse::{CsiParam, VTActor, VTParser};
 
 use crate::{
     session::{
-        styled_line::{SpanInfo, Style},
+        styled_line::{LinkAction, LinkSpan, SpanInfo, Style},
         StyledLine,
     },
     trigger::TriggerManager,
 };
 
+mod mxp;
 mod sgr;
+use mxp::{MxpEvent, MxpParser, Tag, TagKind};
 pub use sgr::{AnsiColor, Color};
 
+/// An MXP tag that hasn't been closed yet
+#[derive(Debug)]
+struct OpenTag {
+    tag: Tag,
+    /// The style to go back to once the tag is closed
+    previous_style: Style,
+    /// Where in the current line the tag's text begins
+    begin_pos: usize,
+}
+
 #[derive(Debug)]
 pub struct VtProcessor {
     cursor_style: Style,
     buf: String,
+    /// The current line as it was received, escape codes and all
+    raw_buf: Vec<u8>,
     span_info: Vec<SpanInfo>,
     trigger_manager: Arc<TriggerManager>,
+    /// Set while MXP has been negotiated with the server
+    mxp: Option<MxpParser>,
+    mxp_events: Vec<MxpEvent>,
+    open_tags: Vec<OpenTag>,
+    links: Vec<LinkSpan>,
+    /// Set on the processor `#debug replay` feeds captured lines through
+    replaying: bool,
 }
 
 const INPUT_BUFFER_CAPACITY: usize = 1024;
@@ -26,15 +47,44 @@ const INPUT_BUFFER_CAPACITY: usize = 1024;
 impl VtProcessor {
     pub fn new(trigger_manager: Arc<TriggerManager>) -> Self {
         VtProcessor {
-            cursor_style: Style {
-                fg: Color::AnsiColor {
-                    color: AnsiColor::White,
-                    bold: false,
-                },
-            },
+            cursor_style: Style::default(),
             buf: String::with_capacity(INPUT_BUFFER_CAPACITY),
+            raw_buf: Vec::with_capacity(INPUT_BUFFER_CAPACITY),
             span_info: Vec::new(),
             trigger_manager,
+            mxp: None,
+            mxp_events: Vec::new(),
+            open_tags: Vec::new(),
+            links: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    /// A processor for replaying captured output, whose lines are passed on marked as replayed
+    pub fn for_replay(trigger_manager: Arc<TriggerManager>) -> Self {
+        VtProcessor {
+            replaying: true,
+            ..VtProcessor::new(trigger_manager)
+        }
+    }
+
+    /// Feeds a byte of server output through `vt_parser`, keeping a copy for raw triggers
+    pub fn parse_byte(&mut self, vt_parser: &mut VTParser, b: u8) {
+        self.raw_buf.push(b);
+        vt_parser.parse_byte(b, self);
+    }
+
+    /// Turns MXP parsing on or off, following its telnet negotiation
+    pub fn set_mxp_enabled(&mut self, enabled: bool) {
+        if enabled == self.mxp.is_some() {
+            return;
+        }
+
+        if enabled {
+            self.mxp = Some(MxpParser::new());
+        } else {
+            self.mxp = None;
+            self.close_tags(0);
         }
     }
 
@@ -53,16 +103,27 @@ impl VtProcessor {
 
     pub fn get_remaining_current_line(&mut self) -> StyledLine {
         self.change_style(self.cursor_style);
-        let mut forwarded_spans = self.span_info.clone();
-        forwarded_spans.retain(|info| (info.end_pos - info.begin_pos) > 0);
-        StyledLine::new(&self.buf, forwarded_spans)
+        // Empty and repeated spans are compacted away by the line
+        StyledLine::new(&self.buf, self.span_info.clone()).with_links(self.links.clone())
     }
 
     pub fn notify_end_of_buffer(&mut self) {
         let current_partial_line = Arc::new(self.get_remaining_current_line());
         if !self.buf.is_empty() {
-            self.trigger_manager
-                .process_partial_line(current_partial_line);
+            let is_prompt = self.trigger_manager.process_partial_line(
+                current_partial_line,
+                &String::from_utf8_lossy(&self.raw_buf),
+            );
+
+            if is_prompt {
+                // The prompt is pinned on its own, so what follows it starts a new line
+                for open_tag in &mut self.open_tags {
+                    open_tag.begin_pos = 0;
+                }
+                self.clear_line();
+                self.trigger_manager.request_repaint();
+                return;
+            }
 
             self.span_info.clear();
             self.span_info.push(SpanInfo {
@@ -75,22 +136,142 @@ impl VtProcessor {
     }
 
     fn commit_line(&mut self) {
+        self.with_mxp_events(MxpParser::end_of_line);
+
+        // Tags that are still open carry on to the next line, so their links are split here
+        for open_tag in &mut self.open_tags {
+            if let Some(link) = VtProcessor::link_for(&open_tag.tag, open_tag.begin_pos, &self.buf) {
+                self.links.push(link);
+            }
+            open_tag.begin_pos = 0;
+        }
+
         let current_partial_line = Arc::new(self.get_remaining_current_line());
-        self.trigger_manager
-            .process_incoming_line(current_partial_line);
+        let raw_line = String::from_utf8_lossy(&self.raw_buf);
+        let raw_line = raw_line.trim_end_matches(['\r', '\n']);
+        if self.replaying {
+            self.trigger_manager
+                .process_replayed_line(current_partial_line, raw_line);
+        } else {
+            self.trigger_manager
+                .process_incoming_line(current_partial_line, raw_line);
+        }
+        self.clear_line();
+    }
+
+    fn clear_line(&mut self) {
+        self.raw_buf.clear();
         self.buf.clear();
         self.buf.shrink_to(INPUT_BUFFER_CAPACITY);
         self.span_info.clear();
+        self.links.clear();
     }
 
     fn push_incoming_char(&mut self, ch: char) {
         self.buf.push(ch);
     }
+
+    /// Runs `f` against the MXP parser, then acts on whatever events it produced
+    fn with_mxp_events(&mut self, f: impl FnOnce(&mut MxpParser, &mut Vec<MxpEvent>)) {
+        let Some(mxp) = self.mxp.as_mut() else {
+            return;
+        };
+
+        let mut events = std::mem::take(&mut self.mxp_events);
+        f(mxp, &mut events);
+
+        for event in events.drain(..) {
+            match event {
+                MxpEvent::Text(ch) => self.push_incoming_char(ch),
+                MxpEvent::Open(tag) => self.open_tag(tag),
+                MxpEvent::Close(kind) => {
+                    if let Some(index) = self
+                        .open_tags
+                        .iter()
+                        .rposition(|open_tag| open_tag.tag.kind() == kind)
+                    {
+                        self.close_tags(index);
+                    }
+                }
+                MxpEvent::Reset => self.close_tags(0),
+            }
+        }
+
+        self.mxp_events = events;
+    }
+
+    fn open_tag(&mut self, tag: Tag) {
+        let previous_style = self.cursor_style;
+        let mut style = previous_style;
+
+        match &tag {
+            Tag::Bold => {
+                if let Color::AnsiColor { color, .. } = style.fg {
+                    style.fg = Color::AnsiColor { color, bold: true };
+                }
+            }
+            Tag::Italic => style.italic = true,
+            Tag::Underline | Tag::Send { .. } | Tag::Link { .. } => style.underline = true,
+            Tag::Color { fg } => style.fg = fg.unwrap_or(style.fg),
+        }
+
+        self.open_tags.push(OpenTag {
+            tag,
+            previous_style,
+            begin_pos: self.buf.len(),
+        });
+        self.change_style(style);
+    }
+
+    /// Closes the open tag at `index` along with every tag opened after it
+    fn close_tags(&mut self, index: usize) {
+        if index >= self.open_tags.len() {
+            return;
+        }
+
+        let style = self.open_tags[index].previous_style;
+
+        for open_tag in self.open_tags.drain(index..) {
+            if let Some(link) = VtProcessor::link_for(&open_tag.tag, open_tag.begin_pos, &self.buf) {
+                self.links.push(link);
+            }
+        }
+
+        self.change_style(style);
+    }
+
+    /// The link covering `buf[begin_pos..]`, when `tag` is a link
+    fn link_for(tag: &Tag, begin_pos: usize, buf: &str) -> Option<LinkSpan> {
+        let text = buf.get(begin_pos..).filter(|text| !text.is_empty())?;
+
+        let action = match tag {
+            Tag::Send { href } => {
+                let command = match href {
+                    Some(href) => href.replace("&text;", text),
+                    None => text.to_string(),
+                };
+                // Menus list several commands separated by |; the first is the default
+                LinkAction::Send(command.split('|').next().unwrap_or_default().to_string())
+            }
+            Tag::Link { href } => LinkAction::Url(href.clone()),
+            _ => return None,
+        };
+
+        Some(LinkSpan {
+            begin_pos,
+            end_pos: buf.len(),
+            action,
+        })
+    }
 }
 
 impl VTActor for VtProcessor {
     fn print(&mut self, b: char) {
-        self.push_incoming_char(b);
+        if self.mxp.is_some() {
+            self.with_mxp_events(|mxp, events| mxp.feed(b, events));
+        } else {
+            self.push_incoming_char(b);
+        }
     }
 
     fn execute_c0_or_c1(&mut self, control: u8) {
@@ -125,6 +306,12 @@ impl VTActor for VtProcessor {
         if byte == b'm' {
             let new_style = sgr::process_sgr(self.cursor_style, params);
             self.change_style(new_style)
+        } else if byte == b'z' {
+            let mode = match params.first() {
+                Some(CsiParam::Integer(mode)) => *mode,
+                _ => 0,
+            };
+            self.with_mxp_events(|mxp, events| mxp.set_mode(mode, events));
         }
     }
 
@@ -132,3 +319,111 @@ impl VTActor for VtProcessor {
 
     fn apc_dispatch(&mut self, _data: Vec<u8>) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use regex::Regex;
+
+    use super::*;
+    use crate::script_runtime::RuntimeAction;
+
+    /// Feeds server output through a processor that has negotiated MXP, returning the complete
+    /// lines that come out of it
+    fn process(output: &str) -> Vec<Arc<StyledLine>> {
+        process_reads(None, &[output])
+    }
+
+    /// Feeds each read's worth of output in turn, with the end of the buffer reached after each
+    /// one, as a connection does
+    fn process_reads(prompt_regex: Option<Regex>, reads: &[&str]) -> Vec<Arc<StyledLine>> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (lines_tx, lines_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            while let Some(action) = rx.blocking_recv() {
+                match action {
+                    RuntimeAction::CompileJavascriptAlias(_, reply) => {
+                        if let Some(reply) = Arc::into_inner(reply) {
+                            reply.send(0).ok();
+                        }
+                    }
+                    RuntimeAction::PassthroughCompleteLine(line) => {
+                        lines_tx.send(line).ok();
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let mut processor = VtProcessor::new(Arc::new(TriggerManager::new(tx, ';', prompt_regex)));
+        processor.set_mxp_enabled(true);
+        let mut vt_parser = VTParser::new();
+        for read in reads {
+            for b in read.bytes() {
+                processor.parse_byte(&mut vt_parser, b);
+            }
+            processor.notify_end_of_buffer();
+        }
+
+        // Dropping the processor drops the trigger manager, which ends the thread above
+        drop(processor);
+        lines_rx.iter().collect()
+    }
+
+    #[test]
+    fn test_prompts_are_not_committed_again() {
+        let lines = process_reads(
+            Some(Regex::new(r">\s*$").unwrap()),
+            &["HP:100 MP:50> ", "You rest.\r\n"],
+        );
+
+        let lines: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+        assert_eq!(lines, vec!["You rest."]);
+    }
+
+    #[test]
+    fn test_send_tag_becomes_a_clickable_command() {
+        let lines = process("\x1b[1z<send \"look\">look here</send> or don't\n");
+
+        let line = &lines[0];
+        assert_eq!(line.as_str(), "look here or don't");
+        assert_eq!(
+            line.links,
+            vec![LinkSpan {
+                begin_pos: 0,
+                end_pos: 9,
+                action: LinkAction::Send("look".into()),
+            }]
+        );
+        assert_eq!(line.link_at(5), Some(&LinkAction::Send("look".into())));
+        assert_eq!(line.link_at(10), None);
+    }
+
+    #[test]
+    fn test_links_and_unknown_tags() {
+        let lines = process(
+            "\x1b[1z<frame name=map>See <a href=\"https://example.com\">the site</a></frame>\n",
+        );
+
+        let line = &lines[0];
+        assert_eq!(line.as_str(), "See the site");
+        assert_eq!(
+            line.links,
+            vec![LinkSpan {
+                begin_pos: 4,
+                end_pos: 12,
+                action: LinkAction::Url("https://example.com".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_send_tags_need_a_secure_line() {
+        let lines = process("<send \"drop all\">click me</send>\n");
+
+        assert_eq!(lines[0].as_str(), "click me");
+        assert!(lines[0].links.is_empty());
+    }
+}
@@ -0,0 +1,501 @@
+use super::Color;
+use crate::session::parse_hex_color;
+
+/// Longest tag or entity we'll buffer before deciding it's just text
+const MAX_TAG_LEN: usize = 1024;
+const MAX_ENTITY_LEN: usize = 10;
+
+const NAMED_COLORS: &[(&str, (u8, u8, u8))] = &[
+    ("black", (0, 0, 0)),
+    ("red", (255, 0, 0)),
+    ("green", (0, 128, 0)),
+    ("yellow", (255, 255, 0)),
+    ("blue", (0, 0, 255)),
+    ("magenta", (255, 0, 255)),
+    ("fuchsia", (255, 0, 255)),
+    ("cyan", (0, 255, 255)),
+    ("aqua", (0, 255, 255)),
+    ("white", (255, 255, 255)),
+    ("gray", (128, 128, 128)),
+    ("grey", (128, 128, 128)),
+    ("silver", (192, 192, 192)),
+    ("maroon", (128, 0, 0)),
+    ("olive", (128, 128, 0)),
+    ("lime", (0, 255, 0)),
+    ("navy", (0, 0, 128)),
+    ("purple", (128, 0, 128)),
+    ("teal", (0, 128, 128)),
+    ("orange", (255, 165, 0)),
+];
+
+/// Which tags are honored on the current line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Only formatting tags are allowed
+    #[default]
+    Open,
+    /// All supported tags are allowed
+    Secure,
+    /// Nothing is parsed; `<` and `&` are plain text
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagKind {
+    Bold,
+    Italic,
+    Underline,
+    Color,
+    Send,
+    Link,
+}
+
+impl TagKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "b" | "bold" | "strong" => Some(TagKind::Bold),
+            "i" | "italic" | "em" => Some(TagKind::Italic),
+            "u" | "underline" => Some(TagKind::Underline),
+            "c" | "color" => Some(TagKind::Color),
+            "send" => Some(TagKind::Send),
+            "a" => Some(TagKind::Link),
+            _ => None,
+        }
+    }
+
+    /// Whether the tag may be used outside of a secure line
+    fn is_open(self) -> bool {
+        !matches!(self, TagKind::Send | TagKind::Link)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tag {
+    Bold,
+    Italic,
+    Underline,
+    Color { fg: Option<Color> },
+    /// Sends `href` when clicked, or the tagged text itself when there's no href
+    Send { href: Option<String> },
+    Link { href: String },
+}
+
+impl Tag {
+    pub fn kind(&self) -> TagKind {
+        match self {
+            Tag::Bold => TagKind::Bold,
+            Tag::Italic => TagKind::Italic,
+            Tag::Underline => TagKind::Underline,
+            Tag::Color { .. } => TagKind::Color,
+            Tag::Send { .. } => TagKind::Send,
+            Tag::Link { .. } => TagKind::Link,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MxpEvent {
+    Text(char),
+    Open(Tag),
+    Close(TagKind),
+    /// Every open tag should be closed
+    Reset,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Text,
+    Tag(String),
+    Entity(String),
+}
+
+/// Separates MXP tags and entities from the text they're embedded in, one character at a time.
+/// Tags we don't support are dropped, but the text between them is kept.
+#[derive(Debug, Default)]
+pub struct MxpParser {
+    state: State,
+    default_mode: Mode,
+    line_mode: Mode,
+    /// Set by mode 4: the next tag is parsed as if the line were secure
+    temp_secure: bool,
+}
+
+impl MxpParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a mode switch sent as `ESC [ n z`
+    pub fn set_mode(&mut self, mode: i64, events: &mut Vec<MxpEvent>) {
+        match mode {
+            0 => self.line_mode = Mode::Open,
+            1 => self.line_mode = Mode::Secure,
+            2 => self.line_mode = Mode::Locked,
+            3 => {
+                self.default_mode = Mode::Open;
+                self.line_mode = Mode::Open;
+                events.push(MxpEvent::Reset);
+            }
+            4 => self.temp_secure = true,
+            5 => self.lock_mode(Mode::Open),
+            6 => self.lock_mode(Mode::Secure),
+            7 => self.lock_mode(Mode::Locked),
+            _ => {}
+        }
+    }
+
+    fn lock_mode(&mut self, mode: Mode) {
+        self.default_mode = mode;
+        self.line_mode = mode;
+    }
+
+    pub fn feed(&mut self, ch: char, events: &mut Vec<MxpEvent>) {
+        match &mut self.state {
+            State::Text => match ch {
+                '<' if self.line_mode != Mode::Locked => self.state = State::Tag(String::new()),
+                '&' if self.line_mode != Mode::Locked => self.state = State::Entity(String::new()),
+                _ => events.push(MxpEvent::Text(ch)),
+            },
+            State::Tag(tag) => {
+                if ch == '>' {
+                    let tag = std::mem::take(tag);
+                    self.state = State::Text;
+                    self.parse_tag(&tag, events);
+                } else if tag.len() >= MAX_TAG_LEN {
+                    self.flush(events);
+                    self.feed(ch, events);
+                } else {
+                    tag.push(ch);
+                }
+            }
+            State::Entity(entity) => {
+                if ch == ';' {
+                    let entity = std::mem::take(entity);
+                    self.state = State::Text;
+                    if let Some(decoded) = decode_entity(&entity) {
+                        events.push(MxpEvent::Text(decoded));
+                    } else {
+                        events.push(MxpEvent::Text('&'));
+                        events.extend(entity.chars().map(MxpEvent::Text));
+                        events.push(MxpEvent::Text(';'));
+                    }
+                } else if (ch.is_ascii_alphanumeric() || ch == '#') && entity.len() < MAX_ENTITY_LEN
+                {
+                    entity.push(ch);
+                } else {
+                    self.flush(events);
+                    self.feed(ch, events);
+                }
+            }
+        }
+    }
+
+    /// Called at the end of every line: anything left unfinished was text after all, and the line
+    /// mode goes back to the default. Tags opened on an open line end with it.
+    pub fn end_of_line(&mut self, events: &mut Vec<MxpEvent>) {
+        self.flush(events);
+
+        if self.line_mode == Mode::Open {
+            events.push(MxpEvent::Reset);
+        }
+
+        self.line_mode = self.default_mode;
+        self.temp_secure = false;
+    }
+
+    /// Emits a partially collected tag or entity as plain text
+    fn flush(&mut self, events: &mut Vec<MxpEvent>) {
+        match std::mem::take(&mut self.state) {
+            State::Text => {}
+            State::Tag(tag) => {
+                events.push(MxpEvent::Text('<'));
+                events.extend(tag.chars().map(MxpEvent::Text));
+            }
+            State::Entity(entity) => {
+                events.push(MxpEvent::Text('&'));
+                events.extend(entity.chars().map(MxpEvent::Text));
+            }
+        }
+    }
+
+    fn parse_tag(&mut self, tag: &str, events: &mut Vec<MxpEvent>) {
+        let secure = self.line_mode == Mode::Secure || std::mem::take(&mut self.temp_secure);
+
+        // Definitions (<!ELEMENT ...>) and the like aren't supported
+        if tag.starts_with('!') {
+            return;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            match TagKind::from_name(&name.trim().to_lowercase()) {
+                Some(kind) if secure || kind.is_open() => events.push(MxpEvent::Close(kind)),
+                _ => {}
+            }
+            return;
+        }
+
+        let mut attributes = tokenize(tag.trim_end_matches('/')).into_iter();
+        let Some((None, name)) = attributes.next() else {
+            return;
+        };
+        let Some(kind) = TagKind::from_name(&name.to_lowercase()) else {
+            return;
+        };
+        if !secure && !kind.is_open() {
+            return;
+        }
+
+        let attributes: Vec<_> = attributes.collect();
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|(name, _)| name.as_deref() == Some(key))
+                .or_else(|| attributes.iter().find(|(name, _)| name.is_none()))
+                .map(|(_, value)| value.clone())
+        };
+
+        let tag = match kind {
+            TagKind::Bold => Tag::Bold,
+            TagKind::Italic => Tag::Italic,
+            TagKind::Underline => Tag::Underline,
+            TagKind::Color => Tag::Color {
+                fg: attribute("fore").and_then(|color| parse_color(&color)),
+            },
+            TagKind::Send => Tag::Send {
+                href: attribute("href"),
+            },
+            TagKind::Link => match attribute("href") {
+                Some(href) => Tag::Link { href },
+                None => return,
+            },
+        };
+
+        events.push(MxpEvent::Open(tag));
+    }
+}
+
+/// Splits a tag into its attributes, as `(Some(key), value)` for `key=value` and `(None, value)`
+/// for positional ones. Quotes group words together and are removed.
+fn tokenize(tag: &str) -> Vec<(Option<String>, String)> {
+    let mut attributes = Vec::new();
+    let mut key: Option<String> = None;
+    let mut value = String::new();
+    let mut quote: Option<char> = None;
+    let mut quoted = false;
+
+    for ch in tag.chars() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => value.push(ch),
+            None => match ch {
+                '"' | '\'' => {
+                    quote = Some(ch);
+                    quoted = true;
+                }
+                '=' if key.is_none() && !value.is_empty() => {
+                    key = Some(std::mem::take(&mut value).to_lowercase());
+                }
+                ch if ch.is_whitespace() => {
+                    if !value.is_empty() || quoted {
+                        attributes.push((key.take(), std::mem::take(&mut value)));
+                    }
+                    quoted = false;
+                }
+                _ => value.push(ch),
+            },
+        }
+    }
+
+    if !value.is_empty() || quoted || key.is_some() {
+        attributes.push((key, value));
+    }
+
+    attributes
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let number = entity.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code).filter(|ch| !ch.is_control())
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<Color> {
+    if color.starts_with('#') {
+        let color = parse_hex_color(color).ok()?;
+        return Some(Color::RGB {
+            r: color.red(),
+            g: color.green(),
+            b: color.blue(),
+        });
+    }
+
+    let color = color.to_lowercase();
+
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == color)
+        .map(|(_, (r, g, b))| Color::RGB {
+            r: *r,
+            g: *g,
+            b: *b,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut MxpParser, text: &str) -> Vec<MxpEvent> {
+        let mut events = Vec::new();
+        for ch in text.chars() {
+            parser.feed(ch, &mut events);
+        }
+        events
+    }
+
+    fn secure_parser() -> MxpParser {
+        let mut parser = MxpParser::new();
+        parser.set_mode(6, &mut Vec::new());
+        parser
+    }
+
+    fn text(text: &str) -> Vec<MxpEvent> {
+        text.chars().map(MxpEvent::Text).collect()
+    }
+
+    #[test]
+    fn test_formatting_tags() {
+        let mut parser = MxpParser::new();
+
+        let mut expected = vec![MxpEvent::Open(Tag::Bold)];
+        expected.extend(text("hi"));
+        expected.push(MxpEvent::Close(TagKind::Bold));
+        expected.push(MxpEvent::Open(Tag::Color {
+            fg: Some(Color::RGB { r: 255, g: 0, b: 0 }),
+        }));
+        expected.push(MxpEvent::Open(Tag::Color {
+            fg: Some(Color::RGB {
+                r: 0x12,
+                g: 0x34,
+                b: 0x56,
+            }),
+        }));
+
+        assert_eq!(
+            parse(&mut parser, "<B>hi</b><color fore=red><c #123456>"),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_send_and_links() {
+        let mut parser = secure_parser();
+
+        let mut expected = vec![MxpEvent::Open(Tag::Send {
+            href: Some("look at sign".into()),
+        })];
+        expected.extend(text("sign"));
+        expected.push(MxpEvent::Close(TagKind::Send));
+        expected.push(MxpEvent::Open(Tag::Send { href: None }));
+        expected.push(MxpEvent::Open(Tag::Link {
+            href: "https://example.com".into(),
+        }));
+
+        assert_eq!(
+            parse(
+                &mut parser,
+                r#"<send "look at sign">sign</send><send><a href='https://example.com'>"#
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_open_mode_ignores_secure_tags() {
+        let mut parser = MxpParser::new();
+
+        assert_eq!(parse(&mut parser, "<send look>look</send>"), text("look"));
+
+        // ...unless the next tag is made temporarily secure
+        parser.set_mode(4, &mut Vec::new());
+        assert_eq!(
+            parse(&mut parser, "<send look><send look>"),
+            vec![MxpEvent::Open(Tag::Send {
+                href: Some("look".into())
+            })]
+        );
+    }
+
+    #[test]
+    fn test_unknown_tags_keep_their_text() {
+        let mut parser = secure_parser();
+
+        assert_eq!(
+            parse(&mut parser, "<RNum 1234/><frame name=map>text</frame><!ELEMENT x>"),
+            text("text")
+        );
+    }
+
+    #[test]
+    fn test_entities() {
+        let mut parser = MxpParser::new();
+
+        assert_eq!(
+            parse(&mut parser, "&lt;b&gt; &amp;&#65;&#x42; &bogus; a & b"),
+            text("<b> &AB &bogus; a & b")
+        );
+    }
+
+    #[test]
+    fn test_locked_mode_is_plain_text() {
+        let mut parser = MxpParser::new();
+        parser.set_mode(2, &mut Vec::new());
+
+        assert_eq!(parse(&mut parser, "<b>&lt;"), text("<b>&lt;"));
+
+        // Line modes only last until the end of the line
+        let mut events = Vec::new();
+        parser.end_of_line(&mut events);
+        assert_eq!(parse(&mut parser, "<b>"), vec![MxpEvent::Open(Tag::Bold)]);
+    }
+
+    #[test]
+    fn test_end_of_line() {
+        let mut parser = MxpParser::new();
+        parse(&mut parser, "<b>bold <unfinished");
+
+        let mut events = Vec::new();
+        parser.end_of_line(&mut events);
+
+        let mut expected = text("<unfinished");
+        expected.push(MxpEvent::Reset);
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize(r#"send href="say hello there" hint='a=b' x"#),
+            vec![
+                (None, "send".to_string()),
+                (Some("href".to_string()), "say hello there".to_string()),
+                (Some("hint".to_string()), "a=b".to_string()),
+                (None, "x".to_string()),
+            ]
+        );
+    }
+}
@@ -2,7 +2,7 @@ use vtparse::CsiParam;
 
 use crate::session::styled_line::Style;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum AnsiColor {
     Black,
     Red,
@@ -14,7 +14,7 @@ pub enum AnsiColor {
     White,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Color {
     AnsiColor { color: AnsiColor, bold: bool },
     RGB { r: u8, g: u8, b: u8 },
@@ -22,246 +22,442 @@ pub enum Color {
     Output,
 }
 
-enum SgrState {
-    Ready { style: Style },
-    SetForegroundReceived,
-    SetForegroundAwaitMode,
-    SetForegroundMode2,
-    SetForegroundMode2Red,
-    SetForegroundMode2ReceivedRed { r: u8 },
-    SetForegroundMode2Green { r: u8 },
-    SetForegroundMode2ReceivedGreen { r: u8, g: u8 },
-    SetForegroundMode2Blue { r: u8, g: u8 },
-    SetForegroundMode5,
-    SetForegroundMode5Number,
-    Invalid,
+/// The levels each channel of the 6x6x6 cube in the 256-color palette steps through, as xterm
+/// draws them
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const ANSI_COLORS: [AnsiColor; 8] = [
+    AnsiColor::Black,
+    AnsiColor::Red,
+    AnsiColor::Green,
+    AnsiColor::Yellow,
+    AnsiColor::Blue,
+    AnsiColor::Magenta,
+    AnsiColor::Cyan,
+    AnsiColor::White,
+];
+
+/// Splits SGR parameters into the values between semicolons, each followed by any
+/// colon-separated subparameters (as in `38:2::255:0:0`). Missing values count as 0.
+fn param_groups(params: &[CsiParam]) -> Vec<Vec<i64>> {
+    let mut groups = Vec::new();
+    let mut group = Vec::new();
+    let mut value = None;
+
+    for param in params {
+        match param {
+            CsiParam::Integer(n) => value = Some(*n),
+            CsiParam::P(b':') => group.push(value.take().unwrap_or(0)),
+            CsiParam::P(b';') => {
+                group.push(value.take().unwrap_or(0));
+                groups.push(std::mem::take(&mut group));
+            }
+            _ => {}
+        }
+    }
+    group.push(value.unwrap_or(0));
+    groups.push(group);
+    groups
 }
 
-pub fn process_sgr(initial_style: Style, params: &[CsiParam]) -> Style {
-    let mut state = SgrState::Ready {
-        style: initial_style,
+/// A color from the 256-color palette. The first 16 are the ANSI colors, so the profile's
+/// palette still applies to them.
+fn indexed_color(n: u8) -> Color {
+    match n {
+        0..=15 => Color::AnsiColor {
+            color: ANSI_COLORS[n as usize % 8],
+            bold: n >= 8,
+        },
+        16..=231 => {
+            let n = n - 16;
+            Color::RGB {
+                r: CUBE_LEVELS[n as usize / 36],
+                g: CUBE_LEVELS[n as usize / 6 % 6],
+                b: CUBE_LEVELS[n as usize % 6],
+            }
+        }
+        232..=255 => {
+            let level = 8 + (n - 232) * 10;
+            Color::RGB {
+                r: level,
+                g: level,
+                b: level,
+            }
+        }
+    }
+}
+
+/// The color described after a 38 or 48: `5;n` for the 256-color palette, or `2;r;g;b` for
+/// truecolor. The colon form may put a color space id before the channels, which is ignored.
+fn extended_color(values: &[i64]) -> Option<Color> {
+    let byte = |value: &i64| u8::try_from(*value).ok();
+
+    match values {
+        [5, n, ..] => byte(n).map(indexed_color),
+        [2, _, r, g, b, ..] | [2, r, g, b] => Some(Color::RGB {
+            r: byte(r)?,
+            g: byte(g)?,
+            b: byte(b)?,
+        }),
+        _ => None,
+    }
+}
+
+/// Reads a semicolon-separated extended color from the parameters following a 38 or 48
+fn extended_color_from(groups: &mut impl Iterator<Item = Vec<i64>>) -> Option<Color> {
+    let mode = groups.next()?[0];
+    let count = match mode {
+        5 => 1,
+        2 => 3,
+        _ => return None,
     };
-    for param in params {
-        state = match state {
-            SgrState::Invalid => state,
-            SgrState::Ready { style } => match param {
-                CsiParam::Integer(n) => match n {
-                    0 => SgrState::Ready {
-                        style: Style {
-                            fg: Color::AnsiColor {
-                                color: AnsiColor::White,
-                                bold: false,
-                            },
-                        },
-                    },
-                    1 => SgrState::Ready {
-                        style: Style {
-                            fg: match style.fg {
-                                Color::AnsiColor { color, bold: _bold } => {
-                                    Color::AnsiColor { color, bold: true }
-                                }
-                                _ => style.fg,
-                            },
-                            ..style
-                        },
-                    },
-                    30..=37 => SgrState::Ready {
-                        style: Style {
-                            fg: Color::AnsiColor {
-                                color: match n {
-                                    30 => AnsiColor::Black,
-                                    31 => AnsiColor::Red,
-                                    32 => AnsiColor::Green,
-                                    33 => AnsiColor::Yellow,
-                                    34 => AnsiColor::Blue,
-                                    35 => AnsiColor::Magenta,
-                                    36 => AnsiColor::Cyan,
-                                    37 => AnsiColor::White,
-                                    _ => unreachable!(),
-                                },
-                                bold: match style.fg {
-                                    Color::AnsiColor {
-                                        color: _,
-                                        bold: is_bold,
-                                    } => is_bold,
-                                    _ => false,
-                                },
-                            },
-                            ..style
-                        },
-                    },
-                    90..=97 => SgrState::Ready {
-                        style: Style {
-                            fg: Color::AnsiColor {
-                                color: match n {
-                                    90 => AnsiColor::Black,
-                                    91 => AnsiColor::Red,
-                                    92 => AnsiColor::Green,
-                                    93 => AnsiColor::Yellow,
-                                    94 => AnsiColor::Blue,
-                                    95 => AnsiColor::Magenta,
-                                    96 => AnsiColor::Cyan,
-                                    97 => AnsiColor::White,
-                                    _ => unreachable!(),
-                                },
-                                bold: true,
-                            },
-                            ..style
-                        },
-                    },
-                    38 => SgrState::SetForegroundReceived,
-                    _ => SgrState::Invalid,
-                },
-                _ => SgrState::Ready { style },
-            },
-            SgrState::SetForegroundReceived => match param {
-                CsiParam::P(b';') => SgrState::SetForegroundAwaitMode,
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundAwaitMode => match param {
-                CsiParam::Integer(2) => SgrState::SetForegroundMode2,
-                CsiParam::Integer(5) => SgrState::SetForegroundMode5,
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2 => match param {
-                CsiParam::P(b';') => SgrState::SetForegroundMode2Red,
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2Red => match param {
-                CsiParam::Integer(r) => SgrState::SetForegroundMode2ReceivedRed { r: *r as u8 },
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2ReceivedRed { r } => match param {
-                CsiParam::P(b';') => SgrState::SetForegroundMode2Green { r },
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2Green { r } => match param {
-                CsiParam::Integer(g) => {
-                    SgrState::SetForegroundMode2ReceivedGreen { r, g: *g as u8 }
+
+    let mut values = vec![mode];
+    for _ in 0..count {
+        values.push(groups.next()?[0]);
+    }
+    extended_color(&values)
+}
+
+/// Applies one SGR code other than an extended color. Codes we don't support are ignored.
+fn apply_code(style: &mut Style, code: i64) {
+    let bold = matches!(style.fg, Color::AnsiColor { bold: true, .. });
+
+    match code {
+        0 => *style = Style::default(),
+        1 | 22 => {
+            if let Color::AnsiColor { color, .. } = style.fg {
+                style.fg = Color::AnsiColor {
+                    color,
+                    bold: code == 1,
+                };
+            }
+        }
+        3 => style.italic = true,
+        4 => style.underline = true,
+        5 | 6 => style.blink = true,
+        7 => style.reverse = true,
+        9 => style.strikethrough = true,
+        23 => style.italic = false,
+        24 => style.underline = false,
+        25 => style.blink = false,
+        27 => style.reverse = false,
+        29 => style.strikethrough = false,
+        30..=37 => {
+            style.fg = Color::AnsiColor {
+                color: ANSI_COLORS[(code - 30) as usize],
+                bold,
+            }
+        }
+        39 => {
+            style.fg = Color::AnsiColor {
+                color: AnsiColor::White,
+                bold,
+            }
+        }
+        40..=47 => {
+            style.bg = Some(Color::AnsiColor {
+                color: ANSI_COLORS[(code - 40) as usize],
+                bold: false,
+            })
+        }
+        49 => style.bg = None,
+        90..=97 => {
+            style.fg = Color::AnsiColor {
+                color: ANSI_COLORS[(code - 90) as usize],
+                bold: true,
+            }
+        }
+        100..=107 => {
+            style.bg = Some(Color::AnsiColor {
+                color: ANSI_COLORS[(code - 100) as usize],
+                bold: true,
+            })
+        }
+        _ => {}
+    }
+}
+
+pub fn process_sgr(initial_style: Style, params: &[CsiParam]) -> Style {
+    // A sequence without any parameters resets, like SGR 0
+    if params.is_empty() {
+        return Style::default();
+    }
+
+    let mut style = initial_style;
+    let mut groups = param_groups(params).into_iter();
+
+    while let Some(group) = groups.next() {
+        match group[0] {
+            code @ (38 | 48) => {
+                let color = if group.len() > 1 {
+                    extended_color(&group[1..])
+                } else {
+                    extended_color_from(&mut groups)
+                };
+
+                // Where a malformed color ends can't be known, so nothing after it is applied
+                let Some(color) = color else {
+                    break;
+                };
+                if code == 38 {
+                    style.fg = color;
+                } else {
+                    style.bg = Some(color);
                 }
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2ReceivedGreen { r, g } => match param {
-                CsiParam::P(b';') => SgrState::SetForegroundMode2Blue { r, g },
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode2Blue { r, g } => match param {
-                CsiParam::Integer(b) => SgrState::Ready {
-                    style: Style {
-                        fg: Color::RGB { r, g, b: *b as u8 },
-                    },
-                },
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode5 => match param {
-                CsiParam::P(b';') => SgrState::SetForegroundMode5Number,
-                _ => SgrState::Invalid,
-            },
-            SgrState::SetForegroundMode5Number => match param {
-                CsiParam::Integer(n) => SgrState::Ready {
-                    style: Style {
-                        fg: match n {
-                            16..=231 => {
-                                let n = (n - 16) as f32;
-                                let r = (n / 36.0).floor();
-                                let g = ((n - (r * 36.0)) / 6.0).floor();
-                                let b = n - (r * 36.0) - (g * 6.0);
-                                let mul = 255.0 / 6.0;
-
-                                Color::RGB {
-                                    r: (r * mul).round() as u8,
-                                    g: (g * mul).round() as u8,
-                                    b: (b * mul).round() as u8,
-                                }
-                            }
-                            232..=255 => {
-                                let range = 255.0 / (255.0 - 232.0);
-                                let val = (range * (n - 232) as f32).round() as u8;
-
-                                Color::RGB {
-                                    r: val,
-                                    g: val,
-                                    b: val,
-                                }
-                            }
-                            0 => Color::AnsiColor {
-                                color: AnsiColor::Black,
-                                bold: false,
-                            },
-                            1 => Color::AnsiColor {
-                                color: AnsiColor::Red,
-                                bold: false,
-                            },
-                            2 => Color::AnsiColor {
-                                color: AnsiColor::Green,
-                                bold: false,
-                            },
-                            3 => Color::AnsiColor {
-                                color: AnsiColor::Yellow,
-                                bold: false,
-                            },
-                            4 => Color::AnsiColor {
-                                color: AnsiColor::Blue,
-                                bold: false,
-                            },
-                            5 => Color::AnsiColor {
-                                color: AnsiColor::Magenta,
-                                bold: false,
-                            },
-                            6 => Color::AnsiColor {
-                                color: AnsiColor::Cyan,
-                                bold: false,
-                            },
-                            7 => Color::AnsiColor {
-                                color: AnsiColor::White,
-                                bold: false,
-                            },
-                            8 => Color::AnsiColor {
-                                color: AnsiColor::Black,
-                                bold: true,
-                            },
-                            9 => Color::AnsiColor {
-                                color: AnsiColor::Red,
-                                bold: true,
-                            },
-                            10 => Color::AnsiColor {
-                                color: AnsiColor::Green,
-                                bold: true,
-                            },
-                            11 => Color::AnsiColor {
-                                color: AnsiColor::Yellow,
-                                bold: true,
-                            },
-                            12 => Color::AnsiColor {
-                                color: AnsiColor::Blue,
-                                bold: true,
-                            },
-                            13 => Color::AnsiColor {
-                                color: AnsiColor::Magenta,
-                                bold: true,
-                            },
-                            14 => Color::AnsiColor {
-                                color: AnsiColor::Cyan,
-                                bold: true,
-                            },
-                            15 => Color::AnsiColor {
-                                color: AnsiColor::White,
-                                bold: true,
-                            },
-                            _ => Color::AnsiColor {
-                                color: AnsiColor::White,
-                                bold: false,
-                            },
-                        },
-                    },
-                },
-                _ => SgrState::Invalid,
-            },
+            }
+            code => apply_code(&mut style, code),
         }
     }
 
-    match state {
-        SgrState::Ready { style } => style,
-        _ => initial_style,
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parameters of an SGR sequence such as `ESC[1;31m`, as the parser hands them over
+    fn params(codes: &[i64]) -> Vec<CsiParam> {
+        let mut params = Vec::new();
+        for (idx, code) in codes.iter().enumerate() {
+            if idx > 0 {
+                params.push(CsiParam::P(b';'));
+            }
+            params.push(CsiParam::Integer(*code));
+        }
+        params
+    }
+
+    fn style_after(sequences: &[&[i64]]) -> Style {
+        sequences.iter().fold(Style::default(), |style, codes| {
+            process_sgr(style, &params(codes))
+        })
+    }
+
+    #[test]
+    fn test_blink_and_reverse() {
+        let style = style_after(&[&[5], &[7]]);
+        assert!(style.blink);
+        assert!(style.reverse);
+
+        let style = style_after(&[&[1, 31, 5, 7]]);
+        assert!(style.blink && style.reverse);
+        assert_eq!(
+            style.fg,
+            Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: true
+            }
+        );
+
+        // Rapid blink is treated like blink
+        assert!(style_after(&[&[6]]).blink);
+    }
+
+    #[test]
+    fn test_256_colors() {
+        assert_eq!(
+            style_after(&[&[38, 5, 196]]).fg,
+            Color::RGB { r: 255, g: 0, b: 0 }
+        );
+        assert_eq!(
+            style_after(&[&[38, 5, 244]]).fg,
+            Color::RGB {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+        // The first 16 stay ANSI colors, so the profile's palette applies to them
+        assert_eq!(
+            style_after(&[&[38, 5, 9]]).fg,
+            Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: true
+            }
+        );
+        assert_eq!(
+            style_after(&[&[48, 5, 21]]).bg,
+            Some(Color::RGB { r: 0, g: 0, b: 255 })
+        );
+    }
+
+    #[test]
+    fn test_truecolor() {
+        let style = style_after(&[&[38, 2, 255, 128, 0, 48, 2, 0, 0, 64]]);
+        assert_eq!(
+            style.fg,
+            Color::RGB {
+                r: 255,
+                g: 128,
+                b: 0
+            }
+        );
+        assert_eq!(style.bg, Some(Color::RGB { r: 0, g: 0, b: 64 }));
+    }
+
+    #[test]
+    fn test_colon_separated_colors() {
+        // ESC[38:2::10:20:30;48:5:196m, with and without a color space id
+        let with_color_space = [
+            CsiParam::Integer(38),
+            CsiParam::P(b':'),
+            CsiParam::Integer(2),
+            CsiParam::P(b':'),
+            CsiParam::P(b':'),
+            CsiParam::Integer(10),
+            CsiParam::P(b':'),
+            CsiParam::Integer(20),
+            CsiParam::P(b':'),
+            CsiParam::Integer(30),
+            CsiParam::P(b';'),
+            CsiParam::Integer(48),
+            CsiParam::P(b':'),
+            CsiParam::Integer(5),
+            CsiParam::P(b':'),
+            CsiParam::Integer(196),
+        ];
+        let style = process_sgr(Style::default(), &with_color_space);
+        assert_eq!(
+            style.fg,
+            Color::RGB {
+                r: 10,
+                g: 20,
+                b: 30
+            }
+        );
+        assert_eq!(style.bg, Some(Color::RGB { r: 255, g: 0, b: 0 }));
+
+        let without_color_space: Vec<_> = params(&[38])
+            .into_iter()
+            .chain([
+                CsiParam::P(b':'),
+                CsiParam::Integer(2),
+                CsiParam::P(b':'),
+                CsiParam::Integer(1),
+                CsiParam::P(b':'),
+                CsiParam::Integer(2),
+                CsiParam::P(b':'),
+                CsiParam::Integer(3),
+            ])
+            .collect();
+        assert_eq!(
+            process_sgr(Style::default(), &without_color_space).fg,
+            Color::RGB { r: 1, g: 2, b: 3 }
+        );
+    }
+
+    #[test]
+    fn test_extended_colors_mixed_with_legacy_codes() {
+        // Codes before and after an extended color all apply
+        let style = style_after(&[&[4, 38, 5, 46, 1, 44, 3]]);
+        assert_eq!(style.fg, Color::RGB { r: 0, g: 255, b: 0 });
+        assert!(style.underline && style.italic);
+        assert_eq!(
+            style.bg,
+            Some(Color::AnsiColor {
+                color: AnsiColor::Blue,
+                bold: false
+            })
+        );
+
+        // A legacy color after an extended one replaces it, keeping the other attributes
+        let style = style_after(&[&[1, 38, 2, 1, 2, 3], &[32]]);
+        assert_eq!(
+            style.fg,
+            Color::AnsiColor {
+                color: AnsiColor::Green,
+                bold: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_default_color_resets() {
+        let style = style_after(&[&[1, 31, 41], &[39]]);
+        assert_eq!(
+            style.fg,
+            Color::AnsiColor {
+                color: AnsiColor::White,
+                bold: true
+            }
+        );
+        assert!(style.bg.is_some());
+
+        let style = style_after(&[&[1, 31, 41], &[39, 49, 22]]);
+        assert_eq!(style, Style::default());
+
+        let style = style_after(&[&[48, 5, 17], &[49]]);
+        assert_eq!(style.bg, None);
+    }
+
+    #[test]
+    fn test_italic_and_strikethrough() {
+        let style = style_after(&[&[3, 9]]);
+        assert!(style.italic && style.strikethrough);
+
+        let style = style_after(&[&[3, 9], &[23]]);
+        assert!(!style.italic && style.strikethrough);
+
+        let style = style_after(&[&[3, 9], &[29]]);
+        assert!(style.italic && !style.strikethrough);
+    }
+
+    #[test]
+    fn test_malformed_params() {
+        // An unknown mode, a truncated color and an out of range channel are all dropped, along
+        // with anything after them, but not what came before
+        let style = style_after(&[&[31, 38, 7, 1, 4]]);
+        assert_eq!(
+            style.fg,
+            Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: false
+            }
+        );
+        assert!(!style.underline);
+
+        assert_eq!(style_after(&[&[38, 5]]), Style::default());
+        assert_eq!(style_after(&[&[38, 2, 300, 0, 0]]), Style::default());
+        assert_eq!(
+            style_after(&[&[4, 48, 2, 1, 2]]),
+            Style {
+                underline: true,
+                ..Style::default()
+            }
+        );
+
+        // Unsupported codes are skipped rather than throwing the whole sequence away
+        let style = style_after(&[&[53, 31, 8]]);
+        assert_eq!(
+            style.fg,
+            Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: false
+            }
+        );
+
+        // Empty parameters count as 0, so `ESC[31;m` resets
+        let style = process_sgr(
+            style_after(&[&[4]]),
+            &[CsiParam::Integer(31), CsiParam::P(b';')],
+        );
+        assert_eq!(style, Style::default());
+    }
+
+    #[test]
+    fn test_blink_and_reverse_are_reset() {
+        let style = style_after(&[&[5, 7], &[27]]);
+        assert!(style.blink);
+        assert!(!style.reverse);
+
+        let style = style_after(&[&[5, 7], &[25]]);
+        assert!(!style.blink);
+        assert!(style.reverse);
+
+        assert_eq!(style_after(&[&[31, 5, 7], &[0]]), Style::default());
+        // `ESC[m` is the same as `ESC[0m`
+        assert_eq!(style_after(&[&[31, 5, 7], &[]]), Style::default());
     }
 }
@@ -0,0 +1,92 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use vtparse::VTParser;
+
+use super::connection::vt_processor::VtProcessor;
+use crate::trigger::TriggerManager;
+
+/// Where captures are written, under the profile's directory
+pub const DEBUG_DIR: &str = "debug";
+
+/// Writes raw lines, escape codes and all, to a new file in `dir`, returning its path
+pub fn write_capture(dir: &Path, lines: &[String]) -> Result<PathBuf> {
+    fs::create_dir_all(dir).context("Could not create the debug directory")?;
+
+    let path = dir.join(format!(
+        "capture-{}.txt",
+        chrono::Local::now().format("%Y%m%d-%H%M%S%.3f")
+    ));
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(&path, contents).context("Could not write the capture")?;
+
+    Ok(path)
+}
+
+/// Reads a capture written by `write_capture`. Only the names of files directly in `dir` are
+/// accepted, since replayed lines fire triggers that can send to the server.
+pub fn read_capture(dir: &Path, file_name: &str) -> Result<Vec<String>> {
+    let mut components = Path::new(file_name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        bail!("Captures are replayed by file name, from the {DEBUG_DIR} directory");
+    }
+
+    let path = dir.join(file_name);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Could not read capture {}", path.display()))?;
+
+    Ok(contents.lines().map(str::to_string).collect())
+}
+
+/// Feeds captured lines through a fresh VT processor into the triggers, as if they had just
+/// arrived from the server. Live output keeps going through the connection's own processor.
+pub fn replay(trigger_manager: &Arc<TriggerManager>, lines: &[String]) {
+    let mut vt_parser = VTParser::new();
+    let mut vt_processor = VtProcessor::for_replay(trigger_manager.clone());
+    for line in lines {
+        for b in line.bytes().chain(*b"\r\n") {
+            vt_processor.parse_byte(&mut vt_parser, b);
+        }
+    }
+    vt_processor.notify_end_of_buffer();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_round_trip() {
+        let dir = std::env::temp_dir().join(format!("smudgy-debug-{}", std::process::id()));
+        let lines = vec![
+            "\x1b[1;31mThe goblin hits you.\x1b[0m".to_string(),
+            String::new(),
+            "You flee.".to_string(),
+        ];
+
+        let path = write_capture(&dir, &lines).unwrap();
+        let file_name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(read_capture(&dir, file_name).unwrap(), lines);
+
+        let outside = dir.join(file_name);
+        for path in [
+            outside.to_str().unwrap(),
+            "../secrets.txt",
+            "sub/capture.txt",
+            "",
+            "..",
+        ] {
+            assert!(read_capture(&dir, path).is_err(), "{path} was read");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
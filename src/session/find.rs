@@ -0,0 +1,115 @@
+use std::ops::Range;
+
+/// Where a search query was found: the view's number for the line and the character columns the
+/// match covers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FindMatch {
+    pub line: usize,
+    pub columns: Range<usize>,
+}
+
+/// Which way to move through the matches, which are kept in the order their lines appear
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Older,
+    Newer,
+}
+
+/// Where each (ASCII case-insensitive, non-overlapping) occurrence of `query` is in `text`, in
+/// character columns
+pub fn find_in_line(text: &str, query: &str) -> Vec<Range<usize>> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    // ASCII lowercasing keeps byte offsets lined up with the original text
+    let haystack = text.to_ascii_lowercase();
+    let needle = query.to_ascii_lowercase();
+
+    let mut columns = Vec::new();
+    let mut column = 0;
+    let mut searched_to = 0;
+    for (offset, found) in haystack.match_indices(&needle) {
+        column += text[searched_to..offset].chars().count();
+        let width = found.chars().count();
+        columns.push(column..column + width);
+        column += width;
+        searched_to = offset + found.len();
+    }
+    columns
+}
+
+/// The match to move to from `current`, wrapping around at either end. With nothing selected
+/// yet, searching for older matches starts from the newest one, and vice versa.
+pub fn step(current: Option<usize>, count: usize, direction: Direction) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+
+    Some(match (current.filter(|idx| *idx < count), direction) {
+        (None, Direction::Older) => count - 1,
+        (None, Direction::Newer) => 0,
+        (Some(0), Direction::Older) => count - 1,
+        (Some(idx), Direction::Older) => idx - 1,
+        (Some(idx), Direction::Newer) => (idx + 1) % count,
+    })
+}
+
+/// Shown next to the find bar
+pub fn status(current: Option<usize>, count: usize) -> String {
+    match (current, count) {
+        (_, 0) => "No matches".to_string(),
+        (Some(idx), count) => format!("{} of {count}", idx + 1),
+        (None, 1) => "1 match".to_string(),
+        (None, count) => format!("{count} matches"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_in_line() {
+        assert_eq!(
+            find_in_line("The Goblin hits the goblin.", "goblin"),
+            vec![4..10, 20..26]
+        );
+        assert_eq!(find_in_line("aaaa", "aa"), vec![0..2, 2..4]);
+        assert_eq!(find_in_line("Café goblin", "GOBLIN"), vec![5..11]);
+        assert!(find_in_line("nothing here", "goblin").is_empty());
+        assert!(find_in_line("anything", "").is_empty());
+    }
+
+    #[test]
+    fn test_step_cycles_older() {
+        assert_eq!(step(None, 3, Direction::Older), Some(2));
+        assert_eq!(step(Some(2), 3, Direction::Older), Some(1));
+        assert_eq!(step(Some(1), 3, Direction::Older), Some(0));
+        assert_eq!(step(Some(0), 3, Direction::Older), Some(2));
+    }
+
+    #[test]
+    fn test_step_cycles_newer() {
+        assert_eq!(step(None, 3, Direction::Newer), Some(0));
+        assert_eq!(step(Some(0), 3, Direction::Newer), Some(1));
+        assert_eq!(step(Some(2), 3, Direction::Newer), Some(0));
+    }
+
+    #[test]
+    fn test_step_without_matches() {
+        assert_eq!(step(None, 0, Direction::Older), None);
+        assert_eq!(step(Some(4), 0, Direction::Newer), None);
+        // A stale index from a longer list of matches starts over
+        assert_eq!(step(Some(7), 3, Direction::Older), Some(2));
+        assert_eq!(step(Some(0), 1, Direction::Older), Some(0));
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(status(None, 0), "No matches");
+        assert_eq!(status(None, 1), "1 match");
+        assert_eq!(status(None, 4), "4 matches");
+        assert_eq!(status(Some(1), 4), "2 of 4");
+    }
+}
@@ -1,29 +1,47 @@
-use std::{
-    collections::{HashSet, VecDeque},
-    sync::Arc,
-};
+use std::{collections::VecDeque, sync::Arc};
 
-use regex::Regex;
-
-use super::StyledLine;
+use super::{completion::CompletionProvider, StyledLine};
 
 pub struct IncomingLineHistory {
     max_len: usize,
     lines: VecDeque<Arc<StyledLine>>,
     line_terminated: bool,
+    completions: CompletionProvider,
 }
 
 impl IncomingLineHistory {
-    pub fn new() -> Self {
+    pub fn new(max_len: usize) -> Self {
         IncomingLineHistory {
-            max_len: 10000,
+            max_len: max_len.max(1),
             lines: VecDeque::new(),
             line_terminated: false,
+            completions: CompletionProvider::default(),
         }
     }
 
-    pub fn commit_current_line(&mut self) {
+    /// Changes how many lines are kept, dropping the oldest ones if there are now too many
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len.max(1);
+        let excess = self.lines.len().saturating_sub(self.max_len);
+        self.lines.drain(..excess);
+    }
+
+    /// Marks the current line as complete, returning it
+    pub fn commit_current_line(&mut self) -> Option<Arc<StyledLine>> {
         self.line_terminated = true;
+
+        let line = self.lines.back()?;
+        self.completions.index_line(line.as_str());
+        Some(line.clone())
+    }
+
+    /// The most recent line, which may still be arriving
+    pub fn last_line(&self) -> Option<Arc<StyledLine>> {
+        self.lines.back().cloned()
+    }
+
+    pub fn completions_mut(&mut self) -> &mut CompletionProvider {
+        &mut self.completions
     }
 
     pub fn extend_line(&mut self, line_in: Arc<StyledLine>) {
@@ -37,7 +55,7 @@ impl IncomingLineHistory {
         } else {
             match self.lines.pop_back() {
                 Some(line) => {
-                    self.lines.push_back(line);
+                    self.lines.push_back(Arc::new(line.append(&line_in)));
                 }
                 None => {
                     self.lines.push_back(line_in);
@@ -45,33 +63,4 @@ impl IncomingLineHistory {
             }
         }
     }
-
-    pub fn find_recent_word_by_prefix(
-        &self,
-        prefix: &str,
-        skip_words_in: Option<&HashSet<String>>,
-        n_recent_lines: usize,
-    ) -> Option<String> {
-        let regex = Regex::new(r"\b").unwrap();
-        let rr = &regex;
-        let lowercase_prefix = prefix.to_lowercase();
-        let found = self
-            .lines
-            .iter()
-            .rev()
-            .take(n_recent_lines)
-            .flat_map(|line| line.as_str().split(rr))
-            .find_map(|str| {
-                if let Some(history) = skip_words_in {
-                    if history.contains(str) {
-                        return None;
-                    }
-                }
-
-                str.to_lowercase()
-                    .starts_with(lowercase_prefix.as_str())
-                    .then_some(str.to_string())
-            });
-        found
-    }
 }
@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use deno_core::serde::{Deserialize, Serialize};
+use regex::Regex;
+
+/// One step of a profile's login sequence: once the server's output matches `expect`, `send` is
+/// sent. Prompts like `Name:` usually arrive without a newline, so partial lines are matched too.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoginStep {
+    pub expect: String,
+    pub send: String,
+    /// Sent without being echoed, as for a password
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl LoginStep {
+    pub fn validate(&self) -> Result<()> {
+        Regex::new(&self.expect)
+            .map(|_| ())
+            .map_err(|e| anyhow!("Invalid login prompt pattern '{}': {e}", self.expect))
+    }
+}
+
+/// Works through a login sequence as the server's output arrives, one step at a time
+#[derive(Debug, Default)]
+pub struct LoginSequence {
+    steps: Vec<(Regex, LoginStep)>,
+    next: usize,
+}
+
+impl LoginSequence {
+    /// Starts the sequence over, skipping any steps with invalid patterns
+    pub fn start(&mut self, steps: &[LoginStep]) {
+        self.steps = steps
+            .iter()
+            .filter_map(|step| Some((Regex::new(&step.expect).ok()?, step.clone())))
+            .collect();
+        self.next = 0;
+    }
+
+    pub fn cancel(&mut self) {
+        self.steps.clear();
+        self.next = 0;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.next < self.steps.len()
+    }
+
+    /// Matches the line being received so far against the next step, returning that step once
+    /// it matches. A step is only ever taken once, however often the line is seen again.
+    pub fn on_line(&mut self, text: &str) -> Option<&LoginStep> {
+        let (expect, step) = self.steps.get(self.next)?;
+        if !expect.is_match(text) {
+            return None;
+        }
+        self.next += 1;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(expect: &str, send: &str, hidden: bool) -> LoginStep {
+        LoginStep {
+            expect: expect.to_string(),
+            send: send.to_string(),
+            hidden,
+        }
+    }
+
+    #[test]
+    fn test_login_handshake() {
+        let mut login = LoginSequence::default();
+        login.start(&[
+            step("^Name:", "gandalf", false),
+            step("^Password:", "mellon", true),
+        ]);
+
+        // The server's banner, then the name prompt arriving in two pieces
+        assert_eq!(login.on_line("Welcome to the Shire!"), None);
+        assert_eq!(login.on_line("Na"), None);
+        assert_eq!(
+            login.on_line("Name: "),
+            Some(&step("^Name:", "gandalf", false))
+        );
+        // The same partial line seen again, once more has arrived, doesn't send the name twice
+        assert_eq!(login.on_line("Name: "), None);
+
+        assert_eq!(
+            login.on_line("Password: "),
+            Some(&step("^Password:", "mellon", true))
+        );
+        assert!(!login.is_running());
+        assert_eq!(login.on_line("Password: "), None);
+    }
+
+    #[test]
+    fn test_cancel_and_restart() {
+        let steps = [step("Name:", "gandalf", false)];
+        let mut login = LoginSequence::default();
+        login.start(&steps);
+        login.cancel();
+        assert_eq!(login.on_line("Name:"), None);
+
+        login.start(&steps);
+        assert!(login.is_running());
+        assert!(login.on_line("Name:").is_some());
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(step("(", "x", false).validate().is_err());
+        assert!(step("^Name:", "x", false).validate().is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    cell::Cell,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use super::StyledLine;
+
+/// How many lines can wait to be written before new ones are dropped, for when nothing has opened
+/// the named pipe yet or its reader can't keep up
+const QUEUED_LINES: usize = 1_000;
+
+/// Mirrors completed output lines as plain text to a file or named pipe, so that other programs
+/// can follow the session. Opening a named pipe blocks until something reads from it, so the
+/// writing happens on a thread of its own.
+pub struct OutputSink {
+    tx: SyncSender<String>,
+    /// Set while lines are being dropped, so that's only logged once each time it starts
+    dropping: Cell<bool>,
+    /// Disconnected once the writing thread is done
+    done_rx: Receiver<()>,
+}
+
+impl OutputSink {
+    pub fn new(path: PathBuf) -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUED_LINES);
+        let (done_tx, done_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            OutputSink::run(&path, &rx);
+            drop(done_tx);
+        });
+
+        Self {
+            tx,
+            dropping: Cell::new(false),
+            done_rx,
+        }
+    }
+
+    fn run(path: &Path, rx: &Receiver<String>) {
+        let file = match OpenOptions::new().append(true).create(true).open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Could not open output sink {}: {e}", path.display());
+                return;
+            }
+        };
+
+        let mut writer = BufWriter::new(file);
+        while let Ok(mut text) = rx.recv() {
+            // Write out whatever else has queued up before flushing
+            while let Ok(more) = rx.try_recv() {
+                text.push_str(&more);
+            }
+
+            if let Err(e) = writer
+                .write_all(text.as_bytes())
+                .and_then(|()| writer.flush())
+            {
+                log::warn!(
+                    "Output sink {} stopped accepting output: {e}",
+                    path.display()
+                );
+                return;
+            }
+        }
+    }
+
+    /// Queues a completed line. Lines are dropped while the queue is full, and once the sink
+    /// has failed.
+    pub fn write_line(&self, line: &StyledLine) {
+        match self.tx.try_send(plain_text(line)) {
+            Ok(()) => self.dropping.set(false),
+            Err(TrySendError::Full(_)) if !self.dropping.replace(true) => {
+                log::warn!("Output sink is falling behind; dropping lines until it catches up");
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Waits up to `timeout` for the queued lines to be written out and the file closed,
+    /// returning whether they were. A named pipe nobody has opened never will be.
+    pub fn close(self, timeout: Duration) -> bool {
+        drop(self.tx);
+        matches!(
+            self.done_rx.recv_timeout(timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        )
+    }
+}
+
+/// The text of a line without styling or control characters, terminated by a newline
+pub fn plain_text(line: &StyledLine) -> String {
+    let mut text = line.plain_text().into_owned();
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::styled_line::{Color, SpanInfo, Style};
+
+    #[test]
+    fn test_styles_are_dropped() {
+        let line = StyledLine::new(
+            "You hit the goblin.",
+            vec![
+                SpanInfo {
+                    style: Style {
+                        fg: Color::Output,
+                        ..Style::default()
+                    },
+                    begin_pos: 0,
+                    end_pos: 8,
+                },
+                SpanInfo {
+                    style: Style {
+                        fg: Color::Echo,
+                        ..Style::default()
+                    },
+                    begin_pos: 8,
+                    end_pos: 19,
+                },
+            ],
+        );
+
+        assert_eq!(plain_text(&line), "You hit the goblin.\n");
+    }
+
+    #[test]
+    fn test_control_characters_are_stripped() {
+        let line = StyledLine::from_output_str("Score:\t10\r\x07");
+
+        assert_eq!(plain_text(&line), "Score:\t10\n");
+    }
+
+    #[test]
+    fn test_close_writes_queued_lines() {
+        let path = std::env::temp_dir().join(format!("smudgy-sink-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let sink = OutputSink::new(path.clone());
+        for n in 0..100 {
+            sink.write_line(&StyledLine::from_output_str(&format!("Line {n}")));
+        }
+        assert!(sink.close(Duration::from_secs(5)));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().count(), 100);
+        assert_eq!(written.lines().last(), Some("Line 99"));
+    }
+
+    #[test]
+    fn test_appended_lines() {
+        let line = StyledLine::from_output_str("Hit points: ")
+            .append(&StyledLine::from_echo_str("42"));
+
+        assert_eq!(plain_text(&line), "Hit points: 42\n");
+    }
+}
@@ -0,0 +1,153 @@
+use super::StyledLine;
+
+/// A character cell in the terminal: the number of the line it's on (as counted by the view,
+/// so it stays put as older lines are dropped) and its character index within that line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SelectionPoint {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The cells between where a drag started and where the pointer is now, inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: SelectionPoint,
+    pub head: SelectionPoint,
+}
+
+impl Selection {
+    pub fn new(point: SelectionPoint) -> Self {
+        Self {
+            anchor: point,
+            head: point,
+        }
+    }
+
+    /// A click that never moved selects nothing
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// The first and last selected cells
+    pub fn ordered(&self) -> (SelectionPoint, SelectionPoint) {
+        if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        }
+    }
+}
+
+/// The plain text of the selected cells, with a newline between lines. `lines` are numbered the
+/// same way as the selection's points; ones outside of it are skipped.
+pub fn selected_text<'a>(
+    lines: impl IntoIterator<Item = (usize, &'a StyledLine)>,
+    selection: &Selection,
+) -> String {
+    let (start, end) = selection.ordered();
+
+    let byte_offset = |line: &StyledLine, column: usize| {
+        line.text
+            .char_indices()
+            .nth(column)
+            .map_or(line.text.len(), |(offset, _)| offset)
+    };
+
+    lines
+        .into_iter()
+        .filter(|(number, _)| (start.line..=end.line).contains(number))
+        .map(|(number, line)| {
+            let begin = if number == start.line {
+                byte_offset(line, start.column)
+            } else {
+                0
+            };
+            let end = if number == end.line {
+                byte_offset(line, end.column + 1)
+            } else {
+                line.text.len()
+            };
+
+            line.slice(begin..end.max(begin)).plain_text().into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(line: usize, column: usize) -> SelectionPoint {
+        SelectionPoint { line, column }
+    }
+
+    fn selection(anchor: SelectionPoint, head: SelectionPoint) -> Selection {
+        Selection { anchor, head }
+    }
+
+    fn numbered(lines: &[StyledLine]) -> impl Iterator<Item = (usize, &StyledLine)> {
+        lines.iter().enumerate().map(|(idx, line)| (idx + 10, line))
+    }
+
+    #[test]
+    fn test_single_line() {
+        let lines = [StyledLine::from_output_str("You hit the goblin.")];
+
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 4), point(10, 6))),
+            "hit"
+        );
+        // Dragging backwards selects the same cells
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 6), point(10, 4))),
+            "hit"
+        );
+        // Past the end of the line is clamped
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 12), point(10, 100))),
+            "goblin."
+        );
+    }
+
+    #[test]
+    fn test_multiple_lines() {
+        let lines = [
+            StyledLine::from_output_str("The goblin attacks!"),
+            StyledLine::from_output_str("It misses."),
+            StyledLine::from_output_str("You hit the goblin."),
+            StyledLine::from_output_str("It dies."),
+        ];
+
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 11), point(12, 2))),
+            "attacks!\nIt misses.\nYou"
+        );
+    }
+
+    #[test]
+    fn test_escape_codes_are_stripped() {
+        let lines = [StyledLine::from_output_str("Score:\x07 10")];
+
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 0), point(10, 9))),
+            "Score: 10"
+        );
+    }
+
+    #[test]
+    fn test_multibyte_characters() {
+        let lines = [StyledLine::from_output_str("Café crème")];
+
+        assert_eq!(
+            selected_text(numbered(&lines), &selection(point(10, 5), point(10, 9))),
+            "crème"
+        );
+    }
+
+    #[test]
+    fn test_click_is_empty() {
+        assert!(Selection::new(point(3, 4)).is_empty());
+        assert!(!selection(point(3, 4), point(3, 5)).is_empty());
+    }
+}
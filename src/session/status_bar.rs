@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use deno_core::serde::{Deserialize, Serialize};
+
+use super::ansi_palette::parse_hex_color;
+
+/// One field of a session's status bar, bound to a variable the server sends over MSDP or GMCP.
+/// GMCP variables are named after their package, e.g. `Char.Vitals.hp`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusBinding {
+    pub label: String,
+    pub variable: String,
+    /// When set, the field is drawn as a bar filled to `variable` out of this variable's value
+    #[serde(default)]
+    pub max_variable: String,
+    /// A `#rrggbb` color for the bar; empty uses the default
+    #[serde(default)]
+    pub color: String,
+}
+
+impl StatusBinding {
+    pub fn validate(&self) -> Result<()> {
+        if self.variable.is_empty() {
+            return Err(anyhow!("Status bar field '{}' has no variable", self.label));
+        }
+        if !self.color.is_empty() {
+            parse_hex_color(&self.color)
+                .map_err(|e| anyhow!("{e} for status bar field '{}'", self.label))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a binding shows, given the variables received so far
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusValue {
+    pub label: String,
+    pub text: String,
+    /// How full the bar is, from 0 to 1, for fields with a maximum
+    pub fraction: Option<f32>,
+    pub color: Option<slint::Color>,
+}
+
+/// Resolves a binding against the variables received so far; fields whose variable hasn't been
+/// received are hidden
+pub fn resolve(
+    binding: &StatusBinding,
+    variables: &HashMap<String, String>,
+) -> Option<StatusValue> {
+    let value = variables.get(&binding.variable)?;
+    let max = (!binding.max_variable.is_empty())
+        .then(|| variables.get(&binding.max_variable))
+        .flatten();
+
+    let (text, fraction) = match max {
+        Some(max) => {
+            let fraction = match (value.trim().parse::<f32>(), max.trim().parse::<f32>()) {
+                (Ok(value), Ok(max)) if max > 0.0 => Some((value / max).clamp(0.0, 1.0)),
+                _ => None,
+            };
+            (format!("{value}/{max}"), fraction)
+        }
+        None => (value.clone(), None),
+    };
+
+    Some(StatusValue {
+        label: binding.label.clone(),
+        text,
+        fraction,
+        color: parse_hex_color(&binding.color).ok(),
+    })
+}
+
+/// A field a script put in the status bar with `smudgy.ui.setGauge` or `setStatus`. The session
+/// keeps them rather than the runtime, so they're still shown after scripts are reloaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gauge {
+    pub value: StatusValue,
+    /// Gauges are shown after the profile's fields, lowest order first
+    pub order: i32,
+}
+
+impl Gauge {
+    /// A bar filled to `value` out of `max`, or just the value when there's no maximum
+    pub fn bar(
+        label: String,
+        value: f64,
+        max: Option<f64>,
+        color: Option<slint::Color>,
+        order: i32,
+    ) -> Self {
+        let (text, fraction) = match max {
+            Some(max) => (
+                format!("{value}/{max}"),
+                (max > 0.0).then(|| (value / max).clamp(0.0, 1.0) as f32),
+            ),
+            None => (value.to_string(), None),
+        };
+        Self {
+            value: StatusValue {
+                label,
+                text,
+                fraction,
+                color,
+            },
+            order,
+        }
+    }
+
+    /// Text on its own, like the name of whatever is being fought
+    pub fn text(text: String, order: i32) -> Self {
+        Self {
+            value: StatusValue {
+                label: String::new(),
+                text,
+                fraction: None,
+                color: None,
+            },
+            order,
+        }
+    }
+}
+
+/// Keeps the latest value of every variable the server has sent, so bound fields can be redrawn
+/// whenever one of them changes, along with the gauges scripts have set
+#[derive(Debug, Default)]
+pub struct StatusBar {
+    bindings: Vec<StatusBinding>,
+    variables: HashMap<String, String>,
+    /// In the order they were first set, which breaks ties between equal orders
+    gauges: Vec<(String, Gauge)>,
+}
+
+impl StatusBar {
+    pub fn set_bindings(&mut self, bindings: Vec<StatusBinding>) {
+        self.bindings = bindings;
+    }
+
+    /// Records new values, returning whether any bound field changed
+    pub fn update(&mut self, variables: &[(String, String)]) -> bool {
+        let mut changed = false;
+        for (name, value) in variables {
+            let bound = self
+                .bindings
+                .iter()
+                .any(|binding| binding.variable == *name || binding.max_variable == *name);
+            if self.variables.insert(name.clone(), value.clone()).as_ref() != Some(value) && bound {
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Sets a gauge, or removes it when `gauge` is None, returning whether anything changed
+    pub fn set_gauge(&mut self, id: &str, gauge: Option<Gauge>) -> bool {
+        let existing = self.gauges.iter().position(|(gauge_id, _)| gauge_id == id);
+        match (existing, gauge) {
+            (Some(idx), Some(gauge)) => {
+                let changed = self.gauges[idx].1 != gauge;
+                self.gauges[idx].1 = gauge;
+                changed
+            }
+            (None, Some(gauge)) => {
+                self.gauges.push((id.to_string(), gauge));
+                true
+            }
+            (Some(idx), None) => {
+                self.gauges.remove(idx);
+                true
+            }
+            (None, None) => false,
+        }
+    }
+
+    pub fn values(&self) -> Vec<StatusValue> {
+        let mut gauges: Vec<_> = self.gauges.iter().map(|(_, gauge)| gauge).collect();
+        gauges.sort_by_key(|gauge| gauge.order);
+
+        self.bindings
+            .iter()
+            .filter_map(|binding| resolve(binding, &self.variables))
+            .chain(gauges.into_iter().map(|gauge| gauge.value.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(label: &str, variable: &str, max_variable: &str) -> StatusBinding {
+        StatusBinding {
+            label: label.into(),
+            variable: variable.into(),
+            max_variable: max_variable.into(),
+            color: String::new(),
+        }
+    }
+
+    fn variables(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_bar_and_text() {
+        let vars: HashMap<_, _> = variables(&[
+            ("HEALTH", "84"),
+            ("HEALTH_MAX", "120"),
+            ("ROOM", "Town Square"),
+        ])
+        .into_iter()
+        .collect();
+
+        let health = resolve(&binding("HP", "HEALTH", "HEALTH_MAX"), &vars).unwrap();
+        assert_eq!(health.text, "84/120");
+        assert_eq!(health.fraction, Some(0.7));
+
+        let room = resolve(&binding("Room", "ROOM", ""), &vars).unwrap();
+        assert_eq!(room.text, "Town Square");
+        assert_eq!(room.fraction, None);
+
+        // A maximum that hasn't arrived yet just shows the value
+        let mana = resolve(&binding("MP", "ROOM", "MANA_MAX"), &vars).unwrap();
+        assert_eq!(mana.text, "Town Square");
+        assert_eq!(mana.fraction, None);
+    }
+
+    #[test]
+    fn test_missing_variables_are_hidden() {
+        let mut status_bar = StatusBar::default();
+        status_bar.set_bindings(vec![
+            binding("HP", "HEALTH", "HEALTH_MAX"),
+            binding("MP", "MANA", "MANA_MAX"),
+        ]);
+
+        assert!(status_bar.values().is_empty());
+        assert!(status_bar.update(&variables(&[("HEALTH", "200"), ("HEALTH_MAX", "100")])));
+
+        let values = status_bar.values();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].label, "HP");
+        assert_eq!(values[0].fraction, Some(1.0));
+    }
+
+    #[test]
+    fn test_update_reports_bound_changes_only() {
+        let mut status_bar = StatusBar::default();
+        status_bar.set_bindings(vec![binding("HP", "HEALTH", "")]);
+
+        assert!(status_bar.update(&variables(&[("HEALTH", "10")])));
+        assert!(!status_bar.update(&variables(&[("HEALTH", "10")])));
+        assert!(!status_bar.update(&variables(&[("MANA", "5")])));
+    }
+
+    #[test]
+    fn test_gauges_follow_bound_fields_in_order() {
+        let mut status_bar = StatusBar::default();
+        status_bar.set_bindings(vec![binding("Room", "ROOM", "")]);
+        status_bar.update(&variables(&[("ROOM", "Town Square")]));
+
+        let hp = Gauge::bar("HP".into(), 84.0, Some(120.0), None, 0);
+        assert!(status_bar.set_gauge("hp", Some(hp.clone())));
+        assert!(!status_bar.set_gauge("hp", Some(hp)));
+        assert!(status_bar.set_gauge("enemy", Some(Gauge::text("an orc".into(), -1))));
+        assert!(status_bar.set_gauge("mp", Some(Gauge::bar("MP".into(), 5.0, None, None, 0))));
+
+        let texts: Vec<_> = status_bar
+            .values()
+            .into_iter()
+            .map(|value| (value.text, value.fraction))
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("Town Square".to_string(), None),
+                ("an orc".to_string(), None),
+                ("84/120".to_string(), Some(0.7)),
+                ("5".to_string(), None),
+            ]
+        );
+
+        assert!(status_bar.set_gauge("enemy", None));
+        assert!(!status_bar.set_gauge("enemy", None));
+        assert_eq!(status_bar.values().len(), 3);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(binding("HP", "HEALTH", "").validate().is_ok());
+        assert!(binding("HP", "", "").validate().is_err());
+        let mut colored = binding("HP", "HEALTH", "");
+        colored.color = "red".into();
+        assert!(colored.validate().is_err());
+        colored.color = "#ff0000".into();
+        assert!(colored.validate().is_ok());
+    }
+}
@@ -1,10 +1,40 @@
+use std::{borrow::Cow, ops::Range};
+
 use super::connection::vt_processor;
 
-pub use vt_processor::Color;
+mod wrap;
 
-#[derive(Debug, Clone, Copy)]
+pub use vt_processor::{AnsiColor, Color};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Style {
     pub fg: vt_processor::Color,
+    /// Drawn as a block behind the text; None leaves the line's own background showing
+    pub bg: Option<vt_processor::Color>,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// Blinking text is drawn steadily
+    pub blink: bool,
+    /// Drawn in the background color over a block of its own color
+    pub reverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fg: Color::AnsiColor {
+                color: AnsiColor::White,
+                bold: false,
+            },
+            bg: None,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            blink: false,
+            reverse: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -14,33 +44,68 @@ pub struct SpanInfo {
     pub end_pos: usize,
 }
 
+/// What activating a link in the output does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkAction {
+    /// Sends a command to the server, as if it had been typed
+    Send(String),
+    /// Opens a URL
+    Url(String),
+}
+
+/// A range of a line's text that can be clicked on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkSpan {
+    pub begin_pos: usize,
+    pub end_pos: usize,
+    pub action: LinkAction,
+}
+
 #[derive(Debug, Clone)]
 pub struct StyledLine {
     pub text: String,
     pub spans: Vec<SpanInfo>,
+    pub links: Vec<LinkSpan>,
+    /// When the line arrived, in milliseconds since the Unix epoch
+    pub received_at: i64,
 }
 
 impl StyledLine {
     pub fn new(text: &str, span_info: Vec<SpanInfo>) -> Self {
         Self {
             text: String::from(text),
-            spans: span_info,
+            spans: compact_spans(span_info),
+            links: Vec::new(),
+            received_at: chrono::Utc::now().timestamp_millis(),
         }
     }
 
+    pub fn with_links(self, links: Vec<LinkSpan>) -> Self {
+        Self { links, ..self }
+    }
+
     pub fn append(&self, other_line: &StyledLine) -> Self {
         Self {
             text: format!("{}{}", self.text, other_line.text),
-            spans: self
-                .spans
-                .clone()
-                .into_iter()
-                .chain(other_line.spans.iter().map(|span| SpanInfo {
+            spans: compact_spans(self.spans.iter().copied().chain(other_line.spans.iter().map(
+                |span| SpanInfo {
                     style: span.style,
                     begin_pos: span.begin_pos + self.text.len(),
                     end_pos: span.end_pos + self.text.len(),
+                },
+            ))),
+            links: self
+                .links
+                .iter()
+                .cloned()
+                .chain(other_line.links.iter().map(|link| LinkSpan {
+                    begin_pos: link.begin_pos + self.text.len(),
+                    end_pos: link.end_pos + self.text.len(),
+                    action: link.action.clone(),
                 }))
                 .collect(),
+            // A line arrives when its first part does
+            received_at: self.received_at,
         }
     }
 
@@ -51,9 +116,12 @@ impl StyledLine {
                 end_pos: text.len(),
                 style: Style {
                     fg: { Color::Echo },
+                    ..Style::default()
                 },
             }],
             text: String::from(text),
+            links: Vec::new(),
+            received_at: chrono::Utc::now().timestamp_millis(),
         }
     }
 
@@ -64,9 +132,12 @@ impl StyledLine {
                 end_pos: text.len(),
                 style: Style {
                     fg: { Color::Output },
+                    ..Style::default()
                 },
             }],
             text: String::from(text),
+            links: Vec::new(),
+            received_at: chrono::Utc::now().timestamp_millis(),
         }
     }
 
@@ -74,4 +145,334 @@ impl StyledLine {
     pub fn as_str(&self) -> &str {
         self.text.as_str()
     }
+
+    /// The part of the line in `range` (byte offsets), keeping the styles and links inside it
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        let clip = |begin_pos: usize, end_pos: usize| {
+            let begin_pos = begin_pos.max(range.start);
+            let end_pos = end_pos.min(range.end);
+            (begin_pos < end_pos).then(|| (begin_pos - range.start, end_pos - range.start))
+        };
+
+        Self {
+            text: self.text[range.clone()].to_string(),
+            spans: self
+                .spans
+                .iter()
+                .filter_map(|span| {
+                    clip(span.begin_pos, span.end_pos).map(|(begin_pos, end_pos)| SpanInfo {
+                        style: span.style,
+                        begin_pos,
+                        end_pos,
+                    })
+                })
+                .collect(),
+            links: self
+                .links
+                .iter()
+                .filter_map(|link| {
+                    clip(link.begin_pos, link.end_pos).map(|(begin_pos, end_pos)| LinkSpan {
+                        begin_pos,
+                        end_pos,
+                        action: link.action.clone(),
+                    })
+                })
+                .collect(),
+            received_at: self.received_at,
+        }
+    }
+
+    /// A copy of the line with `restyle` applied to the styles in `range` (byte offsets), splitting
+    /// spans that straddle either end of it
+    pub fn restyle(&self, range: Range<usize>, restyle: impl Fn(&mut Style)) -> Self {
+        let mut spans = Vec::with_capacity(self.spans.len() + 2);
+        for span in &self.spans {
+            let pieces = [
+                (span.begin_pos, span.end_pos.min(range.start), false),
+                (span.begin_pos.max(range.start), span.end_pos.min(range.end), true),
+                (span.begin_pos.max(range.end), span.end_pos, false),
+            ];
+            for (begin_pos, end_pos, inside) in pieces {
+                if begin_pos < end_pos {
+                    let mut style = span.style;
+                    if inside {
+                        restyle(&mut style);
+                    }
+                    spans.push(SpanInfo {
+                        style,
+                        begin_pos,
+                        end_pos,
+                    });
+                }
+            }
+        }
+
+        Self {
+            text: self.text.clone(),
+            spans: compact_spans(spans),
+            links: self.links.clone(),
+            received_at: self.received_at,
+        }
+    }
+
+    /// Splits the line into display rows of at most `columns` characters, breaking between
+    /// words where it can. Styles carry across the breaks, and the line itself is left as it is.
+    pub fn wrap(&self, columns: usize) -> Vec<StyledLine> {
+        let mut rows = Vec::new();
+        let mut row_start = 0;
+        for row_end in wrap::wrap_points(&self.text, columns) {
+            rows.push(self.slice(row_start..row_end));
+            row_start = row_end;
+        }
+        rows.push(self.slice(row_start..self.text.len()));
+        rows
+    }
+
+    /// When the line arrived as local `[HH:MM:SS]`, for display alongside it
+    pub fn timestamp_prefix(&self) -> String {
+        chrono::DateTime::from_timestamp_millis(self.received_at)
+            .map(|received_at| {
+                received_at
+                    .with_timezone(&chrono::Local)
+                    .format("[%H:%M:%S] ")
+                    .to_string()
+            })
+            .unwrap_or_default()
+    }
+
+    /// What clicking on the character at `column` (a character index) does, if it's in a link
+    pub fn link_at(&self, column: usize) -> Option<&LinkAction> {
+        let (offset, _) = self.text.char_indices().nth(column)?;
+        self.links
+            .iter()
+            .find(|link| (link.begin_pos..link.end_pos).contains(&offset))
+            .map(|link| &link.action)
+    }
+
+    /// The visible text of the line: styling is never part of the text, but control characters
+    /// (other than tabs) that made it through are removed too
+    pub fn plain_text(&self) -> Cow<'_, str> {
+        let is_hidden = |ch: char| ch != '\t' && ch.is_control();
+
+        if self.text.contains(is_hidden) {
+            Cow::Owned(self.text.chars().filter(|ch| !is_hidden(*ch)).collect())
+        } else {
+            Cow::Borrowed(&self.text)
+        }
+    }
+}
+
+/// Drops empty spans and merges neighbours with the same style. Servers often repeat or reset
+/// styles without changing them, and every span is kept for as long as its line is in the
+/// scrollback, so the result is also sized exactly.
+fn compact_spans(spans: impl IntoIterator<Item = SpanInfo>) -> Vec<SpanInfo> {
+    let mut compacted: Vec<SpanInfo> = Vec::new();
+    for span in spans {
+        if span.begin_pos >= span.end_pos {
+            continue;
+        }
+        match compacted.last_mut() {
+            Some(last) if last.end_pos == span.begin_pos && last.style == span.style => {
+                last.end_pos = span.end_pos;
+            }
+            _ => compacted.push(span),
+        }
+    }
+    compacted.shrink_to_fit();
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colored(color: AnsiColor) -> Style {
+        Style {
+            fg: Color::AnsiColor { color, bold: false },
+            ..Style::default()
+        }
+    }
+
+    #[test]
+    fn test_wrap_keeps_styles_across_rows() {
+        // "You see " is plain, "a red dragon" red, and " here." green
+        let line = StyledLine::new(
+            "You see a red dragon here.",
+            vec![
+                SpanInfo {
+                    style: Style::default(),
+                    begin_pos: 0,
+                    end_pos: 8,
+                },
+                SpanInfo {
+                    style: colored(AnsiColor::Red),
+                    begin_pos: 8,
+                    end_pos: 20,
+                },
+                SpanInfo {
+                    style: colored(AnsiColor::Green),
+                    begin_pos: 20,
+                    end_pos: 26,
+                },
+            ],
+        );
+
+        let rows = line.wrap(12);
+        let texts: Vec<_> = rows.iter().map(StyledLine::as_str).collect();
+        assert_eq!(texts, vec!["You see a ", "red dragon ", "here."]);
+
+        let spans = |row: &StyledLine| -> Vec<_> {
+            row.spans
+                .iter()
+                .map(|span| (span.begin_pos, span.end_pos, span.style.fg))
+                .collect()
+        };
+        assert_eq!(
+            spans(&rows[0]),
+            vec![(0, 8, Style::default().fg), (8, 10, colored(AnsiColor::Red).fg)]
+        );
+        assert_eq!(
+            spans(&rows[1]),
+            vec![(0, 10, colored(AnsiColor::Red).fg), (10, 11, colored(AnsiColor::Green).fg)]
+        );
+        assert_eq!(spans(&rows[2]), vec![(0, 5, colored(AnsiColor::Green).fg)]);
+
+        // The line itself is untouched
+        assert_eq!(line.as_str(), "You see a red dragon here.");
+    }
+
+    #[test]
+    fn test_repeated_styles_are_merged() {
+        let span = |style, begin_pos, end_pos| SpanInfo {
+            style,
+            begin_pos,
+            end_pos,
+        };
+        let red = colored(AnsiColor::Red);
+        // "\e[31mThe \e[31mgoblin\e[0m\e[0m hits\e[31m\e[0m you."
+        let line = StyledLine::new(
+            "The goblin hits you.",
+            vec![
+                span(red, 0, 4),
+                span(red, 4, 10),
+                span(Style::default(), 10, 10),
+                span(Style::default(), 10, 15),
+                span(red, 15, 15),
+                span(Style::default(), 15, 20),
+            ],
+        );
+
+        let spans: Vec<_> = line
+            .spans
+            .iter()
+            .map(|span| (span.begin_pos, span.end_pos, span.style))
+            .collect();
+        assert_eq!(spans, vec![(0, 10, red), (10, 20, Style::default())]);
+        assert_eq!(line.spans.capacity(), 2);
+
+        // Appending carries on the last span when the style hasn't changed
+        let appended = line.append(&StyledLine::new(" Ouch!", vec![span(Style::default(), 0, 6)]));
+        assert_eq!(appended.spans.len(), 2);
+        assert_eq!(appended.spans[1].end_pos, 26);
+    }
+
+    #[test]
+    fn test_wrap_at_various_widths() {
+        let line = StyledLine::from_output_str("a bb ccc dddd");
+
+        let texts = |columns| -> Vec<String> {
+            line.wrap(columns).iter().map(|row| row.text.clone()).collect()
+        };
+        assert_eq!(texts(100), vec!["a bb ccc dddd"]);
+        assert_eq!(texts(8), vec!["a bb ccc ", "dddd"]);
+        assert_eq!(texts(7), vec!["a bb ", "ccc ", "dddd"]);
+        assert_eq!(texts(3), vec!["a ", "bb ", "ccc ", "ddd", "d"]);
+        assert_eq!(texts(2), vec!["a ", "bb ", "cc", "c ", "dd", "dd"]);
+    }
+
+    #[test]
+    fn test_slice_clips_links() {
+        let line = StyledLine::from_output_str("go north now").with_links(vec![LinkSpan {
+            begin_pos: 3,
+            end_pos: 8,
+            action: LinkAction::Send("north".into()),
+        }]);
+
+        let slice = line.slice(5..12);
+        assert_eq!(slice.as_str(), "rth now");
+        assert_eq!(
+            slice.links,
+            vec![LinkSpan {
+                begin_pos: 0,
+                end_pos: 3,
+                action: LinkAction::Send("north".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_link_at() {
+        let line = StyledLine::from_output_str("Café: menu").with_links(vec![LinkSpan {
+            begin_pos: 7,
+            end_pos: 11,
+            action: LinkAction::Send("read menu".into()),
+        }]);
+
+        assert_eq!(line.link_at(5), None);
+        assert_eq!(line.link_at(6), Some(&LinkAction::Send("read menu".into())));
+        assert_eq!(line.link_at(9), Some(&LinkAction::Send("read menu".into())));
+        assert_eq!(line.link_at(10), None);
+    }
+
+    #[test]
+    fn test_restyle_splits_spans_at_the_range() {
+        // "You see " is plain and "a red dragon" red; "see a red" gets a background
+        let line = StyledLine::new(
+            "You see a red dragon",
+            vec![
+                SpanInfo {
+                    style: Style::default(),
+                    begin_pos: 0,
+                    end_pos: 8,
+                },
+                SpanInfo {
+                    style: colored(AnsiColor::Red),
+                    begin_pos: 8,
+                    end_pos: 20,
+                },
+            ],
+        );
+        let bg = Color::RGB { r: 0, g: 0, b: 128 };
+
+        let restyled = line.restyle(4..13, |style| style.bg = Some(bg));
+        let spans: Vec<_> = restyled
+            .spans
+            .iter()
+            .map(|span| (span.begin_pos, span.end_pos, span.style))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 4, Style::default()),
+                (
+                    4,
+                    8,
+                    Style {
+                        bg: Some(bg),
+                        ..Style::default()
+                    }
+                ),
+                (
+                    8,
+                    13,
+                    Style {
+                        bg: Some(bg),
+                        ..colored(AnsiColor::Red)
+                    }
+                ),
+                (13, 20, colored(AnsiColor::Red)),
+            ]
+        );
+        assert_eq!(restyled.text, line.text);
+    }
 }
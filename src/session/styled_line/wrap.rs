@@ -0,0 +1,88 @@
+/// Where the rows of `text` after the first one start, as byte offsets, when it's wrapped to
+/// `columns` characters. Rows break before the word that would overflow them, and words longer
+/// than a whole row are split. A single whitespace character may hang off the end of a row, but
+/// longer runs, like the padding in tables and maps, break like words do. Joining the rows back
+/// together gives the original text.
+pub fn wrap_points(text: &str, columns: usize) -> Vec<usize> {
+    let columns = columns.max(1);
+    let mut points = Vec::new();
+    let mut row_start = 0;
+    let mut width = 0;
+    let mut word_start = 0;
+    let mut in_whitespace = false;
+
+    for (pos, ch) in text.char_indices() {
+        let is_whitespace = ch.is_whitespace();
+        if in_whitespace && !is_whitespace {
+            word_start = pos;
+        }
+        in_whitespace = is_whitespace;
+
+        // Whitespace may hang one column past the end of the row
+        let row_width = if is_whitespace { columns + 1 } else { columns };
+        if width >= row_width {
+            let row_break = if !is_whitespace && word_start > row_start {
+                word_start
+            } else {
+                pos
+            };
+            points.push(row_break);
+            row_start = row_break;
+            width = text[row_break..pos].chars().count();
+        }
+        width += 1;
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str, columns: usize) -> Vec<&str> {
+        let mut rows = Vec::new();
+        let mut start = 0;
+        for point in wrap_points(text, columns) {
+            rows.push(&text[start..point]);
+            start = point;
+        }
+        rows.push(&text[start..]);
+        rows
+    }
+
+    #[test]
+    fn test_breaks_between_words() {
+        assert_eq!(
+            rows("the quick brown fox jumps", 10),
+            vec!["the quick ", "brown fox ", "jumps"]
+        );
+        assert_eq!(rows("the quick brown fox jumps", 80), vec!["the quick brown fox jumps"]);
+    }
+
+    #[test]
+    fn test_splits_long_words() {
+        assert_eq!(rows("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(rows("to abcdefghij", 4), vec!["to ", "abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn test_counts_characters_not_bytes() {
+        assert_eq!(rows("ééé ééé", 3), vec!["ééé ", "ééé"]);
+    }
+
+    #[test]
+    fn test_long_whitespace_runs_break() {
+        let text = format!("a{}b", " ".repeat(25));
+        let wrapped = rows(&text, 10);
+
+        assert_eq!(wrapped, vec!["a          ", "           ", "    b"]);
+        assert_eq!(wrapped.concat(), text);
+        assert_eq!(rows("the quick  brown", 10), vec!["the quick  ", "brown"]);
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(wrap_points("", 10).is_empty());
+    }
+}
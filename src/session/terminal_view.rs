@@ -1,10 +1,11 @@
-use crate::MainWindow;
+use crate::{MainWindow, StatusField};
 use std::{
     cell::{Ref, RefCell},
     cmp::max,
     collections::VecDeque,
     num::NonZeroU32,
     num::NonZeroUsize,
+    ops::{Range, RangeInclusive},
     rc::Rc,
     sync::Arc,
 };
@@ -14,80 +15,45 @@ use fontdue::{
     Font,
 };
 use lru::LruCache;
-use slint::{ComponentHandle, ModelNotify, ModelTracker, Rgba8Pixel, SharedPixelBuffer};
+use slint::{ComponentHandle, Model, ModelNotify, ModelTracker, Rgba8Pixel, SharedPixelBuffer};
 use tiny_skia::{PixmapMut, PixmapPaint, Transform};
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 use super::{
-    styled_line::{self, Style},
+    activity::{ConnectionState, ConnectionTracker, SessionEvent},
+    ansi_palette::AnsiPalette,
+    find::{self, FindMatch},
+    selection::{self, Selection, SelectionPoint},
+    status_bar::{Gauge, StatusBar, StatusBinding},
+    styled_line::{self, LinkAction, Style},
     StyledLine,
 };
 
 static FONT_DATA: &[u8] = include_bytes!("../../assets/fonts/GeistMonoVF.ttf");
 
-static ECHO_COLOR: slint::Color = slint::Color::from_rgb_u8(255, 192, 255);
-static OUTPUT_COLOR: slint::Color = slint::Color::from_rgb_u8(255, 255, 192);
-
-static ANSI_BLACK: slint::Color = slint::Color::from_rgb_u8(0, 0, 0);
-static ANSI_RED: slint::Color = slint::Color::from_rgb_u8(170, 0, 0);
-static ANSI_GREEN: slint::Color = slint::Color::from_rgb_u8(0, 170, 0);
-static ANSI_YELLOW: slint::Color = slint::Color::from_rgb_u8(170, 170, 0);
-static ANSI_BLUE: slint::Color = slint::Color::from_rgb_u8(0, 0, 170);
-static ANSI_MAGENTA: slint::Color = slint::Color::from_rgb_u8(170, 0, 170);
-static ANSI_CYAN: slint::Color = slint::Color::from_rgb_u8(0, 170, 170);
-static ANSI_WHITE: slint::Color = slint::Color::from_rgb_u8(204, 204, 204);
-static ANSI_BLACK_BOLD: slint::Color = slint::Color::from_rgb_u8(85, 85, 85);
-static ANSI_RED_BOLD: slint::Color = slint::Color::from_rgb_u8(255, 85, 85);
-static ANSI_GREEN_BOLD: slint::Color = slint::Color::from_rgb_u8(85, 255, 85);
-static ANSI_YELLOW_BOLD: slint::Color = slint::Color::from_rgb_u8(255, 255, 85);
-static ANSI_BLUE_BOLD: slint::Color = slint::Color::from_rgb_u8(85, 85, 255);
-static ANSI_MAGENTA_BOLD: slint::Color = slint::Color::from_rgb_u8(255, 85, 255);
-static ANSI_CYAN_BOLD: slint::Color = slint::Color::from_rgb_u8(85, 255, 255);
-static ANSI_WHITE_BOLD: slint::Color = slint::Color::from_rgb_u8(255, 255, 255);
-
-static ANSI_COLOR_TABLE: [slint::Color; 16] = [
-    ANSI_BLACK,
-    ANSI_RED,
-    ANSI_GREEN,
-    ANSI_YELLOW,
-    ANSI_BLUE,
-    ANSI_MAGENTA,
-    ANSI_CYAN,
-    ANSI_WHITE,
-    ANSI_BLACK_BOLD,
-    ANSI_RED_BOLD,
-    ANSI_GREEN_BOLD,
-    ANSI_YELLOW_BOLD,
-    ANSI_BLUE_BOLD,
-    ANSI_MAGENTA_BOLD,
-    ANSI_CYAN_BOLD,
-    ANSI_WHITE_BOLD,
-];
+const TIMESTAMP_COLOR: styled_line::Color = styled_line::Color::RGB {
+    r: 112,
+    g: 112,
+    b: 112,
+};
+static FIND_HIGHLIGHT_COLOR: slint::Color = slint::Color::from_rgb_u8(96, 80, 0);
+static FIND_CURRENT_HIGHLIGHT_COLOR: slint::Color = slint::Color::from_rgb_u8(176, 96, 0);
+/// What status bar fields are drawn in when their binding doesn't pick a color
+static STATUS_FIELD_COLOR: slint::Color = slint::Color::from_rgb_u8(179, 128, 255);
 
 const NON_SCROLLBACK_SIZE_IN_LINES: i32 = 15;
 
+/// How far italic glyphs lean, as horizontal pixels per vertical pixel
+const ITALIC_SKEW: f32 = 0.2;
+
+/// How far above the baseline strikethroughs are drawn, as a fraction of the ascent
+const STRIKETHROUGH_HEIGHT: f32 = 0.3;
+
 enum ScrollPosition {
     PinnedToEnd,
     ToLine(i32),
 }
 
-impl From<styled_line::Color> for slint::Color {
-    fn from(value: styled_line::Color) -> Self {
-        match value {
-            styled_line::Color::AnsiColor { color, bold } => {
-                if bold {
-                    ANSI_COLOR_TABLE[color as usize + 8]
-                } else {
-                    ANSI_COLOR_TABLE[color as usize]
-                }
-            }
-            styled_line::Color::Output => OUTPUT_COLOR,
-            styled_line::Color::Echo => ECHO_COLOR,
-            styled_line::Color::RGB { r, g, b } => slint::Color::from_rgb_u8(r, g, b),
-        }
-    }
-}
-
 // TODO: Benchmark inline
 #[inline(always)]
 pub fn premultiply_u8(c: u8, a: u8) -> u8 {
@@ -95,7 +61,9 @@ pub fn premultiply_u8(c: u8, a: u8) -> u8 {
     ((prod + (prod >> 8)) >> 8) as u8
 }
 
-type ImageCache = Rc<RefCell<LruCache<usize, SharedPixelBuffer<Rgba8Pixel>>>>;
+// Rasterized lines are cached as images rather than pixel buffers, so the renderer sees the same
+// image (and can keep its texture) for a line until that line changes
+type ImageCache = Rc<RefCell<LruCache<usize, slint::Image>>>;
 pub enum ViewableRowCount {
     Clean(usize),
     Dirty,
@@ -106,24 +74,61 @@ struct TerminalLine {
     row_number: usize,
     layout: fontdue::layout::Layout<Style>,
     styled_line: Arc<StyledLine>,
+    show_timestamp: bool,
+    soft_wrap: bool,
     last_rasterized_width: u32,
     last_rasterized_height: u32,
     layout_max_width: u32,
+    /// The character in `styled_line` that each laid out glyph draws, if any
+    glyph_chars: Vec<Option<usize>>,
+    /// Character columns to draw a find highlight behind, and whether each is the current match
+    highlights: Vec<(Range<usize>, bool)>,
+    /// Drawn behind the whole line, when a script has highlighted it
+    background: Option<slint::Color>,
 }
 
 impl TerminalLine {
-    pub fn new(row_number: usize, styled_line: Arc<StyledLine>, font_size: f32) -> Self {
+    pub fn new(
+        row_number: usize,
+        styled_line: Arc<StyledLine>,
+        font_size: f32,
+        show_timestamp: bool,
+        soft_wrap: bool,
+    ) -> Self {
         Self {
             row_number: row_number,
             last_rasterized_width: 0,
             last_rasterized_height: 0,
             layout_max_width: 0,
             layout: Layout::new(CoordinateSystem::PositiveYDown),
+            glyph_chars: Vec::new(),
+            highlights: Vec::new(),
+            background: None,
             styled_line,
+            show_timestamp,
+            soft_wrap,
             font_size,
         }
     }
 
+    pub fn set_soft_wrap(&mut self, soft_wrap: bool) {
+        // force recalc
+        self.layout_max_width = 0;
+        self.soft_wrap = soft_wrap;
+    }
+
+    pub fn set_show_timestamp(&mut self, show_timestamp: bool) {
+        // force recalc
+        self.layout_max_width = 0;
+        self.show_timestamp = show_timestamp;
+    }
+
+    pub fn set_font_size(&mut self, font_size: f32) {
+        // force recalc
+        self.layout_max_width = 0;
+        self.font_size = font_size;
+    }
+
     pub fn append(&mut self, styled_line: Arc<StyledLine>) {
         // force recalc
         self.layout_max_width = 0;
@@ -138,38 +143,80 @@ impl TerminalLine {
             max_width: Some(max_width as f32),
             ..Default::default()
         });
+        self.glyph_chars.clear();
+
+        // The timestamp is only drawn; it's never part of the line's text
+        let timestamp = self
+            .show_timestamp
+            .then(|| self.styled_line.timestamp_prefix());
+
+        // In soft wrap mode the line is broken into rows by column, between words, rather than
+        // wherever fontdue runs out of width
+        let wrapped;
+        let rows = if self.soft_wrap {
+            let advance_width = font.metrics('M', self.font_size).advance_width.max(1.0);
+            let columns = (max_width as f32 / advance_width) as usize;
+            let timestamp_columns = timestamp.as_ref().map_or(0, |timestamp| timestamp.len());
+            wrapped = self
+                .styled_line
+                .wrap(columns.saturating_sub(timestamp_columns));
+            wrapped.as_slice()
+        } else {
+            std::slice::from_ref(self.styled_line.as_ref())
+        };
 
-        for span in self.styled_line.spans.clone() {
+        if let Some(timestamp) = timestamp {
             self.layout.append(
                 &[font],
                 &TextStyle::with_user_data(
-                    self.styled_line
-                        .text
-                        .get(span.begin_pos..span.end_pos)
-                        .unwrap(),
+                    &timestamp,
                     self.font_size,
                     0,
-                    span.style,
+                    Style {
+                        fg: TIMESTAMP_COLOR,
+                        ..Style::default()
+                    },
                 ),
-            )
+            );
+            self.glyph_chars.resize(self.layout.glyphs().len(), None);
+        }
+
+        // Wrapped rows keep every character, so they can be counted through in order
+        let mut row_char_start = 0;
+        for (row_idx, row) in rows.iter().enumerate() {
+            if row_idx > 0 {
+                self.layout.append(
+                    &[font],
+                    &TextStyle::with_user_data("\n", self.font_size, 0, Style::default()),
+                );
+                self.glyph_chars.resize(self.layout.glyphs().len(), None);
+            }
+
+            for span in &row.spans {
+                let text = row.text.get(span.begin_pos..span.end_pos).unwrap();
+                let first_glyph = self.layout.glyphs().len();
+                self.layout.append(
+                    &[font],
+                    &TextStyle::with_user_data(text, self.font_size, 0, span.style),
+                );
+
+                let span_char_start = row_char_start + row.text[..span.begin_pos].chars().count();
+                for glyph in &self.layout.glyphs()[first_glyph..] {
+                    self.glyph_chars.push(Some(
+                        span_char_start + text[..glyph.byte_offset].chars().count(),
+                    ));
+                }
+            }
+            row_char_start += row.text.chars().count();
         }
 
         // If we're a line, we need to at least render one space
         if self.layout.height() == 0.0f32 {
             self.layout.append(
                 &[font],
-                &TextStyle::with_user_data(
-                    " ",
-                    self.font_size,
-                    0,
-                    Style {
-                        fg: super::connection::vt_processor::Color::AnsiColor {
-                            color: super::connection::vt_processor::AnsiColor::White,
-                            bold: false,
-                        },
-                    },
-                ),
-            )
+                &TextStyle::with_user_data(" ", self.font_size, 0, Style::default()),
+            );
+            self.glyph_chars.resize(self.layout.glyphs().len(), None);
         }
         self.last_rasterized_width = max(
             1,
@@ -189,12 +236,174 @@ impl TerminalLine {
         self.last_rasterized_height = self.layout.height() as u32;
     }
 
-    pub fn pixel_buffer(
+    /// The character drawn nearest to `x`, `y` (in pixels from the top left of the line); points
+    /// past the end of a row land on its last character
+    fn char_at(&self, x: f32, y: f32) -> usize {
+        let Some(rows) = self.layout.lines() else {
+            return 0;
+        };
+        let Some(row) = rows
+            .iter()
+            .rev()
+            .find(|row| row.baseline_y - row.max_ascent <= y)
+            .or(rows.first())
+        else {
+            return 0;
+        };
+
+        let glyphs = self.layout.glyphs();
+        let chars = &self.glyph_chars[row.glyph_start..=row.glyph_end];
+        let hit = (row.glyph_start..=row.glyph_end)
+            .zip(chars)
+            .filter_map(|(idx, char)| char.map(|char| (&glyphs[idx], char)))
+            .take_while(|(glyph, _)| glyph.x <= x)
+            .last()
+            .map(|(_, char)| char);
+
+        // Clicks left of a row's text land on its first character; rows without any text start
+        // after whatever came before them
+        hit.or_else(|| chars.iter().flatten().next().copied())
+            .or_else(|| {
+                self.glyph_chars[..row.glyph_start]
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .next()
+                    .map(|char| char + 1)
+            })
+            .unwrap_or(0)
+    }
+
+    fn draw_highlights(&self, pixmap: &mut PixmapMut, font: &Font) {
+        let glyphs = self.layout.glyphs();
+        for row in self.layout.lines().into_iter().flatten() {
+            let top = row.baseline_y - row.max_ascent;
+            let height = row.max_ascent - row.max_descent;
+
+            for idx in row.glyph_start..=row.glyph_end {
+                let Some(char) = self.glyph_chars[idx] else {
+                    continue;
+                };
+                let Some((_, current)) = self
+                    .highlights
+                    .iter()
+                    .find(|(columns, _)| columns.contains(&char))
+                else {
+                    continue;
+                };
+
+                let glyph = &glyphs[idx];
+                let metrics = font.metrics_indexed(glyph.key.glyph_index, glyph.key.px);
+                let color = if *current {
+                    FIND_CURRENT_HIGHLIGHT_COLOR
+                } else {
+                    FIND_HIGHLIGHT_COLOR
+                };
+                let mut paint = tiny_skia::Paint::default();
+                paint.set_color_rgba8(color.red(), color.green(), color.blue(), 255);
+
+                if let Some(rect) = tiny_skia::Rect::from_xywh(
+                    glyph.x - metrics.xmin as f32,
+                    top,
+                    metrics.advance_width.max(1.0),
+                    height,
+                ) {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
+            }
+        }
+    }
+
+    /// The block drawn behind a glyph: its foreground color when reversed, otherwise its
+    /// background color if it has one
+    fn glyph_background(style: &Style, palette: &AnsiPalette) -> Option<slint::Color> {
+        if style.reverse {
+            Some(palette.resolve(style.fg))
+        } else {
+            style.bg.map(|bg| palette.resolve(bg))
+        }
+    }
+
+    /// What a glyph itself is drawn in, which reverse video swaps with its background
+    fn glyph_color(style: &Style, palette: &AnsiPalette) -> slint::Color {
+        if style.reverse {
+            style
+                .bg
+                .map_or_else(|| palette.background(), |bg| palette.resolve(bg))
+        } else {
+            palette.resolve(style.fg)
+        }
+    }
+
+    fn draw_backgrounds(&self, pixmap: &mut PixmapMut, font: &Font, palette: &AnsiPalette) {
+        for row in self.layout.lines().into_iter().flatten() {
+            let top = row.baseline_y - row.max_ascent;
+            let height = row.max_ascent - row.max_descent;
+
+            for glyph in &self.layout.glyphs()[row.glyph_start..=row.glyph_end] {
+                let Some(color) = TerminalLine::glyph_background(&glyph.user_data, palette) else {
+                    continue;
+                };
+
+                let metrics = font.metrics_indexed(glyph.key.glyph_index, glyph.key.px);
+                let Some(rect) = tiny_skia::Rect::from_xywh(
+                    glyph.x - metrics.xmin as f32,
+                    top,
+                    metrics.advance_width.max(1.0),
+                    height,
+                ) else {
+                    continue;
+                };
+
+                let mut paint = tiny_skia::Paint::default();
+                paint.set_color_rgba8(color.red(), color.green(), color.blue(), 255);
+                pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+            }
+        }
+    }
+
+    /// Draws underlines below the baseline and strikethroughs across the middle of the x-height
+    fn draw_lines_through(&self, pixmap: &mut PixmapMut, font: &Font, palette: &AnsiPalette) {
+        let thickness = (self.font_size / 14.0).max(1.0);
+
+        for line in self.layout.lines().into_iter().flatten() {
+            for glyph in &self.layout.glyphs()[line.glyph_start..=line.glyph_end] {
+                let style = &glyph.user_data;
+                let offsets = [
+                    style.underline.then_some(thickness),
+                    style
+                        .strikethrough
+                        .then_some(-line.max_ascent * STRIKETHROUGH_HEIGHT),
+                ];
+
+                let metrics = font.metrics_indexed(glyph.key.glyph_index, glyph.key.px);
+                let color = TerminalLine::glyph_color(style, palette);
+                let mut paint = tiny_skia::Paint::default();
+                paint.set_color_rgba8(color.red(), color.green(), color.blue(), 255);
+
+                for offset in offsets.into_iter().flatten() {
+                    if let Some(rect) = tiny_skia::Rect::from_xywh(
+                        glyph.x - metrics.xmin as f32,
+                        line.baseline_y + offset,
+                        metrics.advance_width,
+                        thickness,
+                    ) {
+                        pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The rendered line, which is only laid out again when it, the width or the view's settings
+    /// have changed, and only rasterized again when it isn't in the cache
+    pub fn image(
         &mut self,
         cache: &ImageCache,
         font: &Font,
+        palette: &AnsiPalette,
         max_width: u32,
-    ) -> SharedPixelBuffer<Rgba8Pixel> {
+    ) -> slint::Image {
         // recalculate if we have a different amount of room than last render
         let recalc_layout = max_width != self.layout_max_width;
 
@@ -204,7 +413,7 @@ impl TerminalLine {
             self.recalc_layout(font, max_width);
         }
 
-        let existing_buffer = if !recalc_layout {
+        let existing_image = if !recalc_layout {
             cache.get(&self.row_number)
         } else {
             // TODO: this branch can also check the cache and short circuit if the dimensions
@@ -212,7 +421,7 @@ impl TerminalLine {
             None
         };
 
-        if existing_buffer.is_none() {
+        if existing_image.is_none() {
             let mut buf: SharedPixelBuffer<Rgba8Pixel> =
                 SharedPixelBuffer::new(self.last_rasterized_width, self.last_rasterized_height);
 
@@ -223,16 +432,30 @@ impl TerminalLine {
             )
             .unwrap();
 
-            line_pixmap.fill(tiny_skia::Color::TRANSPARENT);
+            line_pixmap.fill(match self.background {
+                Some(color) => {
+                    tiny_skia::Color::from_rgba8(color.red(), color.green(), color.blue(), 255)
+                }
+                None => tiny_skia::Color::TRANSPARENT,
+            });
+            self.draw_highlights(&mut line_pixmap, font);
+            self.draw_backgrounds(&mut line_pixmap, font, palette);
+            let has_backgrounds = !self.highlights.is_empty()
+                || self.background.is_some()
+                || self
+                    .layout
+                    .glyphs()
+                    .iter()
+                    .any(|glyph| glyph.user_data.reverse || glyph.user_data.bg.is_some());
 
             for glyph in self.layout.glyphs() {
                 if glyph.char_data.rasterize() {
                     let (metrics, bitmap) = font.rasterize_config(glyph.key);
 
+                    let color = TerminalLine::glyph_color(&glyph.user_data, palette);
                     let mut glyph_pixels = bitmap
                         .iter()
                         .flat_map(|a| {
-                            let color: slint::Color = glyph.user_data.fg.into();
                             [
                                 premultiply_u8(color.red(), *a),
                                 premultiply_u8(color.green(), *a),
@@ -247,25 +470,47 @@ impl TerminalLine {
                         metrics.height as u32,
                     )
                     .unwrap();
+                    let transform = if glyph.user_data.italic {
+                        // There's no italic face, so slant the glyph around its baseline instead
+                        let baseline = glyph.y + metrics.height as f32 + metrics.ymin as f32;
+                        Transform::from_row(
+                            1.0,
+                            0.0,
+                            -ITALIC_SKEW,
+                            1.0,
+                            ITALIC_SKEW * baseline,
+                            0.0,
+                        )
+                    } else {
+                        Transform::default()
+                    };
                     line_pixmap.draw_pixmap(
                         glyph.x as i32,
                         glyph.y as i32,
                         glyph_pixmap.as_ref(),
                         &PixmapPaint {
-                            blend_mode: tiny_skia::BlendMode::Source,
+                            // Glyphs are blended over highlights rather than punching through them
+                            blend_mode: if has_backgrounds {
+                                tiny_skia::BlendMode::SourceOver
+                            } else {
+                                tiny_skia::BlendMode::Source
+                            },
                             opacity: 1.0,
                             quality: tiny_skia::FilterQuality::Nearest,
                         },
-                        Transform::default(),
+                        transform,
                         None,
                     );
                 }
             }
 
-            cache.put(self.row_number, buf.clone());
-            buf
+            self.draw_lines_through(&mut line_pixmap, font, palette);
+
+            let image = slint::Image::from_rgba8_premultiplied(buf);
+            cache.put(self.row_number, image.clone());
+            image
         } else {
-            existing_buffer.unwrap().clone()
+            existing_image.unwrap().clone()
         }
     }
 }
@@ -273,27 +518,196 @@ impl TerminalLine {
 pub enum ViewAction {
     AppendCompleteLine(Arc<StyledLine>),
     AppendPartialLine(Arc<StyledLine>),
+    /// Replaces the prompt, which stays below all other lines until the next one arrives
+    UpdatePrompt(Arc<StyledLine>),
+    /// Masks the input area while the server isn't echoing input
+    SetInputMasked(bool),
+    PerformLineOperation(LineOperation),
+    /// Values the server sent over MSDP or GMCP, redrawing any status bar fields bound to them
+    UpdateStatusVariables(Arc<Vec<(String, String)>>),
+    /// Sets a script's status bar gauge, or removes it when there's none
+    SetGauge(Arc<String>, Option<Gauge>),
+    /// Replaces the text in the input area, as a script asked
+    SetInputLine(Arc<String>),
+    /// Something that changes the session's connection state, like the connection opening
+    SessionEvent(SessionEvent),
+    /// How many commands the send queue is holding back
+    SendQueueDepth(usize),
+}
+
+/// Changes scripts can make to lines already in the buffer. Lines are counted back from the most
+/// recent one, which is 0, not counting a pinned prompt. Ranges include both ends, in either
+/// order, and lines past the oldest one are ignored.
+#[derive(Clone, Debug)]
+pub enum LineOperation {
+    Highlight {
+        line: usize,
+        color: slint::Color,
+    },
+    HighlightRange {
+        start_line: usize,
+        end_line: usize,
+        color: slint::Color,
+    },
+    /// Colors the background behind part of a line; the range is of byte offsets into its text
+    HighlightText {
+        line: usize,
+        range: Range<usize>,
+        color: slint::Color,
+    },
+    Replace {
+        line: usize,
+        with: Arc<StyledLine>,
+    },
+    Remove {
+        line: usize,
+    },
+    RemoveRange {
+        start_line: usize,
+        end_line: usize,
+    },
+}
+
+impl LineOperation {
+    /// The lines the operation applies to, counted back from the most recent
+    fn lines(&self) -> RangeInclusive<usize> {
+        match *self {
+            LineOperation::Highlight { line, .. }
+            | LineOperation::HighlightText { line, .. }
+            | LineOperation::Replace { line, .. }
+            | LineOperation::Remove { line } => line..=line,
+            LineOperation::HighlightRange {
+                start_line,
+                end_line,
+                ..
+            }
+            | LineOperation::RemoveRange {
+                start_line,
+                end_line,
+            } => start_line.min(end_line)..=start_line.max(end_line),
+        }
+    }
+}
+
+/// Applies an operation to the buffered lines, leaving a pinned prompt (the last line) alone.
+/// Returns the row numbers of the lines that changed or were removed.
+fn apply_line_operation(
+    lines: &mut VecDeque<TerminalLine>,
+    prompt_pinned: bool,
+    operation: LineOperation,
+) -> Vec<usize> {
+    let output_lines = lines.len() - usize::from(prompt_pinned && !lines.is_empty());
+    let back = operation.lines();
+    if *back.start() >= output_lines {
+        return Vec::new();
+    }
+    let newest = output_lines - 1;
+    let indices = newest - (*back.end()).min(newest)..=newest - back.start();
+    let changed = indices.clone().map(|idx| lines[idx].row_number).collect();
+
+    match operation {
+        LineOperation::Highlight { color, .. } | LineOperation::HighlightRange { color, .. } => {
+            for line in lines.range_mut(indices) {
+                line.background = Some(color);
+            }
+        }
+        LineOperation::HighlightText { range, color, .. } => {
+            let bg = styled_line::Color::RGB {
+                r: color.red(),
+                g: color.green(),
+                b: color.blue(),
+            };
+            for line in lines.range_mut(indices) {
+                let background = line.background;
+                *line = TerminalLine::new(
+                    line.row_number,
+                    Arc::new(
+                        line.styled_line
+                            .restyle(range.clone(), |style| style.bg = Some(bg)),
+                    ),
+                    line.font_size,
+                    line.show_timestamp,
+                    line.soft_wrap,
+                );
+                line.background = background;
+            }
+        }
+        LineOperation::Replace { with, .. } => {
+            for line in lines.range_mut(indices) {
+                *line = TerminalLine::new(
+                    line.row_number,
+                    with.clone(),
+                    line.font_size,
+                    line.show_timestamp,
+                    line.soft_wrap,
+                );
+            }
+        }
+        LineOperation::Remove { .. } | LineOperation::RemoveRange { .. } => {
+            lines.drain(indices);
+        }
+    }
+
+    changed
 }
 
 pub struct TerminalView {
     font: fontdue::Font,
-    row_pixel_buffer_cache: ImageCache,
+    row_image_cache: ImageCache,
     viewable_size: RefCell<(NonZeroU32, NonZeroU32)>,
     cached_row_count: Rc<RefCell<ViewableRowCount>>,
     current_row_number: RefCell<usize>,
     lines: Rc<RefCell<VecDeque<TerminalLine>>>,
+    max_lines: RefCell<usize>,
     notify: slint::ModelNotify,
     pub tx: UnboundedSender<ViewAction>,
     rx: RefCell<UnboundedReceiver<ViewAction>>,
-    font_size: f32,
+    scale_factor: f32,
+    font_size: RefCell<f32>,
     last_line_terminated: RefCell<bool>,
+    prompt_pinned: RefCell<bool>,
+    show_timestamps: RefCell<bool>,
+    soft_wrap: RefCell<bool>,
+    palette: RefCell<AnsiPalette>,
     row_count_model: Rc<SharedSingleIntModel>,
+    input_masked_model: Rc<SharedSingleIntModel>,
+    status_bar: RefCell<StatusBar>,
+    status_fields_model: Rc<slint::VecModel<StatusField>>,
+    input_override_model: Rc<slint::VecModel<slint::SharedString>>,
+    input_override_serial_model: Rc<SharedSingleIntModel>,
+    connection_tracker: RefCell<ConnectionTracker>,
+    connection_state_model: Rc<SharedSingleIntModel>,
+    send_queue_depth_model: Rc<SharedSingleIntModel>,
     scroll_position: RefCell<ScrollPosition>,
 }
 
 impl TerminalView {
-    pub fn new(weak_window: slint::Weak<MainWindow>) -> Self {
-        let font_size = weak_window.upgrade().unwrap().window().scale_factor() * 16.0;
+    pub fn new(
+        weak_window: slint::Weak<MainWindow>,
+        font_size: f32,
+        max_lines: usize,
+        show_timestamps: bool,
+        soft_wrap: bool,
+    ) -> Self {
+        let scale_factor = weak_window.upgrade().unwrap().window().scale_factor();
+        Self::with_scale_factor(
+            scale_factor,
+            font_size,
+            max_lines,
+            show_timestamps,
+            soft_wrap,
+        )
+    }
+
+    /// Creates a view for a window with the given scale factor, without needing the window itself
+    pub fn with_scale_factor(
+        scale_factor: f32,
+        font_size: f32,
+        max_lines: usize,
+        show_timestamps: bool,
+        soft_wrap: bool,
+    ) -> Self {
+        let font_size = scale_factor * font_size;
 
         let font = fontdue::Font::from_bytes(
             FONT_DATA,
@@ -311,25 +725,116 @@ impl TerminalView {
             font,
             viewable_size: RefCell::new((NonZeroU32::MIN, NonZeroU32::MIN)),
             current_row_number: RefCell::new(0),
-            row_pixel_buffer_cache: Rc::new(RefCell::new(LruCache::new(
-                NonZeroUsize::new(500).unwrap(),
-            ))),
-            lines: Rc::new(RefCell::new(VecDeque::with_capacity(10000))),
+            row_image_cache: Rc::new(RefCell::new(LruCache::new(NonZeroUsize::new(500).unwrap()))),
+            lines: Rc::new(RefCell::new(VecDeque::with_capacity(max_lines))),
+            max_lines: RefCell::new(max_lines.max(1)),
             notify: ModelNotify::default(),
             cached_row_count: Rc::new(RefCell::new(ViewableRowCount::Dirty)),
-            font_size,
+            scale_factor,
+            font_size: RefCell::new(font_size),
             tx,
             rx: RefCell::new(rx),
             last_line_terminated: RefCell::new(true),
+            prompt_pinned: RefCell::new(false),
+            show_timestamps: RefCell::new(show_timestamps),
+            soft_wrap: RefCell::new(soft_wrap),
+            palette: RefCell::new(AnsiPalette::default()),
             row_count_model: Rc::new(SharedSingleIntModel::new(0)),
+            input_masked_model: Rc::new(SharedSingleIntModel::new(0)),
+            status_bar: RefCell::new(StatusBar::default()),
+            status_fields_model: Rc::new(slint::VecModel::default()),
+            input_override_model: Rc::new(slint::VecModel::from(vec![slint::SharedString::new()])),
+            input_override_serial_model: Rc::new(SharedSingleIntModel::new(0)),
+            connection_tracker: RefCell::new(ConnectionTracker::default()),
+            connection_state_model: Rc::new(SharedSingleIntModel::new(
+                ConnectionState::Disconnected as i32,
+            )),
+            send_queue_depth_model: Rc::new(SharedSingleIntModel::new(0)),
             scroll_position: RefCell::new(ScrollPosition::PinnedToEnd),
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.lines.borrow().is_empty()
+    }
+
     pub fn row_count_model(&self) -> Rc<SharedSingleIntModel> {
         self.row_count_model.clone()
     }
 
+    /// 1 while the input area should be masked, 0 otherwise
+    pub fn input_masked_model(&self) -> Rc<SharedSingleIntModel> {
+        self.input_masked_model.clone()
+    }
+
+    pub fn is_input_masked(&self) -> bool {
+        *self.input_masked_model.value.borrow() != 0
+    }
+
+    /// The status bar fields whose variables the server has sent
+    pub fn status_fields_model(&self) -> Rc<slint::VecModel<StatusField>> {
+        self.status_fields_model.clone()
+    }
+
+    /// The text a script last put in the input area
+    pub fn input_override_model(&self) -> Rc<slint::VecModel<slint::SharedString>> {
+        self.input_override_model.clone()
+    }
+
+    /// Counts the times a script has set the input area, so the UI knows to copy the text in
+    pub fn input_override_serial_model(&self) -> Rc<SharedSingleIntModel> {
+        self.input_override_serial_model.clone()
+    }
+
+    /// The session's ConnectionState, as an int
+    pub fn connection_state_model(&self) -> Rc<SharedSingleIntModel> {
+        self.connection_state_model.clone()
+    }
+
+    pub fn send_queue_depth_model(&self) -> Rc<SharedSingleIntModel> {
+        self.send_queue_depth_model.clone()
+    }
+
+    pub fn handle_session_event(&self, event: SessionEvent) {
+        let mut connection_tracker = self.connection_tracker.borrow_mut();
+        if connection_tracker.handle(event) {
+            self.connection_state_model
+                .replace(connection_tracker.state() as i32);
+        }
+    }
+
+    pub fn set_status_bindings(&self, bindings: Vec<StatusBinding>) {
+        self.status_bar.borrow_mut().set_bindings(bindings);
+        self.refresh_status_fields();
+    }
+
+    fn refresh_status_fields(&self) {
+        let fields = self
+            .status_bar
+            .borrow()
+            .values()
+            .into_iter()
+            .map(|value| StatusField {
+                label: value.label.into(),
+                text: value.text.into(),
+                has_bar: value.fraction.is_some(),
+                fraction: value.fraction.unwrap_or_default(),
+                color: value.color.unwrap_or(STATUS_FIELD_COLOR),
+            })
+            .collect::<Vec<_>>();
+
+        // Fields are updated in place where they can be, so their bars animate to the new value
+        if fields.len() == self.status_fields_model.row_count() {
+            for (row, field) in fields.into_iter().enumerate() {
+                if self.status_fields_model.row_data(row).as_ref() != Some(&field) {
+                    self.status_fields_model.set_row_data(row, field);
+                }
+            }
+        } else {
+            self.status_fields_model.set_vec(fields);
+        }
+    }
+
     pub fn set_scroll_position(&self, value: i32) {
         let mut scroll_position = self.scroll_position.borrow_mut();
 
@@ -351,20 +856,115 @@ impl TerminalView {
             let mut lines = self.lines.borrow_mut();
             let mut current_row_number = self.current_row_number.borrow_mut();
             let mut last_line_terminated = self.last_line_terminated.borrow_mut();
+            let mut prompt_pinned = self.prompt_pinned.borrow_mut();
 
             for _ in 0..pending {
                 let (line, is_terminated) = match rx.blocking_recv().unwrap() {
-                    ViewAction::AppendCompleteLine(line) => (line, true),
+                    ViewAction::AppendCompleteLine(line) => {
+                        self.handle_session_event(SessionEvent::LinesArrived);
+                        (line, true)
+                    }
                     ViewAction::AppendPartialLine(line) => (line, false),
+                    ViewAction::UpdatePrompt(line) => {
+                        if *prompt_pinned {
+                            lines.pop_back();
+                        }
+                        // The prompt takes the place of the partial line it grew out of
+                        if !*last_line_terminated {
+                            lines.pop_back();
+                            *last_line_terminated = true;
+                        }
+                        while lines.len() >= *self.max_lines.borrow() {
+                            lines.pop_front();
+                        }
+                        lines.push_back(TerminalLine::new(
+                            *current_row_number,
+                            line,
+                            *self.font_size.borrow(),
+                            *self.show_timestamps.borrow(),
+                            *self.soft_wrap.borrow(),
+                        ));
+                        *current_row_number += 1;
+                        *prompt_pinned = true;
+                        continue;
+                    }
+                    ViewAction::SetInputMasked(masked) => {
+                        self.input_masked_model.replace(i32::from(masked));
+                        continue;
+                    }
+                    ViewAction::UpdateStatusVariables(variables) => {
+                        if self.status_bar.borrow_mut().update(&variables) {
+                            self.refresh_status_fields();
+                        }
+                        continue;
+                    }
+                    ViewAction::SetGauge(id, gauge) => {
+                        if self.status_bar.borrow_mut().set_gauge(&id, gauge) {
+                            self.refresh_status_fields();
+                        }
+                        continue;
+                    }
+                    ViewAction::SessionEvent(event) => {
+                        self.handle_session_event(event);
+                        continue;
+                    }
+                    ViewAction::SendQueueDepth(depth) => {
+                        self.send_queue_depth_model.replace(depth as i32);
+                        continue;
+                    }
+                    ViewAction::SetInputLine(text) => {
+                        self.input_override_model.set_row_data(0, text.as_str().into());
+                        let serial = *self.input_override_serial_model.value.borrow();
+                        self.input_override_serial_model.replace(serial + 1);
+                        continue;
+                    }
+                    ViewAction::PerformLineOperation(operation) => {
+                        // Output that arrives after the newest line is removed can't continue it
+                        if matches!(
+                            operation,
+                            LineOperation::Remove { .. } | LineOperation::RemoveRange { .. }
+                        ) && *operation.lines().start() == 0
+                        {
+                            *last_line_terminated = true;
+                        }
+
+                        let mut cache = self.row_image_cache.borrow_mut();
+                        for row_number in
+                            apply_line_operation(&mut lines, *prompt_pinned, operation)
+                        {
+                            cache.pop(&row_number);
+                        }
+                        continue;
+                    }
+                };
+
+                // Output goes above the pinned prompt
+                let prompt = if *prompt_pinned {
+                    lines.pop_back()
+                } else {
+                    None
                 };
 
                 if *last_line_terminated {
-                    lines.push_back(TerminalLine::new(*current_row_number, line, self.font_size));
+                    while lines.len() >= *self.max_lines.borrow() {
+                        lines.pop_front();
+                    }
+                    lines.push_back(TerminalLine::new(
+                        *current_row_number,
+                        line,
+                        *self.font_size.borrow(),
+                        *self.show_timestamps.borrow(),
+                        *self.soft_wrap.borrow(),
+                    ));
                     *current_row_number += 1;
                 } else {
                     lines.back_mut().unwrap().append(line);
                 }
 
+                if let Some(prompt) = prompt {
+                    lines.push_back(prompt);
+                }
+
                 *last_line_terminated = is_terminated;
             }
 
@@ -374,6 +974,228 @@ impl TerminalView {
         }
     }
 
+    /// How many columns and rows of monospaced text fit into the given physical size
+    /// Which of `lines` is shown in the given visible row
+    fn line_index(&self, row: usize, line_count: usize) -> usize {
+        let scroll_position = self.scroll_position.borrow();
+
+        let mut offset = line_count - self.row_count();
+
+        if let ScrollPosition::ToLine(scroll_line) = *scroll_position {
+            if row + offset + (NON_SCROLLBACK_SIZE_IN_LINES as usize) < line_count {
+                offset = max(
+                    0,
+                    (scroll_line as usize)
+                        .checked_sub(self.row_count())
+                        .or(Some(0))
+                        .unwrap(),
+                );
+            }
+        }
+
+        row + offset
+    }
+
+    /// The character cell under a point in the visible lines, given in pixels from the left and
+    /// from the bottom (lines are stacked up from the bottom of the view)
+    pub fn hit_test(&self, x: f32, y_from_bottom: f32) -> Option<SelectionPoint> {
+        let row_count = self.row_count();
+        let lines = self.lines.borrow();
+
+        let mut bottom = 0.0;
+        for row in (0..row_count).rev() {
+            let line = lines.get(self.line_index(row, lines.len()))?;
+            let height = line.last_rasterized_height as f32;
+            // Anything above the top line is treated as being on it
+            if y_from_bottom < bottom + height || row == 0 {
+                return Some(SelectionPoint {
+                    line: line.row_number,
+                    column: line.char_at(x, (bottom + height - y_from_bottom).max(0.0)),
+                });
+            }
+            bottom += height;
+        }
+
+        None
+    }
+
+    /// The plain text of the selected cells in all of the view's lines, including scrollback
+    pub fn selected_text(&self, selection: &Selection) -> String {
+        let lines = self.lines.borrow();
+        selection::selected_text(
+            lines
+                .iter()
+                .map(|line| (line.row_number, line.styled_line.as_ref())),
+            selection,
+        )
+    }
+
+    /// What clicking on a character cell does, if it's in a link
+    pub fn link_at(&self, point: SelectionPoint) -> Option<LinkAction> {
+        let lines = self.lines.borrow();
+        lines
+            .iter()
+            .find(|line| line.row_number == point.line)
+            .and_then(|line| line.styled_line.link_at(point.column).cloned())
+    }
+
+    /// Every match of `query` in the view's lines, oldest first
+    pub fn search(&self, query: &str) -> Vec<FindMatch> {
+        let lines = self.lines.borrow();
+        lines
+            .iter()
+            .flat_map(|line| {
+                find::find_in_line(&line.styled_line.text, query)
+                    .into_iter()
+                    .map(|columns| FindMatch {
+                        line: line.row_number,
+                        columns,
+                    })
+            })
+            .collect()
+    }
+
+    /// Highlights the given matches (replacing any earlier ones), with `current` drawn brighter
+    pub fn set_highlights(&self, matches: &[FindMatch], current: Option<usize>) {
+        let mut lines = self.lines.borrow_mut();
+        let mut cache = self.row_image_cache.borrow_mut();
+        let mut changed = false;
+        for line in lines.iter_mut() {
+            let highlights: Vec<_> = matches
+                .iter()
+                .enumerate()
+                .filter(|(_, found)| found.line == line.row_number)
+                .map(|(idx, found)| (found.columns.clone(), Some(idx) == current))
+                .collect();
+
+            // Only lines whose highlights changed are drawn again
+            if line.highlights != highlights {
+                line.highlights = highlights;
+                cache.pop(&line.row_number);
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.notify.reset();
+        }
+    }
+
+    /// Scrolls so the given line is in view, returning false if it's no longer in the buffer
+    pub fn scroll_to_line(&self, row_number: usize) -> bool {
+        let row_count = self.row_count();
+        let (index, line_count) = {
+            let lines = self.lines.borrow();
+            let Some(index) = lines.iter().position(|line| line.row_number == row_number) else {
+                return false;
+            };
+            (index, lines.len())
+        };
+
+        // Lines already on screen while pinned to the end don't need a scroll
+        if index + row_count >= line_count {
+            self.set_scroll_position(-1);
+        } else {
+            self.set_scroll_position((index + NON_SCROLLBACK_SIZE_IN_LINES as usize + 1) as i32);
+        }
+        true
+    }
+
+    pub fn size_in_characters(&self, width: u32, height: u32) -> (u16, u16) {
+        let font_size = *self.font_size.borrow();
+        let advance_width = self.font.metrics('M', font_size).advance_width.max(1.0);
+        let line_height = self
+            .font
+            .horizontal_line_metrics(font_size)
+            .map(|metrics| metrics.new_line_size)
+            .unwrap_or(font_size)
+            .max(1.0);
+
+        (
+            (width as f32 / advance_width)
+                .floor()
+                .clamp(1.0, u16::MAX as f32) as u16,
+            (height as f32 / line_height)
+                .floor()
+                .clamp(1.0, u16::MAX as f32) as u16,
+        )
+    }
+
+    /// Changes the size (in logical pixels) that text is rendered at; every line is laid out again
+    pub fn set_font_size(&self, font_size: f32) {
+        let font_size = self.scale_factor * font_size;
+
+        if *self.font_size.borrow() == font_size {
+            return;
+        }
+
+        self.font_size.replace(font_size);
+
+        for line in self.lines.borrow_mut().iter_mut() {
+            line.set_font_size(font_size);
+        }
+
+        self.row_image_cache.borrow_mut().clear();
+        self.cached_row_count.replace(ViewableRowCount::Dirty);
+        self.notify.reset();
+    }
+
+    /// Changes how many lines are kept, dropping the oldest ones if there are now too many
+    pub fn set_max_lines(&self, max_lines: usize) {
+        let max_lines = max_lines.max(1);
+        if self.max_lines.replace(max_lines) == max_lines {
+            return;
+        }
+
+        let mut lines = self.lines.borrow_mut();
+        if lines.len() > max_lines {
+            let excess = lines.len() - max_lines;
+            lines.drain(..excess);
+            self.cached_row_count.replace(ViewableRowCount::Dirty);
+            self.notify.reset();
+        }
+    }
+
+    /// Shows or hides when each line arrived, in front of it
+    pub fn set_show_timestamps(&self, show_timestamps: bool) {
+        if self.show_timestamps.replace(show_timestamps) == show_timestamps {
+            return;
+        }
+
+        for line in self.lines.borrow_mut().iter_mut() {
+            line.set_show_timestamp(show_timestamps);
+        }
+
+        self.row_image_cache.borrow_mut().clear();
+        self.cached_row_count.replace(ViewableRowCount::Dirty);
+        self.notify.reset();
+    }
+
+    /// Switches between wrapping lines by column between words, and fontdue's own wrapping
+    /// Recolors every line; only their images are redrawn, since the layout doesn't change
+    pub fn set_palette(&self, palette: AnsiPalette) {
+        if self.palette.replace(palette) == palette {
+            return;
+        }
+
+        self.row_image_cache.borrow_mut().clear();
+        self.notify.reset();
+    }
+
+    pub fn set_soft_wrap(&self, soft_wrap: bool) {
+        if self.soft_wrap.replace(soft_wrap) == soft_wrap {
+            return;
+        }
+
+        for line in self.lines.borrow_mut().iter_mut() {
+            line.set_soft_wrap(soft_wrap);
+        }
+
+        self.row_image_cache.borrow_mut().clear();
+        self.cached_row_count.replace(ViewableRowCount::Dirty);
+        self.notify.reset();
+    }
+
     pub fn set_viewable_size(&self, width: NonZeroU32, height: NonZeroU32) {
         let mut viewable_size = self.viewable_size.borrow_mut();
 
@@ -415,12 +1237,13 @@ impl slint::Model for TerminalView {
                 let mut scrollback_iter = lines.iter_mut().rev();
                 // the first NON_SCROLLBACK_SIZE_IN_LINES (bottom) lines always start from the end
                 for line in &mut scrollback_iter {
-                    let pixel_buffer = line.pixel_buffer(
-                        &self.row_pixel_buffer_cache,
+                    let image = line.image(
+                        &self.row_image_cache,
                         &self.font,
+                        &self.palette.borrow(),
                         viewable_size.0.into(),
                     );
-                    let line_height = pixel_buffer.height();
+                    let line_height = image.size().height;
                     if line_height >= height {
                         break;
                     }
@@ -435,12 +1258,13 @@ impl slint::Model for TerminalView {
                         // subsequent lines come from the scrollback
 
                         for line in scrollback_iter {
-                            let pixel_buffer = line.pixel_buffer(
-                                &self.row_pixel_buffer_cache,
+                            let image = line.image(
+                                &self.row_image_cache,
                                 &self.font,
+                                &self.palette.borrow(),
                                 viewable_size.0.into(),
                             );
-                            let line_height = pixel_buffer.height();
+                            let line_height = image.size().height;
                             if line_height >= height {
                                 break;
                             }
@@ -461,31 +1285,15 @@ impl slint::Model for TerminalView {
     fn row_data(&self, row: usize) -> Option<Self::Data> {
         let viewable_size = self.viewable_size.borrow();
         let mut lines = self.lines.borrow_mut();
-        let scroll_position = self.scroll_position.borrow();
-
-        let mut offset = lines.len() - self.row_count();
-
-        if let ScrollPosition::ToLine(scroll_line) = *scroll_position {
-            if row + offset + (NON_SCROLLBACK_SIZE_IN_LINES as usize) < lines.len() {
-                offset = max(
-                    0,
-                    (scroll_line as usize)
-                        .checked_sub(self.row_count())
-                        .or(Some(0))
-                        .unwrap(),
-                );
-            }
-        }
-
-        match lines.get_mut(row + offset) {
-            Some(line) => {
-                let pixel_buffer = line.pixel_buffer(
-                    &self.row_pixel_buffer_cache,
-                    &self.font,
-                    viewable_size.0.into(),
-                );
-                Some(slint::Image::from_rgba8_premultiplied(pixel_buffer))
-            }
+        let line_index = self.line_index(row, lines.len());
+
+        match lines.get_mut(line_index) {
+            Some(line) => Some(line.image(
+                &self.row_image_cache,
+                &self.font,
+                &self.palette.borrow(),
+                viewable_size.0.into(),
+            )),
             _ => None,
         }
     }
@@ -534,3 +1342,231 @@ impl slint::Model for SharedSingleIntModel {
         &self.notify
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use slint::Model;
+
+    use super::*;
+    use crate::session::styled_line::{AnsiColor, Color, SpanInfo};
+
+    /// Lines numbered 0 (oldest) to `count - 1`, with text matching their number
+    fn buffered_lines(count: usize) -> VecDeque<TerminalLine> {
+        (0..count)
+            .map(|row_number| {
+                let line = StyledLine::from_output_str(&format!("line {row_number}"));
+                TerminalLine::new(row_number, Arc::new(line), 14.0, false, false)
+            })
+            .collect()
+    }
+
+    fn backgrounds(lines: &VecDeque<TerminalLine>) -> Vec<Option<slint::Color>> {
+        lines.iter().map(|line| line.background).collect()
+    }
+
+    fn texts(lines: &VecDeque<TerminalLine>) -> Vec<&str> {
+        lines
+            .iter()
+            .map(|line| line.styled_line.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_highlight_range() {
+        let red = slint::Color::from_rgb_u8(128, 0, 0);
+        let mut lines = buffered_lines(5);
+
+        let changed = apply_line_operation(
+            &mut lines,
+            false,
+            LineOperation::HighlightRange {
+                start_line: 3,
+                end_line: 1,
+                color: red,
+            },
+        );
+
+        assert_eq!(changed, vec![1, 2, 3]);
+        assert_eq!(
+            backgrounds(&lines),
+            vec![None, Some(red), Some(red), Some(red), None]
+        );
+    }
+
+    #[test]
+    fn test_operations_skip_the_pinned_prompt() {
+        let red = slint::Color::from_rgb_u8(128, 0, 0);
+        let mut lines = buffered_lines(4);
+
+        apply_line_operation(
+            &mut lines,
+            true,
+            LineOperation::Highlight {
+                line: 0,
+                color: red,
+            },
+        );
+        assert_eq!(backgrounds(&lines), vec![None, None, Some(red), None]);
+
+        apply_line_operation(
+            &mut lines,
+            true,
+            LineOperation::Replace {
+                line: 1,
+                with: Arc::new(StyledLine::from_output_str("replaced")),
+            },
+        );
+        assert_eq!(
+            texts(&lines),
+            vec!["line 0", "replaced", "line 2", "line 3"]
+        );
+    }
+
+    #[test]
+    fn test_highlight_text_keeps_the_line_background() {
+        let red = slint::Color::from_rgb_u8(128, 0, 0);
+        let mut lines = buffered_lines(2);
+        apply_line_operation(&mut lines, false, LineOperation::Highlight { line: 0, color: red });
+
+        let changed = apply_line_operation(
+            &mut lines,
+            false,
+            LineOperation::HighlightText {
+                line: 0,
+                range: 0..4,
+                color: red,
+            },
+        );
+
+        assert_eq!(changed, vec![1]);
+        assert_eq!(backgrounds(&lines), vec![None, Some(red)]);
+        let bgs: Vec<_> = lines[1]
+            .styled_line
+            .spans
+            .iter()
+            .map(|span| (span.begin_pos..span.end_pos, span.style.bg))
+            .collect();
+        assert_eq!(
+            bgs,
+            vec![
+                (0..4, Some(Color::RGB { r: 128, g: 0, b: 0 })),
+                (4..6, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remove_range_is_clipped_to_the_buffer() {
+        let mut lines = buffered_lines(4);
+
+        let changed = apply_line_operation(
+            &mut lines,
+            false,
+            LineOperation::RemoveRange {
+                start_line: 2,
+                end_line: 10,
+            },
+        );
+        assert_eq!(changed, vec![0, 1]);
+        assert_eq!(texts(&lines), vec!["line 2", "line 3"]);
+
+        let changed = apply_line_operation(&mut lines, false, LineOperation::Remove { line: 5 });
+        assert!(changed.is_empty());
+        assert_eq!(texts(&lines), vec!["line 2", "line 3"]);
+    }
+
+    /// A combat line with a few differently colored spans
+    fn combat_line(n: usize) -> StyledLine {
+        let parts = [
+            (format!("[{n:>6}] "), AnsiColor::White),
+            ("The goblin ".to_string(), AnsiColor::Red),
+            ("MAULS".to_string(), AnsiColor::Yellow),
+            (
+                " you with its rusty cleaver! (32 damage)".to_string(),
+                AnsiColor::White,
+            ),
+        ];
+
+        let mut text = String::new();
+        let mut spans = Vec::new();
+        for (part, color) in parts {
+            spans.push(SpanInfo {
+                style: Style {
+                    fg: Color::AnsiColor { color, bold: false },
+                    ..Style::default()
+                },
+                begin_pos: text.len(),
+                end_pos: text.len() + part.len(),
+            });
+            text.push_str(&part);
+        }
+        StyledLine::new(&text, spans)
+    }
+
+    #[test]
+    fn test_lowering_max_lines_drops_the_oldest() {
+        let view = TerminalView::with_scale_factor(1.0, 14.0, 10, false, false);
+        for n in 0..5 {
+            let line = StyledLine::from_output_str(&format!("line {n}"));
+            view.tx
+                .send(ViewAction::AppendCompleteLine(Arc::new(line)))
+                .unwrap();
+        }
+        view.handle_incoming_lines();
+
+        view.set_max_lines(2);
+        assert_eq!(texts(&view.lines.borrow()), vec!["line 3", "line 4"]);
+    }
+
+    /// Pumps styled lines through the view at 10,000 a second, drawing every visible row once per
+    /// 16ms of input, and reports frame time percentiles. It measures rather than asserts, so run
+    /// it on purpose: `cargo test --release stress -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn stress_append_frame_times() {
+        const LINES_PER_SECOND: usize = 10_000;
+        const FRAME_MILLIS: usize = 16;
+        const FRAMES: usize = 600;
+
+        let view = TerminalView::with_scale_factor(1.0, 14.0, 10_000, false, false);
+        view.set_viewable_size(
+            NonZeroU32::new(1280).unwrap(),
+            NonZeroU32::new(900).unwrap(),
+        );
+
+        let mut line_number = 0;
+        let mut frame_times = Vec::with_capacity(FRAMES);
+        for _ in 0..FRAMES {
+            for _ in 0..LINES_PER_SECOND * FRAME_MILLIS / 1000 {
+                line_number += 1;
+                view.tx
+                    .send(ViewAction::AppendCompleteLine(Arc::new(combat_line(
+                        line_number,
+                    ))))
+                    .unwrap();
+            }
+
+            let started = Instant::now();
+            view.handle_incoming_lines();
+            for row in 0..view.row_count() {
+                view.row_data(row);
+            }
+            frame_times.push(started.elapsed());
+        }
+
+        frame_times.sort();
+        let percentile = |p: usize| frame_times[(frame_times.len() - 1) * p / 100];
+        let total: Duration = frame_times.iter().sum();
+        println!(
+            "{FRAMES} frames of {} lines: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}, mean {:?}",
+            LINES_PER_SECOND * FRAME_MILLIS / 1000,
+            percentile(50),
+            percentile(95),
+            percentile(99),
+            percentile(100),
+            total / FRAMES as u32,
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        LazyLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use rodio::{
+    source::{SineWave, Source},
+    Decoder, OutputStream, OutputStreamHandle, Sink,
+};
+
+/// Most sounds that can play at once; starting another stops the oldest
+const MAX_VOICES: usize = 8;
+
+/// Short tones that can be played by name without shipping any sound files
+const BUILTIN_SOUNDS: &[(&str, &[(f32, u64)])] = &[
+    ("beep", &[(880.0, 150)]),
+    ("chime", &[(660.0, 120), (990.0, 200)]),
+    ("alert", &[(988.0, 100), (0.0, 60), (988.0, 100), (0.0, 60), (988.0, 100)]),
+];
+
+/// The audio output lives on a thread of its own for the lifetime of the process, so sounds keep
+/// playing across session and script reloads
+pub static SOUND: LazyLock<SoundPlayer> = LazyLock::new(SoundPlayer::new);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoundSource {
+    Builtin(&'static str),
+    File(PathBuf),
+}
+
+impl SoundSource {
+    /// Resolves a builtin sound name, or a path relative to `base_dir` (typically the profile's directory)
+    pub fn resolve(name_or_path: &str, base_dir: &Path) -> Self {
+        match BUILTIN_SOUNDS.iter().find(|(name, _)| *name == name_or_path) {
+            Some((name, _)) => SoundSource::Builtin(name),
+            None => SoundSource::File(base_dir.join(name_or_path)),
+        }
+    }
+}
+
+/// What a session needs to play sounds: whether the profile allows them, its master volume, and
+/// the directory that sound file paths are relative to
+#[derive(Debug, Clone)]
+pub struct SoundContext {
+    enabled: bool,
+    master_volume: f32,
+    base_dir: PathBuf,
+}
+
+impl SoundContext {
+    pub fn new(enabled: bool, master_volume: f32, base_dir: PathBuf) -> Self {
+        Self {
+            enabled,
+            master_volume,
+            base_dir,
+        }
+    }
+
+    pub fn play(&self, name_or_path: &str, volume: f32, id: Option<String>) {
+        if !self.enabled {
+            return;
+        }
+
+        SOUND.play(
+            SoundSource::resolve(name_or_path, &self.base_dir),
+            volume.clamp(0.0, 1.0) * self.master_volume,
+            id,
+        );
+    }
+
+    pub fn stop(&self, id: String) {
+        SOUND.stop(id);
+    }
+}
+
+enum SoundCommand {
+    Play {
+        source: SoundSource,
+        volume: f32,
+        id: Option<String>,
+    },
+    Stop {
+        id: String,
+    },
+}
+
+pub struct SoundPlayer {
+    tx: Sender<SoundCommand>,
+}
+
+impl SoundPlayer {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || SoundPlayer::run(&rx));
+
+        Self { tx }
+    }
+
+    /// Plays a sound at the given volume (0 to 1), optionally tagged with an id it can be stopped by
+    pub fn play(&self, source: SoundSource, volume: f32, id: Option<String>) {
+        self.tx
+            .send(SoundCommand::Play {
+                source,
+                volume: volume.clamp(0.0, 1.0),
+                id,
+            })
+            .ok();
+    }
+
+    /// Stops every playing sound tagged with `id`
+    pub fn stop(&self, id: String) {
+        self.tx.send(SoundCommand::Stop { id }).ok();
+    }
+
+    fn run(rx: &Receiver<SoundCommand>) {
+        // The stream has to stay alive for as long as anything plays through its handle
+        let (_stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("No audio output available, sounds will not be played: {e}");
+                // Keep draining commands so senders never notice
+                while rx.recv().is_ok() {}
+                return;
+            }
+        };
+
+        let mut voices: Voices<Sink> = Voices::new(MAX_VOICES);
+        // A trigger may try to play the same missing file on every match; only warn the first time
+        let mut failed: Vec<SoundSource> = Vec::new();
+
+        while let Ok(command) = rx.recv() {
+            voices.retain(|sink| !sink.empty());
+
+            match command {
+                SoundCommand::Play { source, volume, id } => {
+                    match SoundPlayer::start(&handle, &source, volume) {
+                        Ok(sink) => {
+                            if let Some(oldest) = voices.push(id, sink) {
+                                oldest.stop();
+                            }
+                        }
+                        Err(e) => {
+                            if !failed.contains(&source) {
+                                warn!("Could not play {source:?}: {e:#}");
+                                failed.push(source);
+                            }
+                        }
+                    }
+                }
+                SoundCommand::Stop { id } => {
+                    for sink in voices.remove(&id) {
+                        sink.stop();
+                    }
+                }
+            }
+        }
+    }
+
+    fn start(handle: &OutputStreamHandle, source: &SoundSource, volume: f32) -> Result<Sink> {
+        let sink = Sink::try_new(handle).context("Could not open an audio sink")?;
+        sink.set_volume(volume);
+
+        match source {
+            SoundSource::Builtin(name) => {
+                let Some((_, tones)) = BUILTIN_SOUNDS.iter().find(|(builtin, _)| builtin == name)
+                else {
+                    bail!("Unknown builtin sound");
+                };
+                for (frequency, millis) in tones.iter() {
+                    sink.append(
+                        SineWave::new(*frequency)
+                            .take_duration(Duration::from_millis(*millis))
+                            .amplify(if *frequency > 0.0 { 0.25 } else { 0.0 }),
+                    );
+                }
+            }
+            SoundSource::File(path) => {
+                let file = File::open(path).context("Could not open sound file")?;
+                let decoder =
+                    Decoder::new(BufReader::new(file)).context("Could not decode sound file")?;
+                sink.append(decoder);
+            }
+        }
+
+        Ok(sink)
+    }
+}
+
+/// The sounds currently playing, oldest first, capped at a maximum number of voices
+struct Voices<T> {
+    max_voices: usize,
+    voices: VecDeque<(Option<String>, T)>,
+}
+
+impl<T> Voices<T> {
+    fn new(max_voices: usize) -> Self {
+        Self {
+            max_voices: max_voices.max(1),
+            voices: VecDeque::new(),
+        }
+    }
+
+    /// Adds a voice, returning the oldest one if it had to make room
+    fn push(&mut self, id: Option<String>, voice: T) -> Option<T> {
+        let evicted = if self.voices.len() >= self.max_voices {
+            self.voices.pop_front().map(|(_, voice)| voice)
+        } else {
+            None
+        };
+        self.voices.push_back((id, voice));
+        evicted
+    }
+
+    /// Removes and returns every voice tagged with `id`
+    fn remove(&mut self, id: &str) -> Vec<T> {
+        let (removed, kept): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.voices)
+            .into_iter()
+            .partition(|(voice_id, _)| voice_id.as_deref() == Some(id));
+        self.voices = kept;
+        removed.into_iter().map(|(_, voice)| voice).collect()
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&T) -> bool) {
+        self.voices.retain(|(_, voice)| f(voice));
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.voices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_voice_dropped() {
+        let mut voices = Voices::new(2);
+
+        assert_eq!(voices.push(None, 1), None);
+        assert_eq!(voices.push(None, 2), None);
+        assert_eq!(voices.push(None, 3), Some(1));
+        assert_eq!(voices.push(None, 4), Some(2));
+        assert_eq!(voices.len(), 2);
+    }
+
+    #[test]
+    fn test_stop_by_id() {
+        let mut voices = Voices::new(MAX_VOICES);
+        voices.push(Some("tell".into()), 1);
+        voices.push(None, 2);
+        voices.push(Some("tell".into()), 3);
+
+        assert_eq!(voices.remove("tell"), vec![1, 3]);
+        assert_eq!(voices.remove("tell"), Vec::<i32>::new());
+        assert_eq!(voices.len(), 1);
+    }
+
+    #[test]
+    fn test_finished_voices_pruned() {
+        let mut voices = Voices::new(MAX_VOICES);
+        voices.push(None, 1);
+        voices.push(None, 2);
+
+        voices.retain(|voice| *voice != 1);
+        assert_eq!(voices.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base_dir = Path::new("profiles/aardwolf");
+
+        assert_eq!(
+            SoundSource::resolve("beep", base_dir),
+            SoundSource::Builtin("beep")
+        );
+        assert_eq!(
+            SoundSource::resolve("sounds/tell.wav", base_dir),
+            SoundSource::File(PathBuf::from("profiles/aardwolf/sounds/tell.wav"))
+        );
+    }
+}
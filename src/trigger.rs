@@ -1,6 +1,8 @@
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     sync::{Arc, Mutex},
+    time::Instant,
     vec,
 };
 
@@ -8,7 +10,31 @@ use anyhow::{bail, Result};
 use regex::{Regex, RegexSet};
 use tokio::sync::{mpsc::UnboundedSender, oneshot};
 
-use crate::{script_runtime::RuntimeAction, session::StyledLine};
+use crate::{dice, script_runtime::RuntimeAction, session::StyledLine};
+
+mod builtin;
+mod command_line;
+mod harness;
+mod highlight;
+mod limits;
+mod matcher;
+mod prefilter;
+mod stats;
+mod style_match;
+mod substitution;
+use builtin::BuiltinCommand;
+pub use builtin::SessionCommand;
+pub use command_line::{split_commands, DEFAULT_COMMAND_SEPARATOR};
+pub use highlight::Highlights;
+pub use limits::FireLimits;
+use limits::TriggerLimiter;
+use matcher::TriggerMatcher;
+use stats::PatternKind;
+pub use stats::{StatsReport, TriggerStats};
+pub use style_match::{ColorMatch, StyleMatcher};
+
+/// How many raw lines are kept for `#debug capture`
+const RAW_HISTORY_LINES: usize = 1_000;
 
 pub enum TriggerResult {
     Processed,
@@ -25,35 +51,60 @@ enum Action {
 
 #[derive(Debug)]
 pub struct TriggerManager {
-    trigger_regex_set: RegexSet,
+    trigger_matcher: TriggerMatcher,
     alias_regex_set: RegexSet,
     triggers: Vec<Trigger>,
     aliases: Vec<Alias>,
     script_eval_tx: UnboundedSender<RuntimeAction>,
-}
-
-fn line_splitter(ch: char) -> bool {
-    ch == ';' || ch == '\n'
+    command_separator: char,
+    prompt_regex: Option<Regex>,
+    stats: Arc<TriggerStats>,
+    limiter: TriggerLimiter,
+    /// Where the built-in commands the session runs itself are handed to it
+    session_command_tx: Option<UnboundedSender<SessionCommand>>,
+    /// The most recent lines as they were received, for `#debug capture`
+    raw_history: Mutex<VecDeque<String>>,
+    /// Restyles complete lines after triggers have run, without running any scripts
+    highlights: Mutex<Highlights>,
 }
 
 impl TriggerManager {
-    pub fn new(script_eval_tx: UnboundedSender<RuntimeAction>) -> Self {
+    pub fn new(
+        script_eval_tx: UnboundedSender<RuntimeAction>,
+        command_separator: char,
+        prompt_regex: Option<Regex>,
+    ) -> Self {
         let triggers = Vec::new();
         let aliases = Vec::new();
-        let trigger_regex_set = RegexSet::empty();
         let alias_regex_set = RegexSet::empty();
 
         let mut me = Self {
-            trigger_regex_set,
+            trigger_matcher: TriggerMatcher::default(),
             alias_regex_set,
             triggers,
             aliases,
             script_eval_tx,
+            command_separator,
+            prompt_regex,
+            stats: Arc::new(TriggerStats::default()),
+            limiter: TriggerLimiter::default(),
+            session_command_tx: None,
+            raw_history: Mutex::new(VecDeque::with_capacity(RAW_HISTORY_LINES)),
+            highlights: Mutex::new(Highlights::default()),
         };
 
         me.push_trigger(Trigger {
             name: "autoloot".into(),
             regex: Regex::new(r"is dead! R\.I\.P\.$").unwrap(),
+            anti_patterns: vec![],
+            prompt: false,
+            raw: false,
+            limits: FireLimits::default(),
+            sound: None,
+            capture: None,
+            priority: 0,
+            keep_evaluating: true,
+            match_style: None,
             script: Action::ProcessAlias(Arc::new(
                 "exa corpse;get all.pile.coins corpse".into(),
             )),
@@ -62,6 +113,8 @@ impl TriggerManager {
         me.push_alias(Alias {
             name: "order joy".into(),
             regex: Regex::new(r"^oj\s+(?<command>.*)$").unwrap(),
+            priority: 0,
+            keep_evaluating: false,
 
             script: Action::EvalJavascript(me.get_precompiled_alias_from_script(
                 r#"
@@ -75,6 +128,8 @@ impl TriggerManager {
         me.push_alias(Alias {
             name: "watch joy".into(),
             regex: Regex::new(r"^wj$").unwrap(),
+            priority: 0,
+            keep_evaluating: false,
 
             script: Action::EvalJavascript(me.get_precompiled_alias_from_script(
                 r#"
@@ -88,6 +143,8 @@ impl TriggerManager {
         me.push_alias(Alias {
             name: "unlock/open".into(),
             regex: Regex::new(r"^unop\s+(.*)$").unwrap(),
+            priority: 0,
+            keep_evaluating: false,
 
             script: Action::EvalJavascript(me.get_precompiled_alias_from_script(
                 r#"
@@ -101,6 +158,8 @@ impl TriggerManager {
         me.push_alias(Alias {
             name: "do whatever".into(),
             regex: Regex::new(r"^/js (.*)$").unwrap(),
+            priority: 0,
+            keep_evaluating: false,
 
             script: Action::EvalJavascript(me.get_precompiled_alias_from_script(
                 r#"
@@ -114,22 +173,71 @@ impl TriggerManager {
         me
     }
 
+    pub fn with_highlights(self, highlights: Highlights) -> Self {
+        Self {
+            highlights: Mutex::new(highlights),
+            ..self
+        }
+    }
+
+    /// Keeps profiling counters in `stats`, which scripts can read through `smudgy.stats()`
+    pub fn with_stats(self, stats: Arc<TriggerStats>) -> Self {
+        let me = Self { stats, ..self };
+        me.reset_trigger_stats();
+        me.reset_alias_stats();
+        me
+    }
+
+    /// Hands the built-in commands that need the session, like `#reconnect`, to `tx`
+    pub fn with_session_commands(self, tx: UnboundedSender<SessionCommand>) -> Self {
+        Self {
+            session_command_tx: Some(tx),
+            ..self
+        }
+    }
+
+    /// Triggers are kept in the order they're evaluated: highest priority first, then by name
     fn push_trigger(&mut self, trigger: Trigger) {
         self.triggers.push(trigger);
-        self.rebuild_trigger_regex_set();
+        self.triggers.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        self.rebuild_trigger_matcher();
     }
 
+    /// Aliases are kept in the order they're evaluated: highest priority first, then by name
     fn push_alias(&mut self, alias: Alias) {
         self.aliases.push(alias);
+        self.aliases.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
         self.rebuild_alias_regex_set();
     }
 
-    fn rebuild_trigger_regex_set(&mut self) {
-        self.trigger_regex_set = RegexSet::new(self.triggers.iter().map(|trigger| trigger.regex.as_str())).unwrap();
+    fn rebuild_trigger_matcher(&mut self) {
+        self.trigger_matcher = TriggerMatcher::new(&self.triggers);
+        self.reset_trigger_stats();
     }
 
     fn rebuild_alias_regex_set(&mut self) {
         self.alias_regex_set = RegexSet::new(self.aliases.iter().map(|alias| alias.regex.as_str())).unwrap();
+        self.reset_alias_stats();
+    }
+
+    fn reset_trigger_stats(&self) {
+        let names = self.triggers.iter().map(|trigger| trigger.name.clone());
+        self.stats
+            .reset_patterns(PatternKind::Trigger, names.collect());
+    }
+
+    fn reset_alias_stats(&self) {
+        let names = self.aliases.iter().map(|alias| alias.name.clone());
+        self.stats
+            .reset_patterns(PatternKind::Alias, names.collect());
     }
 
     fn get_precompiled_alias_from_script(&self, source: &str) -> usize {
@@ -143,120 +251,383 @@ impl TriggerManager {
         rx.blocking_recv().unwrap()
     }
 
-    pub fn process_incoming_line(&self, line: Arc<StyledLine>) {
-        let regex_set = &self.trigger_regex_set;
-        let matches: Vec<_> = regex_set.matches(line.as_str()).iter().collect();
-        if matches.len() > 0 {
-            let triggers = &self.triggers;
-            for trigger_idx in matches {
-                match triggers.get(trigger_idx).unwrap().script {
-                    Action::Noop => {}
-                    Action::SendRaw(ref str) => {
-                        self.script_eval_tx.send(RuntimeAction::SendRaw(str.clone())).unwrap();
-                    }
-                    Action::ProcessAlias(ref str) => {
-                        self.process_outgoing_line(str.as_str());
-                    }
-                    Action::EvalJavascript(_script_id) => {
-                        unimplemented!()
-                    }
+    /// Runs the triggers that match the line in priority order, until one that doesn't keep
+    /// evaluating fires, returning whether any did. `raw_line` is the line as it was received,
+    /// for raw triggers to match against.
+    fn fire_triggers(&self, line: &Arc<StyledLine>, raw_line: &str, is_prompt: bool) -> bool {
+        let started = self.stats.start();
+        let plain_text = line.plain_text();
+        let (matches, screening) =
+            self.trigger_matcher
+                .screened_matches(&plain_text, raw_line, is_prompt);
+        if let Some(screening) = screening {
+            self.stats
+                .record_screening(started, screening.eligible, screening.executed);
+        }
+        self.stats
+            .record_scan(PatternKind::Trigger, started, self.triggers.len());
+
+        let triggers = &self.triggers;
+        let now = Instant::now();
+        let mut fired = false;
+        for trigger_idx in matches.iter().copied() {
+            let trigger = triggers.get(trigger_idx).unwrap();
+            if !trigger.style_matches(line, &plain_text) {
+                continue;
+            }
+            if !self.limiter.try_fire(&trigger.name, &trigger.limits, now) {
+                continue;
+            }
+            fired = true;
+
+            let started = self.stats.start();
+            if let Some(ref sound) = trigger.sound {
+                self.script_eval_tx.send(RuntimeAction::PlaySound(sound.clone())).unwrap();
+            }
+            if let Some(ref buffer_name) = trigger.capture {
+                self.script_eval_tx
+                    .send(RuntimeAction::CaptureLine(buffer_name.clone(), line.clone()))
+                    .unwrap();
+            }
+            match trigger.script {
+                Action::Noop => {}
+                Action::SendRaw(ref str) => {
+                    self.script_eval_tx.send(RuntimeAction::SendRaw(str.clone())).unwrap();
+                }
+                Action::ProcessAlias(ref str) => {
+                    let haystack = if trigger.raw { raw_line } else { &plain_text };
+                    self.process_outgoing_line(&trigger.expand(str, haystack));
+                }
+                Action::EvalJavascript(_script_id) => {
+                    unimplemented!()
                 }
             }
-        } else {
-            self.script_eval_tx
-                .send(RuntimeAction::PassthroughCompleteLine(line))
-                .unwrap();
+            self.stats
+                .record_hit(PatternKind::Trigger, trigger_idx, started);
+
+            if !trigger.keep_evaluating {
+                break;
+            }
+        }
+
+        fired
+    }
+
+    /// Re-arms the named trigger after it has disabled itself, returning whether it exists
+    pub fn enable_trigger(&self, name: &str) -> bool {
+        let found = self.triggers.iter().any(|trigger| trigger.name == name);
+        if found {
+            self.limiter.rearm(name);
+        }
+        found
+    }
+
+    pub fn process_incoming_line(&self, line: Arc<StyledLine>, raw_line: &str) {
+        {
+            let mut raw_history = self.raw_history.lock().unwrap();
+            if raw_history.len() >= RAW_HISTORY_LINES {
+                raw_history.pop_front();
+            }
+            raw_history.push_back(raw_line.to_string());
         }
+
+        self.process_complete_line(line, raw_line, RuntimeAction::PassthroughCompleteLine);
+    }
+
+    /// A line fed back by `#debug replay`. It's never captured again, so a replay can't feed on
+    /// itself.
+    pub fn process_replayed_line(&self, line: Arc<StyledLine>, raw_line: &str) {
+        self.process_complete_line(line, raw_line, RuntimeAction::PassthroughReplayedLine);
+    }
+
+    /// Runs the triggers on a complete line, passing it on with `passthrough` if none fired
+    fn process_complete_line(
+        &self,
+        line: Arc<StyledLine>,
+        raw_line: &str,
+        passthrough: fn(Arc<StyledLine>) -> RuntimeAction,
+    ) {
+        if !self.fire_triggers(&line, raw_line, false) {
+            let line = self.highlight(line);
+            self.script_eval_tx.send(passthrough(line)).unwrap();
+        }
+    }
+
+    /// The line with the profile's highlights applied
+    fn highlight(&self, line: Arc<StyledLine>) -> Arc<StyledLine> {
+        let mut highlights = self.highlights.lock().unwrap();
+        highlights.reload_if_changed();
+        match highlights.apply(&line) {
+            Some(highlighted) => Arc::new(highlighted),
+            None => line,
+        }
+    }
+
+    /// Up to `count` of the most recent lines, as they were received, oldest first
+    pub fn recent_raw_lines(&self, count: usize) -> Vec<String> {
+        let raw_history = self.raw_history.lock().unwrap();
+        raw_history
+            .iter()
+            .skip(raw_history.len().saturating_sub(count))
+            .cloned()
+            .collect()
     }
 
     #[inline(always)]
     fn process_outgoing_line_inner(&self, line: &str, depth: u32) -> Result<()> {
+        if let Some(all) = BuiltinCommand::parse_unsplit(line) {
+            return self.run_builtin_command(all);
+        }
+
+        // Technically an outgoing line can be split into multiple commands, separated by newlines or the
+        // profile's command separator, so we need to process each one
+        for command in command_line::split_commands(line, self.command_separator) {
+            self.process_outgoing_command(&command, depth)?;
+        }
+        Ok(())
+    }
+
+    fn run_builtin_command(&self, command: BuiltinCommand) -> Result<()> {
+        match command {
+            BuiltinCommand::Notify(message) => {
+                self.script_eval_tx.send(RuntimeAction::Notify(
+                    Arc::new(String::new()),
+                    Arc::new(message),
+                ))?;
+            }
+            BuiltinCommand::Roll(expr) => {
+                let message = match dice::parse_and_roll(&expr, &mut rand::thread_rng()) {
+                    Ok(result) => result.to_string(),
+                    Err(e) => format!("{e:#}"),
+                };
+                self.script_eval_tx
+                    .send(RuntimeAction::Echo(Arc::new(message)))?;
+            }
+            BuiltinCommand::Enable(name) => {
+                let message = if self.enable_trigger(&name) {
+                    format!("Trigger {name} enabled")
+                } else {
+                    format!("No trigger named {name}")
+                };
+                self.script_eval_tx
+                    .send(RuntimeAction::Echo(Arc::new(message)))?;
+            }
+            BuiltinCommand::Stats(args) => return self.process_stats_command(&args),
+            BuiltinCommand::Highlight(args) => return self.process_highlight_command(&args),
+            BuiltinCommand::Test(args) => return self.process_test_command(&args),
+            BuiltinCommand::Session(command) => {
+                let Some(session_command_tx) = &self.session_command_tx else {
+                    bail!("There's no session to run that command in");
+                };
+                session_command_tx.send(command)?;
+                // The session runs its commands before the next frame is drawn
+                self.script_eval_tx.send(RuntimeAction::RequestRepaint)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn process_outgoing_command(&self, line: &str, depth: u32) -> Result<()> {
         if depth > 100 {
             bail!("Alias processor bailing, depth limit reached. Do you have an alias that triggers itself?");
         }
-        // Technically an outgoing line can be split into multiple lines, separated by newlines or ';' characters so we need to process each one
-        for line in line.split(line_splitter) {
-            let line_arc = Arc::new(line.to_string());
-
-            let matches: Vec<_> = self.alias_regex_set.matches(line).iter().collect();
-            if matches.len() > 0 {
-                let aliases = &self.aliases;
-                for match_idx in matches {
-                    match aliases.get(match_idx).unwrap() {
-                        Alias {
-                            name: _,
-                            regex,
-                            script: Action::EvalJavascript(script),
-                        } => {
-                            let mut i = 0;
-                            let captures: Arc<Vec<_>> = Arc::new(
-                                regex
-                                    .capture_names()
-                                    .zip(regex.captures(line).unwrap().iter())
-                                    .map(|(k, v)| {
-                                        let pair = (
-                                            k.and_then(|k| Some(k.to_string()))
-                                                .unwrap_or_else(|| format!("${i}")),
-                                            v.and_then(|v| Some(v.as_str()))
-                                                .unwrap_or("")
-                                                .to_string(),
-                                        );
-                                        i += 1;
-                                        pair
-                                    })
-                                    .collect(),
-                            );
-                            let (tx, rx) = oneshot::channel();
-                            self.script_eval_tx.send(RuntimeAction::EvalJavascriptAlias(
-                                line_arc.clone(),
-                                    *script,
-                                    captures,
-                                    Arc::new(tx),
-                            ))?;
-                            rx.blocking_recv().map(|response| {
-                                response.map(|line| {
-                                    self.process_outgoing_line_inner(line.as_str(), depth + 1)
+
+        if let Some((count, command)) = command_line::parse_repetition(line) {
+            if count > command_line::MAX_REPETITIONS {
+                bail!(
+                    "Refusing to repeat a command {count} times; the limit is {}",
+                    command_line::MAX_REPETITIONS
+                );
+            }
+            for _ in 0..count {
+                self.process_outgoing_command(command, depth + 1)?;
+            }
+            return Ok(());
+        }
+
+        if let Some(builtin) = BuiltinCommand::parse(line) {
+            return self.run_builtin_command(builtin);
+        }
+
+        let line_arc = Arc::new(line.to_string());
+
+        let started = self.stats.start();
+        let matches: Vec<_> = self.alias_regex_set.matches(line).iter().collect();
+        self.stats
+            .record_scan(PatternKind::Alias, started, self.aliases.len());
+        if matches.len() > 0 {
+            let aliases = &self.aliases;
+            for match_idx in matches {
+                let started = self.stats.start();
+                let alias = aliases.get(match_idx).unwrap();
+                match alias {
+                    Alias {
+                        regex,
+                        script: Action::EvalJavascript(script),
+                        ..
+                    } => {
+                        let mut i = 0;
+                        let captures: Arc<Vec<_>> = Arc::new(
+                            regex
+                                .capture_names()
+                                .zip(regex.captures(line).unwrap().iter())
+                                .map(|(k, v)| {
+                                    let pair = (
+                                        k.and_then(|k| Some(k.to_string()))
+                                            .unwrap_or_else(|| format!("${i}")),
+                                        v.and_then(|v| Some(v.as_str()))
+                                            .unwrap_or("")
+                                            .to_string(),
+                                    );
+                                    i += 1;
+                                    pair
                                 })
-                            })?;
-                        }
-                        Alias {
-                            name: _,
-                            regex: _,
-                            script: Action::ProcessAlias(script),
-                        } => self.process_outgoing_line_inner(script.as_str(), depth + 1)?,
-                        Alias {
-                            name: _,
-                            regex: _,
-                            script: Action::SendRaw(script),
-                        } => self
-                            .script_eval_tx
-                            .send(RuntimeAction::SendRaw(script.clone()))?,
-                        Alias {
-                            name: _,
-                            regex: _,
-                            script: Action::Noop,
-                        } => {}
+                                .collect(),
+                        );
+                        let (tx, rx) = oneshot::channel();
+                        self.script_eval_tx.send(RuntimeAction::EvalJavascriptAlias(
+                            line_arc.clone(),
+                                *script,
+                                captures,
+                                Arc::new(tx),
+                        ))?;
+                        rx.blocking_recv().map(|response| {
+                            response.map(|line| {
+                                self.process_outgoing_line_inner(line.as_str(), depth + 1)
+                            })
+                        })?;
+                    }
+                    Alias {
+                        regex,
+                        script: Action::ProcessAlias(script),
+                        ..
+                    } => {
+                        let captures = regex.captures(line).unwrap();
+                        let expanded =
+                            substitution::substitute_arguments(script.as_str(), &captures, line);
+                        self.process_outgoing_line_inner(expanded.as_str(), depth + 1)?
                     }
+                    Alias {
+                        script: Action::SendRaw(script),
+                        ..
+                    } => self
+                        .script_eval_tx
+                        .send(RuntimeAction::SendRaw(script.clone()))?,
+                    Alias {
+                        script: Action::Noop,
+                        ..
+                    } => {}
+                }
+                self.stats.record_hit(PatternKind::Alias, match_idx, started);
+
+                if !alias.keep_evaluating {
+                    break;
                 }
-            } else {
-                self.script_eval_tx
-                    .send(RuntimeAction::SendRaw(Arc::new(String::from(
-                        line,
-                    ))))?;
             }
+        } else {
+            self.script_eval_tx
+                .send(RuntimeAction::SendRaw(Arc::new(String::from(
+                    line,
+                ))))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the built-in `#test <trigger> <sample line>` command, which shows what the trigger
+    /// would do with the line without it having arrived. Names with spaces are quoted.
+    fn process_test_command(&self, args: &str) -> Result<()> {
+        let lines = match harness::split_trigger_name(args) {
+            Some((name, sample)) => {
+                let trigger = self.triggers.iter().find(|trigger| trigger.name == name);
+                match trigger {
+                    Some(trigger) => harness::report(name, &harness::test_trigger(trigger, sample)),
+                    None => vec![format!("No trigger named '{name}'")],
+                }
+            }
+            None => vec![
+                r#"Usage: #test <trigger> <sample line>, or #test "<trigger>" <sample line>"#
+                    .to_string(),
+            ],
+        };
+
+        for line in lines {
+            self.script_eval_tx.send(RuntimeAction::Echo(Arc::new(line)))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the built-in `#highlight add|remove|list` command
+    fn process_highlight_command(&self, args: &str) -> Result<()> {
+        let lines = self.highlights.lock().unwrap().process_command(args);
+        for line in lines {
+            self.script_eval_tx.send(RuntimeAction::Echo(Arc::new(line)))?;
+        }
+        Ok(())
+    }
+
+    /// Handles the built-in `#stats [on|off|reset]` command
+    fn process_stats_command(&self, args: &str) -> Result<()> {
+        let lines = match args {
+            "on" => {
+                self.stats.set_enabled(true);
+                vec!["Trigger profiling enabled".to_string()]
+            }
+            "off" => {
+                self.stats.set_enabled(false);
+                vec!["Trigger profiling disabled".to_string()]
+            }
+            "reset" => {
+                self.stats.reset();
+                vec!["Trigger profiling counters reset".to_string()]
+            }
+            "" => {
+                let mut lines = self.stats.report();
+                lines.extend(
+                    self.limiter.report(
+                        self.triggers
+                            .iter()
+                            .map(|trigger| (trigger.name.as_str(), &trigger.limits)),
+                    ),
+                );
+                lines
+            }
+            _ => vec!["Usage: #stats [on|off|reset]".to_string()],
+        };
+
+        for line in lines {
+            self.script_eval_tx.send(RuntimeAction::Echo(Arc::new(line)))?;
         }
         Ok(())
     }
 
     pub fn process_outgoing_line(&self, line: &str) {
-        self.process_outgoing_line_inner(line, 0).unwrap();
+        if let Err(e) = self.process_outgoing_line_inner(line, 0) {
+            self.script_eval_tx
+                .send(RuntimeAction::Echo(Arc::new(e.to_string())))
+                .unwrap();
+        }
     }
 
-    pub fn process_partial_line(&self, line: Arc<StyledLine>) {
-        //TODO: support partial line/prompt triggers
-        self.script_eval_tx
-            .send(RuntimeAction::PassthroughPartialLine(line))
-            .unwrap();
+    /// Partial lines matching the profile's prompt pattern are prompts, which fire prompt
+    /// triggers and stay pinned below the output; any others are passed through as they are.
+    /// Returns whether the line was a prompt, which the caller is done with.
+    pub fn process_partial_line(&self, line: Arc<StyledLine>, raw_line: &str) -> bool {
+        let is_prompt = self
+            .prompt_regex
+            .as_ref()
+            .is_some_and(|prompt_regex| prompt_regex.is_match(&line.plain_text()));
+
+        if is_prompt {
+            self.fire_triggers(&line, raw_line, true);
+            self.script_eval_tx
+                .send(RuntimeAction::UpdatePrompt(line))
+                .unwrap();
+        } else {
+            self.script_eval_tx
+                .send(RuntimeAction::PassthroughPartialLine(line))
+                .unwrap();
+        }
+        is_prompt
     }
 
     pub fn request_repaint(&self) {
@@ -270,23 +641,119 @@ impl TriggerManager {
 pub struct Trigger {
     pub name: String,
     pub regex: Regex,
+    /// The trigger doesn't fire on lines matching any of these, even when `regex` matches
+    pub anti_patterns: Vec<Regex>,
+    /// Fires on prompts rather than on complete lines
+    pub prompt: bool,
+    /// Matches `regex` against the line as it was received, escape codes and all, rather than
+    /// against its plain text
+    pub raw: bool,
+    pub limits: FireLimits,
+    /// A builtin sound name, or a path relative to the profile's directory, to play when the
+    /// trigger fires
+    pub sound: Option<Arc<String>>,
+    /// The capture buffer that lines firing the trigger are diverted into, like a chat window
+    pub capture: Option<Arc<String>>,
+    /// Matching triggers run from the highest priority down
+    pub priority: i32,
+    /// When false, a line that fires this trigger isn't checked against any after it
+    pub keep_evaluating: bool,
+    /// The colors or attributes the matched text must have. This is only checked once `regex`
+    /// has matched, so it doesn't make the literal screen or the regexes any slower.
+    pub match_style: Option<StyleMatcher>,
     pub script: Action,
 }
 
 impl Trigger {
-    pub fn new(name: String, regex: Regex, script: Action) -> Self {
+    pub fn new(
+        name: String,
+        regex: Regex,
+        anti_patterns: Vec<Regex>,
+        prompt: bool,
+        script: Action,
+    ) -> Self {
         Self {
             name,
             regex,
+            anti_patterns,
+            prompt,
+            raw: false,
+            limits: FireLimits::default(),
+            sound: None,
+            capture: None,
+            priority: 0,
+            keep_evaluating: true,
+            match_style: None,
             script,
         }
     }
+
+    pub fn with_raw(self, raw: bool) -> Self {
+        Self { raw, ..self }
+    }
+
+    pub fn with_limits(self, limits: FireLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn with_sound(self, sound: Option<Arc<String>>) -> Self {
+        Self { sound, ..self }
+    }
+
+    pub fn with_priority(self, priority: i32, keep_evaluating: bool) -> Self {
+        Self {
+            priority,
+            keep_evaluating,
+            ..self
+        }
+    }
+
+    pub fn with_capture(self, capture: Option<Arc<String>>) -> Self {
+        Self { capture, ..self }
+    }
+
+    /// A plaintext script with the captures from the line that fired the trigger substituted in
+    fn expand(&self, script: &str, line: &str) -> String {
+        match self.regex.captures(line) {
+            Some(captures) => substitution::substitute_arguments(script, &captures, line),
+            None => script.to_string(),
+        }
+    }
+
+    pub fn with_match_style(self, match_style: Option<StyleMatcher>) -> Self {
+        Self {
+            match_style,
+            ..self
+        }
+    }
+
+    /// Whether the text `regex` matched has the style the trigger requires. Escape codes in a raw
+    /// trigger's match don't line up with the line's text, so those are checked against the
+    /// whole line.
+    fn style_matches(&self, line: &StyledLine, plain_text: &str) -> bool {
+        let Some(ref match_style) = self.match_style else {
+            return true;
+        };
+        let range = if self.raw {
+            0..plain_text.len()
+        } else {
+            match self.regex.find(plain_text) {
+                Some(found) => found.range(),
+                None => return false,
+            }
+        };
+        match_style.matches(line, range)
+    }
 }
 
 #[derive(Debug)]
 pub struct Alias {
     name: String,
     regex: Regex,
+    /// Matching aliases run from the highest priority down
+    priority: i32,
+    /// When false, the first matching alias is the only one that runs for a command
+    keep_evaluating: bool,
     script: Action,
 }
 
@@ -295,7 +762,424 @@ impl Alias {
         Self {
             name,
             regex,
+            priority: 0,
+            keep_evaluating: false,
             script,
         }
     }
+
+    pub fn with_priority(self, priority: i32, keep_evaluating: bool) -> Self {
+        Self {
+            priority,
+            keep_evaluating,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+    use crate::session::{AnsiColor, Color};
+
+    /// A manager whose runtime answers script compilation requests and hands everything else back
+    fn manager() -> (TriggerManager, mpsc::Receiver<RuntimeAction>) {
+        manager_with_prompt(None)
+    }
+
+    fn manager_with_prompt(
+        prompt_regex: Option<Regex>,
+    ) -> (TriggerManager, mpsc::Receiver<RuntimeAction>) {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (forward_tx, forward_rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            while let Some(action) = rx.blocking_recv() {
+                match action {
+                    RuntimeAction::CompileJavascriptAlias(_, reply) => {
+                        if let Some(reply) = Arc::into_inner(reply) {
+                            reply.send(0).ok();
+                        }
+                    }
+                    action => {
+                        if forward_tx.send(action).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        (TriggerManager::new(tx, ';', prompt_regex), forward_rx)
+    }
+
+    #[test]
+    fn test_trigger_sound_is_played() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(
+            Trigger::new(
+                "tells".into(),
+                Regex::new(r"tells you").unwrap(),
+                vec![],
+                false,
+                Action::Noop,
+            )
+            .with_sound(Some(Arc::new("sounds/tell.ogg".into()))),
+        );
+
+        let text = "Gandalf tells you 'run'";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+
+        match rx.recv().unwrap() {
+            RuntimeAction::PlaySound(sound) => assert_eq!(sound.as_str(), "sounds/tell.ogg"),
+            _ => panic!("expected the trigger's sound to be played"),
+        }
+    }
+
+    #[test]
+    fn test_triggers_without_sound_stay_quiet() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(Trigger::new(
+            "tells".into(),
+            Regex::new(r"tells you").unwrap(),
+            vec![],
+            false,
+            Action::SendRaw(Arc::new("reply ok".into())),
+        ));
+
+        let text = "Gandalf tells you 'run'";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+
+        manager.request_repaint();
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::SendRaw(_)));
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::RequestRepaint));
+    }
+
+    #[test]
+    fn test_anti_patterns_stop_triggers_firing() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(Trigger::new(
+            "tells".into(),
+            Regex::new(r"^(\w+) tells you").unwrap(),
+            vec![Regex::new(r"^Saruman ").unwrap()],
+            false,
+            Action::SendRaw(Arc::new("reply busy".into())),
+        ));
+
+        for text in ["Saruman tells you 'come'", "Gandalf tells you 'run'"] {
+            manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        }
+
+        assert_eq!(sent(&manager, &rx), vec!["reply busy"]);
+    }
+
+    #[test]
+    fn test_trigger_requires_its_style() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(
+            Trigger::new(
+                "hungry".into(),
+                Regex::new(r"You are hungry\.").unwrap(),
+                vec![],
+                false,
+                Action::SendRaw(Arc::new("eat bread".into())),
+            )
+            .with_match_style(Some(StyleMatcher {
+                fg: Some(ColorMatch::Palette(1)),
+                ..StyleMatcher::default()
+            })),
+        );
+
+        // Another player saying it, in white, and the server's own message, in red
+        let text = "Frodo says 'You are hungry.'";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        let text = "You are hungry.";
+        let line = StyledLine::from_output_str(text).restyle(0..text.len(), |style| {
+            style.fg = Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: false,
+            }
+        });
+        manager.process_incoming_line(Arc::new(line), text);
+
+        assert_eq!(sent(&manager, &rx), vec!["eat bread"]);
+    }
+
+    #[test]
+    fn test_prompts_only_fire_prompt_triggers() {
+        let (mut manager, rx) = manager_with_prompt(Some(Regex::new(r">\s*$").unwrap()));
+        let vitals = |prompt| {
+            Trigger::new(
+                format!("vitals {prompt}"),
+                Regex::new(r"HP:(\d+)").unwrap(),
+                vec![],
+                prompt,
+                Action::SendRaw(Arc::new(format!("prompt {prompt}"))),
+            )
+        };
+        manager.push_trigger(vitals(true));
+        manager.push_trigger(vitals(false));
+
+        let text = "HP:100 MP:50>";
+        assert!(manager.process_partial_line(Arc::new(StyledLine::from_output_str(text)), text));
+        let text = "HP:100";
+        assert!(!manager.process_partial_line(Arc::new(StyledLine::from_output_str(text)), text));
+
+        assert_eq!(sent(&manager, &rx), vec!["prompt true"]);
+    }
+
+    #[test]
+    fn test_trigger_captures_are_substituted() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(Trigger::new(
+            "tells".into(),
+            Regex::new(r"^(\w+) tells you").unwrap(),
+            vec![],
+            false,
+            Action::ProcessAlias(Arc::new("tell %1 busy;wave %1".into())),
+        ));
+
+        let text = "Gandalf tells you 'run'";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+
+        assert_eq!(
+            sent(&manager, &rx),
+            vec!["tell Gandalf busy", "wave Gandalf"]
+        );
+    }
+
+    #[test]
+    fn test_triggers_can_run_session_commands() {
+        let (manager, rx) = manager();
+        let (session_tx, mut session_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut manager = manager.with_session_commands(session_tx);
+        manager.push_trigger(Trigger::new(
+            "map".into(),
+            Regex::new(r"^You study the map").unwrap(),
+            vec![],
+            false,
+            Action::ProcessAlias(Arc::new("look;#wrap off".into())),
+        ));
+
+        let text = "You study the map.";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+
+        assert_eq!(sent(&manager, &rx), vec!["look"]);
+        assert_eq!(session_rx.try_recv(), Ok(SessionCommand::Wrap(false)));
+    }
+
+    /// Everything sent so far; the repaint request marks where that ends
+    fn sent(manager: &TriggerManager, rx: &mpsc::Receiver<RuntimeAction>) -> Vec<String> {
+        manager.request_repaint();
+        let mut sent = Vec::new();
+        loop {
+            match rx.recv().unwrap() {
+                RuntimeAction::SendRaw(line) => sent.push(line.to_string()),
+                RuntimeAction::RequestRepaint => return sent,
+                _ => {}
+            }
+        }
+    }
+
+    fn send_alias(name: &str, pattern: &str, send: &str) -> Alias {
+        Alias::new(
+            name.into(),
+            Regex::new(pattern).unwrap(),
+            Action::SendRaw(Arc::new(send.into())),
+        )
+    }
+
+    #[test]
+    fn test_first_matching_alias_wins() {
+        let (mut manager, rx) = manager();
+        manager.push_alias(send_alias("b", r"^k (.*)$", "kick"));
+        manager.push_alias(send_alias("a", r"^k (.*)$", "kill"));
+        manager.push_alias(send_alias("c", r"^k (.*)$", "bash").with_priority(10, false));
+
+        manager.process_outgoing_line("k rat");
+        assert_eq!(sent(&manager, &rx), vec!["bash"]);
+    }
+
+    #[test]
+    fn test_aliases_can_keep_evaluating() {
+        let (mut manager, rx) = manager();
+        manager.push_alias(send_alias("b", r"^k (.*)$", "kick"));
+        manager.push_alias(send_alias("a", r"^k (.*)$", "kill").with_priority(0, true));
+
+        // Equal priorities are evaluated by name
+        manager.process_outgoing_line("k rat");
+        assert_eq!(sent(&manager, &rx), vec!["kill", "kick"]);
+    }
+
+    #[test]
+    fn test_trigger_can_stop_evaluation() {
+        let (mut manager, rx) = manager();
+        let trigger = |name: &str, pattern: &str, send: &str| {
+            Trigger::new(
+                name.into(),
+                Regex::new(pattern).unwrap(),
+                vec![],
+                false,
+                Action::SendRaw(Arc::new(send.into())),
+            )
+        };
+        // Pushed lowest first, so insertion order is the opposite of evaluation order
+        manager.push_trigger(trigger("retreat", r"arrives", "hide").with_priority(-5, true));
+        manager.push_trigger(trigger("greet", r"arrives", "wave"));
+        manager.push_trigger(trigger("bow", r"arrives", "bow"));
+        manager.push_trigger(trigger("guard", r"arrives", "protect").with_priority(5, false));
+        manager.push_trigger(trigger("cheer", r"arrives", "cheer").with_priority(10, true));
+
+        let text = "The king arrives.";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), vec!["cheer", "protect"]);
+
+        // A stopping trigger that doesn't match the line doesn't stop anything, and equal
+        // priorities are evaluated by name
+        manager.triggers.retain(|trigger| trigger.name != "guard");
+        manager.push_trigger(trigger("guard", r"leaves", "protect").with_priority(5, false));
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), vec!["cheer", "bow", "wave", "hide"]);
+    }
+
+    #[test]
+    fn test_limits_follow_triggers_when_they_are_resorted() {
+        let (mut manager, rx) = manager();
+        let trigger = |name: &str, send: &str| {
+            Trigger::new(
+                name.into(),
+                Regex::new(r"arrives").unwrap(),
+                vec![],
+                false,
+                Action::SendRaw(Arc::new(send.into())),
+            )
+        };
+        let once = FireLimits {
+            cooldown_ms: None,
+            max_fires: Some(1),
+        };
+        manager.push_trigger(
+            trigger("greet", "wave")
+                .with_priority(0, true)
+                .with_limits(once),
+        );
+
+        let text = "The king arrives.";
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), vec!["wave"]);
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), Vec::<String>::new());
+
+        // The new trigger sorts ahead of the exhausted one, which stays exhausted
+        manager.push_trigger(trigger("cheer", "cheer").with_priority(10, true));
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), vec!["cheer"]);
+
+        assert!(manager.enable_trigger("greet"));
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        assert_eq!(sent(&manager, &rx), vec!["cheer", "wave"]);
+    }
+
+    #[test]
+    fn test_captured_lines_skip_the_main_buffer() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(
+            Trigger::new(
+                "chat".into(),
+                Regex::new(r"^\[gossip\]").unwrap(),
+                vec![],
+                false,
+                Action::Noop,
+            )
+            .with_capture(Some(Arc::new("chat".into()))),
+        );
+
+        for text in ["[gossip] Bob: hi", "You are hungry."] {
+            manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        }
+        manager.request_repaint();
+
+        let mut main = Vec::new();
+        let mut captured = Vec::new();
+        loop {
+            match rx.recv().unwrap() {
+                RuntimeAction::PassthroughCompleteLine(line) => main.push(line.text.clone()),
+                RuntimeAction::CaptureLine(buffer_name, line) => {
+                    captured.push((buffer_name.to_string(), line.text.clone()));
+                }
+                RuntimeAction::RequestRepaint => break,
+                _ => {}
+            }
+        }
+
+        assert_eq!(main, vec!["You are hungry."]);
+        assert_eq!(captured, vec![("chat".to_string(), "[gossip] Bob: hi".to_string())]);
+    }
+
+    #[test]
+    fn test_replayed_lines_are_marked_and_not_recorded() {
+        let (manager, rx) = manager();
+        let text = "\x1b[32mA goblin arrives.\x1b[0m";
+
+        let live = "A goblin leaves.";
+
+        // Live output arriving in the middle of a replay is still treated as live
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        manager.process_replayed_line(Arc::new(StyledLine::from_output_str(text)), text);
+        manager.process_incoming_line(Arc::new(StyledLine::from_output_str(live)), live);
+        manager.request_repaint();
+
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::PassthroughCompleteLine(_)));
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::PassthroughReplayedLine(_)));
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::PassthroughCompleteLine(_)));
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::RequestRepaint));
+        assert_eq!(
+            manager.recent_raw_lines(10),
+            vec![text.to_string(), live.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_highlights_apply_after_triggers() {
+        let (mut manager, rx) = manager();
+        manager.push_trigger(Trigger::new(
+            "hungry".into(),
+            Regex::new(r"hungry").unwrap(),
+            vec![],
+            false,
+            Action::Noop,
+        ));
+        manager.process_outgoing_line("#highlight add #00ffff tells you");
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::Echo(_)));
+
+        for text in ["Bob tells you hi", "You are hungry."] {
+            manager.process_incoming_line(Arc::new(StyledLine::from_output_str(text)), text);
+        }
+        manager.request_repaint();
+
+        match rx.recv().unwrap() {
+            RuntimeAction::PassthroughCompleteLine(line) => assert_eq!(line.spans.len(), 3),
+            _ => panic!("expected the highlighted line to be passed through"),
+        }
+        // The line that fired a trigger is never passed through, so it isn't highlighted either
+        assert!(matches!(rx.recv().unwrap(), RuntimeAction::RequestRepaint));
+    }
+
+    #[test]
+    fn test_roll_echoes_the_result() {
+        let (manager, rx) = manager();
+        manager.process_outgoing_line("#roll 2d1+3");
+        manager.process_outgoing_line("#roll 1000000d1000000");
+
+        match rx.recv().unwrap() {
+            RuntimeAction::Echo(message) => assert_eq!(message.as_str(), "2d1+3: [1, 1] + 3 = 5"),
+            _ => panic!("expected the roll to be echoed"),
+        }
+        match rx.recv().unwrap() {
+            RuntimeAction::Echo(message) => assert!(message.contains("between 1 and 1000")),
+            _ => panic!("expected the error to be echoed"),
+        }
+    }
 }
@@ -0,0 +1,161 @@
+/// The built-in `#` commands. They're recognized wherever a command is processed, so aliases and
+/// triggers can use them as well as the input line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuiltinCommand {
+    Notify(String),
+    Roll(String),
+    Enable(String),
+    Stats(String),
+    Highlight(String),
+    Test(String),
+    /// Needs the session's connection, views or hotkeys, so is handed to it to run
+    Session(SessionCommand),
+}
+
+/// The built-in commands the session runs itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionCommand {
+    Reconnect,
+    Disconnect,
+    Debug(String),
+    /// Sends the rest of the line, separators and all, from every open session
+    All(String),
+    Macro(String),
+    Hotkeys(String),
+    FlushQueue,
+    Timestamps(bool),
+    Wrap(bool),
+}
+
+/// The arguments after `#name`, if `command` is that built-in
+fn arguments<'a>(command: &'a str, name: &str) -> Option<&'a str> {
+    let rest = command.strip_prefix('#')?.strip_prefix(name)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim())
+}
+
+/// The arguments after `#name`, if `command` is that built-in and has some
+fn required_arguments(command: &str, name: &str) -> Option<String> {
+    arguments(command, name)
+        .filter(|args| !args.is_empty())
+        .map(str::to_string)
+}
+
+fn on_off(command: &str, name: &str) -> Option<bool> {
+    match arguments(command, name)? {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl BuiltinCommand {
+    /// Recognizes a single command, after the line it came from has been split
+    pub fn parse(command: &str) -> Option<Self> {
+        let command = command.trim();
+        if !command.starts_with('#') {
+            return None;
+        }
+
+        let parsed = if let Some(message) = required_arguments(command, "notify") {
+            BuiltinCommand::Notify(message)
+        } else if let Some(expr) = required_arguments(command, "roll") {
+            BuiltinCommand::Roll(expr)
+        } else if let Some(name) = required_arguments(command, "enable") {
+            BuiltinCommand::Enable(name)
+        } else if let Some(args) = arguments(command, "stats") {
+            BuiltinCommand::Stats(args.to_string())
+        } else if let Some(args) = arguments(command, "highlight") {
+            BuiltinCommand::Highlight(args.to_string())
+        } else if let Some(args) = required_arguments(command, "test") {
+            BuiltinCommand::Test(args)
+        } else {
+            BuiltinCommand::Session(SessionCommand::parse(command)?)
+        };
+        Some(parsed)
+    }
+
+    /// Recognizes `#all`, which takes the rest of the line as it is, before the line is split
+    pub fn parse_unsplit(line: &str) -> Option<Self> {
+        required_arguments(line.trim(), "all").map(|line| Self::Session(SessionCommand::All(line)))
+    }
+}
+
+impl SessionCommand {
+    fn parse(command: &str) -> Option<Self> {
+        let parsed = match command {
+            "#reconnect" => SessionCommand::Reconnect,
+            "#disconnect" => SessionCommand::Disconnect,
+            "#flushqueue" => SessionCommand::FlushQueue,
+            _ => {
+                if let Some(args) = required_arguments(command, "debug") {
+                    SessionCommand::Debug(args)
+                } else if let Some(line) = required_arguments(command, "all") {
+                    SessionCommand::All(line)
+                } else if let Some(args) = arguments(command, "macro") {
+                    SessionCommand::Macro(args.to_string())
+                } else if let Some(args) = arguments(command, "hotkeys") {
+                    SessionCommand::Hotkeys(args.to_string())
+                } else if let Some(on) = on_off(command, "timestamps") {
+                    SessionCommand::Timestamps(on)
+                } else {
+                    SessionCommand::Wrap(on_off(command, "wrap")?)
+                }
+            }
+        };
+        Some(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            BuiltinCommand::parse("#roll 3d6+2"),
+            Some(BuiltinCommand::Roll("3d6+2".into()))
+        );
+        assert_eq!(
+            BuiltinCommand::parse(" #stats "),
+            Some(BuiltinCommand::Stats(String::new()))
+        );
+        assert_eq!(
+            BuiltinCommand::parse("#timestamps off"),
+            Some(BuiltinCommand::Session(SessionCommand::Timestamps(false)))
+        );
+        assert_eq!(
+            BuiltinCommand::parse("#macro play quick"),
+            Some(BuiltinCommand::Session(SessionCommand::Macro(
+                "play quick".into()
+            )))
+        );
+        assert_eq!(
+            BuiltinCommand::parse("#reconnect"),
+            Some(BuiltinCommand::Session(SessionCommand::Reconnect))
+        );
+
+        // Commands sent on to the server
+        for command in [
+            "look",
+            "#statsx",
+            "#notify",
+            "#wrap maybe",
+            "#reconnect now",
+            "#3 n",
+        ] {
+            assert_eq!(BuiltinCommand::parse(command), None, "{command}");
+        }
+    }
+
+    #[test]
+    fn test_all_takes_the_whole_line() {
+        assert_eq!(
+            BuiltinCommand::parse_unsplit("#all wake;stand"),
+            Some(BuiltinCommand::Session(SessionCommand::All(
+                "wake;stand".into()
+            )))
+        );
+        assert_eq!(BuiltinCommand::parse_unsplit("wake;#all stand"), None);
+    }
+}
@@ -0,0 +1,84 @@
+pub const DEFAULT_COMMAND_SEPARATOR: char = ';';
+
+/// Upper bound on `#N command` / `N*command` so a typo can't flood the server
+pub const MAX_REPETITIONS: usize = 100;
+
+/// Splits an outgoing line into individual commands on newlines and on `separator`.
+/// A doubled separator (e.g. `;;`) is an escape, and produces a single literal separator
+/// within the current command.
+pub fn split_commands(line: &str, separator: char) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '\n' {
+            commands.push(std::mem::take(&mut current));
+        } else if ch == separator {
+            if chars.peek() == Some(&separator) {
+                chars.next();
+                current.push(separator);
+            } else {
+                commands.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(ch);
+        }
+    }
+
+    commands.push(current);
+    commands
+}
+
+/// Recognizes the repetition prefixes `#3 kill rat` and `3*kill rat`, returning the repeat count
+/// and the command to be repeated
+pub fn parse_repetition(command: &str) -> Option<(usize, &str)> {
+    let (count, rest) = if let Some(rest) = command.strip_prefix('#') {
+        let digits_end = rest.find(|ch: char| !ch.is_ascii_digit())?;
+        let (count, rest) = rest.split_at(digits_end);
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        (count, rest.trim_start())
+    } else {
+        let (count, rest) = command.split_once('*')?;
+        (count, rest)
+    };
+
+    if count.is_empty() || !count.chars().all(|ch| ch.is_ascii_digit()) || rest.is_empty() {
+        return None;
+    }
+
+    count.parse().ok().map(|count| (count, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(split_commands("", ';'), vec![""]);
+        assert_eq!(split_commands("look", ';'), vec!["look"]);
+        assert_eq!(
+            split_commands("n;n;n;open door;e", ';'),
+            vec!["n", "n", "n", "open door", "e"]
+        );
+        assert_eq!(split_commands("n\ne", ';'), vec!["n", "e"]);
+        assert_eq!(split_commands("say hi;;there;e", ';'), vec!["say hi;there", "e"]);
+        assert_eq!(split_commands("say a;b|e", '|'), vec!["say a;b", "e"]);
+    }
+
+    #[test]
+    fn test_parse_repetition() {
+        assert_eq!(parse_repetition("#3 kill rat"), Some((3, "kill rat")));
+        assert_eq!(parse_repetition("3*kill rat"), Some((3, "kill rat")));
+        assert_eq!(parse_repetition("#12   n"), Some((12, "n")));
+        assert_eq!(parse_repetition("kill rat"), None);
+        assert_eq!(parse_repetition("#3"), None);
+        assert_eq!(parse_repetition("#3kill"), None);
+        assert_eq!(parse_repetition("3*"), None);
+        assert_eq!(parse_repetition("say 2*2 is 4"), None);
+        assert_eq!(parse_repetition("#cast"), None);
+    }
+}
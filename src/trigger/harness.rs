@@ -0,0 +1,191 @@
+use super::{Action, Trigger};
+
+/// What a trigger would do with a sample line, worked out without a connection or the script
+/// runtime
+#[derive(Debug, PartialEq, Eq)]
+pub enum TestOutcome {
+    NoMatch,
+    /// The pattern matched, but this anti-pattern rules the line out
+    Suppressed(String),
+    Matched {
+        /// Each capture group's name (or `$N` when it has none) and what it captured
+        captures: Vec<(String, String)>,
+        /// What a plaintext script would send, or None for a JavaScript one
+        output: Option<String>,
+    },
+}
+
+/// Runs a sample line through a trigger's real patterns and plaintext substitution. The line is
+/// used as both the plain and raw text, and whether it's a prompt isn't considered.
+pub fn test_trigger(trigger: &Trigger, line: &str) -> TestOutcome {
+    let Some(captures) = trigger.regex.captures(line) else {
+        return TestOutcome::NoMatch;
+    };
+
+    if let Some(anti_pattern) = trigger
+        .anti_patterns
+        .iter()
+        .find(|anti_pattern| anti_pattern.is_match(line))
+    {
+        return TestOutcome::Suppressed(anti_pattern.as_str().to_string());
+    }
+
+    let named_captures = trigger
+        .regex
+        .capture_names()
+        .zip(captures.iter())
+        .enumerate()
+        .map(|(i, (name, capture))| {
+            (
+                name.map_or_else(|| format!("${i}"), str::to_string),
+                capture.map_or("", |capture| capture.as_str()).to_string(),
+            )
+        })
+        .collect();
+
+    let output = match &trigger.script {
+        Action::Noop => Some(String::new()),
+        Action::SendRaw(script) => Some(script.to_string()),
+        Action::ProcessAlias(script) => Some(trigger.expand(script, line)),
+        Action::EvalJavascript(_) => None,
+    };
+
+    TestOutcome::Matched {
+        captures: named_captures,
+        output,
+    }
+}
+
+/// Splits `#test` arguments into the trigger's name, which is quoted when it has spaces in it,
+/// and the sample line
+pub fn split_trigger_name(args: &str) -> Option<(&str, &str)> {
+    let (name, sample) = match args.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?,
+        None => args.split_once(char::is_whitespace)?,
+    };
+    Some((name, sample.trim_start()))
+}
+
+/// Lines describing an outcome, to echo into the session
+pub fn report(trigger_name: &str, outcome: &TestOutcome) -> Vec<String> {
+    match outcome {
+        TestOutcome::NoMatch => vec![format!("Trigger '{trigger_name}' doesn't match")],
+        TestOutcome::Suppressed(anti_pattern) => vec![format!(
+            "Trigger '{trigger_name}' matches, but anti-pattern '{anti_pattern}' rules the line out"
+        )],
+        TestOutcome::Matched { captures, output } => {
+            let mut lines = vec![format!("Trigger '{trigger_name}' matches")];
+            lines.extend(
+                captures
+                    .iter()
+                    .map(|(name, value)| format!("  {name} = \"{value}\"")),
+            );
+            lines.push(match output {
+                Some(output) => format!("  sends: {output}"),
+                None => "  runs a JavaScript script, which isn't previewed".to_string(),
+            });
+            lines
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use regex::Regex;
+
+    use super::*;
+
+    fn trigger(pattern: &str, anti_patterns: &[&str], script: Action) -> Trigger {
+        Trigger::new(
+            "test".to_string(),
+            Regex::new(pattern).unwrap(),
+            anti_patterns
+                .iter()
+                .map(|anti_pattern| Regex::new(anti_pattern).unwrap())
+                .collect(),
+            false,
+            script,
+        )
+    }
+
+    #[test]
+    fn test_match_groups_and_output() {
+        let trigger = trigger(
+            r"^(?<attacker>\w+) hits you for (\d+) damage",
+            &[],
+            Action::ProcessAlias(Arc::new("say ouch, %2 from %1".to_string())),
+        );
+
+        assert_eq!(
+            test_trigger(&trigger, "Goblin hits you for 12 damage."),
+            TestOutcome::Matched {
+                captures: vec![
+                    (
+                        "$0".to_string(),
+                        "Goblin hits you for 12 damage".to_string()
+                    ),
+                    ("attacker".to_string(), "Goblin".to_string()),
+                    ("$2".to_string(), "12".to_string()),
+                ],
+                output: Some("say ouch, 12 from Goblin".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_no_match_and_anti_patterns() {
+        let trigger = trigger(
+            r"hits you",
+            &[r"^Your pet"],
+            Action::SendRaw(Arc::new("flee".to_string())),
+        );
+
+        assert_eq!(
+            test_trigger(&trigger, "The goblin misses you."),
+            TestOutcome::NoMatch
+        );
+        assert_eq!(
+            test_trigger(&trigger, "Your pet hits you by accident."),
+            TestOutcome::Suppressed("^Your pet".to_string())
+        );
+        assert_eq!(
+            test_trigger(&trigger, "The goblin hits you."),
+            TestOutcome::Matched {
+                captures: vec![("$0".to_string(), "hits you".to_string())],
+                output: Some("flee".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_quoted_trigger_names() {
+        assert_eq!(
+            split_trigger_name("tells Bob tells you 'hi'"),
+            Some(("tells", "Bob tells you 'hi'"))
+        );
+        assert_eq!(
+            split_trigger_name(r#""auto loot" The rat is dead! R.I.P."#),
+            Some(("auto loot", "The rat is dead! R.I.P."))
+        );
+        assert_eq!(split_trigger_name(r#""auto loot The rat"#), None);
+        assert_eq!(split_trigger_name("tells"), None);
+    }
+
+    #[test]
+    fn test_javascript_isnt_previewed() {
+        let trigger = trigger(r"^(\w+) arrives", &[], Action::EvalJavascript(0));
+        let outcome = test_trigger(&trigger, "Bob arrives from the north.");
+
+        assert_eq!(
+            report("greet", &outcome),
+            vec![
+                "Trigger 'greet' matches",
+                "  $0 = \"Bob arrives\"",
+                "  $1 = \"Bob\"",
+                "  runs a JavaScript script, which isn't previewed",
+            ]
+        );
+    }
+}
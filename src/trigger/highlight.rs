@@ -0,0 +1,471 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use deno_core::serde::{Deserialize, Serialize};
+use regex::Regex;
+
+use crate::session::{parse_hex_color, Color, StyledLine};
+
+const HIGHLIGHTS_JSON_FILENAME: &str = "highlights.json";
+
+/// How often highlights.json is checked for changes made outside the session
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Colors text matching a pattern, without running a script
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighlightRule {
+    pub pattern: String,
+    /// Whether `pattern` is a regular expression rather than plain text
+    #[serde(default)]
+    pub regex: bool,
+    /// `#rrggbb` colors; empty leaves the text's own color
+    #[serde(default)]
+    pub fg: String,
+    #[serde(default)]
+    pub bg: String,
+    /// Brightens ANSI colors, as servers do for bold text
+    #[serde(default)]
+    pub bold: bool,
+    /// Styles the whole line rather than only the matched text
+    #[serde(default)]
+    pub whole_line: bool,
+}
+
+impl HighlightRule {
+    fn compile(&self) -> Result<CompiledRule> {
+        if self.pattern.is_empty() {
+            return Err(anyhow!("Highlight has no pattern"));
+        }
+        let color = |color: &str| -> Result<Option<Color>> {
+            if color.is_empty() {
+                return Ok(None);
+            }
+            parse_hex_color(color).map(|color| {
+                Some(Color::RGB {
+                    r: color.red(),
+                    g: color.green(),
+                    b: color.blue(),
+                })
+            })
+        };
+
+        let regex = if self.regex {
+            Regex::new(&self.pattern)
+                .with_context(|| format!("Invalid highlight pattern /{}/", self.pattern))?
+        } else {
+            Regex::new(&regex::escape(&self.pattern))?
+        };
+
+        Ok(CompiledRule {
+            regex,
+            fg: color(&self.fg)?,
+            bg: color(&self.bg)?,
+            bold: self.bold,
+            whole_line: self.whole_line,
+        })
+    }
+
+    /// How the rule is shown by `#highlight list`
+    fn describe(&self) -> String {
+        let mut description = if self.regex {
+            format!("/{}/", self.pattern)
+        } else {
+            format!("\"{}\"", self.pattern)
+        };
+        for (name, color) in [("fg", &self.fg), ("bg", &self.bg)] {
+            if !color.is_empty() {
+                description.push_str(&format!(" {name} {color}"));
+            }
+        }
+        if self.bold {
+            description.push_str(" bold");
+        }
+        if self.whole_line {
+            description.push_str(" whole line");
+        }
+        description
+    }
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    regex: Regex,
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    whole_line: bool,
+}
+
+impl CompiledRule {
+    fn apply(&self, line: &StyledLine) -> Option<StyledLine> {
+        let ranges: Vec<_> = if self.whole_line {
+            self.regex
+                .is_match(&line.text)
+                .then(|| 0..line.text.len())
+                .into_iter()
+                .collect()
+        } else {
+            self.regex
+                .find_iter(&line.text)
+                .map(|found| found.range())
+                .filter(|range| !range.is_empty())
+                .collect()
+        };
+        if ranges.is_empty() {
+            return None;
+        }
+
+        let mut highlighted = line.clone();
+        for range in ranges {
+            highlighted = highlighted.restyle(range, |style| {
+                if let Some(fg) = self.fg {
+                    style.fg = fg;
+                }
+                if self.bg.is_some() {
+                    style.bg = self.bg;
+                }
+                if let Color::AnsiColor { ref mut bold, .. } = style.fg {
+                    *bold |= self.bold;
+                }
+            });
+        }
+        Some(highlighted)
+    }
+}
+
+/// A profile's highlight rules, kept in highlights.json in its directory and reloaded when that
+/// changes
+#[derive(Debug)]
+pub struct Highlights {
+    path: Option<PathBuf>,
+    rules: Vec<HighlightRule>,
+    /// Lines up with `rules`; rules that don't compile are kept, but never applied
+    compiled: Vec<Option<CompiledRule>>,
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl Default for Highlights {
+    fn default() -> Self {
+        Self {
+            path: None,
+            rules: Vec::new(),
+            compiled: Vec::new(),
+            modified: None,
+            checked_at: Instant::now(),
+        }
+    }
+}
+
+impl Highlights {
+    /// Loads the highlights kept in `dir`, starting with none when there are none or they're
+    /// unreadable
+    pub fn in_dir(mut dir: PathBuf) -> Self {
+        dir.push(HIGHLIGHTS_JSON_FILENAME);
+        let mut highlights = Highlights {
+            path: Some(dir),
+            ..Highlights::default()
+        };
+        highlights.reload();
+        highlights
+    }
+
+    fn reload(&mut self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        self.checked_at = Instant::now();
+        self.modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        let rules = match fs::read_to_string(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(anyhow!(e).context("Could not read highlights.json")),
+            Ok(json) => serde_json::from_str(&json).context("Could not parse highlights.json"),
+        };
+        match rules {
+            Ok(rules) => self.set_rules(rules),
+            Err(e) => warn!("Keeping the current highlights: {e:#}"),
+        }
+    }
+
+    /// Reloads the rules if highlights.json has changed since they were loaded, checking no
+    /// more often than every couple of seconds
+    pub fn reload_if_changed(&mut self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        if self.checked_at.elapsed() < RELOAD_CHECK_INTERVAL {
+            return;
+        }
+        self.checked_at = Instant::now();
+
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified != self.modified {
+            self.reload();
+        }
+    }
+
+    fn set_rules(&mut self, rules: Vec<HighlightRule>) {
+        self.compiled = rules
+            .iter()
+            .map(|rule| match rule.compile() {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    warn!("Skipping highlight {}: {e:#}", rule.describe());
+                    None
+                }
+            })
+            .collect();
+        self.rules = rules;
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let Some(path) = self.path.as_ref() else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&self.rules)
+            .context("Could not generate highlights json")?;
+        fs::write(path, json).context("Could not save highlights")?;
+        self.modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        Ok(())
+    }
+
+    pub fn add(&mut self, rule: HighlightRule) -> Result<()> {
+        let compiled = rule.compile()?;
+        self.rules.push(rule);
+        self.compiled.push(Some(compiled));
+        self.save()
+    }
+
+    /// Removes the rule at `index`, counting from 0
+    pub fn remove(&mut self, index: usize) -> Result<HighlightRule> {
+        if index >= self.rules.len() {
+            return Err(anyhow!("No highlight number {}", index + 1));
+        }
+        self.compiled.remove(index);
+        let rule = self.rules.remove(index);
+        self.save()?;
+        Ok(rule)
+    }
+
+    /// The line with every matching rule applied in order, or None when none match
+    pub fn apply(&self, line: &StyledLine) -> Option<StyledLine> {
+        let mut highlighted: Option<StyledLine> = None;
+        for rule in self.compiled.iter().flatten() {
+            if let Some(line) = rule.apply(highlighted.as_ref().unwrap_or(line)) {
+                highlighted = Some(line);
+            }
+        }
+        highlighted
+    }
+
+    /// Handles the built-in `#highlight add|remove|list` command, returning what to echo
+    pub fn process_command(&mut self, args: &str) -> Vec<String> {
+        let usage = || {
+            vec![
+                "Usage: #highlight add <#rrggbb> <text or /regex/> | remove <number> | list"
+                    .to_string(),
+            ]
+        };
+        let (command, args) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let args = args.trim();
+
+        match command {
+            "" | "list" => {
+                if self.rules.is_empty() {
+                    return vec!["No highlights".to_string()];
+                }
+                self.rules
+                    .iter()
+                    .zip(&self.compiled)
+                    .enumerate()
+                    .map(|(i, (rule, compiled))| {
+                        let invalid = if compiled.is_none() { " (invalid)" } else { "" };
+                        format!("{}. {}{invalid}", i + 1, rule.describe())
+                    })
+                    .collect()
+            }
+            "add" => {
+                let Some((fg, pattern)) = args.split_once(char::is_whitespace) else {
+                    return usage();
+                };
+                let pattern = pattern.trim();
+                let (pattern, regex) = match pattern
+                    .strip_prefix('/')
+                    .and_then(|pattern| pattern.strip_suffix('/'))
+                {
+                    Some(regex) => (regex, true),
+                    None => (pattern, false),
+                };
+                let rule = HighlightRule {
+                    pattern: pattern.to_string(),
+                    regex,
+                    fg: fg.to_string(),
+                    bg: String::new(),
+                    bold: false,
+                    whole_line: false,
+                };
+                let description = rule.describe();
+                match self.add(rule) {
+                    Ok(()) => vec![format!("Highlighting {description}")],
+                    Err(e) => vec![format!("{e:#}")],
+                }
+            }
+            "remove" => match args.parse::<usize>() {
+                Ok(number) if number > 0 => match self.remove(number - 1) {
+                    Ok(rule) => vec![format!("No longer highlighting {}", rule.describe())],
+                    Err(e) => vec![format!("{e:#}")],
+                },
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Style;
+
+    fn rule(pattern: &str, regex: bool, fg: &str) -> HighlightRule {
+        HighlightRule {
+            pattern: pattern.into(),
+            regex,
+            fg: fg.into(),
+            bg: String::new(),
+            bold: false,
+            whole_line: false,
+        }
+    }
+
+    fn highlights(rules: Vec<HighlightRule>) -> Highlights {
+        let mut highlights = Highlights::default();
+        highlights.set_rules(rules);
+        highlights
+    }
+
+    /// The text of each span, and its foreground color
+    fn spans(line: &StyledLine) -> Vec<(&str, Color)> {
+        line.spans
+            .iter()
+            .map(|span| (&line.text[span.begin_pos..span.end_pos], span.style.fg))
+            .collect()
+    }
+
+    const CYAN: Color = Color::RGB {
+        r: 0,
+        g: 255,
+        b: 255,
+    };
+
+    #[test]
+    fn test_matched_text_is_colored() {
+        let highlights = highlights(vec![rule("tells you", false, "#00ffff")]);
+        let line = StyledLine::from_output_str("Gandalf tells you 'run'");
+
+        let highlighted = highlights.apply(&line).unwrap();
+        assert_eq!(
+            spans(&highlighted),
+            vec![
+                ("Gandalf ", Color::Output),
+                ("tells you", CYAN),
+                (" 'run'", Color::Output)
+            ]
+        );
+        assert!(highlights
+            .apply(&StyledLine::from_output_str("You are hungry."))
+            .is_none());
+    }
+
+    #[test]
+    fn test_plain_patterns_are_not_regexes() {
+        let highlights = highlights(vec![rule("a.c", false, "#00ffff")]);
+        assert!(highlights
+            .apply(&StyledLine::from_output_str("abc"))
+            .is_none());
+        assert!(highlights
+            .apply(&StyledLine::from_output_str("a.c"))
+            .is_some());
+    }
+
+    #[test]
+    fn test_whole_line_and_background() {
+        let highlights = highlights(vec![HighlightRule {
+            bg: "#800000".into(),
+            whole_line: true,
+            ..rule(r"^\w+ attacks", true, "")
+        }]);
+        let line = StyledLine::from_output_str("Orc attacks you!");
+
+        let highlighted = highlights.apply(&line).unwrap();
+        assert_eq!(highlighted.spans.len(), 1);
+        assert_eq!(
+            highlighted.spans[0].style,
+            Style {
+                fg: Color::Output,
+                bg: Some(Color::RGB { r: 128, g: 0, b: 0 }),
+                ..Style::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules_are_kept_but_skipped() {
+        let mut highlights = highlights(vec![
+            rule("(unclosed", true, "#00ffff"),
+            rule("tells", false, "#00ffff"),
+        ]);
+        assert_eq!(
+            highlights.process_command("list"),
+            vec![
+                "1. /(unclosed/ fg #00ffff (invalid)",
+                "2. \"tells\" fg #00ffff"
+            ]
+        );
+        assert!(highlights
+            .apply(&StyledLine::from_output_str("Bob tells you hi"))
+            .is_some());
+        assert!(highlights.add(rule("x", false, "cyan")).is_err());
+    }
+
+    #[test]
+    fn test_commands_persist_rules() {
+        let dir = std::env::temp_dir().join(format!("smudgy-highlights-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut highlights = Highlights::in_dir(dir.clone());
+
+        assert_eq!(
+            highlights.process_command("add #00ffff /^\\w+ tells you/"),
+            vec!["Highlighting /^\\w+ tells you/ fg #00ffff"]
+        );
+        highlights.process_command("add #ff0000 hungry");
+        assert_eq!(Highlights::in_dir(dir.clone()).rules, highlights.rules);
+
+        assert_eq!(
+            highlights.process_command("remove 1"),
+            vec!["No longer highlighting /^\\w+ tells you/ fg #00ffff"]
+        );
+        assert_eq!(
+            Highlights::in_dir(dir.clone()).rules,
+            vec![rule("hungry", false, "#ff0000")]
+        );
+        assert_eq!(
+            highlights.process_command("remove 5"),
+            vec!["No highlight number 5"]
+        );
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
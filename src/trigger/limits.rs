@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Optional limits on how often a trigger may fire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FireLimits {
+    /// After firing, the trigger ignores matches for this long
+    pub cooldown_ms: Option<u64>,
+    /// The trigger disables itself after firing this many times, until it's enabled again
+    pub max_fires: Option<u32>,
+}
+
+impl FireLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.cooldown_ms.is_none() && self.max_fires.is_none()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct LimitState {
+    last_fired: Option<Instant>,
+    fires: u32,
+    suppressed: u64,
+}
+
+/// Enforces each trigger's `FireLimits`, and counts the matches they've suppressed. Triggers are
+/// identified by name, like `#enable` does, so their state follows them when the triggers are
+/// re-sorted.
+#[derive(Debug, Default)]
+pub struct TriggerLimiter {
+    states: Mutex<HashMap<String, LimitState>>,
+}
+
+impl TriggerLimiter {
+    /// Decides whether the named trigger may fire at `now`, recording the outcome either way
+    pub fn try_fire(&self, name: &str, limits: &FireLimits, now: Instant) -> bool {
+        if limits.is_unlimited() {
+            return true;
+        }
+
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(name.to_string()).or_default();
+
+        let exhausted = limits.max_fires.is_some_and(|max_fires| state.fires >= max_fires);
+        let cooling_down = match (limits.cooldown_ms, state.last_fired) {
+            (Some(cooldown_ms), Some(last_fired)) => {
+                now.saturating_duration_since(last_fired) < Duration::from_millis(cooldown_ms)
+            }
+            _ => false,
+        };
+
+        if exhausted || cooling_down {
+            state.suppressed += 1;
+            return false;
+        }
+
+        state.last_fired = Some(now);
+        state.fires += 1;
+        true
+    }
+
+    /// Re-arms a trigger that disabled itself after reaching its maximum number of fires, and
+    /// ends any cooldown it's in
+    pub fn rearm(&self, name: &str) {
+        if let Some(state) = self.states.lock().unwrap().get_mut(name) {
+            state.last_fired = None;
+            state.fires = 0;
+        }
+    }
+
+    /// One line for each trigger that has suppressed a match or disabled itself
+    pub fn report<'a>(
+        &self,
+        triggers: impl Iterator<Item = (&'a str, &'a FireLimits)>,
+    ) -> Vec<String> {
+        let states = self.states.lock().unwrap();
+        let mut reported = HashSet::new();
+
+        triggers
+            .filter_map(|(name, limits)| Some((name, limits, states.get(name)?)))
+            .filter(|(name, _, state)| state.suppressed > 0 && reported.insert(*name))
+            .map(|(name, limits, state)| {
+                let disabled = limits.max_fires.is_some_and(|max_fires| state.fires >= max_fires);
+                format!(
+                    "trigger {name}: {} matches suppressed{}",
+                    state.suppressed,
+                    if disabled {
+                        format!(", disabled after {} fires", state.fires)
+                    } else {
+                        String::new()
+                    }
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown() {
+        let limiter = TriggerLimiter::default();
+        let limits = FireLimits {
+            cooldown_ms: Some(1000),
+            max_fires: None,
+        };
+        let start = Instant::now();
+
+        assert!(limiter.try_fire("sip", &limits, start));
+        assert!(!limiter.try_fire("sip", &limits, start + Duration::from_millis(500)));
+        assert!(!limiter.try_fire("sip", &limits, start + Duration::from_millis(999)));
+        assert!(limiter.try_fire("sip", &limits, start + Duration::from_millis(1000)));
+
+        // Other triggers aren't affected
+        assert!(limiter.try_fire("other", &limits, start + Duration::from_millis(1000)));
+
+        assert_eq!(
+            limiter.report([("sip", &limits), ("other", &limits)].into_iter()),
+            vec!["trigger sip: 2 matches suppressed".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_max_fires_and_rearm() {
+        let limiter = TriggerLimiter::default();
+        let limits = FireLimits {
+            cooldown_ms: None,
+            max_fires: Some(2),
+        };
+        let now = Instant::now();
+
+        assert!(limiter.try_fire("login", &limits, now));
+        assert!(limiter.try_fire("login", &limits, now));
+        assert!(!limiter.try_fire("login", &limits, now));
+
+        assert_eq!(
+            limiter.report([("login", &limits)].into_iter()),
+            vec!["trigger login: 1 matches suppressed, disabled after 2 fires".to_string()]
+        );
+
+        limiter.rearm("login");
+        assert!(limiter.try_fire("login", &limits, now));
+    }
+
+    #[test]
+    fn test_unlimited_always_fires() {
+        let limiter = TriggerLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            assert!(limiter.try_fire("any", &FireLimits::default(), now));
+        }
+        assert!(limiter.report([("any", &FireLimits::default())].into_iter()).is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+use regex::{Regex, RegexSet};
+
+use super::{prefilter::LiteralPrefilter, Trigger};
+
+/// Finds the triggers that fire for a line. Every anti-pattern is compiled into one set, so a
+/// line is scanned once for all of them, and they're checked first. Patterns are then screened
+/// for the literal text they require, and only the regexes of triggers that are still in the
+/// running and whose literal is in the line are run. Prompt triggers only fire on prompts, and
+/// other triggers only on complete lines. Raw triggers are matched against the line as it was
+/// received, escape codes and all, and everything else against its plain text.
+#[derive(Debug)]
+pub struct TriggerMatcher {
+    patterns: Vec<Regex>,
+    /// The index of the trigger each pattern in `patterns` belongs to
+    pattern_owners: Vec<usize>,
+    prefilter: LiteralPrefilter,
+    raw_patterns: Vec<Regex>,
+    raw_pattern_owners: Vec<usize>,
+    raw_prefilter: LiteralPrefilter,
+    anti_pattern_set: RegexSet,
+    anti_pattern_owners: Vec<usize>,
+    prompt: Vec<bool>,
+}
+
+impl Default for TriggerMatcher {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            pattern_owners: Vec::new(),
+            prefilter: LiteralPrefilter::default(),
+            raw_patterns: Vec::new(),
+            raw_pattern_owners: Vec::new(),
+            raw_prefilter: LiteralPrefilter::default(),
+            anti_pattern_set: RegexSet::empty(),
+            anti_pattern_owners: Vec::new(),
+            prompt: Vec::new(),
+        }
+    }
+}
+
+/// How much work the literal screen saved on a line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Screening {
+    /// Patterns whose trigger hadn't been ruled out by the line's kind or an anti-pattern
+    pub eligible: usize,
+    /// Eligible patterns whose regex was run, because their literal was in the line or they
+    /// don't have one
+    pub executed: usize,
+}
+
+impl TriggerMatcher {
+    pub fn new(triggers: &[Trigger]) -> Self {
+        let mut patterns = Vec::new();
+        let mut pattern_owners = Vec::new();
+        let mut raw_patterns = Vec::new();
+        let mut raw_pattern_owners = Vec::new();
+        let mut anti_patterns = Vec::new();
+        let mut anti_pattern_owners = Vec::new();
+
+        for (trigger_idx, trigger) in triggers.iter().enumerate() {
+            if trigger.raw {
+                raw_patterns.push(trigger.regex.clone());
+                raw_pattern_owners.push(trigger_idx);
+            } else {
+                patterns.push(trigger.regex.clone());
+                pattern_owners.push(trigger_idx);
+            }
+            for anti_pattern in &trigger.anti_patterns {
+                anti_patterns.push(anti_pattern.as_str());
+                anti_pattern_owners.push(trigger_idx);
+            }
+        }
+
+        Self {
+            prefilter: LiteralPrefilter::new(patterns.iter().map(Regex::as_str)),
+            patterns,
+            pattern_owners,
+            raw_prefilter: LiteralPrefilter::new(raw_patterns.iter().map(Regex::as_str)),
+            raw_patterns,
+            raw_pattern_owners,
+            anti_pattern_set: RegexSet::new(anti_patterns).unwrap(),
+            anti_pattern_owners,
+            prompt: triggers.iter().map(|trigger| trigger.prompt).collect(),
+        }
+    }
+
+    /// Indices of the triggers whose pattern matches the line and none of whose anti-patterns do,
+    /// in trigger order, limited to prompt triggers when the line is a prompt and to the others
+    /// when it isn't. `line` is the plain text, and `raw_line` what was received.
+    pub fn matches(&self, line: &str, raw_line: &str, is_prompt: bool) -> Vec<usize> {
+        self.screened_matches(line, raw_line, is_prompt).0
+    }
+
+    /// The same as `matches`, along with how many regexes the literal screen let through, or
+    /// None when every trigger was ruled out before screening
+    pub fn screened_matches(
+        &self,
+        line: &str,
+        raw_line: &str,
+        is_prompt: bool,
+    ) -> (Vec<usize>, Option<Screening>) {
+        let mut suppressed: Vec<bool> = self
+            .prompt
+            .iter()
+            .map(|prompt| *prompt != is_prompt)
+            .collect();
+        let mut remaining = suppressed.iter().filter(|suppressed| !**suppressed).count();
+
+        if !self.anti_pattern_owners.is_empty() {
+            for anti_pattern_idx in &self.anti_pattern_set.matches(line) {
+                let owner = self.anti_pattern_owners[anti_pattern_idx];
+                if !suppressed[owner] {
+                    suppressed[owner] = true;
+                    remaining -= 1;
+                }
+            }
+        }
+
+        if remaining == 0 {
+            return (Vec::new(), None);
+        }
+
+        let mut screening = Screening::default();
+        let mut matches = Vec::new();
+        let mut run_screened =
+            |haystack: &str, patterns: &[Regex], owners: &[usize], prefilter: &LiteralPrefilter| {
+                if patterns.is_empty() {
+                    return;
+                }
+                let candidates = prefilter.candidates(haystack);
+                for (pattern_idx, pattern) in patterns.iter().enumerate() {
+                    let trigger_idx = owners[pattern_idx];
+                    if suppressed[trigger_idx] {
+                        continue;
+                    }
+                    screening.eligible += 1;
+                    if !candidates[pattern_idx] {
+                        continue;
+                    }
+                    screening.executed += 1;
+                    if pattern.is_match(haystack) {
+                        matches.push(trigger_idx);
+                    }
+                }
+            };
+
+        run_screened(line, &self.patterns, &self.pattern_owners, &self.prefilter);
+        run_screened(
+            raw_line,
+            &self.raw_patterns,
+            &self.raw_pattern_owners,
+            &self.raw_prefilter,
+        );
+        if !self.raw_pattern_owners.is_empty() {
+            matches.sort_unstable();
+        }
+
+        (matches, Some(screening))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+    use crate::trigger::Action;
+
+    fn trigger(pattern: &str, anti_patterns: &[&str], prompt: bool) -> Trigger {
+        Trigger::new(
+            pattern.to_string(),
+            Regex::new(pattern).unwrap(),
+            anti_patterns
+                .iter()
+                .map(|anti_pattern| Regex::new(anti_pattern).unwrap())
+                .collect(),
+            prompt,
+            Action::Noop,
+        )
+    }
+
+    /// Matches a line that was received without any escape codes
+    fn matches(matcher: &TriggerMatcher, line: &str, is_prompt: bool) -> Vec<usize> {
+        matcher.matches(line, line, is_prompt)
+    }
+
+    fn raw_trigger(pattern: &str) -> Trigger {
+        trigger(pattern, &[], false).with_raw(true)
+    }
+
+    #[test]
+    fn test_patterns_without_anti_patterns() {
+        let matcher = TriggerMatcher::new(&[
+            trigger("is dead!", &[], false),
+            trigger("goblin", &[], false),
+        ]);
+
+        assert_eq!(matches(&matcher, "The goblin is dead!", false), vec![0, 1]);
+        assert_eq!(matches(&matcher, "The rat is dead!", false), vec![0]);
+        assert!(matches(&matcher, "Nothing happens.", false).is_empty());
+    }
+
+    #[test]
+    fn test_anti_pattern_suppresses_trigger() {
+        let matcher = TriggerMatcher::new(&[
+            trigger("is dead!", &["^Your pet", "tells you"], false),
+            trigger("goblin", &[], false),
+        ]);
+
+        assert_eq!(matches(&matcher, "The goblin is dead!", false), vec![0, 1]);
+        assert_eq!(
+            matches(&matcher, "Your pet goblin is dead!", false),
+            vec![1]
+        );
+        assert!(matches(&matcher, "Bob tells you 'the rat is dead!'", false).is_empty());
+    }
+
+    #[test]
+    fn test_all_triggers_suppressed() {
+        let matcher = TriggerMatcher::new(&[
+            trigger("dead", &["spam"], false),
+            trigger("dead", &["spam"], false),
+        ]);
+
+        assert!(matches(&matcher, "spam spam dead spam", false).is_empty());
+        assert_eq!(matches(&matcher, "dead", false), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_prompt_triggers() {
+        let matcher = TriggerMatcher::new(&[
+            trigger(r"^HP:(\d+) MP:(\d+)>", &[], true),
+            trigger(r"HP:", &[], false),
+        ]);
+
+        assert_eq!(matches(&matcher, "HP:100 MP:50>", true), vec![0]);
+        assert_eq!(matches(&matcher, "HP:100 MP:50>", false), vec![1]);
+    }
+
+    #[test]
+    fn test_raw_patterns_match_escape_codes() {
+        let matcher = TriggerMatcher::new(&[
+            raw_trigger(r"\x1b\[31mYou are hungry"),
+            trigger("^You are hungry", &[], false),
+            raw_trigger("^You are hungry"),
+        ]);
+
+        let plain = "You are hungry.";
+        let raw = "\x1b[31mYou are hungry.\x1b[0m";
+
+        assert_eq!(matcher.matches(plain, raw, false), vec![0, 1]);
+        // Without the color, only the plain and uncolored raw patterns match
+        assert_eq!(matcher.matches(plain, plain, false), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_screening_keeps_anchored_and_case_insensitive_matches() {
+        let matcher = TriggerMatcher::new(&[
+            trigger("^You are hungry", &[], false),
+            trigger("(?i)tells you", &[], false),
+            trigger(r"^(\d+)$", &[], false),
+        ]);
+
+        assert_eq!(matches(&matcher, "You are hungry.", false), vec![0]);
+        assert!(matches(&matcher, "Bob says 'You are hungry.'", false).is_empty());
+        assert_eq!(matches(&matcher, "Bob TELLS YOU 'hi'", false), vec![1]);
+        assert_eq!(matches(&matcher, "12345", false), vec![2]);
+    }
+
+    #[test]
+    fn test_screening_counts() {
+        let matcher = TriggerMatcher::new(&[
+            trigger("goblin", &[], false),
+            trigger("(?i)tells you", &[], false),
+            trigger(r"^(\d+)$", &[], false),
+            trigger("HP:", &[], true),
+            raw_trigger(r"\x1b\[31mYou are hungry"),
+        ]);
+
+        // The pattern without a literal is always run, and the prompt trigger isn't eligible
+        assert_eq!(
+            matcher.screened_matches("The goblin arrives.", "The goblin arrives.", false),
+            (
+                vec![0],
+                Some(Screening {
+                    eligible: 4,
+                    executed: 2
+                })
+            )
+        );
+        assert_eq!(
+            matcher.screened_matches("You are hungry.", "\x1b[31mYou are hungry.\x1b[0m", false),
+            (
+                vec![4],
+                Some(Screening {
+                    eligible: 4,
+                    executed: 2
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let matcher = TriggerMatcher::default();
+
+        assert!(matches(&matcher, "anything", false).is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use aho_corasick::AhoCorasick;
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+/// Screens lines for the literal text each pattern can't match without, so only the patterns
+/// that might match have their regex run. Literals are searched for together in one pass, ASCII
+/// case-insensitively so that `(?i)` patterns are covered; that can only shortlist a pattern that
+/// then fails to match, never leave out one that would have matched. Patterns without a usable
+/// literal are always shortlisted.
+#[derive(Debug, Default)]
+pub struct LiteralPrefilter {
+    searcher: Option<AhoCorasick>,
+    /// The patterns requiring each literal in `searcher`
+    literal_owners: Vec<Vec<usize>>,
+    /// Patterns without a literal, which are always candidates
+    unscreened: Vec<usize>,
+    pattern_count: usize,
+}
+
+impl LiteralPrefilter {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut literals: Vec<Vec<u8>> = Vec::new();
+        let mut literal_owners: Vec<Vec<usize>> = Vec::new();
+        let mut literal_indices: HashMap<Vec<u8>, usize> = HashMap::new();
+        let mut unscreened = Vec::new();
+        let mut pattern_count = 0;
+
+        for (pattern_idx, pattern) in patterns.into_iter().enumerate() {
+            pattern_count += 1;
+            let Some(literal) = required_literal(pattern) else {
+                unscreened.push(pattern_idx);
+                continue;
+            };
+
+            let literal = literal.to_ascii_lowercase();
+            let literal_idx = *literal_indices.entry(literal.clone()).or_insert_with(|| {
+                literals.push(literal);
+                literal_owners.push(Vec::new());
+                literal_owners.len() - 1
+            });
+            literal_owners[literal_idx].push(pattern_idx);
+        }
+
+        let searcher = (!literals.is_empty()).then(|| {
+            AhoCorasick::builder()
+                .ascii_case_insensitive(true)
+                .build(&literals)
+                .unwrap()
+        });
+
+        Self {
+            searcher,
+            literal_owners,
+            unscreened,
+            pattern_count,
+        }
+    }
+
+    /// Which patterns might match `haystack`, indexed by pattern
+    pub fn candidates(&self, haystack: &str) -> Vec<bool> {
+        let mut candidates = vec![false; self.pattern_count];
+        for pattern_idx in &self.unscreened {
+            candidates[*pattern_idx] = true;
+        }
+
+        if let Some(searcher) = &self.searcher {
+            for found in searcher.find_overlapping_iter(haystack) {
+                for pattern_idx in &self.literal_owners[found.pattern().as_usize()] {
+                    candidates[*pattern_idx] = true;
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+/// The longest run of text every match of `pattern` has to contain, if it has one
+fn required_literal(pattern: &str) -> Option<Vec<u8>> {
+    let hir = regex_syntax::parse(pattern).ok()?;
+    longest_required(&hir).filter(|literal| !literal.is_empty())
+}
+
+fn longest_required(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(literal) => Some(literal.0.to_vec()),
+        HirKind::Class(class) => ascii_letter_either_case(class).map(|byte| vec![byte]),
+        HirKind::Capture(capture) => longest_required(&capture.sub),
+        HirKind::Repetition(repetition) if repetition.min > 0 => longest_required(&repetition.sub),
+        HirKind::Concat(children) => {
+            let mut longest: Option<Vec<u8>> = None;
+            let mut keep_if_longer = |candidate: Vec<u8>| {
+                if longest
+                    .as_ref()
+                    .is_none_or(|longest| candidate.len() > longest.len())
+                {
+                    longest = Some(candidate);
+                }
+            };
+
+            // Adjacent literals (and letters in either case) are a longer run of text together
+            let mut run: Vec<u8> = Vec::new();
+            for child in children {
+                let adjacent = match child.kind() {
+                    HirKind::Literal(literal) => Some(literal.0.to_vec()),
+                    HirKind::Class(class) => ascii_letter_either_case(class).map(|byte| vec![byte]),
+                    _ => None,
+                };
+                match adjacent {
+                    Some(bytes) => run.extend(bytes),
+                    None => {
+                        keep_if_longer(std::mem::take(&mut run));
+                        if let Some(nested) = longest_required(child) {
+                            keep_if_longer(nested);
+                        }
+                    }
+                }
+            }
+            keep_if_longer(run);
+
+            longest
+        }
+        // Nothing in particular is required by alternatives, optional repetitions, look-arounds
+        // or other classes
+        _ => None,
+    }
+}
+
+/// The lowercase letter when a class is exactly one ASCII letter in both cases, which is how
+/// `(?i)` letters end up. Letters with non-ASCII case variants, like `k` and the Kelvin sign,
+/// don't count, since searching for the ASCII letter could miss them.
+fn ascii_letter_either_case(class: &Class) -> Option<u8> {
+    let members: Vec<u32> = match class {
+        Class::Unicode(class) => class
+            .iter()
+            .flat_map(|range| range.start() as u32..=range.end() as u32)
+            .take(3)
+            .collect(),
+        Class::Bytes(class) => class
+            .iter()
+            .flat_map(|range| range.start() as u32..=range.end() as u32)
+            .take(3)
+            .collect(),
+    };
+
+    match members[..] {
+        [upper, lower]
+            if upper < 0x80
+                && (upper as u8).is_ascii_uppercase()
+                && lower == (upper as u8).to_ascii_lowercase() as u32 =>
+        {
+            Some(lower as u8)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::*;
+
+    fn literal(pattern: &str) -> Option<String> {
+        required_literal(pattern).map(|literal| String::from_utf8(literal).unwrap())
+    }
+
+    #[test]
+    fn test_required_literals() {
+        assert_eq!(literal("tells you"), Some("tells you".to_string()));
+        assert_eq!(
+            literal(r"^(\w+) tells you '(.*)'$"),
+            Some(" tells you '".to_string())
+        );
+        assert_eq!(
+            literal(r"is dead! R\.I\.P\.$"),
+            Some("is dead! R.I.P.".to_string())
+        );
+        assert_eq!(
+            literal(r"(?:You are )+hungry"),
+            Some("You are ".to_string())
+        );
+        assert_eq!(
+            literal(r"(?i)YOU ARE HUNGRY"),
+            Some("you are hungry".to_string())
+        );
+        assert_eq!(literal(r"\x1b\[31m"), Some("\x1b[31m".to_string()));
+        assert_eq!(literal(r"^(\d+)$"), None);
+        assert_eq!(literal(r"goblin|orc"), None);
+        assert_eq!(literal(r"(?:goblin)?"), None);
+    }
+
+    #[test]
+    fn test_screening_matches_running_every_regex() {
+        let patterns = [
+            r"^You are hungry",
+            r"(?i)tells you",
+            r"(?i)kill",
+            r"\x1b\[31mYou are hungry",
+            r"^(\d+)$",
+            r"goblin|orc",
+            r"Café",
+        ];
+        let lines = [
+            "You are hungry.",
+            "Bob says 'You are hungry.'",
+            "\x1b[31mYou are hungry.\x1b[0m",
+            "Bob TELLS YOU 'hi'",
+            "Bob tells you 'hi'",
+            "You KILL the goblin.",
+            // The Kelvin sign is a `k` to `(?i)`
+            "\u{212a}ILL",
+            "12345",
+            "The orc arrives at the Café.",
+            "Nothing happens.",
+        ];
+
+        let prefilter = LiteralPrefilter::new(patterns);
+        let regexes: Vec<Regex> = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern).unwrap())
+            .collect();
+
+        for line in lines {
+            let candidates = prefilter.candidates(line);
+            for (pattern_idx, regex) in regexes.iter().enumerate() {
+                if regex.is_match(line) {
+                    assert!(
+                        candidates[pattern_idx],
+                        "'{}' was screened out for '{line}'",
+                        patterns[pattern_idx]
+                    );
+                }
+            }
+        }
+
+        let candidates = prefilter.candidates("Nothing happens.");
+        assert_eq!(
+            candidates,
+            vec![false, false, false, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(LiteralPrefilter::default()
+            .candidates("anything")
+            .is_empty());
+    }
+}
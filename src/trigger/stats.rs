@@ -0,0 +1,331 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+const REPORT_MAX_ROWS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PatternKind {
+    Trigger,
+    Alias,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PatternStats {
+    pub attempts: u64,
+    pub hits: u64,
+    pub elapsed: Duration,
+}
+
+/// One trigger or alias's counters, as `smudgy.stats()` hands them to scripts
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatternReport {
+    pub kind: PatternKind,
+    pub name: String,
+    pub attempts: u64,
+    pub hits: u64,
+    pub elapsed_ms: f64,
+}
+
+/// Everything profiled so far, most expensive patterns first
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsReport {
+    pub enabled: bool,
+    pub trigger_scan_ms: f64,
+    pub alias_scan_ms: f64,
+    pub patterns: Vec<PatternReport>,
+}
+
+/// Counters for how often each trigger/alias is tested and matched, and how long its action takes
+/// to run. Everything is a no-op unless profiling has been switched on, so the cost when disabled
+/// is a single relaxed atomic load per line.
+#[derive(Debug, Default)]
+pub struct TriggerStats {
+    enabled: AtomicBool,
+    triggers: Mutex<Vec<PatternStats>>,
+    aliases: Mutex<Vec<PatternStats>>,
+    /// The names of the patterns counted, in the order they're indexed
+    trigger_names: Mutex<Vec<String>>,
+    alias_names: Mutex<Vec<String>>,
+    trigger_scan_time: Mutex<Duration>,
+    alias_scan_time: Mutex<Duration>,
+    screening: Mutex<ScreeningStats>,
+}
+
+/// Totals for the trigger matcher's literal screen
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScreeningStats {
+    pub lines: u64,
+    /// Trigger regexes that would have been run without the screen
+    pub eligible: u64,
+    pub executed: u64,
+}
+
+impl TriggerStats {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.triggers.lock().unwrap().clear();
+        self.aliases.lock().unwrap().clear();
+        *self.trigger_scan_time.lock().unwrap() = Duration::ZERO;
+        *self.alias_scan_time.lock().unwrap() = Duration::ZERO;
+        *self.screening.lock().unwrap() = ScreeningStats::default();
+    }
+
+    /// Starts counting afresh for a rebuilt set of triggers or aliases, since the old counters are
+    /// indexed by where patterns used to be
+    pub fn reset_patterns(&self, kind: PatternKind, names: Vec<String>) {
+        self.counters(kind).lock().unwrap().clear();
+        *self.scan_time(kind).lock().unwrap() = Duration::ZERO;
+        if kind == PatternKind::Trigger {
+            *self.screening.lock().unwrap() = ScreeningStats::default();
+        }
+        *self.names(kind).lock().unwrap() = names;
+    }
+
+    /// Returns the start time of a measurement, or None when profiling is off
+    #[inline(always)]
+    pub fn start(&self) -> Option<Instant> {
+        self.is_enabled().then(Instant::now)
+    }
+
+    fn counters(&self, kind: PatternKind) -> &Mutex<Vec<PatternStats>> {
+        match kind {
+            PatternKind::Trigger => &self.triggers,
+            PatternKind::Alias => &self.aliases,
+        }
+    }
+
+    fn names(&self, kind: PatternKind) -> &Mutex<Vec<String>> {
+        match kind {
+            PatternKind::Trigger => &self.trigger_names,
+            PatternKind::Alias => &self.alias_names,
+        }
+    }
+
+    fn scan_time(&self, kind: PatternKind) -> &Mutex<Duration> {
+        match kind {
+            PatternKind::Trigger => &self.trigger_scan_time,
+            PatternKind::Alias => &self.alias_scan_time,
+        }
+    }
+
+    /// Records that a line was tested against every one of `pattern_count` patterns
+    pub fn record_scan(&self, kind: PatternKind, started: Option<Instant>, pattern_count: usize) {
+        if let Some(started) = started {
+            let mut counters = self.counters(kind).lock().unwrap();
+            if counters.len() < pattern_count {
+                counters.resize(pattern_count, PatternStats::default());
+            }
+            for counter in counters.iter_mut().take(pattern_count) {
+                counter.attempts += 1;
+            }
+
+            *self.scan_time(kind).lock().unwrap() += started.elapsed();
+        }
+    }
+
+    /// Records that a line went through the literal screen, which let `executed` of `eligible`
+    /// trigger regexes be run
+    pub fn record_screening(&self, started: Option<Instant>, eligible: usize, executed: usize) {
+        if started.is_some() {
+            let mut screening = self.screening.lock().unwrap();
+            screening.lines += 1;
+            screening.eligible += eligible as u64;
+            screening.executed += executed as u64;
+        }
+    }
+
+    /// Records that the pattern at `index` matched, and how long its action took to run
+    pub fn record_hit(&self, kind: PatternKind, index: usize, started: Option<Instant>) {
+        if let Some(started) = started {
+            let mut counters = self.counters(kind).lock().unwrap();
+            if counters.len() <= index {
+                counters.resize(index + 1, PatternStats::default());
+            }
+            counters[index].hits += 1;
+            counters[index].elapsed += started.elapsed();
+        }
+    }
+
+    /// Everything counted so far, with the most expensive patterns first
+    pub fn snapshot(&self) -> StatsReport {
+        let mut patterns = Vec::new();
+        for kind in [PatternKind::Trigger, PatternKind::Alias] {
+            let names = self.names(kind).lock().unwrap();
+            let counters = self.counters(kind).lock().unwrap();
+            for (name, stats) in names.iter().zip(counters.iter()) {
+                patterns.push(PatternReport {
+                    kind,
+                    name: name.clone(),
+                    attempts: stats.attempts,
+                    hits: stats.hits,
+                    elapsed_ms: stats.elapsed.as_millis_f64(),
+                });
+            }
+        }
+        patterns.sort_by(|a, b| {
+            b.elapsed_ms
+                .total_cmp(&a.elapsed_ms)
+                .then(b.hits.cmp(&a.hits))
+        });
+
+        StatsReport {
+            enabled: self.is_enabled(),
+            trigger_scan_ms: self.trigger_scan_time.lock().unwrap().as_millis_f64(),
+            alias_scan_ms: self.alias_scan_time.lock().unwrap().as_millis_f64(),
+            patterns,
+        }
+    }
+
+    /// Builds a table of the most expensive triggers and aliases, one row per line
+    pub fn report(&self) -> Vec<String> {
+        let snapshot = self.snapshot();
+
+        let mut lines = vec![
+            format!(
+                "Profiling is {}; scanning took {:.3}ms for triggers and {:.3}ms for aliases",
+                if snapshot.enabled { "on" } else { "off" },
+                snapshot.trigger_scan_ms,
+                snapshot.alias_scan_ms,
+            ),
+            {
+                let screening = self.screening.lock().unwrap();
+                format!(
+                    "Literal screening: {} lines screened, {} of {} trigger regexes run",
+                    screening.lines, screening.executed, screening.eligible
+                )
+            },
+            format!(
+                "{:<8} {:<24} {:>10} {:>8} {:>12}",
+                "kind", "name", "attempts", "hits", "time (ms)"
+            ),
+        ];
+
+        lines.extend(
+            snapshot
+                .patterns
+                .iter()
+                .take(REPORT_MAX_ROWS)
+                .map(|pattern| {
+                    format!(
+                        "{:<8} {:<24} {:>10} {:>8} {:>12.3}",
+                        match pattern.kind {
+                            PatternKind::Trigger => "trigger",
+                            PatternKind::Alias => "alias",
+                        },
+                        pattern.name,
+                        pattern.attempts,
+                        pattern.hits,
+                        pattern.elapsed_ms
+                    )
+                }),
+        );
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_records_nothing() {
+        let stats = TriggerStats::default();
+
+        let started = stats.start();
+        assert!(started.is_none());
+
+        stats.record_scan(PatternKind::Trigger, started, 3);
+        stats.record_hit(PatternKind::Trigger, 1, started);
+
+        assert!(stats.triggers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_counts_attempts_and_hits() {
+        let stats = TriggerStats::default();
+        stats.set_enabled(true);
+
+        stats.record_scan(PatternKind::Alias, stats.start(), 2);
+        stats.record_scan(PatternKind::Alias, stats.start(), 2);
+        stats.record_hit(PatternKind::Alias, 1, stats.start());
+
+        let aliases = stats.aliases.lock().unwrap().clone();
+        assert_eq!(aliases[0].attempts, 2);
+        assert_eq!(aliases[0].hits, 0);
+        assert_eq!(aliases[1].attempts, 2);
+        assert_eq!(aliases[1].hits, 1);
+
+        stats.reset();
+        assert!(stats.aliases.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rebuilding_patterns_resets_their_counters() {
+        let stats = TriggerStats::default();
+        stats.set_enabled(true);
+        stats.reset_patterns(PatternKind::Trigger, vec!["autoloot".into()]);
+        stats.reset_patterns(PatternKind::Alias, vec!["oj".into()]);
+
+        stats.record_scan(PatternKind::Trigger, stats.start(), 1);
+        stats.record_hit(PatternKind::Trigger, 0, stats.start());
+        stats.record_scan(PatternKind::Alias, stats.start(), 1);
+
+        let snapshot = stats.snapshot();
+        let counts = |name: &str| {
+            let pattern = snapshot
+                .patterns
+                .iter()
+                .find(|pattern| pattern.name == name);
+            pattern.map(|pattern| (pattern.kind, pattern.attempts, pattern.hits))
+        };
+        assert_eq!(counts("autoloot"), Some((PatternKind::Trigger, 1, 1)));
+        assert_eq!(counts("oj"), Some((PatternKind::Alias, 1, 0)));
+
+        // A new trigger shifts the others along, so none of their counts carry over
+        stats.reset_patterns(PatternKind::Trigger, vec!["a".into(), "autoloot".into()]);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.patterns.len(), 1);
+        assert_eq!(snapshot.patterns[0].name, "oj");
+        assert_eq!(snapshot.trigger_scan_ms, 0.0);
+    }
+
+    #[test]
+    fn test_screening_totals() {
+        let stats = TriggerStats::default();
+        stats.record_screening(stats.start(), 10, 3);
+        stats.set_enabled(true);
+        stats.record_screening(stats.start(), 10, 3);
+        stats.record_screening(stats.start(), 8, 1);
+
+        assert_eq!(
+            *stats.screening.lock().unwrap(),
+            ScreeningStats {
+                lines: 2,
+                eligible: 18,
+                executed: 4,
+            }
+        );
+        assert_eq!(
+            stats.report()[1],
+            "Literal screening: 2 lines screened, 4 of 18 trigger regexes run"
+        );
+    }
+}
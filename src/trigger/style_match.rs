@@ -0,0 +1,216 @@
+use std::ops::Range;
+
+use crate::session::{AnsiColor, Color, Style, StyledLine};
+
+const ANSI_COLORS: [AnsiColor; 8] = [
+    AnsiColor::Black,
+    AnsiColor::Red,
+    AnsiColor::Green,
+    AnsiColor::Yellow,
+    AnsiColor::Blue,
+    AnsiColor::Magenta,
+    AnsiColor::Cyan,
+    AnsiColor::White,
+];
+
+/// A color a trigger can require of the text it matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMatch {
+    /// One of the 16 ANSI colors. 0 to 7 match the color whether or not it's bright, and 8 to 15
+    /// only its bright version.
+    Palette(u8),
+    Rgb {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+}
+
+impl ColorMatch {
+    fn matches(&self, color: Color) -> bool {
+        match (*self, color) {
+            (ColorMatch::Palette(n), Color::AnsiColor { color, bold }) => {
+                n < 16 && ANSI_COLORS[n as usize % 8] == color && (n < 8 || bold)
+            }
+            (
+                ColorMatch::Rgb { r, g, b },
+                Color::RGB {
+                    r: r2,
+                    g: g2,
+                    b: b2,
+                },
+            ) => (r, g, b) == (r2, g2, b2),
+            _ => false,
+        }
+    }
+}
+
+/// The colors and attributes a trigger's matched text must be shown with, for servers that
+/// tell lines apart by color alone, like a red "You are hungry." against a white one said by
+/// another player. Anything left as None isn't checked.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StyleMatcher {
+    pub fg: Option<ColorMatch>,
+    pub bg: Option<ColorMatch>,
+    pub bold: Option<bool>,
+    pub underline: Option<bool>,
+    /// Requires the style of every part of the matched text, rather than of any part of it
+    pub whole_span: bool,
+}
+
+impl StyleMatcher {
+    fn matches_style(&self, style: &Style) -> bool {
+        let bold = matches!(style.fg, Color::AnsiColor { bold: true, .. });
+
+        self.fg.map_or(true, |fg| fg.matches(style.fg))
+            && self
+                .bg
+                .map_or(true, |bg| style.bg.is_some_and(|color| bg.matches(color)))
+            && self.bold.map_or(true, |wanted| wanted == bold)
+            && self
+                .underline
+                .map_or(true, |wanted| wanted == style.underline)
+    }
+
+    /// Whether the text at `range` of the line's plain text has the style. An empty range is
+    /// checked against the character it's in front of.
+    pub fn matches(&self, line: &StyledLine, range: Range<usize>) -> bool {
+        let range = text_range(line, range);
+        let range = range.start..range.end.max(range.start + 1);
+        let mut overlapping = line
+            .spans
+            .iter()
+            .filter(|span| span.begin_pos < range.end && range.start < span.end_pos);
+
+        if self.whole_span {
+            overlapping.all(|span| self.matches_style(&span.style))
+        } else {
+            overlapping.any(|span| self.matches_style(&span.style))
+        }
+    }
+}
+
+/// Converts a byte range of the line's plain text into one of its text, which can also hold
+/// the control characters `plain_text` leaves out
+fn text_range(line: &StyledLine, range: Range<usize>) -> Range<usize> {
+    let is_hidden = |ch: char| ch != '\t' && ch.is_control();
+    if !line.text.contains(is_hidden) {
+        return range;
+    }
+
+    let mut start = line.text.len();
+    let mut end = line.text.len();
+    let mut plain_pos = 0;
+    for (pos, ch) in line.text.char_indices().filter(|(_, ch)| !is_hidden(*ch)) {
+        if plain_pos == range.start {
+            start = pos;
+        }
+        if plain_pos == range.end {
+            end = pos;
+            break;
+        }
+        plain_pos += ch.len_utf8();
+    }
+    start..end.max(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::AnsiColor {
+        color: AnsiColor::Red,
+        bold: false,
+    };
+
+    /// `text`, shown in the default style except for `range`, which is red
+    fn line_with_red(text: &str, range: Range<usize>) -> StyledLine {
+        StyledLine::from_output_str(text).restyle(range, |style| style.fg = RED)
+    }
+
+    fn red() -> StyleMatcher {
+        StyleMatcher {
+            fg: Some(ColorMatch::Palette(1)),
+            ..StyleMatcher::default()
+        }
+    }
+
+    #[test]
+    fn test_same_text_in_different_colors() {
+        let text = "You are hungry.";
+        let red_line = line_with_red(text, 0..text.len());
+        let white_line = StyledLine::from_output_str(text);
+
+        assert!(red().matches(&red_line, 0..text.len()));
+        assert!(!red().matches(&white_line, 0..text.len()));
+
+        let bright_red = StyleMatcher {
+            fg: Some(ColorMatch::Palette(9)),
+            ..StyleMatcher::default()
+        };
+        assert!(!bright_red.matches(&red_line, 0..text.len()));
+        let bold_line = StyledLine::from_output_str(text).restyle(0..text.len(), |style| {
+            style.fg = Color::AnsiColor {
+                color: AnsiColor::Red,
+                bold: true,
+            }
+        });
+        assert!(bright_red.matches(&bold_line, 0..text.len()));
+        assert!(red().matches(&bold_line, 0..text.len()));
+    }
+
+    #[test]
+    fn test_any_or_whole_span() {
+        // Only "Gandalf" is red
+        let line = line_with_red("Gandalf tells you 'run'", 0..7);
+        let whole = StyleMatcher {
+            whole_span: true,
+            ..red()
+        };
+
+        assert!(red().matches(&line, 0..13));
+        assert!(!whole.matches(&line, 0..13));
+        assert!(whole.matches(&line, 0..7));
+        assert!(!red().matches(&line, 8..13));
+    }
+
+    #[test]
+    fn test_attributes_and_rgb() {
+        let text = "A shimmering portal";
+        let line = StyledLine::from_output_str(text).restyle(2..12, |style| {
+            style.fg = Color::RGB {
+                r: 255,
+                g: 0,
+                b: 255,
+            };
+            style.underline = true;
+        });
+        let underlined_magenta = StyleMatcher {
+            fg: Some(ColorMatch::Rgb {
+                r: 255,
+                g: 0,
+                b: 255,
+            }),
+            underline: Some(true),
+            whole_span: true,
+            ..StyleMatcher::default()
+        };
+
+        assert!(underlined_magenta.matches(&line, 2..12));
+        assert!(!underlined_magenta.matches(&line, 13..19));
+        assert!(!StyleMatcher {
+            bold: Some(true),
+            ..StyleMatcher::default()
+        }
+        .matches(&line, 0..text.len()));
+    }
+
+    #[test]
+    fn test_hidden_characters_are_skipped() {
+        // The bell isn't in the plain text, so "hungry" starts a byte earlier there
+        let text = "\x07You are hungry.";
+        let line = line_with_red(text, 9..15);
+        assert!(red().matches(&line, 8..14));
+        assert!(!red().matches(&line, 0..7));
+    }
+}
@@ -0,0 +1,96 @@
+use regex::Captures;
+
+/// Expands `%0` (the whole input), `%1`..`%N` (capture groups) and `%*` (everything after the first
+/// word of the input) in a plaintext alias body. Missing captures expand to an empty string, and
+/// `%%` produces a literal `%`.
+pub fn substitute_arguments(script: &str, captures: &Captures, line: &str) -> String {
+    let mut result = String::with_capacity(script.len());
+    let mut chars = script.char_indices().peekable();
+
+    while let Some((_, ch)) = chars.next() {
+        if ch != '%' {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.peek() {
+            Some((_, '%')) => {
+                chars.next();
+                result.push('%');
+            }
+            Some((_, '*')) => {
+                chars.next();
+                result.push_str(rest_of_line(line));
+            }
+            Some((start, digit)) if digit.is_ascii_digit() => {
+                let start = *start;
+                let mut end = start;
+                while let Some((i, digit)) = chars.peek() {
+                    if !digit.is_ascii_digit() {
+                        break;
+                    }
+                    end = i + digit.len_utf8();
+                    chars.next();
+                }
+
+                match script[start..end].parse().unwrap_or(usize::MAX) {
+                    0 => result.push_str(line),
+                    index => {
+                        if let Some(capture) = captures.get(index) {
+                            result.push_str(capture.as_str());
+                        }
+                    }
+                }
+            }
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
+fn rest_of_line(line: &str) -> &str {
+    line.trim_start()
+        .split_once(char::is_whitespace)
+        .map(|(_, rest)| rest.trim_start())
+        .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    fn expand(pattern: &str, script: &str, line: &str) -> String {
+        let regex = Regex::new(pattern).unwrap();
+        let captures = regex.captures(line).unwrap();
+        substitute_arguments(script, &captures, line)
+    }
+
+    #[test]
+    fn test_positional_arguments() {
+        assert_eq!(
+            expand(r"^cast (\w+) (\w+)$", "prepare %1;cast '%1' %2", "cast fireball orc"),
+            "prepare fireball;cast 'fireball' orc"
+        );
+    }
+
+    #[test]
+    fn test_whole_input_and_rest() {
+        assert_eq!(
+            expand(r"^tell (\w+)", "say %0 (%*)", "tell bob hello there"),
+            "say tell bob hello there (bob hello there)"
+        );
+        assert_eq!(expand(r"^look$", "l %*", "look"), "l ");
+    }
+
+    #[test]
+    fn test_missing_arguments_are_empty() {
+        assert_eq!(expand(r"^get(?: (\w+))?$", "get %1 %2 all", "get"), "get   all");
+    }
+
+    #[test]
+    fn test_literal_percent() {
+        assert_eq!(expand(r"^hp$", "say 100%% healthy%", "hp"), "say 100% healthy%");
+    }
+}
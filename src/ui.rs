@@ -1,3 +1,6 @@
 mod connect_window_builder;
+mod open_session;
+pub mod pane_splits;
 
-pub use connect_window_builder::ConnectWindowBuilder;
\ No newline at end of file
+pub use connect_window_builder::ConnectWindowBuilder;
+pub use open_session::open_session;
\ No newline at end of file
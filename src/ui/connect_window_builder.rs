@@ -5,12 +5,12 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use anyhow::Context;
 use i_slint_backend_winit::winit::event;
 use slint::{ComponentHandle, Model};
 use slint::{VecModel, Weak};
 use smudgy_connect_window::{ConnectWindow, UiResult};
 
+use super::open_session;
 use crate::{
     models::{Character, Profile, ProfileData},
     session::Session,
@@ -81,35 +81,16 @@ impl ConnectWindowBuilder {
         let event_main_window = main_window.clone();
         let event_connect_window = window.as_weak();
         window.on_connect_clicked(move |profile, character| {
-            let mut sessions = event_sessions.borrow_mut();
-            let new_session_id = sessions.len() as i32;
-
-            let session_name = format!("{} - {}", character.name, character.name);
-
-            let profile = Rc::new(Profile::try_from(ProfileData::from(profile)).unwrap());
-            let character = Character::load(character.name.as_str(), Rc::downgrade(&profile))
-                .context("Error loading character from file")
-                .unwrap();
-            character.touch();
-
-            let session = Arc::new(Mutex::new(Session::new(
-                new_session_id,
-                event_main_window.clone(),
-                Rc::into_inner(profile).unwrap(),
-            )));
-
-            sessions.push(session.clone());
-
-            let mut session_guard = session.lock().unwrap();
-
-            let session_state = SessionState {
-                name: session_name.into(),
-                buffer: session_guard.view().into(),
-                scrollback_size: session_guard.view().row_count_model().into(),
-            };
-            event_sessions_model.push(session_state);
-
-            session_guard.connect();
+            if let Err(e) = open_session(
+                &event_main_window,
+                &event_sessions,
+                &event_sessions_model,
+                profile.name.as_str(),
+                character.name.as_str(),
+            ) {
+                error!("Could not open a session: {e:#}");
+                return;
+            }
 
             event_main_window
                 .upgrade()
@@ -0,0 +1,64 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use slint::{VecModel, Weak};
+
+use crate::{
+    models::{Character, Profile},
+    session::Session,
+    MainWindow, SessionState,
+};
+
+/// Opens a pane for the character and connects it. Fails when the profile or character can't be
+/// loaded, e.g. because it has since been deleted.
+pub fn open_session(
+    main_window: &Weak<MainWindow>,
+    sessions: &Rc<RefCell<Vec<Arc<Mutex<Session>>>>>,
+    sessions_model: &Rc<VecModel<SessionState>>,
+    profile_name: &str,
+    character_name: &str,
+) -> Result<()> {
+    let mut sessions = sessions.borrow_mut();
+    let new_session_id = sessions.len() as i32;
+
+    let session_name = format!("{} - {}", character_name, character_name);
+
+    // Load from disk rather than converting the ui struct, which doesn't carry every setting
+    let profile = Rc::new(Profile::load(profile_name).context("Error loading profile from file")?);
+    let character = Character::load(character_name, Rc::downgrade(&profile))
+        .context("Error loading character from file")?;
+    character.touch();
+
+    let session = Arc::new(Mutex::new(Session::new(
+        new_session_id,
+        main_window.clone(),
+        Rc::into_inner(profile).unwrap(),
+        &character,
+    )));
+
+    sessions.push(session.clone());
+
+    let mut session_guard = session.lock().unwrap();
+
+    let session_state = SessionState {
+        name: session_name.into(),
+        buffer: session_guard.view().into(),
+        capture_buffer: session_guard.capture_view().into(),
+        scrollback_size: session_guard.view().row_count_model().into(),
+        input_masked: session_guard.view().input_masked_model().into(),
+        status_fields: session_guard.view().status_fields_model().into(),
+        input_override: session_guard.view().input_override_model().into(),
+        input_override_serial: session_guard.view().input_override_serial_model().into(),
+        connection_state: session_guard.view().connection_state_model().into(),
+        send_queue_depth: session_guard.view().send_queue_depth_model().into(),
+    };
+    sessions_model.push(session_state);
+
+    session_guard.connect();
+
+    Ok(())
+}
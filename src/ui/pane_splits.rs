@@ -0,0 +1,69 @@
+/// The narrowest a pane can be dragged to, as a fraction of the terminal area
+const MIN_PANE_FRACTION: f32 = 0.1;
+
+/// Stops for `count` panes of the same size: where each one starts, as a fraction of the terminal
+/// area, followed by 1.0 for where the last one ends
+pub fn even_stops(count: usize) -> Vec<f32> {
+    let count = count.max(1);
+    (0..=count).map(|i| i as f32 / count as f32).collect()
+}
+
+/// Whether `stops` can lay out `count` panes: one more of them, running up from 0.0 to 1.0
+pub fn fits(stops: &[f32], count: usize) -> bool {
+    stops.len() == count.max(1) + 1
+        && stops.first() == Some(&0.0)
+        && stops.last() == Some(&1.0)
+        && stops.windows(2).all(|pair| pair[0] < pair[1])
+}
+
+/// Moves the divider at `stops[index]` towards `fraction`, stopping short of squeezing either
+/// neighbouring pane below the minimum. Returns where it ended up, or None for the outer edges,
+/// which don't move.
+pub fn move_stop(stops: &mut [f32], index: usize, fraction: f32) -> Option<f32> {
+    if index == 0 || index + 1 >= stops.len() {
+        return None;
+    }
+
+    let lowest = stops[index - 1] + MIN_PANE_FRACTION;
+    let highest = stops[index + 1] - MIN_PANE_FRACTION;
+    if lowest > highest {
+        return None;
+    }
+
+    stops[index] = fraction.clamp(lowest, highest);
+    Some(stops[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_even_stops() {
+        assert_eq!(even_stops(1), vec![0.0, 1.0]);
+        assert_eq!(even_stops(4), vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(even_stops(0), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_fits() {
+        assert!(fits(&even_stops(3), 3));
+        assert!(fits(&[0.0, 0.7, 1.0], 2));
+        assert!(!fits(&even_stops(3), 2));
+        assert!(!fits(&[0.0, 1.2, 1.0], 2));
+        assert!(!fits(&[], 1));
+    }
+
+    #[test]
+    fn test_dividers_keep_panes_from_vanishing() {
+        let mut stops = even_stops(2);
+
+        assert_eq!(move_stop(&mut stops, 1, 0.7), Some(0.7));
+        assert_eq!(move_stop(&mut stops, 1, 0.99), Some(0.9));
+        assert_eq!(move_stop(&mut stops, 1, -1.0), Some(0.1));
+        assert_eq!(stops, vec![0.0, 0.1, 1.0]);
+
+        assert_eq!(move_stop(&mut stops, 0, 0.5), None);
+        assert_eq!(move_stop(&mut stops, 2, 0.5), None);
+    }
+}